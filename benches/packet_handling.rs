@@ -0,0 +1,121 @@
+//! Criterion benchmarks, `cargo bench --features bench [-- filter]`; see "Benchmarks" in
+//! the README for the baseline.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use simple_mdns_client::bench::{Handler, Packet};
+use std::{
+    hint::black_box,
+    net::{Ipv4Addr, SocketAddr},
+};
+
+const SERVICE: &str = "_bench._tcp.local";
+const SIZES: [usize; 3] = [10, 100, 1000];
+
+const TYPE_A: u16 = 1;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+
+fn name(out: &mut Vec<u8>, name: &str) {
+    for label in name.split('.') {
+        out.push(label.len() as u8);
+        out.extend_from_slice(label.as_bytes());
+    }
+    out.push(0);
+}
+
+fn record(out: &mut Vec<u8>, owner: &str, kind: u16, data: &[u8]) {
+    name(out, owner);
+    out.extend_from_slice(&kind.to_be_bytes());
+    out.extend_from_slice(&0x8001u16.to_be_bytes());
+    out.extend_from_slice(&120u32.to_be_bytes());
+    out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    out.extend_from_slice(data);
+}
+
+fn srv(out: &mut Vec<u8>, i: usize) {
+    let mut data = vec![0, 0, 0, 0, 0, 80];
+    name(&mut data, &format!("h{}.local", i));
+    record(out, &format!("s{}.{}", i, SERVICE), TYPE_SRV, &data);
+}
+
+fn a(out: &mut Vec<u8>, i: usize) {
+    let address = Ipv4Addr::new(10, 0, (i / 250) as u8, (i % 250) as u8 + 1);
+    record(out, &format!("h{}.local", i), TYPE_A, &address.octets());
+}
+
+fn txt(out: &mut Vec<u8>, i: usize) {
+    record(
+        out,
+        &format!("s{}.{}", i, SERVICE),
+        TYPE_TXT,
+        b"\x06path=/\x0bversion=1.0",
+    );
+}
+
+// A response with `answers` records, added by `build`.
+fn response(answers: u16, build: impl FnOnce(&mut Vec<u8>)) -> Vec<u8> {
+    let mut packet = vec![0, 0, 0x84, 0, 0, 0];
+    packet.extend_from_slice(&answers.to_be_bytes());
+    packet.extend_from_slice(&[0, 0, 0, 0]);
+    build(&mut packet);
+    packet
+}
+
+// A handler that knows `size` services, each with its address.
+fn handler(size: usize) -> Handler {
+    let handler = Handler::new(SERVICE);
+
+    for i in 0..size {
+        let packet = response(2, |out| {
+            srv(out, i);
+            a(out, i);
+        });
+        handler.handle_response(&Packet::parse(&packet).unwrap(), from());
+    }
+
+    assert_eq!(handler.get_services().len(), size);
+    handler
+}
+
+fn from() -> SocketAddr {
+    (Ipv4Addr::new(10, 0, 0, 1), 5353).into()
+}
+
+fn benches(c: &mut Criterion) {
+    // Answers for services already known, as most are once the client has been running.
+    let single = response(1, |out| srv(out, 0));
+    let with_address = response(3, |out| {
+        srv(out, 0);
+        a(out, 0);
+        txt(out, 0);
+    });
+
+    for size in SIZES {
+        let handler = handler(size);
+        let burst = response(50, |out| {
+            for i in 0..25 {
+                srv(out, i % size);
+                a(out, i % size);
+            }
+        });
+
+        for (case, packet) in [
+            ("srv", &single),
+            ("srv+a+txt", &with_address),
+            ("burst50", &burst),
+        ] {
+            let packet = Packet::parse(packet).unwrap();
+
+            c.bench_function(&format!("handle_response/{}/{}", case, size), |b| {
+                b.iter(|| handler.handle_response(black_box(&packet), from()))
+            });
+        }
+
+        c.bench_function(&format!("get_services/{}", size), |b| {
+            b.iter(|| black_box(handler.get_services()))
+        });
+    }
+}
+
+criterion_group!(packet_handling, benches);
+criterion_main!(packet_handling);
@@ -0,0 +1,42 @@
+// Hex-dumps every datagram the client sends and receives, e.g.
+// cargo run --example hexdump -- "_http._tcp.local"
+use simple_mdns_client::{Direction, MdnsClient};
+use std::{env, error::Error, net::SocketAddr, sync::mpsc, thread};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let service = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "_http._tcp.local".to_string());
+
+    // Printed on a thread of its own, so a slow terminal doesn't hold up the client.
+    let (tx, rx) = mpsc::channel::<(Vec<u8>, SocketAddr, Direction)>();
+
+    let _mdns = MdnsClient::builder(&service)
+        .on_packet(move |data, peer, direction| {
+            tx.send((data.to_vec(), peer, direction)).ok();
+        })
+        .build()?;
+
+    thread::spawn(move || {
+        for (data, peer, direction) in rx {
+            let arrow = match direction {
+                Direction::Sent => "->",
+                Direction::Received => "<-",
+            };
+            println!("{} {} ({} bytes)", arrow, peer, data.len());
+
+            for (i, line) in data.chunks(16).enumerate() {
+                let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+                let text: String = line
+                    .iter()
+                    .map(|&b| if b.is_ascii_graphic() { b as char } else { '.' })
+                    .collect();
+                println!("  {:04x}  {:<47}  {}", i * 16, hex.join(" "), text);
+            }
+        }
+    })
+    .join()
+    .ok();
+
+    Ok(())
+}
@@ -0,0 +1,17 @@
+// Prints discovery events as NDJSON, e.g.
+// cargo run --example ndjson_bridge -- "_http._tcp.local" | jq .
+use simple_mdns_client::MdnsClient;
+use std::{env, error::Error, io};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let service = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "libmdns Web Server._http._tcp.local".to_string());
+
+    let mdns = MdnsClient::new(&service)?;
+
+    match mdns.run_ndjson_bridge(io::stdout().lock()) {
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => Ok(result?),
+    }
+}
@@ -0,0 +1,119 @@
+// Cycles services through a fake responder for a long time and checks that nothing the
+// client keeps grows with the number of services it has seen, e.g.
+// cargo run --release --features test-util --example soak -- 192.168.1.20 3600
+// The address is that of a non-loopback interface to answer on.
+use simple_mdns_client::{FakeResponder, MdnsClient, Protocol, ServiceType};
+use std::{
+    env,
+    error::Error,
+    net::Ipv4Addr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+// Services answered for at any time, a new one replacing the oldest every `CYCLE`.
+const LIVE: usize = 20;
+const CYCLE: Duration = Duration::from_millis(100);
+const CHECK_EVERY: Duration = Duration::from_secs(10);
+const GRACE: Duration = Duration::from_secs(2);
+const ACTIVITY: usize = 512;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = env::args().skip(1);
+    let address: Ipv4Addr = args
+        .next()
+        .ok_or("usage: soak ADDRESS [SECONDS]")?
+        .parse()?;
+    let duration = Duration::from_secs(args.next().map_or(Ok(60), |s| s.parse())?);
+
+    let fake = FakeResponder::new(address)?;
+    let mdns = MdnsClient::builder(ServiceType::new("soak", Protocol::Tcp)?)
+        .interface_addresses([address])
+        .expiry_grace(GRACE)
+        .activity_log(ACTIVITY)
+        .build()?;
+
+    // Drained as they come, so any events dropped mean the queue fell behind.
+    let events = Arc::new(AtomicU64::new(0));
+    let subscription = mdns.subscribe();
+    thread::spawn({
+        let events = events.clone();
+        move || {
+            while subscription.recv().is_ok() {
+                events.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    });
+
+    // Those announced within the last expiry and grace may still be there on top of the
+    // live ones, gone without answering for that long.
+    let lingering = (Duration::from_secs(5) + GRACE).as_millis() / CYCLE.as_millis();
+    let bound = LIVE + lingering as usize + LIVE;
+
+    let started = Instant::now();
+    let mut next_check = started + CHECK_EVERY;
+    let mut first_rss = None;
+    let mut added = 0usize;
+
+    while started.elapsed() < duration {
+        fake.add(
+            &format!("soak-{}", added),
+            "_soak._tcp",
+            9000,
+            &[("n", "1")],
+        )?;
+        if added >= LIVE {
+            fake.goodbye(&format!("soak-{}", added - LIVE));
+        }
+        added += 1;
+        thread::sleep(CYCLE);
+
+        if Instant::now() < next_check {
+            continue;
+        }
+        next_check += CHECK_EVERY;
+
+        let services = mdns.get_services().len();
+        let recent = mdns.get_services_including_recent().len();
+        let stats = mdns.stats();
+        let rss = rss_kib();
+        first_rss = first_rss.or(rss);
+
+        println!(
+            "{:>6}s  cycled {:>7}  services {:>3}  with recent {:>3}  events {:>8}  dropped {}  rss {} KiB ({:+})",
+            started.elapsed().as_secs(),
+            added,
+            services,
+            recent,
+            events.load(Ordering::Relaxed),
+            stats.events_dropped,
+            rss.map_or("?".to_string(), |rss| rss.to_string()),
+            rss.zip(first_rss).map_or(0, |(rss, first)| rss as i64 - first as i64),
+        );
+
+        assert!(
+            recent <= bound,
+            "{} services kept, at most {} expected",
+            recent,
+            bound
+        );
+        assert!(services <= recent);
+        assert_eq!(stats.events_dropped, 0, "the event queue fell behind");
+        assert!(mdns.recent_activity().len() <= ACTIVITY);
+        assert!(mdns.recent_errors().len() <= 16);
+    }
+
+    println!("no growth in {} cycles", added);
+    Ok(())
+}
+
+// Resident set size, where /proc tells.
+fn rss_kib() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    Some(pages * 4)
+}
@@ -0,0 +1,48 @@
+// Arbitrary datagrams against a database populated from the seeds, which also make a
+// starting corpus:
+//
+//     cargo fuzz run process_packet fuzz/seeds
+//
+// Built with `--cfg fuzzing`, every unlock of the database checks its invariants.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use simple_mdns_client::{AddressFamily, MdnsState};
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+const SEEDS: &[&[u8]] = &[
+    include_bytes!("../seeds/srv_a.bin"),
+    include_bytes!("../seeds/ptr_additionals.bin"),
+    include_bytes!("../seeds/moved_literal.bin"),
+];
+
+// A record naming a service takes more than this many bytes, even with its names
+// compressed.
+const MIN_SERVICE_RECORD: usize = 16;
+
+fuzz_target!(|data: &[u8]| {
+    let mut state = MdnsState::new("_http._tcp.local", AddressFamily::Both).unwrap();
+    let start = Instant::now();
+    let known: SocketAddr = "192.0.2.1:5353".parse().unwrap();
+    let other: SocketAddr = "[fe80::2]:5353".parse().unwrap();
+
+    for seed in SEEDS {
+        state.handle_packet(start, seed, known).unwrap();
+    }
+    let seeded = state.services().len();
+
+    // From a responder already heard from, and later from one that wasn't, past the
+    // cache-flush window.
+    state.handle_packet(start, data, known).ok();
+    state
+        .handle_packet(start + Duration::from_secs(2), data, other)
+        .ok();
+    assert!(state.services().len() <= seeded + 2 * data.len() / MIN_SERVICE_RECORD);
+
+    state.handle_timeout(start + Duration::from_secs(4));
+    state.handle_timeout(start + Duration::from_secs(8));
+    assert!(state.services().is_empty());
+});
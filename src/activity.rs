@@ -0,0 +1,124 @@
+use crate::{lock::Lock, FailedOperation, Service};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::SystemTime,
+};
+
+// How many entries `MdnsClient::recent_activity` keeps, unless the builder says otherwise.
+pub(crate) const ACTIVITY_LOG: usize = 256;
+
+/// Something the client did or found, from `MdnsClient::recent_activity()`.
+#[derive(Clone, Debug)]
+pub struct ActivityEntry {
+    pub time: SystemTime,
+    pub activity: Activity,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Activity {
+    /// A query went out on the socket of this interface, named as in
+    /// `SocketStats::interface`.
+    QuerySent {
+        interface: String,
+    },
+    ServiceAdded {
+        service: Service,
+    },
+    /// Its addresses changed, or whether it is `conflicting`.
+    ServiceUpdated {
+        service: Service,
+    },
+    /// Heard from again, with nothing else changed.
+    ServiceRefreshed {
+        service: Service,
+    },
+    ServiceExpired {
+        service: Service,
+        reason: ExpiryReason,
+    },
+    /// Its instance was renamed, see `ServiceEvent::Renamed`.
+    ServiceRenamed {
+        service: Service,
+        old: String,
+        new: String,
+    },
+    /// As in `MdnsClient::recent_errors`.
+    Error {
+        operation: FailedOperation,
+        interface: Option<String>,
+        message: String,
+    },
+    /// The socket backend's interfaces changed to these.
+    InterfacesChanged {
+        interfaces: Vec<String>,
+    },
+}
+
+/// Why a service went, see `Activity::ServiceExpired`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExpiryReason {
+    /// Not heard from for five seconds, and `expiry_grace` if set.
+    NotHeard,
+    /// Its instance was announced at another host or port.
+    Moved,
+    /// A cache-flush record placed its instance elsewhere.
+    Flushed,
+    /// Reported with `MdnsClient::report_failure`, and silent when asked after.
+    Unconfirmed,
+    /// Only reachable through an interface that went away.
+    InterfaceGone,
+    /// `MdnsClient::reset`, or the database being cleared after a panic.
+    Reset,
+}
+
+// The last entries, oldest first. A write is a lock and a push, cheap enough to leave on;
+// with no room at all, nothing is built.
+pub(crate) struct ActivityLog {
+    entries: Mutex<VecDeque<ActivityEntry>>,
+    capacity: AtomicUsize,
+}
+
+impl Default for ActivityLog {
+    fn default() -> Self {
+        ActivityLog {
+            entries: Mutex::default(),
+            capacity: AtomicUsize::new(ACTIVITY_LOG),
+        }
+    }
+}
+
+impl ActivityLog {
+    pub(crate) fn set_capacity(&self, capacity: usize) {
+        self.capacity.store(capacity, Ordering::Relaxed);
+
+        let mut entries = self.entries.locked();
+        while entries.len() > capacity {
+            entries.pop_front();
+        }
+    }
+
+    pub(crate) fn record(&self, activity: impl FnOnce() -> Activity) {
+        let capacity = self.capacity.load(Ordering::Relaxed);
+        if capacity == 0 {
+            return;
+        }
+        let activity = activity();
+
+        let mut entries = self.entries.locked();
+        while entries.len() >= capacity {
+            entries.pop_front();
+        }
+        entries.push_back(ActivityEntry {
+            time: SystemTime::now(),
+            activity,
+        });
+    }
+
+    pub(crate) fn entries(&self) -> Vec<ActivityEntry> {
+        self.entries.locked().iter().cloned().collect()
+    }
+}
@@ -0,0 +1,60 @@
+use std::{
+    fmt,
+    net::{Ipv6Addr, SocketAddrV6},
+};
+
+/// An IPv6 address of a service, see `ServiceRecord::ipv6_addresses`. A link-local one,
+/// in fe80::/10, carries the index of the interface it was heard on, which connecting to
+/// it takes; others carry 0. Ordered by address, then scope.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ScopedIpv6Addr {
+    pub ip: Ipv6Addr,
+    pub scope_id: u32,
+}
+
+impl ScopedIpv6Addr {
+    // `ip` as heard on the interface with index `interface`, 0 if unknown.
+    pub(crate) fn heard(ip: Ipv6Addr, interface: u32) -> Self {
+        let mut address = ScopedIpv6Addr { ip, scope_id: 0 };
+        if address.is_link_local() {
+            address.scope_id = interface;
+        }
+        address
+    }
+
+    pub fn is_link_local(&self) -> bool {
+        self.ip.segments()[0] & 0xffc0 == 0xfe80
+    }
+
+    /// The address to connect to `port` at.
+    pub fn socket_addr(&self, port: u16) -> SocketAddrV6 {
+        SocketAddrV6::new(self.ip, port, 0, self.scope_id)
+    }
+}
+
+/// `fe80::1%3` where there is a scope, as `SocketAddrV6` writes it.
+impl fmt::Display for ScopedIpv6Addr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.scope_id {
+            0 => write!(f, "{}", self.ip),
+            scope_id => write!(f, "{}%{}", self.ip, scope_id),
+        }
+    }
+}
+
+/// A neighbor's hardware address, see `ServiceRecord::mac_address`.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MacAddr(pub [u8; 6]);
+
+/// `02:42:ac:11:00:02`.
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            a, b, c, d, e, g
+        )
+    }
+}
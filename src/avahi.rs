@@ -0,0 +1,343 @@
+use crate::{
+    availability::History,
+    catch_panic,
+    changes::Changes,
+    database::{normalize_host, Database},
+    metrics::{FailedOperation, Metrics},
+    remove_old_entries, split_service_type, AddressFamily, Backend, Command, MdnsClient,
+    Provenance, Service, ServiceRecord,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    net::Ipv4Addr,
+    sync::{
+        mpsc::{channel, sync_channel, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use zbus::{
+    blocking::{Connection, MessageIterator},
+    message::Type as MessageType,
+    zvariant::OwnedObjectPath,
+    MatchRule,
+};
+
+const AVAHI: &str = "org.freedesktop.Avahi";
+const SERVER: &str = "org.freedesktop.Avahi.Server";
+const SERVICE_BROWSER: &str = "org.freedesktop.Avahi.ServiceBrowser";
+
+const IF_UNSPEC: i32 = -1;
+const PROTO_UNSPEC: i32 = -1;
+const PROTO_INET: i32 = 0;
+
+// Signals are picked up this often; the database is refreshed and swept once a second
+// like the socket backend.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+struct Item {
+    interface: i32,
+    protocol: i32,
+    name: String,
+    kind: String,
+    domain: String,
+}
+
+impl Item {
+    fn full_name(&self) -> String {
+        format!("{}.{}.{}", self.name, self.kind, self.domain)
+    }
+}
+
+enum Signal {
+    New(Item),
+    Remove(Item),
+}
+
+pub(crate) fn is_available() -> bool {
+    Connection::system()
+        .and_then(|conn| conn.call_method(Some(AVAHI), "/", Some(SERVER), "GetVersionString", &()))
+        .is_ok()
+}
+
+pub(crate) fn spawn(service: &str) -> Result<MdnsClient, Box<dyn Error>> {
+    let (kind, domain) = split_service_type(service)
+        .ok_or_else(|| format!("no service type such as `_http._tcp` in {:?}", service))?;
+
+    let conn = Connection::system()?;
+
+    // Subscribe before creating the browser so no ItemNew can slip past us.
+    let rule = MatchRule::builder()
+        .msg_type(MessageType::Signal)
+        .sender(AVAHI)?
+        .interface(SERVICE_BROWSER)?
+        .build();
+    let messages = MessageIterator::for_match_rule(rule, &conn, None)?;
+
+    let browser: OwnedObjectPath = conn
+        .call_method(
+            Some(AVAHI),
+            "/",
+            Some(SERVER),
+            "ServiceBrowserNew",
+            &(
+                IF_UNSPEC,
+                PROTO_UNSPEC,
+                kind.as_str(),
+                domain.as_str(),
+                0u32,
+            ),
+        )?
+        .body()
+        .deserialize()?;
+
+    let database = Arc::new(Mutex::new(Database::default()));
+    let metrics = Arc::new(Metrics::new(Vec::new()));
+    let changes = Arc::new(Changes::new(metrics.clone()));
+
+    let (signal_tx, signal_rx) = channel();
+    let reader = thread::spawn({
+        let browser = browser.clone();
+        move || read_signals(messages, &browser, signal_tx)
+    });
+
+    let (command_tx, command_rx) = sync_channel(1);
+
+    let thread = thread::spawn({
+        let service = service.to_string();
+        let database = database.clone();
+        let metrics = metrics.clone();
+        let changes = changes.clone();
+
+        move || {
+            catch_panic(
+                || {
+                    run(
+                        &conn,
+                        &service,
+                        &database,
+                        &metrics,
+                        &changes,
+                        &command_rx,
+                        &signal_rx,
+                    )
+                },
+                |message| metrics.error(FailedOperation::Panic, None, &message),
+            );
+
+            conn.call_method(Some(AVAHI), &browser, Some(SERVICE_BROWSER), "Free", &())
+                .ok();
+
+            // Closing the connection ends the reader's message iterator.
+            conn.close().ok();
+            reader.join().ok();
+        }
+    });
+
+    Ok(MdnsClient {
+        service: service.to_string(),
+        database,
+        metrics,
+        changes,
+        scan: Arc::default(),
+        command_tx,
+        thread: Some(thread),
+        registration: None,
+        family: AddressFamily::V4,
+        backend: Backend::Avahi,
+        matcher: None,
+        srv_only: false,
+        keep_records: false,
+        route_aware: false,
+        connect_stagger: None,
+        lint: None,
+        observer: None,
+        inventory: None,
+        share: None,
+        seed: None,
+        #[cfg(feature = "neighbors")]
+        neighbors: None,
+        held: None,
+    })
+}
+
+fn read_signals(messages: MessageIterator, browser: &OwnedObjectPath, signal_tx: Sender<Signal>) {
+    for message in messages {
+        let message = match message {
+            Ok(message) => message,
+            Err(_) => break,
+        };
+
+        let header = message.header();
+        if header.path().map(|p| p.as_str()) != Some(browser.as_str()) {
+            continue;
+        }
+
+        let member = header.member().map(|m| m.to_string());
+        let item = match message
+            .body()
+            .deserialize::<(i32, i32, String, String, String, u32)>()
+        {
+            Ok((interface, protocol, name, kind, domain, _flags)) => Item {
+                interface,
+                protocol,
+                name,
+                kind,
+                domain,
+            },
+            Err(_) => continue,
+        };
+
+        let signal = match member.as_deref() {
+            Some("ItemNew") => Signal::New(item),
+            Some("ItemRemove") => Signal::Remove(item),
+            _ => continue,
+        };
+
+        if signal_tx.send(signal).is_err() {
+            break;
+        }
+    }
+}
+
+fn run(
+    conn: &Connection,
+    service: &str,
+    database: &Mutex<Database>,
+    metrics: &Metrics,
+    changes: &Changes,
+    command_rx: &Receiver<Command>,
+    signal_rx: &Receiver<Signal>,
+) {
+    // Items Avahi currently reports, with their resolution once it has succeeded.
+    let mut items: HashMap<Item, Option<(Service, Ipv4Addr)>> = HashMap::new();
+    let mut next_refresh = Instant::now();
+
+    loop {
+        match command_rx.recv_timeout(POLL_INTERVAL) {
+            Ok(Command::Exit) | Err(RecvTimeoutError::Disconnected) => break,
+            Ok(Command::Query | Command::Recover | Command::Reset) => {
+                items.values_mut().for_each(|resolved| *resolved = None);
+                next_refresh = Instant::now();
+            }
+            Ok(Command::Wake) | Err(RecvTimeoutError::Timeout) => (),
+        }
+
+        while let Ok(signal) = signal_rx.try_recv() {
+            match signal {
+                // The same name filter as the socket backend applies to SRV owner names.
+                Signal::New(item) => {
+                    if item.full_name().contains(service) {
+                        debug!(name = %item.name, interface = item.interface, "avahi item new");
+                        items.entry(item).or_insert(None);
+                        next_refresh = Instant::now();
+                    }
+                }
+                Signal::Remove(item) => {
+                    debug!(name = %item.name, interface = item.interface, "avahi item removed");
+                    items.remove(&item);
+                }
+            }
+        }
+
+        if Instant::now() < next_refresh {
+            continue;
+        }
+
+        let _span = span!("avahi cycle", service = %service);
+
+        for (item, resolved) in items.iter_mut().filter(|(_, r)| r.is_none()) {
+            match resolve(conn, item) {
+                Ok(result) => {
+                    metrics.answer_applied();
+                    *resolved = Some(result);
+                }
+                Err(err) => {
+                    warn!(name = %item.name, error = %err, "avahi resolve failed");
+                    metrics.error(FailedOperation::Backend, None, &err);
+                }
+            }
+        }
+
+        // Entries stay fresh for as long as Avahi reports them, so ItemRemove leads to the
+        // same expiry the socket backend applies to services that stop answering.
+        {
+            let mut database = Database::lock(database);
+
+            for (item, (service, address)) in items
+                .iter()
+                .filter_map(|(item, resolved)| Some((item, resolved.as_ref()?)))
+            {
+                let record = database.get_or_insert_with(service.clone(), || ServiceRecord {
+                    last_seen_time: Instant::now(),
+                    preferred_address: None,
+                    addresses: HashSet::new(),
+                    ipv6_addresses: HashSet::new(),
+                    conflicting: false,
+                    latency: None,
+                    provenance: Provenance::default(),
+                    pinned: false,
+                    withdrawn: false,
+                    seeded: false,
+                    mac_address: None,
+                    records: Arc::default(),
+                    history: History::default(),
+                });
+
+                record.last_seen_time = Instant::now();
+                record.preferred_address = Some(*address);
+                record.addresses.insert(*address);
+
+                database.instance_at(&item.full_name(), service, None, Instant::now());
+            }
+        }
+
+        remove_old_entries(database, metrics, changes, Instant::now());
+        next_refresh = Instant::now() + REFRESH_INTERVAL;
+    }
+}
+
+fn resolve(conn: &Connection, item: &Item) -> Result<(Service, Ipv4Addr), Box<dyn Error>> {
+    let reply = conn.call_method(
+        Some(AVAHI),
+        "/",
+        Some(SERVER),
+        "ResolveService",
+        &(
+            item.interface,
+            item.protocol,
+            item.name.as_str(),
+            item.kind.as_str(),
+            item.domain.as_str(),
+            PROTO_INET,
+            0u32,
+        ),
+    )?;
+
+    #[allow(clippy::type_complexity)]
+    let (_, _, _, _, _, host, _, address, port, _, _): (
+        i32,
+        i32,
+        String,
+        String,
+        String,
+        String,
+        i32,
+        String,
+        u16,
+        Vec<Vec<u8>>,
+        u32,
+    ) = reply.body().deserialize()?;
+
+    Ok((
+        Service {
+            host: normalize_host(&host).into(),
+            port,
+        },
+        address.parse()?,
+    ))
+}
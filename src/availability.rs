@@ -0,0 +1,122 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+// How far back `ServiceRecord::availability` looks by default.
+pub(crate) const AVAILABILITY_WINDOW: Duration = Duration::from_secs(24 * 3600);
+
+// Gaps kept per service; a service that flaps more often than this within the window
+// has its oldest forgotten.
+const MAX_GAPS: usize = 256;
+
+/// How reliably a service was listed over `MdnsClientBuilder::availability_window`, see
+/// `ServiceRecord::availability`. A service expiring and coming back within
+/// `MdnsClientBuilder::expiry_grace` still went once; `MdnsClient::reset` starts it over.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Availability {
+    /// The share of `observed` it was listed for, from 0 to 1.
+    pub uptime: f64,
+    /// How many times it went, by expiring, a goodbye or otherwise.
+    pub disappearances: u32,
+    /// The longest it was gone for within the window; zero if it never was.
+    pub longest_gap: Duration,
+    /// Since it was first heard, or the window if that is shorter.
+    pub observed: Duration,
+}
+
+// When a service was gone, for `Availability`.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct History {
+    // Unset until the database takes the service in.
+    first_seen: Option<Instant>,
+    window: Duration,
+    // Those over, oldest first, as from and until.
+    gaps: VecDeque<(Instant, Instant)>,
+    // While gone, since when.
+    gone_since: Option<Instant>,
+}
+
+impl History {
+    pub(crate) fn new(now: Instant, window: Duration) -> History {
+        History {
+            first_seen: Some(now),
+            window,
+            ..History::default()
+        }
+    }
+
+    // Figures look back `window` from now on.
+    pub(crate) fn set_window(&mut self, window: Duration) {
+        self.window = window;
+    }
+
+    pub(crate) fn is_started(&self) -> bool {
+        self.first_seen.is_some()
+    }
+
+    // Once, however many ways it is removed: from the services, then the tombstones.
+    pub(crate) fn gone(&mut self, now: Instant) {
+        self.gone_since.get_or_insert(now);
+    }
+
+    pub(crate) fn back(&mut self, now: Instant) {
+        if let Some(since) = self.gone_since.take() {
+            if self.gaps.len() == MAX_GAPS {
+                self.gaps.pop_front();
+            }
+            self.gaps.push_back((since, now.max(since)));
+        }
+
+        let start = now.checked_sub(self.window);
+        while self
+            .gaps
+            .front()
+            .is_some_and(|(_, until)| start.is_some_and(|start| *until < start))
+        {
+            self.gaps.pop_front();
+        }
+    }
+
+    // Whether it went longer ago than the window, so nothing of it would be in figures.
+    pub(crate) fn is_past(&self, now: Instant) -> bool {
+        self.gone_since
+            .is_some_and(|since| now.saturating_duration_since(since) > self.window)
+    }
+
+    // As of `now`, gone since `expired` too if it is, as a tombstone's last published copy
+    // may not say.
+    pub(crate) fn availability(&self, now: Instant, expired: Option<Instant>) -> Availability {
+        let first_seen = self.first_seen.unwrap_or(now);
+        let start = match now.checked_sub(self.window) {
+            Some(start) => start.max(first_seen),
+            None => first_seen,
+        };
+        let observed = now.saturating_duration_since(start);
+
+        let mut gone = Duration::ZERO;
+        let mut disappearances = 0;
+        let mut longest_gap = Duration::ZERO;
+        let open = self.gone_since.or(expired).map(|since| (since, now));
+        for (since, until) in self.gaps.iter().copied().chain(open) {
+            let gap = until.min(now).saturating_duration_since(since.max(start));
+            if until > start {
+                gone += gap;
+                disappearances += 1;
+                longest_gap = longest_gap.max(gap);
+            }
+        }
+
+        let uptime = match observed.is_zero() {
+            true => 1.0,
+            false => 1.0 - (gone.as_secs_f64() / observed.as_secs_f64()).min(1.0),
+        };
+        Availability {
+            uptime,
+            disappearances,
+            longest_gap,
+            observed,
+        }
+    }
+}
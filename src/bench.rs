@@ -0,0 +1,57 @@
+// What `benches/` needs of the crate's internals, which it can't reach otherwise. Not a
+// stable API, hence only behind the `bench` feature.
+pub use crate::packet::Packet;
+use crate::{
+    changes::Changes, database::Database, matcher::Filter, metrics::Metrics, AddressFamily,
+    Service, ServiceRecord,
+};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+// The state a client keeps per question, without sockets or a worker.
+pub struct Handler {
+    service: String,
+    database: Mutex<Database>,
+    metrics: Arc<Metrics>,
+    changes: Changes,
+}
+
+impl Handler {
+    pub fn new(service: &str) -> Self {
+        let metrics = Arc::new(Metrics::new(Vec::new()));
+
+        Handler {
+            service: service.to_string(),
+            database: Mutex::default(),
+            changes: Changes::new(metrics.clone()),
+            metrics,
+        }
+    }
+
+    pub fn handle_response(&self, packet: &Packet, from: SocketAddr) {
+        crate::handle_response(
+            packet,
+            from,
+            Filter::new(&self.service, None, false, false),
+            AddressFamily::V4,
+            &self.database,
+            &self.metrics,
+            &self.changes,
+            Instant::now(),
+            None,
+        );
+    }
+
+    // As `MdnsClient::get_services`.
+    pub fn get_services(&self) -> Vec<(Service, ServiceRecord)> {
+        self.changes
+            .published()
+            .load(Instant::now())
+            .iter()
+            .map(|(service, record)| (service.clone(), record.clone()))
+            .collect()
+    }
+}
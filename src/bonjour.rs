@@ -0,0 +1,592 @@
+use crate::{
+    availability::History,
+    catch_panic,
+    changes::Changes,
+    database::{normalize_host, Database},
+    metrics::{FailedOperation, Metrics},
+    remove_old_entries, split_service_type, AddressFamily, Backend, Command, MdnsClient,
+    Provenance, Service, ServiceRecord,
+};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    error::Error,
+    ffi::{c_void, CStr, CString},
+    net::Ipv4Addr,
+    os::raw::{c_char, c_int},
+    ptr,
+    sync::{
+        mpsc::{sync_channel, Receiver, TryRecvError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+type DNSServiceRef = *mut c_void;
+type DNSServiceFlags = u32;
+type DNSServiceErrorType = i32;
+
+const FLAGS_ADD: DNSServiceFlags = 0x2;
+const FLAGS_SHARE_CONNECTION: DNSServiceFlags = 0x4000;
+const PROTOCOL_IPV4: u32 = 0x01;
+const NO_ERROR: DNSServiceErrorType = 0;
+
+type BrowseReply = extern "C" fn(
+    DNSServiceRef,
+    DNSServiceFlags,
+    u32,
+    DNSServiceErrorType,
+    *const c_char,
+    *const c_char,
+    *const c_char,
+    *mut c_void,
+);
+
+type ResolveReply = extern "C" fn(
+    DNSServiceRef,
+    DNSServiceFlags,
+    u32,
+    DNSServiceErrorType,
+    *const c_char,
+    *const c_char,
+    u16,
+    u16,
+    *const u8,
+    *mut c_void,
+);
+
+type AddrInfoReply = extern "C" fn(
+    DNSServiceRef,
+    DNSServiceFlags,
+    u32,
+    DNSServiceErrorType,
+    *const c_char,
+    *const libc::sockaddr,
+    u32,
+    *mut c_void,
+);
+
+// dns_sd.h, provided by libSystem.
+extern "C" {
+    fn DNSServiceCreateConnection(sd_ref: *mut DNSServiceRef) -> DNSServiceErrorType;
+    fn DNSServiceRefSockFD(sd_ref: DNSServiceRef) -> c_int;
+    fn DNSServiceProcessResult(sd_ref: DNSServiceRef) -> DNSServiceErrorType;
+    fn DNSServiceRefDeallocate(sd_ref: DNSServiceRef);
+
+    fn DNSServiceBrowse(
+        sd_ref: *mut DNSServiceRef,
+        flags: DNSServiceFlags,
+        interface_index: u32,
+        regtype: *const c_char,
+        domain: *const c_char,
+        callback: BrowseReply,
+        context: *mut c_void,
+    ) -> DNSServiceErrorType;
+
+    fn DNSServiceResolve(
+        sd_ref: *mut DNSServiceRef,
+        flags: DNSServiceFlags,
+        interface_index: u32,
+        name: *const c_char,
+        regtype: *const c_char,
+        domain: *const c_char,
+        callback: ResolveReply,
+        context: *mut c_void,
+    ) -> DNSServiceErrorType;
+
+    fn DNSServiceGetAddrInfo(
+        sd_ref: *mut DNSServiceRef,
+        flags: DNSServiceFlags,
+        interface_index: u32,
+        protocol: u32,
+        hostname: *const c_char,
+        callback: AddrInfoReply,
+        context: *mut c_void,
+    ) -> DNSServiceErrorType;
+}
+
+// Replies are only delivered from DNSServiceProcessResult on the worker thread, so polling
+// the connection doubles as the wait for commands; dropping the client waits this long at
+// most.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+struct Item {
+    interface: u32,
+    name: String,
+    kind: String,
+    domain: String,
+}
+
+enum Event {
+    Found(Item),
+    Lost(Item),
+    Resolved(Item, Service),
+    Address(Item, Ipv4Addr),
+}
+
+// Callbacks run synchronously inside DNSServiceProcessResult and only append to the
+// worker's event list, which the worker reads once the call has returned.
+struct Context {
+    events: *const RefCell<Vec<Event>>,
+    item: Option<Item>,
+}
+
+impl Context {
+    unsafe fn push(context: *mut c_void, event: impl FnOnce(Option<&Item>) -> Option<Event>) {
+        let context = &*(context as *const Context);
+
+        if let Some(event) = event(context.item.as_ref()) {
+            (*context.events).borrow_mut().push(event);
+        }
+    }
+}
+
+// An operation sharing the main connection. Dropping it deallocates the operation before
+// its context.
+struct Operation {
+    sd_ref: DNSServiceRef,
+    _context: Box<Context>,
+}
+
+impl Drop for Operation {
+    fn drop(&mut self) {
+        unsafe { DNSServiceRefDeallocate(self.sd_ref) };
+    }
+}
+
+struct Connection(DNSServiceRef);
+
+impl Connection {
+    fn new() -> Result<Self, Box<dyn Error>> {
+        let mut sd_ref = ptr::null_mut();
+
+        match unsafe { DNSServiceCreateConnection(&mut sd_ref) } {
+            NO_ERROR => Ok(Connection(sd_ref)),
+            err => Err(format!("DNSServiceCreateConnection failed with {}", err).into()),
+        }
+    }
+
+    fn start(
+        &self,
+        context: Box<Context>,
+        start: impl FnOnce(*mut DNSServiceRef, *mut c_void) -> DNSServiceErrorType,
+    ) -> Option<Operation> {
+        let mut sd_ref = self.0;
+        let context_ptr = &*context as *const Context as *mut c_void;
+
+        match start(&mut sd_ref, context_ptr) {
+            NO_ERROR => Some(Operation {
+                sd_ref,
+                _context: context,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        unsafe { DNSServiceRefDeallocate(self.0) };
+    }
+}
+
+// The connection is only ever used by the worker thread once it has moved there.
+unsafe impl Send for Connection {}
+
+fn string(s: *const c_char) -> Option<String> {
+    if s.is_null() {
+        return None;
+    }
+
+    unsafe { CStr::from_ptr(s) }
+        .to_str()
+        .ok()
+        .map(str::to_string)
+}
+
+extern "C" fn browse_reply(
+    _sd_ref: DNSServiceRef,
+    flags: DNSServiceFlags,
+    interface: u32,
+    error: DNSServiceErrorType,
+    name: *const c_char,
+    kind: *const c_char,
+    domain: *const c_char,
+    context: *mut c_void,
+) {
+    if error != NO_ERROR {
+        return;
+    }
+
+    unsafe {
+        Context::push(context, |_| {
+            let item = Item {
+                interface,
+                name: string(name)?,
+                kind: string(kind)?,
+                domain: string(domain)?,
+            };
+
+            Some(if flags & FLAGS_ADD != 0 {
+                Event::Found(item)
+            } else {
+                Event::Lost(item)
+            })
+        })
+    }
+}
+
+extern "C" fn resolve_reply(
+    _sd_ref: DNSServiceRef,
+    _flags: DNSServiceFlags,
+    _interface: u32,
+    error: DNSServiceErrorType,
+    _full_name: *const c_char,
+    host: *const c_char,
+    port: u16,
+    _txt_len: u16,
+    _txt: *const u8,
+    context: *mut c_void,
+) {
+    if error != NO_ERROR {
+        return;
+    }
+
+    unsafe {
+        Context::push(context, |item| {
+            let service = Service {
+                host: normalize_host(&string(host)?).into(),
+                port: u16::from_be(port),
+            };
+
+            Some(Event::Resolved(item?.clone(), service))
+        })
+    }
+}
+
+extern "C" fn addr_info_reply(
+    _sd_ref: DNSServiceRef,
+    flags: DNSServiceFlags,
+    _interface: u32,
+    error: DNSServiceErrorType,
+    _host: *const c_char,
+    address: *const libc::sockaddr,
+    _ttl: u32,
+    context: *mut c_void,
+) {
+    if error != NO_ERROR || flags & FLAGS_ADD == 0 || address.is_null() {
+        return;
+    }
+
+    unsafe {
+        if (*address).sa_family as c_int != libc::AF_INET {
+            return;
+        }
+
+        let address = &*(address as *const libc::sockaddr_in);
+        let address = Ipv4Addr::from(u32::from_be(address.sin_addr.s_addr));
+
+        Context::push(context, |item| Some(Event::Address(item?.clone(), address)))
+    }
+}
+
+pub(crate) fn is_available() -> bool {
+    Connection::new().is_ok()
+}
+
+#[derive(Default)]
+struct Instance {
+    // The full name, "name._http._tcp.local.", which the database knows it by.
+    name: String,
+    resolve: Option<Operation>,
+    addr_info: Option<Operation>,
+    service: Option<Service>,
+    addresses: HashSet<Ipv4Addr>,
+}
+
+pub(crate) fn spawn(service: &str) -> Result<MdnsClient, Box<dyn Error>> {
+    let (kind, domain) = split_service_type(service)
+        .ok_or_else(|| format!("no service type such as `_http._tcp` in {:?}", service))?;
+
+    let connection = Connection::new()?;
+
+    let database = Arc::new(Mutex::new(Database::default()));
+    let metrics = Arc::new(Metrics::new(Vec::new()));
+    let changes = Arc::new(Changes::new(metrics.clone()));
+
+    let (command_tx, command_rx) = sync_channel(1);
+
+    let thread = thread::spawn({
+        let service = service.to_string();
+        let database = database.clone();
+        let metrics = metrics.clone();
+        let changes = changes.clone();
+
+        move || {
+            catch_panic(
+                || {
+                    run(
+                        &connection,
+                        &service,
+                        &kind,
+                        &domain,
+                        &database,
+                        &metrics,
+                        &changes,
+                        &command_rx,
+                    )
+                },
+                |message| metrics.error(FailedOperation::Panic, None, &message),
+            );
+
+            // All operations were deallocated by `run` before the connection goes.
+            drop(connection);
+        }
+    });
+
+    Ok(MdnsClient {
+        service: service.to_string(),
+        database,
+        metrics,
+        changes,
+        scan: Arc::default(),
+        command_tx,
+        thread: Some(thread),
+        registration: None,
+        family: AddressFamily::V4,
+        backend: Backend::Bonjour,
+        matcher: None,
+        srv_only: false,
+        keep_records: false,
+        route_aware: false,
+        connect_stagger: None,
+        lint: None,
+        observer: None,
+        inventory: None,
+        share: None,
+        seed: None,
+        #[cfg(feature = "neighbors")]
+        neighbors: None,
+        held: None,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run(
+    connection: &Connection,
+    service: &str,
+    kind: &str,
+    domain: &str,
+    database: &Mutex<Database>,
+    metrics: &Metrics,
+    changes: &Changes,
+    command_rx: &Receiver<Command>,
+) {
+    let events = RefCell::new(Vec::new());
+    let events_ptr: *const RefCell<Vec<Event>> = &events;
+
+    let context = |item: Option<Item>| {
+        Box::new(Context {
+            events: events_ptr,
+            item,
+        })
+    };
+
+    let (kind, domain) = match (CString::new(kind), CString::new(domain)) {
+        (Ok(kind), Ok(domain)) => (kind, domain),
+        _ => return,
+    };
+
+    let browse = connection.start(context(None), |sd_ref, context| unsafe {
+        DNSServiceBrowse(
+            sd_ref,
+            FLAGS_SHARE_CONNECTION,
+            0,
+            kind.as_ptr(),
+            domain.as_ptr(),
+            browse_reply,
+            context,
+        )
+    });
+
+    if browse.is_none() {
+        warn!("DNSServiceBrowse failed");
+        metrics.error(FailedOperation::Backend, None, &"DNSServiceBrowse failed");
+        return;
+    }
+
+    let mut instances: HashMap<Item, Instance> = HashMap::new();
+    let mut next_refresh = Instant::now();
+
+    let mut poll_fd = libc::pollfd {
+        fd: unsafe { DNSServiceRefSockFD(connection.0) },
+        events: libc::POLLIN,
+        revents: 0,
+    };
+
+    loop {
+        let ready = unsafe { libc::poll(&mut poll_fd, 1, POLL_INTERVAL.as_millis() as c_int) };
+
+        if ready > 0 && unsafe { DNSServiceProcessResult(connection.0) } != NO_ERROR {
+            warn!("lost connection to mDNSResponder");
+            metrics.error(
+                FailedOperation::Backend,
+                None,
+                &"lost connection to mDNSResponder",
+            );
+            break;
+        }
+
+        match command_rx.try_recv() {
+            Ok(Command::Exit) | Err(TryRecvError::Disconnected) => break,
+            Ok(Command::Query | Command::Recover | Command::Reset) => next_refresh = Instant::now(),
+            Ok(Command::Wake) | Err(TryRecvError::Empty) => (),
+        }
+
+        for event in events.take() {
+            match event {
+                // The same name filter as the socket backend applies to SRV owner names.
+                Event::Found(item) => {
+                    let full_name = format!("{}.{}{}", item.name, item.kind, item.domain);
+
+                    if !full_name.trim_end_matches('.').contains(service)
+                        || instances.contains_key(&item)
+                    {
+                        continue;
+                    }
+
+                    debug!(name = %item.name, interface = item.interface, "bonjour instance found");
+
+                    let resolve = match (
+                        CString::new(item.name.as_str()),
+                        CString::new(item.kind.as_str()),
+                        CString::new(item.domain.as_str()),
+                    ) {
+                        (Ok(name), Ok(kind), Ok(domain)) => connection.start(
+                            context(Some(item.clone())),
+                            |sd_ref, context| unsafe {
+                                DNSServiceResolve(
+                                    sd_ref,
+                                    FLAGS_SHARE_CONNECTION,
+                                    item.interface,
+                                    name.as_ptr(),
+                                    kind.as_ptr(),
+                                    domain.as_ptr(),
+                                    resolve_reply,
+                                    context,
+                                )
+                            },
+                        ),
+                        _ => None,
+                    };
+
+                    instances.insert(
+                        item,
+                        Instance {
+                            name: full_name,
+                            resolve,
+                            ..Instance::default()
+                        },
+                    );
+                    next_refresh = Instant::now();
+                }
+                Event::Lost(item) => {
+                    debug!(name = %item.name, interface = item.interface, "bonjour instance lost");
+                    instances.remove(&item);
+                }
+                Event::Resolved(item, service) => {
+                    let instance = match instances.get_mut(&item) {
+                        Some(instance) => instance,
+                        None => continue,
+                    };
+
+                    metrics.answer_applied();
+                    instance.resolve = None;
+
+                    if instance.service.as_ref() != Some(&service) {
+                        instance.addresses.clear();
+                        instance.addr_info = CString::new(&*service.host).ok().and_then(|host| {
+                            connection.start(
+                                context(Some(item.clone())),
+                                |sd_ref, context| unsafe {
+                                    DNSServiceGetAddrInfo(
+                                        sd_ref,
+                                        FLAGS_SHARE_CONNECTION,
+                                        item.interface,
+                                        PROTOCOL_IPV4,
+                                        host.as_ptr(),
+                                        addr_info_reply,
+                                        context,
+                                    )
+                                },
+                            )
+                        });
+                        instance.service = Some(service);
+                    }
+
+                    next_refresh = Instant::now();
+                }
+                Event::Address(item, address) => {
+                    if let Some(instance) = instances.get_mut(&item) {
+                        metrics.answer_applied();
+                        instance.addresses.insert(address);
+                        next_refresh = Instant::now();
+                    }
+                }
+            }
+        }
+
+        if Instant::now() < next_refresh {
+            continue;
+        }
+
+        let _span = span!("bonjour cycle", service = %service);
+
+        // Entries stay fresh for as long as Bonjour reports the instance, so a removal
+        // leads to the same expiry the socket backend applies to silent services.
+        {
+            let mut database = Database::lock(database);
+
+            for instance in instances.values() {
+                let service = match &instance.service {
+                    Some(service) => service.clone(),
+                    None => continue,
+                };
+
+                let record = database.get_or_insert_with(service.clone(), || ServiceRecord {
+                    last_seen_time: Instant::now(),
+                    preferred_address: None,
+                    addresses: HashSet::new(),
+                    ipv6_addresses: HashSet::new(),
+                    conflicting: false,
+                    latency: None,
+                    provenance: Provenance::default(),
+                    pinned: false,
+                    withdrawn: false,
+                    seeded: false,
+                    mac_address: None,
+                    records: Arc::default(),
+                    history: History::default(),
+                });
+
+                record.last_seen_time = Instant::now();
+                record.addresses.extend(&instance.addresses);
+                if record.preferred_address.is_none() {
+                    record.preferred_address = instance.addresses.iter().next().copied();
+                }
+
+                database.instance_at(&instance.name, &service, None, Instant::now());
+            }
+        }
+
+        remove_old_entries(database, metrics, changes, Instant::now());
+        next_refresh = Instant::now() + REFRESH_INTERVAL;
+    }
+
+    // Operations must go before the connection they share.
+    instances.clear();
+    drop(browse);
+}
@@ -0,0 +1,198 @@
+#[cfg(feature = "pcap")]
+mod imp {
+    use crate::{lock::Lock, MULTICAST_ADDR, MULTICAST_PORT};
+    use std::{
+        ffi::OsString,
+        fs::{self, File},
+        io::{self, BufWriter, Write},
+        net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket},
+        path::PathBuf,
+        sync::Mutex,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    const LINKTYPE_IPV4: u32 = 228;
+    const SNAPLEN: u32 = 65535;
+    const GLOBAL_HEADER_LEN: u64 = 24;
+    const RECORD_HEADER_LEN: u64 = 16;
+    const IP_UDP_HEADER_LEN: usize = 28;
+
+    struct Writer {
+        path: PathBuf,
+        limit: Option<u64>,
+        file: BufWriter<File>,
+        written: u64,
+    }
+
+    impl Writer {
+        fn create(path: PathBuf, limit: Option<u64>) -> io::Result<Self> {
+            let mut file = BufWriter::new(File::create(&path)?);
+
+            file.write_all(&0xa1b2_c3d4u32.to_ne_bytes())?;
+            file.write_all(&2u16.to_ne_bytes())?;
+            file.write_all(&4u16.to_ne_bytes())?;
+            file.write_all(&0i32.to_ne_bytes())?;
+            file.write_all(&0u32.to_ne_bytes())?;
+            file.write_all(&SNAPLEN.to_ne_bytes())?;
+            file.write_all(&LINKTYPE_IPV4.to_ne_bytes())?;
+
+            Ok(Writer {
+                path,
+                limit,
+                file,
+                written: GLOBAL_HEADER_LEN,
+            })
+        }
+
+        // Keeps at most one previous file around, as `<path>.1`.
+        fn rotate(&mut self) -> io::Result<()> {
+            self.file.flush()?;
+
+            let mut previous = OsString::from(self.path.as_os_str());
+            previous.push(".1");
+            fs::rename(&self.path, &previous)?;
+
+            *self = Writer::create(self.path.clone(), self.limit)?;
+            Ok(())
+        }
+
+        fn write(&mut self, src: SocketAddrV4, dst: SocketAddrV4, data: &[u8]) -> io::Result<()> {
+            let len = (IP_UDP_HEADER_LEN + data.len()) as u32;
+
+            if let Some(limit) = self.limit {
+                if self.written + RECORD_HEADER_LEN + len as u64 > limit
+                    && self.written > GLOBAL_HEADER_LEN
+                {
+                    self.rotate()?;
+                }
+            }
+
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+
+            self.file.write_all(&(now.as_secs() as u32).to_ne_bytes())?;
+            self.file.write_all(&now.subsec_micros().to_ne_bytes())?;
+            self.file.write_all(&len.to_ne_bytes())?;
+            self.file.write_all(&len.to_ne_bytes())?;
+
+            let mut ip = [0u8; 20];
+            ip[0] = 0x45;
+            ip[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+            ip[8] = 255;
+            ip[9] = 17;
+            ip[12..16].copy_from_slice(&src.ip().octets());
+            ip[16..20].copy_from_slice(&dst.ip().octets());
+            let checksum = checksum(&ip);
+            ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+            self.file.write_all(&ip)?;
+
+            // A zero UDP checksum means "not computed", which IPv4 allows.
+            self.file.write_all(&src.port().to_be_bytes())?;
+            self.file.write_all(&dst.port().to_be_bytes())?;
+            self.file
+                .write_all(&((8 + data.len()) as u16).to_be_bytes())?;
+            self.file.write_all(&0u16.to_be_bytes())?;
+            self.file.write_all(data)?;
+
+            self.written += RECORD_HEADER_LEN + len as u64;
+            Ok(())
+        }
+    }
+
+    fn checksum(header: &[u8]) -> u16 {
+        let mut sum: u32 = header
+            .chunks(2)
+            .map(|w| u32::from(u16::from_be_bytes([w[0], w[1]])))
+            .sum();
+
+        while sum > 0xffff {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+
+        !(sum as u16)
+    }
+
+    fn v4(addr: SocketAddr) -> Option<SocketAddrV4> {
+        match addr {
+            SocketAddr::V4(addr) => Some(addr),
+            SocketAddr::V6(_) => None,
+        }
+    }
+
+    pub(crate) struct Capture {
+        writer: Mutex<Writer>,
+    }
+
+    impl Capture {
+        pub(crate) fn create(path: PathBuf, limit: Option<u64>) -> io::Result<Self> {
+            Ok(Capture {
+                writer: Mutex::new(Writer::create(path, limit)?),
+            })
+        }
+
+        pub(crate) fn sent(&self, socket: &UdpSocket, dst: SocketAddr, data: &[u8]) {
+            if let (Some(src), Some(dst)) = (socket.local_addr().ok().and_then(v4), v4(dst)) {
+                self.write(src, dst, data);
+            }
+        }
+
+        // Without IP_PKTINFO the destination of a datagram received on a wildcard socket
+        // is unknown; almost all of them are sent to the group, so record that.
+        pub(crate) fn received(&self, socket: &UdpSocket, src: SocketAddr, data: &[u8]) {
+            let dst = match socket.local_addr().ok().and_then(v4) {
+                Some(local) if local.ip() != &Ipv4Addr::UNSPECIFIED => local,
+                _ => SocketAddrV4::new(MULTICAST_ADDR, MULTICAST_PORT),
+            };
+
+            if let Some(src) = v4(src) {
+                self.write(src, dst, data);
+            }
+        }
+
+        // For when the last reference may only go some time after the client.
+        pub(crate) fn flush(&self) {
+            self.writer.locked().file.flush().ok();
+        }
+
+        fn write(&self, src: SocketAddrV4, dst: SocketAddrV4, data: &[u8]) {
+            let mut writer = self.writer.locked();
+
+            if let Err(_err) = writer.write(src, dst, data) {
+                warn!(path = ?writer.path, error = %_err, "failed to write capture");
+            }
+        }
+    }
+
+    impl Drop for Capture {
+        fn drop(&mut self) {
+            if let Ok(writer) = self.writer.get_mut() {
+                writer.file.flush().ok();
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "pcap"))]
+mod imp {
+    use std::net::{SocketAddr, UdpSocket};
+
+    // Never constructed without the feature; keeps the call sites free of cfgs.
+    pub(crate) enum Capture {}
+
+    impl Capture {
+        pub(crate) fn sent(&self, _socket: &UdpSocket, _dst: SocketAddr, _data: &[u8]) {
+            match *self {}
+        }
+
+        pub(crate) fn received(&self, _socket: &UdpSocket, _src: SocketAddr, _data: &[u8]) {
+            match *self {}
+        }
+
+        pub(crate) fn flush(&self) {
+            match *self {}
+        }
+    }
+}
+
+pub(crate) use imp::Capture;
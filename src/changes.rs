@@ -0,0 +1,492 @@
+use crate::{
+    activity::{Activity, ExpiryReason},
+    database::{self, Database, Guard, EXPIRE_AFTER},
+    events::{Queue, ServiceEvent, Subscription},
+    lock::Lock,
+    metrics::Metrics,
+    store::{Mirror, ServiceStore, Stored},
+    sync::{AtomicU64, Condvar, Mutex, Ordering},
+    Service, ServiceRecord,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, OnceLock, Weak},
+    time::{Duration, Instant},
+};
+
+// Changes to `last_seen_time` alone are published at most this often, each publication
+// being a copy of the database.
+const REFRESH_PUBLISH_INTERVAL: Duration = Duration::from_secs(1);
+
+// A refresh is published at once if the copy readers have would otherwise look expired
+// within this long: waiting for the next publication, a second or two off with workers
+// updating once a second, could be too late.
+const REFRESH_URGENT: Duration = Duration::from_secs(3);
+
+#[cfg(feature = "watch")]
+pub(crate) type Snapshot = Arc<Vec<(Service, ServiceRecord)>>;
+
+pub(crate) type Services = Arc<HashMap<Service, ServiceRecord>>;
+
+// The services as last published, for readers. Only cloning or replacing the `Arc` happens
+// under the lock, so readers never wait for the workers handling packets, nor they for
+// readers.
+#[derive(Default)]
+pub(crate) struct Published(Mutex<Publication>);
+
+#[derive(Default)]
+struct Publication {
+    services: Services,
+    // When the first of them expires, if any do.
+    expires_at: Option<Instant>,
+    // `services` and the tombstones, the same `Arc` when there are none.
+    recent: Services,
+}
+
+// Pinned services never are.
+fn expired(record: &ServiceRecord, now: Instant) -> bool {
+    !record.pinned && record.last_seen_time + EXPIRE_AFTER <= now
+}
+
+impl Published {
+    // Services past their deadline as of `now` are left out, so readers never see one the
+    // worker hasn't got round to removing; only then are the rest copied.
+    pub(crate) fn load(&self, now: Instant) -> Services {
+        let (services, expires_at) = {
+            let publication = self.0.locked();
+            (publication.services.clone(), publication.expires_at)
+        };
+
+        if expires_at.is_none_or(|at| now < at) {
+            return services;
+        }
+
+        Arc::new(
+            services
+                .iter()
+                .filter(|(_, record)| !expired(record, now))
+                .map(|(service, record)| (service.clone(), record.clone()))
+                .collect(),
+        )
+    }
+
+    // Including the services expired within the grace period.
+    pub(crate) fn load_recent(&self) -> Services {
+        self.0.locked().recent.clone()
+    }
+
+    fn store(&self, services: Services, recent: Services) {
+        let expires_at = services
+            .values()
+            .filter(|record| !record.pinned)
+            .map(|record| record.last_seen_time + EXPIRE_AFTER)
+            .min();
+
+        *self.0.locked() = Publication {
+            services,
+            expires_at,
+            recent,
+        };
+    }
+}
+
+struct Last {
+    services: Services,
+    // `None` before the first publication.
+    published_at: Option<Instant>,
+    // Refreshes not published yet.
+    refreshed: bool,
+}
+
+// Tracks what the database looked like when it last changed in a way readers care about:
+// services coming and going and their addresses, but not `last_seen_time` refreshes.
+// Those are published too, but without counting as changes.
+pub(crate) struct Changes {
+    generation: AtomicU64,
+    // Taken between moving `generation` and notifying, so a waiter that found it unmoved
+    // is waiting by the time it is told.
+    bumped: Mutex<()>,
+    waiters: Condvar,
+    last: Mutex<Last>,
+    published: Arc<Published>,
+    subscribers: Mutex<Vec<Weak<Queue>>>,
+    store: OnceLock<Mirror>,
+    metrics: Arc<Metrics>,
+    #[cfg(feature = "watch")]
+    watch: tokio::sync::watch::Sender<Snapshot>,
+}
+
+impl Changes {
+    pub(crate) fn new(metrics: Arc<Metrics>) -> Self {
+        Changes {
+            generation: AtomicU64::new(0),
+            bumped: Mutex::new(()),
+            waiters: Condvar::new(),
+            last: Mutex::new(Last {
+                services: Services::default(),
+                published_at: None,
+                refreshed: false,
+            }),
+            published: Arc::default(),
+            subscribers: Mutex::new(Vec::new()),
+            store: OnceLock::new(),
+            metrics,
+            #[cfg(feature = "watch")]
+            watch: tokio::sync::watch::channel(Arc::new(Vec::new())).0,
+        }
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    // Moves `generation` without events, for a change to what the queries are built from
+    // beyond the services, such as `MdnsClient::track`.
+    pub(crate) fn invalidate(&self) {
+        self.bump();
+    }
+
+    fn bump(&self) {
+        self.generation.fetch_add(1, Ordering::Release);
+        self.wake_waiters();
+    }
+
+    // Also without a change, as a spurious wakeup would, for the models.
+    pub(crate) fn wake_waiters(&self) {
+        drop(self.bumped.locked());
+        self.waiters.notify_all();
+    }
+
+    // The generation once it is other than `last`, at once if it already is; `None` if it
+    // isn't by `timeout`, which may be too long to end. Waking up without a change just
+    // waits on.
+    pub(crate) fn wait_for_change(&self, last: u64, timeout: Duration) -> Option<u64> {
+        let deadline = Instant::now().checked_add(timeout);
+        let mut bumped = self.bumped.locked();
+
+        loop {
+            let generation = self.generation();
+            if generation != last {
+                return Some(generation);
+            }
+
+            bumped = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return None;
+                    }
+                    self.waiters
+                        .wait_timeout(bumped, deadline - now)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .0
+                }
+                None => self
+                    .waiters
+                    .wait(bumped)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()),
+            };
+        }
+    }
+
+    pub(crate) fn published(&self) -> Arc<Published> {
+        self.published.clone()
+    }
+
+    // Called by the workers after anything that may have modified the database as of
+    // `now`, which only needs to stay locked while the services it changed are compared with
+    // the last published. Subscribers are told after it is unlocked.
+    pub(crate) fn update(&self, mut database: Guard<'_>, now: Instant) {
+        let mut last = self.last.locked();
+        let mut events = Vec::new();
+        let mut stored = Vec::new();
+        let mut refreshed = false;
+        let mut urgent = false;
+
+        let activity = self.metrics.activity();
+        let store = self.store.get();
+
+        database.drain_changed(
+            |service, record| match (last.services.get(service), record) {
+                (None, Some(record)) => {
+                    activity.record(|| Activity::ServiceAdded {
+                        service: service.clone(),
+                    });
+                    if store.is_some() {
+                        stored.push((service.clone(), Stored::Insert(record.clone())));
+                    }
+                    events.push(ServiceEvent::Added(service.clone(), record.clone()))
+                }
+                (Some(previous), Some(record))
+                    if previous.preferred_address != record.preferred_address
+                        || previous.addresses != record.addresses
+                        || previous.ipv6_addresses != record.ipv6_addresses
+                        || previous.conflicting != record.conflicting
+                        || previous.pinned != record.pinned
+                        || previous.withdrawn != record.withdrawn
+                        || previous.seeded != record.seeded
+                        || previous.mac_address != record.mac_address =>
+                {
+                    activity.record(|| Activity::ServiceUpdated {
+                        service: service.clone(),
+                    });
+                    if store.is_some() {
+                        stored.push((service.clone(), Stored::Update(record.clone())));
+                    }
+                    events.push(ServiceEvent::Updated(service.clone(), record.clone()))
+                }
+                (Some(previous), Some(record)) => {
+                    if previous.last_seen_time != record.last_seen_time {
+                        activity.record(|| Activity::ServiceRefreshed {
+                            service: service.clone(),
+                        });
+                        if store.is_some() {
+                            stored.push((service.clone(), Stored::Refresh(record.clone())));
+                        }
+                        refreshed = true;
+                        urgent |= expired(previous, now + REFRESH_URGENT);
+                    }
+                }
+                (Some(_), None) => {
+                    if store.is_some() {
+                        stored.push((service.clone(), Stored::Remove));
+                    }
+                    events.push(ServiceEvent::Expired(service.clone()))
+                }
+                (None, None) => (),
+            },
+        );
+
+        let mut removals = database.take_removals();
+        for event in &events {
+            if let ServiceEvent::Expired(service) = event {
+                activity.record(|| Activity::ServiceExpired {
+                    service: service.clone(),
+                    reason: removals.remove(service).unwrap_or(ExpiryReason::NotHeard),
+                });
+            }
+        }
+
+        // A service moving within one update is one event, as subscribers had it.
+        for (from, to) in database.take_moves() {
+            let gone = events
+                .iter()
+                .position(|e| matches!(e, ServiceEvent::Expired(s) if *s == from));
+            let added = events
+                .iter()
+                .position(|e| matches!(e, ServiceEvent::Added(s, _) if *s == to));
+
+            let (Some(gone), Some(added)) = (gone, added) else {
+                continue;
+            };
+            if let ServiceEvent::Added(_, record) = &events[added] {
+                events[gone] = ServiceEvent::Moved {
+                    from,
+                    to,
+                    record: record.clone(),
+                };
+                events.remove(added);
+            }
+        }
+
+        // A rename is one event, whatever else changed in the same update; subscribers
+        // that are told of the service being added or gone now have nothing to rename.
+        for (service, (old, new)) in database.take_renames() {
+            let Some(record) = database.get(&service) else {
+                continue;
+            };
+            let (old, new) = (String::from(old), String::from(new));
+            let event = ServiceEvent::Renamed {
+                service: service.clone(),
+                old: old.clone(),
+                new: new.clone(),
+                record: record.clone(),
+            };
+
+            match events.iter().position(|e| *e.service() == service) {
+                Some(i) if matches!(events[i], ServiceEvent::Updated(..)) => events[i] = event,
+                Some(_) => continue,
+                None if last.services.contains_key(&service) => events.push(event),
+                None => continue,
+            }
+            activity.record(|| Activity::ServiceRenamed { service, old, new });
+        }
+
+        last.refreshed |= refreshed;
+        events.sort_unstable_by(|a, b| a.service().cmp(b.service()));
+        if let Some(store) = store {
+            store.queue(stored);
+        }
+
+        if events.is_empty() {
+            let due = last
+                .published_at
+                .is_none_or(|at| now.saturating_duration_since(at) >= REFRESH_PUBLISH_INTERVAL);
+            if last.refreshed && (urgent || due) {
+                self.publish(&mut last, &database, now);
+            }
+            return;
+        }
+
+        self.publish(&mut last, &database, now);
+        drop(database);
+        debug_assert!(!database::held(), "events sent with a database locked");
+
+        self.bump();
+
+        // Stored even without receivers so later subscribers start from it.
+        #[cfg(feature = "watch")]
+        self.watch.send_replace({
+            let mut snapshot: Vec<_> = self
+                .published
+                .load(now)
+                .iter()
+                .map(|(service, record)| (service.clone(), record.clone()))
+                .collect();
+            snapshot.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+            Arc::new(snapshot)
+        });
+
+        // Pushing never waits for a subscriber; a full queue drops instead. `last` stays
+        // locked, so a new subscriber sees either the state before these events or them.
+        let mut dropped = 0;
+        let mut subscribers = self.subscribers.locked();
+        subscribers.retain(|queue| match queue.upgrade() {
+            Some(queue) => {
+                for event in &events {
+                    dropped += queue.push(event.clone()) as usize;
+                }
+                true
+            }
+            None => false,
+        });
+
+        self.metrics.events_dropped(dropped);
+    }
+
+    // Readers get the live services; tombstones only count for comparing with later.
+    fn publish(&self, last: &mut Last, database: &Database, now: Instant) {
+        let services: Services = Arc::new((**database).clone());
+        last.services = if database.tombstones().is_empty() {
+            services.clone()
+        } else {
+            let mut recent = (*services).clone();
+            recent.extend(
+                database
+                    .tombstones()
+                    .iter()
+                    .map(|(service, record)| (service.clone(), record.clone())),
+            );
+            Arc::new(recent)
+        };
+        last.published_at = Some(now);
+        last.refreshed = false;
+        self.published.store(services, last.services.clone());
+    }
+
+    // New subscribers see everything known and not expired as of `now` as `Added` first, as
+    // much of it as fits, in order like every batch of events.
+    pub(crate) fn subscribe(&self, capacity: usize, now: Instant) -> Subscription {
+        let last = self.last.locked();
+        let queue = Arc::new(Queue::new(capacity));
+
+        let mut services: Vec<_> = last
+            .services
+            .iter()
+            .filter(|(_, r)| !expired(r, now))
+            .collect();
+        services.sort_unstable_by(|a, b| a.0.cmp(b.0));
+
+        let mut dropped = 0;
+        for (service, record) in services {
+            dropped += queue.push(ServiceEvent::Added(service.clone(), record.clone())) as usize;
+        }
+        self.metrics.events_dropped(dropped);
+
+        self.subscribers.locked().push(Arc::downgrade(&queue));
+
+        Subscription::new(queue)
+    }
+
+    // Given what is known so far, as a new subscriber is. Only the first store is kept.
+    pub(crate) fn set_store(&self, store: Arc<dyn ServiceStore>) {
+        let last = self.last.locked();
+
+        if self.store.get().is_some() {
+            return;
+        }
+
+        let now = Instant::now();
+        let mirror = Mirror::start(store, self.metrics.clone());
+        mirror.queue(
+            last.services
+                .iter()
+                .filter(|(_, r)| !expired(r, now))
+                .map(|(service, record)| (service.clone(), Stored::Insert(record.clone()))),
+        );
+
+        self.store.set(mirror).ok();
+    }
+
+    pub(crate) fn has_subscribers(&self) -> bool {
+        self.subscribers
+            .locked()
+            .iter()
+            .any(|queue| queue.strong_count() > 0)
+    }
+
+    #[cfg(feature = "watch")]
+    pub(crate) fn watch(&self) -> tokio::sync::watch::Receiver<Snapshot> {
+        self.watch.subscribe()
+    }
+}
+
+impl Drop for Changes {
+    fn drop(&mut self) {
+        for queue in self.subscribers.get_mut().unwrap().iter() {
+            if let Some(queue) = queue.upgrade() {
+                queue.close();
+            }
+        }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refresh_published_by_the_time_given() {
+        // Years from now, so that anything reading the real clock instead is far off.
+        let start = Instant::now() + Duration::from_secs(10 * 365 * 24 * 3600);
+        let changes = Changes::new(Arc::new(Metrics::new([])));
+        let database = std::sync::Mutex::new(Database::default());
+        let service = Service {
+            host: "printer.local".into(),
+            port: 631,
+        };
+        let heard = |now: Instant| {
+            let mut locked = Database::lock(&database);
+            locked
+                .get_or_insert_with(service.clone(), || ServiceRecord::heard_at(now))
+                .last_seen_time = now;
+            changes.update(locked, now);
+        };
+
+        heard(start);
+        assert!(changes.published().load(start).contains_key(&service));
+
+        // Due to expire within `REFRESH_URGENT`, so published at once, though it is
+        // only a refresh.
+        let refreshed = start + Duration::from_secs(4);
+        heard(refreshed);
+        assert_eq!(changes.generation(), 1);
+
+        let services = changes.published().load(start + Duration::from_secs(6));
+        assert_eq!(services[&service].last_seen_time, refreshed);
+        assert!(!changes
+            .published()
+            .load(refreshed + EXPIRE_AFTER)
+            .contains_key(&service));
+    }
+}
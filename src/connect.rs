@@ -0,0 +1,109 @@
+use std::{
+    io,
+    net::{SocketAddr, TcpStream},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+// Connects to the first of `addresses` that accepts within `timeout` of being tried, in
+// order: each once the attempts before it have failed or, with a `stagger`, also once that
+// long has passed since the last attempt started, as Happy Eyeballs (RFC 8305) does.
+// Attempts still running when one succeeds finish on their own threads and are closed.
+pub(crate) fn connect(
+    addresses: &[SocketAddr],
+    timeout: Duration,
+    stagger: Option<Duration>,
+) -> io::Result<(TcpStream, SocketAddr)> {
+    let mut last_error = None;
+
+    let Some(stagger) = stagger else {
+        for address in addresses {
+            match TcpStream::connect_timeout(address, timeout) {
+                Ok(stream) => return Ok((stream, *address)),
+                Err(err) => last_error = Some(err),
+            }
+        }
+        return Err(last_error.unwrap_or_else(no_addresses));
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let mut remaining = addresses.iter().copied();
+    let mut running = 0;
+    // When the next attempt starts unless one fails first; never, for a stagger too long
+    // to add to an `Instant`.
+    let mut next_start = Some(Instant::now());
+
+    loop {
+        let now = Instant::now();
+        if running == 0 || next_start.is_some_and(|at| now >= at) {
+            if let Some(address) = remaining.next() {
+                let tx = tx.clone();
+                thread::spawn(move || {
+                    tx.send((address, TcpStream::connect_timeout(&address, timeout)))
+                        .ok();
+                });
+                running += 1;
+                next_start = now.checked_add(stagger);
+            } else if running == 0 {
+                return Err(last_error.unwrap_or_else(no_addresses));
+            }
+        }
+
+        // With every address tried, only the attempts' ends are waited for.
+        let wait = match (remaining.len(), next_start) {
+            (0, _) | (_, None) => timeout,
+            (_, Some(at)) => at.saturating_duration_since(Instant::now()),
+        };
+
+        match rx.recv_timeout(wait) {
+            Ok((address, Ok(stream))) => return Ok((stream, address)),
+            Ok((_, Err(err))) => {
+                running -= 1;
+                last_error = Some(err);
+                // The next starts at once rather than at its time.
+                next_start = Some(Instant::now());
+            }
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => (),
+        }
+    }
+}
+
+fn no_addresses() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        "no addresses known for the service",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    // An address nothing listens on, refused at once.
+    fn closed() -> SocketAddr {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    #[test]
+    fn stagger_too_long_to_add_waits_for_failures() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap();
+
+        for stagger in [None, Some(Duration::ZERO), Some(Duration::MAX)] {
+            let (_, address) = connect(&[closed(), open], Duration::from_secs(5), stagger)
+                .unwrap_or_else(|err| panic!("stagger {:?}: {}", stagger, err));
+            assert_eq!(address, open);
+        }
+
+        let all_closed = [closed(), closed()];
+        let err = connect(&all_closed, Duration::from_secs(5), Some(Duration::MAX)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+        let err = connect(&[], Duration::from_secs(5), Some(Duration::MAX)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,2475 @@
+use crate::{
+    availability,
+    capture::Capture,
+    catch_panic,
+    changes::Changes,
+    database::{Database, MAX_ADDRESSES},
+    dedup::Recent,
+    diagnostics::{InterfaceDiagnostics, QuestionDiagnostics},
+    inventory::Inventory,
+    limit::Sources,
+    lint::Lint,
+    lock::Lock,
+    metrics::{FailedOperation, Metrics},
+    observe::Observer,
+    packet::Packet,
+    responder::{Advert, Advertisement, Responder},
+    scan::Scan,
+    schedule::QuerySchedule,
+    send_mdns_query, share,
+    socket::{self, Batch, ReceiveError},
+    state::MdnsState,
+    subnet::SourceFilter,
+    updates::Updates,
+    AddressFamily, Backend, Command, Direction, ExpiryReason, MdnsClient, MdnsClientBuilder,
+    PacketHook, SocketFactory, MULTICAST_ADDR, MULTICAST_ADDR_V6, MULTICAST_PORT,
+};
+use if_addrs::IfAddr;
+use std::{
+    error::Error,
+    fmt, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, sync_channel, Receiver, RecvTimeoutError, SyncSender},
+        Arc, Mutex, Weak,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime},
+};
+
+// The database is refreshed on the same schedule as the daemon backends. In between, the
+// worker sleeps until a service may expire or an announcement is due.
+pub(crate) const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+// A socket whose sends and receives have failed this many times in a row, for at least
+// this long, is closed and set up again; attempts that fail back off up to the maximum.
+const BROKEN_AFTER_ERRORS: u32 = 5;
+const BROKEN_AFTER: Duration = Duration::from_secs(10);
+const RECREATE_BACKOFF: Duration = Duration::from_secs(1);
+const RECREATE_BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+// After a failed send a socket waits this long before sending again, doubling with each
+// further failure up to the maximum, so a dead interface isn't tried and reported every
+// second.
+const SEND_BACKOFF: Duration = Duration::from_secs(1);
+const SEND_BACKOFF_MAX: Duration = Duration::from_secs(10);
+
+// Recovery queries, sent at once when a client's services have all expired or its first
+// subscriber finds none, go out at most this often, whatever the network does.
+const RECOVERY_INTERVAL: Duration = Duration::from_secs(10);
+
+// Datagrams a second from any one address, and new services a second per client, unless
+// the builder sets other limits. A host announces a handful of services at once.
+pub(crate) const SOURCE_RATE_LIMIT: u32 = 50;
+pub(crate) const DISCOVERY_RATE_LIMIT: u32 = 200;
+
+// The worker runs at least every `REFRESH_INTERVAL`; a far longer gap means the system was
+// suspended, unless the builder says otherwise.
+const SUSPEND_THRESHOLD: Duration = Duration::from_secs(10);
+
+// Query cycles without a datagram from another host before the client warns; a network
+// with other mDNS hosts on it is never this quiet.
+const SILENCE_THRESHOLD: u32 = 10;
+
+// How often the sockets leave the group and join it again unless the builder says
+// otherwise, well within the 260 seconds after which IGMP snooping switches commonly
+// forget a port whose host sent no membership report (RFC 3376, section 8.4).
+const GROUP_REJOIN_INTERVAL: Duration = Duration::from_secs(120);
+
+// Queries going out together (see `state::COALESCE_WINDOW`) share a datagram while they
+// fit in this many bytes: an Ethernet frame less IPv6 and UDP headers.
+const COALESCED_SIZE: usize = 1452;
+
+// How long `MdnsClient::new` waits for the sockets being set up, and each cycle for those
+// of new interfaces, before going on with those that are ready; the others are taken
+// up once they are.
+const SETUP_DEADLINE: Duration = Duration::from_secs(1);
+
+// How long a receive thread blocks on its socket before checking whether to stop, where
+// it can't be woken instead, and how long it waits after an error.
+const RECEIVE_TIMEOUT: Duration = Duration::from_millis(100);
+
+// Where a wildcard-bound socket is known to only hear the group on the interface it joined
+// on, so that one IPv4 socket per interface works. Elsewhere a single socket joins on the
+// unspecified address and the kernel picks the interface, as for any other program.
+const JOINS_PER_INTERFACE: bool = cfg!(any(
+    target_os = "windows",
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly",
+));
+
+// The daemon all socket-backend clients with default options share, so that several
+// service types cost one set of sockets and one worker between them.
+static SHARED: Mutex<Weak<Daemon>> = Mutex::new(Weak::new());
+
+// A registered client: answers are parsed once and applied to every question's state.
+struct Question {
+    state: MdnsState,
+    suspend_threshold: Duration,
+    silence: Mutex<Silence>,
+    silence_threshold: u32,
+    lint: Option<Arc<Lint>>,
+    observer: Option<Arc<Observer>>,
+    inventory: Option<Arc<Inventory>>,
+}
+
+// Starts every question's schedule over, its first query due at once.
+fn restart_queries(questions: &[Question], now: Instant) {
+    for question in questions {
+        question.state.restart_queries(now);
+    }
+}
+
+// The datagrams of one send, each with the questions, by index, it is for and their
+// service types for the log. Queries are appended to the first query datagram they fit
+// in; the questions by name go alone.
+#[derive(Default)]
+struct Outgoing {
+    datagrams: Vec<Datagram>,
+}
+
+struct Datagram {
+    packet: Vec<u8>,
+    questions: Vec<usize>,
+    services: String,
+    coalesced: bool,
+}
+
+impl Outgoing {
+    fn query(&mut self, question: usize, service: &str, packet: &[u8]) {
+        let fits = |datagram: &&mut Datagram| {
+            datagram.coalesced && datagram.packet.len() + packet.len() - 12 <= COALESCED_SIZE
+        };
+
+        match self.datagrams.iter_mut().find(fits) {
+            Some(datagram) => {
+                let count = |packet: &[u8]| u16::from_be_bytes([packet[4], packet[5]]);
+                let questions = count(&datagram.packet) + count(packet);
+                datagram.packet[4..6].copy_from_slice(&questions.to_be_bytes());
+                datagram.packet.extend_from_slice(&packet[12..]);
+                datagram.questions.push(question);
+                datagram.services.push_str(", ");
+                datagram.services.push_str(service);
+            }
+            None => self.datagrams.push(Datagram {
+                packet: packet.to_vec(),
+                questions: vec![question],
+                services: service.to_string(),
+                coalesced: true,
+            }),
+        }
+    }
+
+    fn alone(&mut self, question: usize, service: &str, packet: Vec<u8>) {
+        self.datagrams.push(Datagram {
+            packet,
+            questions: vec![question],
+            services: service.to_string(),
+            coalesced: false,
+        });
+    }
+}
+
+// Query cycles in a row in which nothing arrived from other hosts, and `Traffic::foreign`
+// as of the last one.
+#[derive(Default)]
+struct Silence {
+    heard: u64,
+    cycles: u32,
+}
+
+pub(crate) struct Daemon {
+    questions: Arc<Mutex<Vec<Question>>>,
+    responder: Arc<Mutex<Responder>>,
+    setup: Setup,
+    // Named after the sockets' addresses, in socket order, for per-socket metrics.
+    interfaces: Arc<Mutex<Vec<String>>>,
+    skipped: Arc<Mutex<Vec<SkippedInterface>>>,
+    // Why the sockets are `wildcard_fallback`'s, while they are.
+    fallback: Arc<Mutex<Option<String>>>,
+    schedule: Arc<Mutex<Schedule>>,
+    command_tx: SyncSender<Command>,
+    thread: Option<JoinHandle<()>>,
+}
+
+// What the worker had planned as of its last wakeup, for `MdnsClient::diagnostics`.
+#[derive(Default)]
+struct Schedule {
+    next_refresh: Option<Instant>,
+    links: Vec<LinkStatus>,
+}
+
+struct LinkStatus {
+    interface: Interface,
+    legacy_unicast: bool,
+    failing_since: Option<Instant>,
+    send_failures: u32,
+    send_after: Option<Instant>,
+}
+
+impl Daemon {
+    pub(crate) fn withdraw(&self, id: u64) {
+        self.responder.locked().withdraw(id);
+        self.command_tx.try_send(Command::Wake).ok();
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        self.command_tx.send(Command::Exit).ok();
+        self.thread.take().map(JoinHandle::join);
+    }
+}
+
+// Held by the client; deregisters its question and, for the last client, stops the daemon.
+pub(crate) struct Registration {
+    // Empty while the daemon of a client set up in the background is started, and for good
+    // if that failed. The setup thread registers the question with this locked, so a client
+    // dropped meanwhile can't miss it.
+    daemon: Arc<Mutex<Option<Arc<Daemon>>>>,
+    setup: Option<JoinHandle<()>>,
+    database: Arc<Mutex<Database>>,
+    // The question's, which it is registered with once the daemon is started.
+    schedule: Arc<Mutex<QuerySchedule>>,
+    bind_group: bool,
+}
+
+impl Registration {
+    fn daemon(&self) -> Option<Arc<Daemon>> {
+        self.daemon.locked().clone()
+    }
+
+    pub(crate) fn is_ready(&self) -> bool {
+        self.daemon.locked().is_some()
+    }
+
+    pub(crate) fn is_running(&self) -> bool {
+        match self.daemon() {
+            Some(daemon) => daemon
+                .thread
+                .as_ref()
+                .is_some_and(|thread| !thread.is_finished()),
+            None => self
+                .setup
+                .as_ref()
+                .is_some_and(|thread| !thread.is_finished()),
+        }
+    }
+
+    pub(crate) fn skipped_interfaces(&self) -> Vec<SkippedInterface> {
+        self.daemon()
+            .map(|daemon| daemon.skipped.locked().clone())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn wildcard_fallback(&self) -> Option<String> {
+        self.daemon()
+            .and_then(|daemon| daemon.fallback.locked().clone())
+    }
+
+    // How long until the client's next query, every question's schedule, and the sockets.
+    pub(crate) fn diagnostics(
+        &self,
+        now: Instant,
+    ) -> (
+        Option<Duration>,
+        Vec<QuestionDiagnostics>,
+        Vec<InterfaceDiagnostics>,
+    ) {
+        let Some(daemon) = self.daemon() else {
+            return (None, Vec::new(), Vec::new());
+        };
+        let until = |at: Instant| at.saturating_duration_since(now);
+
+        let mut next_query = None;
+        let questions = daemon
+            .questions
+            .locked()
+            .iter()
+            .map(|question| {
+                let timer = question.state.timer.locked();
+                if Arc::ptr_eq(&question.state.database, &self.database) {
+                    next_query = Some(timer.next);
+                }
+
+                QuestionDiagnostics {
+                    service: question.state.service.clone(),
+                    schedule: *question.state.schedule.locked(),
+                    next_query_in: until(timer.next),
+                    next_interval: timer.interval,
+                }
+            })
+            .collect();
+
+        let schedule = daemon.schedule.locked();
+
+        let interfaces = schedule
+            .links
+            .iter()
+            .map(|link| InterfaceDiagnostics {
+                name: link.interface.name.clone(),
+                address: link.interface.ip,
+                legacy_unicast: link.legacy_unicast,
+                failing_for: link
+                    .failing_since
+                    .map(|since| now.saturating_duration_since(since)),
+                send_failures: link.send_failures,
+                sends_resume_in: link.send_after.map(until),
+            })
+            .collect();
+
+        // Without sockets, nothing is sent before they are set up.
+        let next_query_in = match schedule.links.is_empty() {
+            true => schedule.next_refresh,
+            false => next_query.or(schedule.next_refresh),
+        };
+        (next_query_in.map(until), questions, interfaces)
+    }
+
+    // The next query goes out as it was due, or a new interval from now if that is sooner,
+    // and the intervals after it follow `schedule`.
+    pub(crate) fn set_schedule(&self, schedule: QuerySchedule) -> Result<(), Box<dyn Error>> {
+        schedule.check()?;
+        if self.bind_group && schedule.unicast_response {
+            return Err("unicast answers (QU) can't be heard with bind_to_group".into());
+        }
+        *self.schedule.locked() = schedule;
+
+        let Some(daemon) = self.daemon() else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        for question in daemon.questions.locked().iter() {
+            if Arc::ptr_eq(&question.state.database, &self.database) {
+                question.state.reschedule(schedule, now);
+            }
+        }
+        daemon.command_tx.try_send(Command::Wake).ok();
+        Ok(())
+    }
+
+    pub(crate) fn advertise(&self, advert: Advert) -> Result<Advertisement, Box<dyn Error>> {
+        let daemon = self.daemon().ok_or("the sockets aren't set up")?;
+
+        let mut responder = daemon.responder.locked();
+        responder.set_addresses(local_addresses(&daemon.setup));
+
+        let advertisement = Advertisement::new(daemon.clone(), responder.add(advert));
+        daemon.command_tx.try_send(Command::Wake).ok();
+        Ok(advertisement)
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let daemon = self.daemon.locked().take();
+
+        if let Some(daemon) = daemon {
+            daemon
+                .questions
+                .locked()
+                .retain(|question| !Arc::ptr_eq(&question.state.database, &self.database));
+        }
+    }
+}
+
+// Which interfaces get sockets, and how they are set up.
+#[derive(Clone)]
+struct Setup {
+    family: AddressFamily,
+    // Devices to bind to with SO_BINDTODEVICE; empty to use every interface unbound.
+    devices: Vec<String>,
+    // Interface indices to restrict to; empty for all.
+    indices: Vec<u32>,
+    // Local IPv4 addresses to restrict to, and the interfaces holding them for IPv6;
+    // empty for all.
+    addresses: Vec<Ipv4Addr>,
+    receive_buffer: Option<usize>,
+    // See `socket::Options::bind_group`.
+    bind_group: bool,
+    // Also interfaces that are down, link-local IPv4 addresses and virtual NAT adapters.
+    all_interfaces: bool,
+    // Query from an ephemeral port where 5353 is held exclusively.
+    legacy_unicast_fallback: bool,
+    // Opens the sockets in place of `socket::multicast`.
+    factory: Option<SocketFactory>,
+    // How often every socket leaves the group and joins it again; `None` for never.
+    rejoin_interval: Option<Duration>,
+}
+
+impl Setup {
+    fn new(builder: &MdnsClientBuilder) -> Setup {
+        Setup {
+            family: builder.family,
+            devices: builder.devices.clone(),
+            indices: builder.indices.clone(),
+            receive_buffer: builder.receive_buffer,
+            bind_group: builder.bind_to_group,
+            addresses: builder.addresses.clone(),
+            all_interfaces: builder.all_interfaces,
+            legacy_unicast_fallback: builder.legacy_unicast_fallback,
+            factory: builder.socket_factory.clone(),
+            rejoin_interval: Some(
+                builder
+                    .group_rejoin_interval
+                    .unwrap_or(GROUP_REJOIN_INTERVAL),
+            )
+            .filter(|interval| !interval.is_zero()),
+        }
+    }
+
+    fn options<'a>(&self, interface: &'a Interface) -> socket::Options<'a> {
+        socket::Options {
+            // Each socket is bound to its own interface's device.
+            device: (!self.devices.is_empty()).then(|| device(&interface.name)),
+            receive_buffer: self.receive_buffer,
+            bind_group: self.bind_group,
+        }
+    }
+
+    fn includes(&self, name: &str, index: u32) -> bool {
+        (self.devices.is_empty() || self.devices.iter().any(|d| d == device(name)))
+            && (self.indices.is_empty() || self.indices.contains(&index))
+    }
+}
+
+// Aliases such as "eth0:1" belong to their device.
+fn device(name: &str) -> &str {
+    name.split(':').next().unwrap_or(name)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Interface {
+    name: String,
+    // What IPv6 sockets are tied to the interface by.
+    index: u32,
+    // Unspecified for IPv6 sockets, which aren't tied to an address.
+    ip: IpAddr,
+    netmask: Ipv4Addr,
+}
+
+impl Interface {
+    // One IPv4 socket bound to the wildcard address, joined on the interface the OS picks.
+    fn default_v4() -> Self {
+        Interface {
+            name: "default".to_string(),
+            index: 0,
+            ip: Ipv4Addr::UNSPECIFIED.into(),
+            netmask: Ipv4Addr::UNSPECIFIED,
+        }
+    }
+
+    fn contains(&self, address: Ipv4Addr) -> bool {
+        match self.ip {
+            IpAddr::V4(ip) if !ip.is_unspecified() => {
+                let mask = u32::from(self.netmask);
+                u32::from(address) & mask == u32::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+
+    // Names the socket in metrics.
+    fn label(&self) -> String {
+        match self.ip {
+            IpAddr::V4(ip) => ip.to_string(),
+            IpAddr::V6(_) => format!("{}%{}", MULTICAST_ADDR_V6, self.name),
+        }
+    }
+}
+
+// What the receive threads share with the worker.
+#[derive(Clone)]
+struct Shared {
+    questions: Arc<Mutex<Vec<Question>>>,
+    responder: Arc<Mutex<Responder>>,
+    recent: Arc<Mutex<Recent>>,
+    sources: Arc<Mutex<Sources>>,
+    filter: Arc<SourceFilter>,
+    capture: Option<Arc<Capture>>,
+    on_packet: Option<PacketHook>,
+    traffic: Arc<Traffic>,
+    fallback: Arc<Mutex<Option<String>>>,
+}
+
+// Datagrams from other hosts, for the silence watchdog: the group is looped back, so the
+// client's own queries keep arriving even where a firewall drops everything else.
+#[derive(Default)]
+struct Traffic {
+    // The addresses of the interfaces with sockets.
+    local: Mutex<Vec<IpAddr>>,
+    foreign: AtomicU64,
+}
+
+impl Traffic {
+    fn set_local(&self, interfaces: &[Interface]) {
+        *self.local.locked() = interfaces.iter().map(|i| i.ip).collect();
+    }
+}
+
+// Errors since the last successful send or receive, and when the first of them was.
+struct Health {
+    errors: u32,
+    failing_since: Option<Instant>,
+    // The wait after the next attempt to recreate the socket, which grows while
+    // recreating doesn't help.
+    backoff: Duration,
+    // Sends failed in a row, and when the socket may send again after them.
+    send_failures: u32,
+    send_after: Option<Instant>,
+}
+
+impl Health {
+    fn succeeded(&mut self) {
+        self.errors = 0;
+        self.failing_since = None;
+        self.backoff = RECREATE_BACKOFF;
+    }
+
+    fn failed(&mut self) {
+        self.errors += 1;
+        self.failing_since.get_or_insert_with(Instant::now);
+    }
+}
+
+// When the worker last ran by both clocks. Whether the monotonic one runs on during a
+// suspend depends on the platform, while the wall clock always does, or jumps.
+struct Clock {
+    at: Instant,
+    wall: SystemTime,
+}
+
+impl Clock {
+    fn new() -> Self {
+        Clock {
+            at: Instant::now(),
+            wall: SystemTime::now(),
+        }
+    }
+
+    // How long it has been since the last call by whichever clock says longer. A wall
+    // clock set back counts as no time.
+    fn gap(&mut self, now: Instant, wall: SystemTime) -> Duration {
+        let gap = now
+            .duration_since(self.at)
+            .max(wall.duration_since(self.wall).unwrap_or_default());
+
+        self.at = now;
+        self.wall = wall;
+        gap
+    }
+}
+
+// A socket and the thread receiving on it, which stops when the link is dropped.
+struct Link {
+    interface: Interface,
+    socket: Arc<UdpSocket>,
+    // Queries from an ephemeral port, see `socket::unicast`.
+    legacy_unicast: bool,
+    health: Arc<Mutex<Health>>,
+    // When the socket may be recreated again.
+    retry_at: Option<Instant>,
+    stop: Arc<AtomicBool>,
+    receiver: Option<JoinHandle<()>>,
+}
+
+impl Link {
+    fn new(interface: Interface, socket: UdpSocket, shared: &Shared) -> Self {
+        let legacy_unicast = socket::is_unicast(&socket);
+        let socket = Arc::new(socket);
+        let health = Arc::new(Mutex::new(Health {
+            errors: 0,
+            failing_since: None,
+            backoff: RECREATE_BACKOFF,
+            send_failures: 0,
+            send_after: None,
+        }));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let receiver = thread::spawn({
+            let receiving = Receiving {
+                socket: socket.clone(),
+                group: group(&interface),
+                interface: interface.clone(),
+                health: health.clone(),
+                stop: stop.clone(),
+            };
+            let interface = interface.clone();
+            let shared = shared.clone();
+
+            move || {
+                catch_panic(
+                    || receiving.run(&shared),
+                    |message| report(&shared, FailedOperation::Panic, Some(&interface), &message),
+                )
+            }
+        });
+
+        Link {
+            interface,
+            socket,
+            legacy_unicast,
+            health,
+            retry_at: None,
+            stop,
+            receiver: Some(receiver),
+        }
+    }
+
+    fn may_send(&self, now: Instant) -> bool {
+        self.health.locked().send_after.is_none_or(|at| now >= at)
+    }
+
+    // Returns whether sends had been failing.
+    fn sent(&self) -> bool {
+        let mut health = self.health.locked();
+        health.succeeded();
+        health.send_after = None;
+        std::mem::take(&mut health.send_failures) > 0
+    }
+
+    fn send_failed(&self, now: Instant) {
+        let mut health = self.health.locked();
+        health.failed();
+        health.send_failures += 1;
+
+        let pause = SEND_BACKOFF * 2u32.pow((health.send_failures - 1).min(4));
+        health.send_after = Some(now + pause.min(SEND_BACKOFF_MAX));
+    }
+
+    fn send_failures(&self) -> u32 {
+        self.health.locked().send_failures
+    }
+
+    fn status(&self) -> LinkStatus {
+        let health = self.health.locked();
+
+        LinkStatus {
+            interface: self.interface.clone(),
+            legacy_unicast: self.legacy_unicast,
+            failing_since: health.failing_since,
+            send_failures: health.send_failures,
+            send_after: health.send_after,
+        }
+    }
+
+    // Also once the receive thread has panicked: it only finishes on its own that way.
+    fn is_broken(&self, now: Instant) -> bool {
+        let health = self.health.locked();
+
+        let failing = health.errors >= BROKEN_AFTER_ERRORS
+            && health
+                .failing_since
+                .is_some_and(|since| now.duration_since(since) >= BROKEN_AFTER);
+
+        failing || self.receiver.as_ref().is_some_and(JoinHandle::is_finished)
+    }
+
+    fn group(&self) -> SocketAddr {
+        group(&self.interface)
+    }
+
+    fn leave(&self) -> io::Result<()> {
+        if self.legacy_unicast {
+            return Ok(());
+        }
+
+        socket::leave(&self.socket, self.interface.ip, self.interface.index)
+    }
+
+    // Leaves the group and joins it again, so the join sends a fresh membership report:
+    // joining while still a member sends none. A failed leave is only logged, as the join
+    // may still work.
+    fn rejoin(&self) -> io::Result<()> {
+        if self.legacy_unicast {
+            return Ok(());
+        }
+
+        if let Err(_err) = self.leave() {
+            debug!(interface = %self.interface.name, error = %_err, "failed to leave multicast group");
+        }
+        socket::join(&self.socket, self.interface.ip, self.interface.index)
+    }
+
+    // Wakes the receive thread, or lets it finish its current wait, while others are
+    // stopped too; the thread is joined on drop where it could be woken.
+    fn stop_receiving(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+        socket::interrupt(&self.socket);
+    }
+}
+
+impl Drop for Link {
+    fn drop(&mut self) {
+        self.stop_receiving();
+
+        // Elsewhere the thread only notices within `RECEIVE_TIMEOUT`, and is left to finish
+        // on its own rather than holding up dropping the client; it applies nothing more.
+        if socket::INTERRUPTIBLE {
+            self.receiver.take().map(JoinHandle::join);
+        }
+    }
+}
+
+fn group(interface: &Interface) -> SocketAddr {
+    match interface.ip {
+        IpAddr::V4(_) => SocketAddrV4::new(MULTICAST_ADDR, MULTICAST_PORT).into(),
+        IpAddr::V6(_) => {
+            SocketAddrV6::new(MULTICAST_ADDR_V6, MULTICAST_PORT, 0, interface.index).into()
+        }
+    }
+}
+
+// A link's receive thread, applying datagrams as they arrive rather than on the worker's
+// schedule.
+struct Receiving {
+    socket: Arc<UdpSocket>,
+    group: SocketAddr,
+    interface: Interface,
+    health: Arc<Mutex<Health>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Receiving {
+    fn run(self, shared: &Shared) {
+        let label = self.interface.label();
+        let mut batch = Batch::new();
+        let mut updates = Updates::default();
+
+        // Also for the sockets of `MdnsClientBuilder::socket_factory`; without, datagrams
+        // count as heard when read.
+        if let Err(_err) = socket::receive_timestamps(&self.socket) {
+            debug!(interface = %self.interface.name, error = %_err, "no receive timestamps");
+        }
+
+        while !self.stop.load(Ordering::Relaxed) {
+            match batch.receive(&self.socket) {
+                // What an interrupted receive returns.
+                Ok(_) if self.stop.load(Ordering::Relaxed) => (),
+                Ok(_) => {
+                    self.health.locked().succeeded();
+                    self.apply(shared, &batch, &mut updates);
+                }
+                Err(err) => match ReceiveError::of(&err) {
+                    ReceiveError::Interrupted | ReceiveError::TimedOut => (),
+                    ReceiveError::Failed => {
+                        warn!(interface = %self.interface.name, error = %err, "receive failed");
+                        self.health.locked().failed();
+
+                        for question in shared.questions.locked().iter() {
+                            question.state.metrics.socket_error_on(&label);
+                            question.state.metrics.error(
+                                FailedOperation::Receive,
+                                Some(label.clone()),
+                                &err,
+                            );
+                        }
+
+                        // Errors such as ENETDOWN come back at once; don't spin on them.
+                        thread::sleep(RECEIVE_TIMEOUT);
+                    }
+                },
+            }
+        }
+    }
+
+    // Answers the queries among the datagrams, then applies the responses to each
+    // question's database in one go, gathered into `updates` before it is locked.
+    fn apply(&self, shared: &Shared, batch: &Batch, updates: &mut Updates) {
+        let socket = &*self.socket;
+        let capture = shared.capture.as_deref();
+        let label = self.interface.label();
+
+        // `None` for datagrams that can't be used.
+        let mut packets = Vec::with_capacity(batch.len());
+        let mut limited = 0;
+        let mut rejected = 0;
+        let now = Instant::now();
+
+        let answer = |packet: &Packet, from: SocketAddr| {
+            if !packet.header.query {
+                return;
+            }
+
+            let answer = shared.responder.locked().answer(packet, from, self.group);
+
+            if let Some((answer, to)) = answer {
+                match socket.send_to(&answer, to) {
+                    Ok(_) => {
+                        sent(shared, &self.interface);
+                        if let Some(capture) = capture {
+                            capture.sent(socket, to, &answer);
+                        }
+                        if let Some(hook) = &shared.on_packet {
+                            hook(&answer, to, Direction::Sent);
+                        }
+                    }
+                    Err(err) => {
+                        warn!(%to, error = %err, "answer failed");
+                        report(shared, FailedOperation::Answer, Some(&self.interface), &err);
+                    }
+                }
+            }
+        };
+
+        let foreign = {
+            let local = shared.traffic.local.locked();
+            batch
+                .datagrams()
+                .filter(|datagram| !local.contains(&datagram.from.ip()))
+                .count()
+        };
+        shared
+            .traffic
+            .foreign
+            .fetch_add(foreign as u64, Ordering::Relaxed);
+
+        for datagram in batch.datagrams() {
+            let from = datagram.from;
+
+            if !shared.filter.accepts(from.ip()) {
+                rejected += 1;
+                continue;
+            }
+            if !shared.sources.locked().allow(from.ip(), now) {
+                limited += 1;
+                continue;
+            }
+
+            if let Some(capture) = capture {
+                capture.received(socket, from, datagram.data);
+            }
+            if let Some(hook) = &shared.on_packet {
+                hook(datagram.data, from, Direction::Received);
+            }
+
+            let packet = if datagram.truncated {
+                warn!(%from, "dropped datagram longer than {} bytes", socket::MAX_DATAGRAM);
+                None
+            } else {
+                match Packet::parse(datagram.data) {
+                    Ok(packet) => {
+                        if packet.skipped > 0 {
+                            debug!(%from, skipped = packet.skipped, "skipped records that failed to parse");
+                        }
+                        Some(packet)
+                    }
+                    Err(_err) => {
+                        warn!(%from, error = %_err, "failed to parse packet");
+                        None
+                    }
+                }
+            };
+
+            if let Some(packet) = &packet {
+                answer(packet, from);
+            }
+
+            packets.push((datagram, packet));
+        }
+
+        let questions = shared.questions.locked();
+
+        // Queries are still answered on every socket, each answer going out on its own
+        // interface.
+        let duplicates = {
+            let mut recent = shared.recent.locked();
+            let before = packets.len();
+
+            packets.retain(|(datagram, ..)| recent.insert(datagram.data, datagram.from, now));
+            before - packets.len()
+        };
+
+        for question in questions.iter() {
+            question.state.metrics.receive_batch(batch.len());
+            question.state.metrics.received_on(&label, batch.len());
+
+            question.state.metrics.duplicates(duplicates);
+            question.state.metrics.sources_rejected(rejected);
+            question.state.metrics.rate_limited(limited);
+
+            if packets.is_empty() {
+                continue;
+            }
+
+            if let Some(lint) = &question.lint {
+                for (datagram, packet) in &packets {
+                    if let Some(packet) = packet {
+                        lint.check(packet, datagram.from, now);
+                    }
+                }
+            }
+
+            // Only other hosts' questions, not the queries of the sockets themselves.
+            if let Some(observer) = &question.observer {
+                let local = shared.traffic.local.locked();
+                for (datagram, packet) in &packets {
+                    if let Some(packet) = packet {
+                        if !local.contains(&datagram.from.ip()) {
+                            observer.observe(packet, datagram.from.ip(), now);
+                        }
+                    }
+                }
+            }
+
+            if let Some(inventory) = &question.inventory {
+                for (datagram, packet) in &packets {
+                    if let Some(packet) = packet {
+                        inventory.record(packet, datagram.from, now);
+                    }
+                }
+            }
+
+            question.state.receive(
+                packets.iter().map(|(datagram, packet)| {
+                    (
+                        packet.as_ref(),
+                        datagram.from,
+                        datagram.received.unwrap_or(now),
+                    )
+                }),
+                self.interface.index,
+                updates,
+                now,
+            );
+        }
+    }
+}
+
+/// An interface the socket backend found but couldn't set up a socket for; see
+/// `MdnsClient::skipped_interfaces`.
+#[derive(Clone, Debug)]
+pub struct SkippedInterface {
+    pub name: String,
+    pub address: IpAddr,
+    pub error: String,
+}
+
+/// Returned by `MdnsClient::new` when no interface could get a socket, e.g. in airplane
+/// mode. `skipped` lists the interfaces that were tried and why they failed; it is empty
+/// if there were none to try. Once the client runs it follows interfaces coming and going,
+/// so creating it again after a network comes up is enough.
+#[derive(Clone, Debug)]
+pub struct NoUsableInterfaces {
+    pub skipped: Vec<SkippedInterface>,
+}
+
+impl fmt::Display for NoUsableInterfaces {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no usable network interfaces")?;
+
+        for (i, skipped) in self.skipped.iter().enumerate() {
+            let separator = if i == 0 { ": " } else { "; " };
+            write!(
+                f,
+                "{}{} ({}): {}",
+                separator, skipped.name, skipped.address, skipped.error
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for NoUsableInterfaces {}
+
+// A single odd adapter (a Hyper-V switch, a disconnected VPN TAP) must not keep the
+// others from working, so failures are recorded rather than returned.
+fn open_link(
+    interface: &Interface,
+    socket: io::Result<UdpSocket>,
+    shared: &Shared,
+    skipped: &mut Vec<SkippedInterface>,
+) -> Option<Link> {
+    match socket {
+        Ok(socket) => {
+            info!(interface = %interface.name, address = %interface.ip, "joined multicast group");
+
+            Some(Link::new(interface.clone(), socket, shared))
+        }
+        Err(err) => {
+            warn!(interface = %interface.name, address = %interface.ip, error = %err, "failed to join multicast group");
+
+            skipped.push(SkippedInterface {
+                name: interface.name.clone(),
+                address: interface.ip,
+                error: err.to_string(),
+            });
+            None
+        }
+    }
+}
+
+fn open_socket(interface: &Interface, setup: &Setup) -> io::Result<UdpSocket> {
+    if let Some(factory) = &setup.factory {
+        let socket = factory(&interface.name, interface.ip, interface.index)?;
+        socket.set_nonblocking(false)?;
+        #[cfg(target_os = "windows")]
+        socket::ignore_connection_reset(socket2::SockRef::from(&socket))?;
+        return receive_timeout(socket);
+    }
+
+    let options = setup.options(interface);
+
+    let socket = match interface.ip {
+        IpAddr::V4(ip) => socket::multicast(ip, interface.index, options),
+        IpAddr::V6(_) => socket::multicast_v6(interface.index, options),
+    };
+
+    let socket = match socket {
+        Err(err) if err.kind() == io::ErrorKind::AddrInUse && setup.legacy_unicast_fallback => {
+            warn!(interface = %interface.name, address = %interface.ip, error = %err, "falling back to legacy unicast queries");
+            socket::unicast(interface.ip, interface.index, options)
+        }
+        socket => socket,
+    }?;
+
+    receive_timeout(socket)
+}
+
+// Sockets being set up, each on a thread of its own, as creating one, setting its options
+// and joining the group can take a while on some adapters (Windows with many virtual
+// NICs), and one slow adapter mustn't hold up the others. What isn't ready by the
+// deadline given to `wait` is picked up by later calls.
+struct Setups {
+    tx: mpsc::Sender<(Interface, io::Result<UdpSocket>)>,
+    rx: Receiver<(Interface, io::Result<UdpSocket>)>,
+    pending: Vec<Interface>,
+}
+
+impl Setups {
+    fn new() -> Setups {
+        let (tx, rx) = mpsc::channel();
+
+        Setups {
+            tx,
+            rx,
+            pending: Vec::new(),
+        }
+    }
+
+    // Interfaces already being set up aren't started again.
+    fn open<'a>(&mut self, interfaces: impl IntoIterator<Item = &'a Interface>, setup: &Setup) {
+        for interface in interfaces {
+            if self.pending.contains(interface) {
+                continue;
+            }
+            self.pending.push(interface.clone());
+
+            let tx = self.tx.clone();
+            let interface = interface.clone();
+            let setup = setup.clone();
+            thread::spawn(move || {
+                let socket = open_socket(&interface, &setup);
+                tx.send((interface, socket)).ok();
+            });
+        }
+    }
+
+    // The setups finished by `deadline`, or as soon as all are.
+    fn wait(&mut self, deadline: Instant) -> Vec<(Interface, io::Result<UdpSocket>)> {
+        let mut finished = Vec::new();
+
+        while !self.pending.is_empty() {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let (interface, socket) = match self.rx.recv_timeout(timeout) {
+                Ok(result) => result,
+                Err(_) => break,
+            };
+
+            if let Some(i) = self.pending.iter().position(|p| *p == interface) {
+                self.pending.swap_remove(i);
+            }
+            finished.push((interface, socket));
+        }
+
+        finished
+    }
+}
+
+// Links for the setups finished by `deadline`, in the order of `known`; those for
+// interfaces that went away or already have a link meanwhile are dropped.
+fn open_links(
+    setups: &mut Setups,
+    deadline: Instant,
+    known: &[Interface],
+    links: &[Link],
+    shared: &Shared,
+    skipped: &mut Vec<SkippedInterface>,
+) -> Vec<Link> {
+    let mut finished = setups.wait(deadline);
+    finished.retain(|(interface, _)| {
+        known.contains(interface) && !links.iter().any(|link| link.interface == *interface)
+    });
+    finished.sort_by_key(|(interface, _)| known.iter().position(|k| k == interface));
+
+    finished
+        .into_iter()
+        .filter_map(|(interface, socket)| open_link(&interface, socket, shared, skipped))
+        .collect()
+}
+
+// Receive threads that can be interrupted block for as long as nothing arrives.
+fn receive_timeout(socket: UdpSocket) -> io::Result<UdpSocket> {
+    socket.set_read_timeout(if socket::INTERRUPTIBLE {
+        None
+    } else {
+        Some(RECEIVE_TIMEOUT)
+    })?;
+    Ok(socket)
+}
+
+// Keeps `err` for every question's `MdnsClient::last_error`.
+fn report(
+    shared: &Shared,
+    operation: FailedOperation,
+    interface: Option<&Interface>,
+    err: &dyn fmt::Display,
+) {
+    for question in shared.questions.locked().iter() {
+        question
+            .state
+            .metrics
+            .error(operation, interface.map(Interface::label), err);
+    }
+}
+
+// Counts a datagram sent on `interface` for every question, as the socket is theirs alike.
+fn sent(shared: &Shared, interface: &Interface) {
+    let label = interface.label();
+
+    for question in shared.questions.locked().iter() {
+        question.state.metrics.sent_on(&label);
+    }
+}
+
+// Sockets can break for good while their interface stays, e.g. across suspend and resume
+// or a renumbering the enumeration doesn't see; sends then fail with ENETUNREACH forever.
+// Broken sockets are closed and set up again, backing off while that fails. Returns the
+// indices of the links that got a new socket.
+fn recreate_broken(links: &mut [Link], setup: &Setup, shared: &Shared) -> Vec<usize> {
+    let now = Instant::now();
+    let mut recreated = Vec::new();
+
+    for (i, link) in links.iter_mut().enumerate() {
+        if !link.is_broken(now) || link.retry_at.is_some_and(|at| now < at) {
+            continue;
+        }
+
+        let backoff = link.health.locked().backoff;
+        link.leave().ok();
+
+        match open_socket(&link.interface, setup) {
+            Ok(socket) => {
+                info!(interface = %link.interface.name, address = %link.interface.ip, "recreated broken socket");
+
+                *link = Link::new(link.interface.clone(), socket, shared);
+                recreated.push(i);
+            }
+            Err(err) => {
+                warn!(interface = %link.interface.name, address = %link.interface.ip, error = %err, retry_in = ?backoff, "failed to recreate broken socket");
+                report(
+                    shared,
+                    FailedOperation::Recreate,
+                    Some(&link.interface),
+                    &err,
+                );
+            }
+        }
+
+        link.retry_at = Some(now + backoff);
+        link.health.locked().backoff = (backoff * 2).min(RECREATE_BACKOFF_MAX);
+    }
+
+    recreated
+}
+
+// Interfaces that can carry mDNS: not loopback and, where the OS tells us, up and
+// multicast capable. Unless asked for, link-local IPv4 addresses, which Windows gives
+// adapters without DHCP, and the virtual adapters reaching only local VMs are left out
+// too. There is one entry per IPv4 address, only the first of each adapter on Windows,
+// and one per interface for its IPv6 socket, for the families in use.
+fn multicast_interfaces(setup: &Setup) -> io::Result<Vec<Interface>> {
+    let family = setup.family;
+    let mut interfaces: Vec<Interface> = Vec::new();
+
+    let all = if_addrs::get_if_addrs()?;
+    let selected = |address: &Ipv4Addr| match setup.addresses.is_empty() {
+        true => setup.all_interfaces || !address.is_link_local(),
+        false => setup.addresses.contains(address),
+    };
+    let holding_selected: Vec<Option<u32>> = all
+        .iter()
+        .filter(|i| matches!(&i.addr, IfAddr::V4(addr) if setup.addresses.contains(&addr.ip)))
+        .map(|i| i.index)
+        .collect();
+
+    for i in all {
+        let index = i.index.unwrap_or(0);
+
+        if i.is_loopback() || !setup.includes(&i.name, index) {
+            continue;
+        }
+
+        let chosen = !setup.devices.is_empty() || !setup.indices.is_empty();
+        if !setup.all_interfaces
+            && (!is_multicast_up(&i.name, index) || is_virtual(&i.name) && !chosen)
+        {
+            continue;
+        }
+
+        match &i.addr {
+            IfAddr::V4(addr)
+                if family.v4() && selected(&addr.ip) && !adapter_joined(&interfaces, index) =>
+            {
+                interfaces.push(Interface {
+                    name: i.name.clone(),
+                    index,
+                    ip: addr.ip.into(),
+                    netmask: addr.netmask,
+                })
+            }
+            _ => (),
+        }
+
+        // if_addrs leaves out link-local addresses, the ones group traffic is sent from,
+        // so IPv6 is tried on every interface with any address at all, or with one of the
+        // selected IPv4 addresses.
+        if family.v6()
+            && index != 0
+            && (setup.addresses.is_empty() || holding_selected.contains(&i.index))
+            && !interfaces
+                .iter()
+                .any(|i| i.ip.is_ipv6() && i.index == index)
+        {
+            interfaces.push(Interface {
+                name: i.name,
+                index,
+                ip: Ipv6Addr::UNSPECIFIED.into(),
+                netmask: Ipv4Addr::UNSPECIFIED,
+            });
+        }
+    }
+
+    Ok(interfaces)
+}
+
+// Windows joins the group by adapter, see `socket::multicast`, so a second IPv4 address
+// of one would only fail to join again.
+#[cfg(target_os = "windows")]
+fn adapter_joined(interfaces: &[Interface], index: u32) -> bool {
+    index != 0
+        && interfaces
+            .iter()
+            .any(|i| i.ip.is_ipv4() && i.index == index)
+}
+
+#[cfg(not(target_os = "windows"))]
+fn adapter_joined(_interfaces: &[Interface], _index: u32) -> bool {
+    false
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn is_multicast_up(name: &str, _index: u32) -> bool {
+    const IFF_UP: u32 = 0x1;
+    const IFF_MULTICAST: u32 = 0x1000;
+
+    match std::fs::read_to_string(format!("/sys/class/net/{}/flags", device(name))) {
+        Ok(flags) => u32::from_str_radix(flags.trim().trim_start_matches("0x"), 16)
+            .map_or(true, |flags| {
+                flags & (IFF_UP | IFF_MULTICAST) == IFF_UP | IFF_MULTICAST
+            }),
+        Err(_) => true,
+    }
+}
+
+// Only the operational status: adapters that can't multicast are rare on Windows.
+#[cfg(target_os = "windows")]
+fn is_multicast_up(_name: &str, index: u32) -> bool {
+    use windows_sys::Win32::NetworkManagement::{
+        IpHelper::{GetIfEntry2, MIB_IF_ROW2},
+        Ndis::IfOperStatusUp,
+    };
+
+    // A plain C struct, for which all zeroes is a valid value; looked up by its index.
+    let mut row: MIB_IF_ROW2 = unsafe { std::mem::zeroed() };
+    row.InterfaceIndex = index;
+
+    match unsafe { GetIfEntry2(&mut row) } {
+        0 => row.OperStatus == IfOperStatusUp,
+        _ => true,
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "windows")))]
+fn is_multicast_up(_name: &str, _index: u32) -> bool {
+    true
+}
+
+// The NAT switches Hyper-V and WSL create, named by Windows in the user's language only
+// past the "vEthernet" prefix. Hyper-V's external switches carry the host's own network
+// and are kept.
+fn is_virtual(name: &str) -> bool {
+    cfg!(target_os = "windows")
+        && (name.starts_with("vEthernet (Default Switch") || name.starts_with("vEthernet (WSL"))
+}
+
+// The interfaces to open sockets on: the multicast interfaces, with the IPv4 ones merged
+// into a single wildcard socket where they can't each have their own.
+// Also why `wildcard_fallback` is in use, if it is.
+fn socket_interfaces(setup: &Setup) -> io::Result<(Vec<Interface>, Option<String>)> {
+    let (mut interfaces, fallback) = wildcard_fallback(
+        multicast_interfaces(setup),
+        setup,
+        cfg!(target_os = "windows"),
+    )?;
+
+    // Selected addresses keep their own sockets, since a wildcard one would reach every
+    // network rather than the chosen ones.
+    if !JOINS_PER_INTERFACE
+        && setup.addresses.is_empty()
+        && interfaces.iter().any(|i| i.ip.is_ipv4())
+    {
+        interfaces.retain(|i| i.ip.is_ipv6());
+        interfaces.insert(0, Interface::default_v4());
+    }
+
+    Ok((interfaces, fallback))
+}
+
+// Windows binds each socket to its interface's address, so where listing the interfaces
+// fails or finds no IPv4 one, as under some VPN clients, there would be no socket at all.
+// One socket bound to the wildcard address and joined on the default interface, as
+// elsewhere, still works there, if only on that interface: it is set up instead, with
+// the reason returned. Not for interfaces or sockets the builder chose.
+fn wildcard_fallback(
+    interfaces: io::Result<Vec<Interface>>,
+    setup: &Setup,
+    enabled: bool,
+) -> io::Result<(Vec<Interface>, Option<String>)> {
+    let chosen = !setup.addresses.is_empty()
+        || !setup.devices.is_empty()
+        || !setup.indices.is_empty()
+        || setup.factory.is_some();
+    if !enabled || chosen || !setup.family.v4() {
+        return interfaces.map(|interfaces| (interfaces, None));
+    }
+
+    let default = Interface::default_v4();
+    match interfaces {
+        Ok(interfaces) if interfaces.iter().any(|i| i.ip.is_ipv4()) => Ok((interfaces, None)),
+        Ok(mut interfaces) => {
+            interfaces.insert(0, default);
+            Ok((interfaces, Some("no IPv4 interface found".to_string())))
+        }
+        Err(err) => Ok((
+            vec![default],
+            Some(format!("listing the interfaces failed: {}", err)),
+        )),
+    }
+}
+
+// Keeps why `wildcard_fallback` is in use for `Diagnostics::wildcard_fallback`, warning
+// and reporting it to every question's `last_error` once it starts.
+fn set_fallback(shared: &Shared, fallback: Option<String>) {
+    let started = {
+        let mut current = shared.fallback.locked();
+        if *current == fallback {
+            return;
+        }
+        *current = fallback.clone();
+        fallback
+    };
+
+    if let Some(reason) = started {
+        warn!(%reason, "falling back to one wildcard socket on the default interface");
+        report(
+            shared,
+            FailedOperation::ListInterfaces,
+            None,
+            &fallback_error(&reason),
+        );
+    } else {
+        info!("interfaces found, leaving the wildcard fallback");
+    }
+}
+
+fn fallback_error(reason: &str) -> String {
+    format!(
+        "{}; using one wildcard socket on the default interface",
+        reason
+    )
+}
+
+fn local_addresses(setup: &Setup) -> Vec<Ipv4Addr> {
+    let setup = Setup {
+        family: AddressFamily::V4,
+        ..setup.clone()
+    };
+
+    multicast_interfaces(&setup)
+        .map(|interfaces| {
+            interfaces
+                .into_iter()
+                .filter_map(|i| match i.ip {
+                    IpAddr::V4(ip) => Some(ip),
+                    IpAddr::V6(_) => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn is_shareable(builder: &MdnsClientBuilder) -> bool {
+    #[cfg(feature = "pcap")]
+    if builder.capture.is_some() {
+        return false;
+    }
+
+    builder.family == AddressFamily::default()
+        && builder.devices.is_empty()
+        && builder.indices.is_empty()
+        && builder.receive_buffer.is_none()
+        && !builder.bind_to_group
+        && builder.addresses.is_empty()
+        && builder.source_rate_limit.is_none()
+        && builder.allowed_sources.is_empty()
+        && builder.denied_sources.is_empty()
+        && builder.group_rejoin_interval.is_none()
+        && !builder.all_interfaces
+        && !builder.legacy_unicast_fallback
+        && builder.socket_factory.is_none()
+        && builder.on_packet.is_none()
+}
+
+pub(crate) fn spawn(builder: MdnsClientBuilder) -> Result<MdnsClient, Box<dyn Error>> {
+    let max_addresses = builder.max_addresses.unwrap_or(MAX_ADDRESSES);
+    let grace = builder.expiry_grace.unwrap_or(Duration::ZERO);
+    let mut database = Database::new(
+        max_addresses,
+        grace,
+        !builder.accept_unverified_addresses,
+        builder
+            .availability_window
+            .unwrap_or(availability::AVAILABILITY_WINDOW),
+    );
+    if builder.defer_resolution {
+        database.defer_resolution();
+    }
+    if let Some(detection) = builder.rename_detection {
+        database.detect_renames(detection);
+    }
+    let database = Arc::new(Mutex::new(database));
+    let metrics = Arc::new(Metrics::new(Vec::new()));
+    let changes = Arc::new(Changes::new(metrics.clone()));
+    let scan = Arc::new(Scan::default());
+    let service = builder.service.clone();
+    let family = builder.family;
+    let lint = builder.lint_responders.then(Arc::<Lint>::default);
+    let observer = builder.observe_queries.then(Arc::<Observer>::default);
+    let inventory = builder
+        .inventory
+        .then(|| Arc::new(Inventory::new(builder.family)));
+    // Followed while its owner keeps it up to date; without one, sockets right away.
+    let attached = builder
+        .attach_shared
+        .clone()
+        .filter(|path| share::is_live(path, &service));
+
+    let schedule = Arc::new(Mutex::new(builder.query_schedule.unwrap_or_default()));
+    let bind_group = builder.bind_to_group;
+
+    let (command_tx, registration) = if !builder.set_up_in_background && attached.is_none() {
+        let daemon = if is_shareable(&builder) {
+            let mut shared = SHARED.locked();
+
+            match shared.upgrade() {
+                Some(daemon) => daemon,
+                None => {
+                    let daemon = Arc::new(start(&builder, channel())?);
+                    *shared = Arc::downgrade(&daemon);
+                    daemon
+                }
+            }
+        } else {
+            Arc::new(start(&builder, channel())?)
+        };
+
+        register(
+            &daemon, &builder, &database, &metrics, &changes, &scan, &schedule, &lint, &observer,
+            &inventory,
+        );
+
+        let registration = Registration {
+            daemon: Arc::new(Mutex::new(Some(daemon.clone()))),
+            setup: None,
+            database: database.clone(),
+            schedule,
+            bind_group,
+        };
+        (daemon.command_tx.clone(), registration)
+    } else {
+        // A daemon of its own, whose channel the client can be given before it exists.
+        let (command_tx, command_rx) = channel();
+        let slot = Arc::new(Mutex::new(None));
+
+        let setup = thread::spawn({
+            let command_tx = command_tx.clone();
+            let slot = Arc::downgrade(&slot);
+            let database = database.clone();
+            let metrics = metrics.clone();
+            let changes = changes.clone();
+            let scan = scan.clone();
+            let schedule = schedule.clone();
+            let lint = lint.clone();
+            let observer = observer.clone();
+            let inventory = inventory.clone();
+
+            move || {
+                if let Some(path) = &attached {
+                    let gone = || slot.strong_count() == 0;
+                    let stale = share::follow(
+                        path,
+                        &builder.service,
+                        &database,
+                        &metrics,
+                        &changes,
+                        &command_rx,
+                        gone,
+                    );
+                    if !stale {
+                        return;
+                    }
+                }
+
+                match start(&builder, (command_tx, command_rx)) {
+                    Ok(daemon) => {
+                        // A client dropped meanwhile takes the daemon down with this.
+                        let Some(slot) = slot.upgrade() else {
+                            return;
+                        };
+                        let mut slot = slot.locked();
+                        let daemon = Arc::new(daemon);
+
+                        register(
+                            &daemon, &builder, &database, &metrics, &changes, &scan, &schedule,
+                            &lint, &observer, &inventory,
+                        );
+                        *slot = Some(daemon);
+                    }
+                    Err(err) => {
+                        warn!(error = %err, "failed to set up sockets");
+                        metrics.error(FailedOperation::Setup, None, &err);
+                    }
+                }
+            }
+        });
+
+        let registration = Registration {
+            daemon: slot,
+            setup: Some(setup),
+            database: database.clone(),
+            schedule,
+            bind_group,
+        };
+        (command_tx, registration)
+    };
+
+    Ok(MdnsClient {
+        service,
+        database,
+        metrics,
+        changes,
+        scan,
+        command_tx,
+        thread: None,
+        registration: Some(registration),
+        family,
+        backend: Backend::Sockets,
+        matcher: None,
+        srv_only: false,
+        keep_records: false,
+        route_aware: false,
+        connect_stagger: None,
+        lint,
+        observer,
+        inventory,
+        share: None,
+        seed: None,
+        #[cfg(feature = "neighbors")]
+        neighbors: None,
+        held: None,
+    })
+}
+
+// Room for one pending on-demand query, which further requests coalesce into, next to a
+// wake-up.
+fn channel() -> (SyncSender<Command>, Receiver<Command>) {
+    sync_channel(2)
+}
+
+// Adds a client's question to `daemon`, and sends its initial query.
+#[allow(clippy::too_many_arguments)]
+fn register(
+    daemon: &Daemon,
+    builder: &MdnsClientBuilder,
+    database: &Arc<Mutex<Database>>,
+    metrics: &Arc<Metrics>,
+    changes: &Arc<Changes>,
+    scan: &Arc<Scan>,
+    schedule: &Arc<Mutex<QuerySchedule>>,
+    lint: &Option<Arc<Lint>>,
+    observer: &Option<Arc<Observer>>,
+    inventory: &Option<Arc<Inventory>>,
+) {
+    // The worker changes the interfaces with the questions locked, so the client's
+    // metrics can't miss a change.
+    let mut questions = daemon.questions.locked();
+    metrics.set_interfaces(daemon.interfaces.locked().iter().cloned());
+
+    questions.push(Question {
+        state: MdnsState::with_options(
+            builder,
+            database.clone(),
+            metrics.clone(),
+            changes.clone(),
+            scan.clone(),
+            schedule.clone(),
+            Instant::now(),
+        ),
+        suspend_threshold: builder.suspend_threshold.unwrap_or(SUSPEND_THRESHOLD),
+        silence: Mutex::default(),
+        silence_threshold: builder.silence_threshold.unwrap_or(SILENCE_THRESHOLD),
+        lint: lint.clone(),
+        observer: observer.clone(),
+        inventory: inventory.clone(),
+    });
+    drop(questions);
+
+    // Reported to the others as it started.
+    if let Some(reason) = daemon.fallback.locked().clone() {
+        metrics.error(
+            FailedOperation::ListInterfaces,
+            None,
+            &fallback_error(&reason),
+        );
+    }
+
+    // Its first query is due at once; the others' stay as they are.
+    daemon.command_tx.try_send(Command::Wake).ok();
+}
+
+// Brings the sockets in line with the interfaces as they are now, so that addresses that
+// appear after startup (DHCP finishing, a cable plugged in, a VPN coming up) get sockets
+// and vanished ones lose theirs. Returns the interfaces that went away, or `None` if
+// nothing changed. Interfaces whose socket couldn't be set up are retried whenever the
+// interfaces change; those still being set up after `SETUP_DEADLINE` are left to `adopt`.
+fn relink(
+    setup: &Setup,
+    shared: &Shared,
+    setups: &mut Setups,
+    known: &mut Vec<Interface>,
+    links: &mut Vec<Link>,
+    skipped: &mut Vec<SkippedInterface>,
+) -> Option<Vec<Interface>> {
+    let current = match socket_interfaces(setup) {
+        Ok((current, fallback)) => {
+            set_fallback(shared, fallback);
+            current
+        }
+        Err(err) => {
+            warn!(error = %err, "failed to list interfaces");
+            report(shared, FailedOperation::ListInterfaces, None, &err);
+            return None;
+        }
+    };
+
+    if current == *known {
+        return None;
+    }
+
+    let vanished: Vec<Interface> = known
+        .iter()
+        .filter(|i| !current.contains(i))
+        .cloned()
+        .collect();
+
+    for _interface in &vanished {
+        info!(interface = %_interface.name, address = %_interface.ip, "interface went away");
+    }
+
+    let (kept, gone): (Vec<Link>, Vec<Link>) = links
+        .drain(..)
+        .partition(|link| current.contains(&link.interface));
+    *links = kept;
+
+    // Usually fails, the address being gone and the membership with it.
+    for link in &gone {
+        if let Err(_err) = link.leave() {
+            debug!(interface = %link.interface.name, error = %_err, "failed to leave multicast group");
+        }
+    }
+
+    skipped.clear();
+
+    setups.open(
+        current
+            .iter()
+            .filter(|interface| !links.iter().any(|link| link.interface == **interface)),
+        setup,
+    );
+
+    *known = current;
+    shared.traffic.set_local(known);
+
+    let deadline = Instant::now() + SETUP_DEADLINE;
+    let opened = open_links(setups, deadline, known, links, shared, skipped);
+    links.extend(opened);
+    give_up_if_unlinked(setups, shared, known, links, skipped);
+
+    Some(vanished)
+}
+
+// Links the sockets whose setup finished after its deadline. Returns whether there were
+// any.
+fn adopt(
+    setups: &mut Setups,
+    shared: &Shared,
+    known: &mut Vec<Interface>,
+    links: &mut Vec<Link>,
+    skipped: &mut Vec<SkippedInterface>,
+) -> bool {
+    if setups.pending.is_empty() {
+        return false;
+    }
+
+    let opened = open_links(setups, Instant::now(), known, links, shared, skipped);
+    let adopted = !opened.is_empty();
+    links.extend(opened);
+    give_up_if_unlinked(setups, shared, known, links, skipped);
+
+    adopted
+}
+
+// With no socket at all and none on the way, as after a reset that failed, every cycle
+// tries again.
+fn give_up_if_unlinked(
+    setups: &Setups,
+    shared: &Shared,
+    known: &mut Vec<Interface>,
+    links: &[Link],
+    skipped: &[SkippedInterface],
+) {
+    if links.is_empty() && !known.is_empty() && setups.pending.is_empty() {
+        let err = NoUsableInterfaces {
+            skipped: skipped.to_vec(),
+        };
+        report(shared, FailedOperation::Setup, None, &err);
+        known.clear();
+    }
+}
+
+// The worker owns the sockets, so it leaves the groups before closing them and stopping
+// their receive threads.
+fn close(links: &[Link]) {
+    for link in links {
+        link.stop_receiving();
+
+        match link.leave() {
+            Ok(()) => {
+                debug!(interface = %link.interface.name, "left multicast group")
+            }
+            Err(_err) => {
+                warn!(interface = %link.interface.name, error = %_err, "failed to leave multicast group")
+            }
+        }
+    }
+}
+
+// After each query cycle: warns once nothing has arrived from other hosts for the
+// question's threshold, and tells when something does again.
+// Returns whether the question just became silent.
+fn check_silence(question: &Question, heard: u64) -> bool {
+    let mut silence = question.silence.locked();
+
+    if heard != silence.heard {
+        if silence.cycles >= question.silence_threshold {
+            info!(service = %question.state.service, "mDNS traffic arriving again");
+            question.state.metrics.set_silent(false);
+        }
+
+        *silence = Silence { heard, cycles: 0 };
+        return false;
+    }
+
+    silence.cycles = silence.cycles.saturating_add(1);
+
+    if silence.cycles == question.silence_threshold {
+        let message = format!(
+            "nothing received from other hosts for {} query cycles; \
+             is inbound UDP port 5353 blocked by a firewall?",
+            silence.cycles
+        );
+        warn!(service = %question.state.service, %message);
+        question.state.metrics.set_silent(true);
+        question
+            .state
+            .metrics
+            .error(FailedOperation::NoTraffic, None, &message);
+        return true;
+    }
+
+    false
+}
+
+// Refreshes every socket's group membership, see `Link::rejoin`.
+fn rejoin(links: &[Link], shared: &Shared) {
+    for (i, link) in links.iter().enumerate() {
+        match link.rejoin() {
+            Ok(()) => {
+                for question in shared.questions.locked().iter() {
+                    question.state.metrics.group_rejoined(i);
+                }
+            }
+            Err(err) => {
+                warn!(interface = %link.interface.name, error = %err, "failed to join multicast group again");
+                report(shared, FailedOperation::Rejoin, Some(&link.interface), &err);
+            }
+        }
+    }
+}
+
+// Addresses on networks we are no longer attached to can't be reached or revalidated.
+// Services that only had such addresses are dropped; the rest are rescanned as usual.
+fn forget_unreachable(
+    question: &Question,
+    vanished: &[Interface],
+    current: &[Interface],
+    now: Instant,
+) {
+    let lost = |address: &Ipv4Addr| {
+        vanished.iter().any(|i| i.contains(*address))
+            && !current.iter().any(|i| i.contains(*address))
+    };
+
+    let mut database = Database::lock(&question.state.database);
+    let before = database.len();
+
+    database.retain(ExpiryReason::InterfaceGone, now, |_, record| {
+        if record.pinned || !record.addresses.iter().any(lost) {
+            return true;
+        }
+
+        record.addresses.retain(|address| !lost(address));
+        if record.preferred_address.as_ref().is_some_and(lost) {
+            record.preferred_address = None;
+        }
+
+        !record.addresses.is_empty() || !record.ipv6_addresses.is_empty()
+    });
+
+    question.state.metrics.expired(before - database.len());
+    question.state.metrics.database_size(database.len());
+    question.state.changes.update(database, now);
+}
+
+// The binding options `builder` asks for that this platform or its other options rule
+// out.
+fn check_bindings(builder: &MdnsClientBuilder) -> Result<(), Box<dyn Error>> {
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    if !builder.devices.is_empty() {
+        return Err("binding to network devices (SO_BINDTODEVICE) needs Linux or Android".into());
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    if builder.bind_to_group {
+        return Err("binding to the multicast group needs Linux or Android".into());
+    }
+    // The answers would be sent to port 5353 of the interface address, which a socket
+    // bound to the group doesn't hear.
+    if builder.bind_to_group
+        && builder
+            .query_schedule
+            .is_some_and(|schedule| schedule.unicast_response)
+    {
+        return Err("unicast answers (QU) can't be heard with bind_to_group".into());
+    }
+    Ok(())
+}
+
+fn start(
+    builder: &MdnsClientBuilder,
+    (command_tx, command_rx): (SyncSender<Command>, Receiver<Command>),
+) -> Result<Daemon, Box<dyn Error>> {
+    check_bindings(builder)?;
+    let setup = Setup::new(builder);
+
+    if !setup.addresses.is_empty() {
+        let present = local_addresses(&setup);
+        let missing: Vec<String> = setup
+            .addresses
+            .iter()
+            .filter(|address| !present.contains(address))
+            .map(|address| address.to_string())
+            .collect();
+
+        if present.is_empty() {
+            return Err(format!(
+                "none of the interface addresses exist: {}",
+                missing.join(", ")
+            )
+            .into());
+        }
+
+        if !missing.is_empty() {
+            warn!(addresses = %missing.join(", "), "interface addresses not found");
+        }
+    }
+
+    let filter = SourceFilter::new(&builder.allowed_sources, &builder.denied_sources)?;
+
+    #[cfg(feature = "pcap")]
+    let capture = match &builder.capture {
+        Some(path) => Some(Arc::new(Capture::create(
+            path.clone(),
+            builder.capture_limit,
+        )?)),
+        None => None,
+    };
+    #[cfg(not(feature = "pcap"))]
+    let capture: Option<Arc<Capture>> = None;
+
+    let questions = Arc::new(Mutex::new(Vec::<Question>::new()));
+    let responder = Arc::new(Mutex::new(Responder::default()));
+
+    let shared = Shared {
+        questions: questions.clone(),
+        responder: responder.clone(),
+        recent: Arc::new(Mutex::new(Recent::default())),
+        sources: Arc::new(Mutex::new(Sources::new(
+            builder.source_rate_limit.unwrap_or(SOURCE_RATE_LIMIT),
+        ))),
+        filter: Arc::new(filter),
+        capture,
+        on_packet: builder.on_packet.clone(),
+        traffic: Arc::default(),
+        fallback: Arc::default(),
+    };
+
+    let (mut known, fallback) = socket_interfaces(&setup)?;
+    set_fallback(&shared, fallback);
+    let fallback = shared.fallback.clone();
+    shared.traffic.set_local(&known);
+    let mut skipped = Vec::new();
+    let mut setups = Setups::new();
+    setups.open(&known, &setup);
+
+    // Where none is ready by the deadline, the first to be is enough.
+    let deadline = Instant::now() + SETUP_DEADLINE;
+    let mut links = open_links(&mut setups, deadline, &known, &[], &shared, &mut skipped);
+    while links.is_empty() && !setups.pending.is_empty() {
+        let deadline = Instant::now() + SETUP_DEADLINE;
+        links = open_links(&mut setups, deadline, &known, &[], &shared, &mut skipped);
+    }
+
+    // Also where socket::multicast has no implementation for the platform, rather than running
+    // without sockets.
+    if links.is_empty() {
+        return Err(NoUsableInterfaces { skipped }.into());
+    }
+
+    let skipped = Arc::new(Mutex::new(skipped));
+
+    let interfaces = Arc::new(Mutex::new(
+        links
+            .iter()
+            .map(|l| l.interface.label())
+            .collect::<Vec<_>>(),
+    ));
+
+    let schedule = Arc::new(Mutex::new(Schedule::default()));
+
+    let thread = thread::spawn({
+        let questions = questions.clone();
+        let responder = responder.clone();
+        let interfaces = interfaces.clone();
+        let skipped = skipped.clone();
+        let schedule = schedule.clone();
+        let setup = setup.clone();
+
+        move || {
+            catch_panic(
+                || {
+                    let capture = shared.capture.as_deref();
+
+                    // Sends the queries of the questions due, or of all of them with `all`,
+                    // those going out together packed into as few datagrams as fit, then each
+                    // question's questions by name, if it has any.
+                    let send = |links: &[Link], all: bool| {
+                        if links.is_empty() {
+                            return;
+                        }
+
+                        let questions = questions.locked();
+                        let now = Instant::now();
+                        let mut outgoing = Outgoing::default();
+
+                        for (q, question) in questions.iter().enumerate() {
+                            question.state.query(now, all, |packet| {
+                                outgoing.query(q, &question.state.service, packet)
+                            });
+
+                            for packet in question.state.questions_by_name(now) {
+                                outgoing.alone(q, &question.state.service, packet);
+                            }
+                        }
+
+                        // Taken before sending, so even the quickest answer is timed from it.
+                        let started = Instant::now();
+                        let mut sent = vec![false; questions.len()];
+                        // Queries sent, for the hook once the questions are unlocked.
+                        let mut sent_queries = Vec::new();
+
+                        for (i, link) in links.iter().enumerate() {
+                            for datagram in &outgoing.datagrams {
+                                let now = Instant::now();
+
+                                if !link.may_send(now) {
+                                    continue;
+                                }
+
+                                match send_mdns_query(
+                                    &link.socket,
+                                    link.group(),
+                                    &datagram.packet,
+                                    &datagram.services,
+                                    capture,
+                                ) {
+                                    Ok(()) => {
+                                        if link.sent() {
+                                            info!(interface = %link.interface.name, "sending works again");
+                                        }
+                                        if shared.on_packet.is_some() {
+                                            sent_queries
+                                                .push((datagram.packet.clone(), link.group()));
+                                        }
+                                        let label = link.interface.label();
+                                        for question in questions.iter() {
+                                            question.state.metrics.sent_on(&label);
+                                        }
+                                        for &q in &datagram.questions {
+                                            questions[q].state.metrics.query_sent(i);
+                                            sent[q] = true;
+                                        }
+                                    }
+                                    Err(err) => {
+                                        warn!(interface = %link.interface.name, error = %err, "send failed");
+                                        link.send_failed(now);
+
+                                        for &q in &datagram.questions {
+                                            let question = &questions[q];
+                                            question.state.metrics.socket_error(i);
+                                            question.state.metrics.error(
+                                                FailedOperation::Query,
+                                                Some(link.interface.label()),
+                                                &err,
+                                            );
+                                        }
+                                    }
+                                }
+                            }
+                        }
+
+                        for (question, sent) in questions.iter().zip(sent) {
+                            if sent {
+                                question.state.scan.queried(started);
+                            }
+                        }
+                        drop(questions);
+
+                        if let Some(hook) = &shared.on_packet {
+                            for (packet, group) in sent_queries {
+                                hook(&packet, group, Direction::Sent);
+                            }
+                        }
+                    };
+
+                    let multicast = |links: &[Link], packets: Vec<Vec<u8>>| {
+                        for packet in packets {
+                            // Responses from any port but 5353 are ignored.
+                            for link in links.iter().filter(|l| !l.legacy_unicast) {
+                                let now = Instant::now();
+                                let group = link.group();
+
+                                if !link.may_send(now) {
+                                    continue;
+                                }
+
+                                match link.socket.send_to(&packet, group) {
+                                    Ok(_) => {
+                                        if link.sent() {
+                                            info!(interface = %link.interface.name, "sending works again");
+                                        }
+                                        sent(&shared, &link.interface);
+
+                                        if let Some(capture) = capture {
+                                            capture.sent(&link.socket, group, &packet);
+                                        }
+                                        if let Some(hook) = &shared.on_packet {
+                                            hook(&packet, group, Direction::Sent);
+                                        }
+                                    }
+                                    Err(err) => {
+                                        warn!(interface = %link.interface.name, error = %err, "announcement failed");
+                                        link.send_failed(now);
+                                        report(
+                                            &shared,
+                                            FailedOperation::Announce,
+                                            Some(&link.interface),
+                                            &err,
+                                        );
+                                    }
+                                }
+                            }
+                        }
+                    };
+
+                    // Everything expiring at once usually means the network went away, as
+                    // when a switch reboots; once it is back, sockets pausing after failed
+                    // sends shouldn't hold up finding the services again. Counted from the
+                    // start, when the first query has only just gone out.
+                    let mut recovered_at = Instant::now();
+                    let mut recover = |links: &[Link]| {
+                        let now = Instant::now();
+                        if now < recovered_at + RECOVERY_INTERVAL {
+                            return;
+                        }
+
+                        let _span = span!("recovery query");
+                        recovered_at = now;
+
+                        for link in links {
+                            link.health.locked().send_after = None;
+                        }
+                        send(links, true);
+                    };
+
+                    let mut next_refresh = Instant::now() + REFRESH_INTERVAL;
+                    // None when too far off to be an `Instant`, and never reached.
+                    let mut next_rejoin = setup
+                        .rejoin_interval
+                        .and_then(|i| Instant::now().checked_add(i));
+                    let mut clock = Clock::new();
+
+                    loop {
+                        let queries: Vec<Instant> = match links.is_empty() {
+                            true => Vec::new(),
+                            false => questions
+                                .locked()
+                                .iter()
+                                .map(|question| question.state.timer.locked().wake(next_refresh))
+                                .collect(),
+                        };
+                        let wake = questions
+                            .locked()
+                            .iter()
+                            .filter_map(|question| question.state.next_expiry())
+                            .chain(responder.locked().next_due())
+                            .chain(queries)
+                            .fold(next_refresh, Instant::min);
+
+                        *schedule.locked() = Schedule {
+                            next_refresh: Some(next_refresh),
+                            links: links.iter().map(Link::status).collect(),
+                        };
+
+                        let command =
+                            command_rx.recv_timeout(wake.saturating_duration_since(Instant::now()));
+
+                        // Checked before anything expires. The refresh, right away, also
+                        // picks up interfaces changed meanwhile.
+                        let now = Instant::now();
+                        let gap = clock.gap(now, SystemTime::now());
+                        let mut suspended = false;
+
+                        for question in questions.locked().iter() {
+                            if gap >= question.suspend_threshold {
+                                Database::lock(&question.state.database).restart_expiry(now);
+                                suspended = true;
+                            }
+                        }
+                        if suspended {
+                            restart_queries(&questions.locked(), now);
+                        }
+
+                        if suspended {
+                            info!(?gap, "worker didn't run for a while, revalidating");
+                            next_refresh = now;
+                        }
+
+                        match command {
+                            Ok(Command::Exit) | Err(RecvTimeoutError::Disconnected) => break,
+                            Ok(Command::Query) => {
+                                let _span = span!("on-demand query");
+
+                                send(&links, true);
+                            }
+                            Ok(Command::Recover) => recover(&links),
+                            // Whatever became due meanwhile, such as a question reported
+                            // unreachable to ask after.
+                            Ok(Command::Wake) => send(&links, false),
+                            Ok(Command::Reset) => {
+                                let _span = span!("reset");
+                                info!(
+                                    sockets = links.len(),
+                                    "closing sockets to set them up again"
+                                );
+
+                                // The cycle right after sets them up as if every interface
+                                // were new, and queries.
+                                close(&links);
+                                links.clear();
+                                known.clear();
+                                restart_queries(&questions.locked(), Instant::now());
+                                *shared.recent.locked() = Recent::default();
+                                next_refresh = Instant::now();
+                            }
+                            Err(RecvTimeoutError::Timeout) => {
+                                let mut emptied = false;
+
+                                if Instant::now() >= next_refresh {
+                                    let _span = span!("cycle");
+
+                                    // New and recreated sockets take part in the query that
+                                    // follows, which also revalidates everything learned so far.
+                                    let relinked = relink(
+                                        &setup,
+                                        &shared,
+                                        &mut setups,
+                                        &mut known,
+                                        &mut links,
+                                        &mut skipped.locked(),
+                                    );
+
+                                    if let Some(vanished) = relinked {
+                                        if !links.is_empty() {
+                                            info!(
+                                                interfaces = links.len(),
+                                                "interfaces changed, rescanning"
+                                            );
+                                        }
+
+                                        let questions = questions.locked();
+                                        let names: Vec<String> =
+                                            links.iter().map(|l| l.interface.label()).collect();
+                                        *interfaces.locked() = names.clone();
+                                        restart_queries(&questions, Instant::now());
+
+                                        for question in questions.iter() {
+                                            question
+                                                .state
+                                                .metrics
+                                                .interfaces_changed(names.iter().cloned());
+                                            forget_unreachable(
+                                                question,
+                                                &vanished,
+                                                &known,
+                                                Instant::now(),
+                                            );
+                                        }
+                                    } else if adopt(
+                                        &mut setups,
+                                        &shared,
+                                        &mut known,
+                                        &mut links,
+                                        &mut skipped.locked(),
+                                    ) {
+                                        info!(
+                                            sockets = links.len(),
+                                            "late sockets set up, querying"
+                                        );
+
+                                        // The same interfaces, only with more sockets.
+                                        let questions = questions.locked();
+                                        let names: Vec<String> =
+                                            links.iter().map(|l| l.interface.label()).collect();
+                                        *interfaces.locked() = names.clone();
+                                        restart_queries(&questions, Instant::now());
+
+                                        for question in questions.iter() {
+                                            question
+                                                .state
+                                                .metrics
+                                                .set_interfaces(names.iter().cloned());
+                                        }
+                                    }
+
+                                    for i in recreate_broken(&mut links, &setup, &shared) {
+                                        for question in questions.locked().iter() {
+                                            question.state.metrics.socket_recreated(i);
+                                        }
+                                    }
+
+                                    send(&links, false);
+
+                                    let mut silent = false;
+                                    if !links.is_empty() {
+                                        let heard = shared.traffic.foreign.load(Ordering::Relaxed);
+
+                                        for question in questions.locked().iter() {
+                                            silent |= check_silence(question, heard);
+                                        }
+                                    }
+
+                                    // Also when the network went quiet, in case a switch
+                                    // forgot the membership: joining again may bring the
+                                    // traffic back.
+                                    let now = Instant::now();
+                                    if setup.rejoin_interval.is_some()
+                                        && (silent || next_rejoin.is_some_and(|at| now >= at))
+                                    {
+                                        let _span = span!("rejoin");
+                                        debug!(
+                                            sockets = links.len(),
+                                            silent, "joining multicast group again"
+                                        );
+                                        rejoin(&links, &shared);
+                                        next_rejoin =
+                                            setup.rejoin_interval.and_then(|i| now.checked_add(i));
+                                    }
+
+                                    let sockets: Vec<&UdpSocket> =
+                                        links.iter().map(|l| &*l.socket).collect();
+                                    let drops = socket::receive_drops(&sockets);
+
+                                    for question in questions.locked().iter() {
+                                        for (i, link) in links.iter().enumerate() {
+                                            question.state.metrics.receive_state(
+                                                i,
+                                                socket::receive_buffer(&link.socket),
+                                                drops[i],
+                                            );
+                                            question
+                                                .state
+                                                .metrics
+                                                .send_failures(i, link.send_failures());
+                                            question
+                                                .state
+                                                .metrics
+                                                .legacy_unicast(i, link.legacy_unicast);
+                                        }
+
+                                        emptied |= question.state.expire(Instant::now());
+                                    }
+
+                                    let mut responder = responder.locked();
+                                    if !responder.is_empty() {
+                                        responder.set_addresses(local_addresses(&setup));
+                                    }
+
+                                    next_refresh = Instant::now() + REFRESH_INTERVAL;
+                                } else {
+                                    let now = Instant::now();
+
+                                    for question in questions.locked().iter() {
+                                        if question.state.next_expiry().is_some_and(|at| at <= now)
+                                        {
+                                            emptied |= question.state.expire(now);
+                                        }
+                                    }
+
+                                    send(&links, false);
+                                }
+
+                                if emptied {
+                                    recover(&links);
+                                }
+                            }
+                        }
+
+                        // Taken out first: a failed send locks the questions to report it.
+                        let due = responder.locked().due(Instant::now());
+                        multicast(&links, due);
+                    }
+
+                    let goodbyes = responder.locked().goodbyes();
+                    multicast(&links, goodbyes);
+
+                    // `Daemon::drop` waits for this.
+                    close(&links);
+
+                    // Receive threads that aren't waited for keep the capture a little longer.
+                    if let Some(capture) = capture {
+                        capture.flush();
+                    }
+                },
+                |message| report(&shared, FailedOperation::Panic, None, &message),
+            );
+        }
+    });
+
+    Ok(Daemon {
+        questions,
+        responder,
+        setup,
+        interfaces,
+        skipped,
+        fallback,
+        schedule,
+        command_tx,
+        thread: Some(thread),
+    })
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+
+    // Every combination of SO_BINDTODEVICE, bind_to_group and unicast answers: what is
+    // refused, and what the sockets are set up with otherwise.
+    #[test]
+    fn binding_options_combined() {
+        let interface = Interface {
+            name: "eth0:1".into(),
+            index: 2,
+            ip: Ipv4Addr::new(192, 0, 2, 1).into(),
+            netmask: Ipv4Addr::new(255, 255, 255, 0),
+        };
+        let devices_supported = cfg!(any(target_os = "linux", target_os = "android"));
+
+        for devices in [false, true] {
+            for group in [false, true] {
+                for unicast_response in [false, true] {
+                    let case = (devices, group, unicast_response);
+                    let mut builder = MdnsClient::builder("_http._tcp.local");
+                    if devices {
+                        builder = builder.bind_to_devices(["eth0"]);
+                    }
+                    if group {
+                        builder = builder.bind_to_group();
+                    }
+                    let schedule = QuerySchedule {
+                        unicast_response,
+                        ..QuerySchedule::default()
+                    };
+                    builder = builder.query_schedule(schedule);
+
+                    let supported = devices_supported || !devices && !group;
+                    let allowed = supported && !(group && unicast_response);
+                    assert_eq!(check_bindings(&builder).is_ok(), allowed, "{:?}", case);
+                    assert_eq!(is_shareable(&builder), !devices && !group, "{:?}", case);
+
+                    let options = Setup::new(&builder).options(&interface);
+                    assert_eq!(options.device, devices.then_some("eth0"), "{:?}", case);
+                    assert_eq!(options.bind_group, group, "{:?}", case);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,1689 @@
+use crate::{
+    availability::{History, AVAILABILITY_WINDOW},
+    packet::{TYPE_A, TYPE_AAAA, TYPE_TXT},
+    ExpiryReason, RenameDetection, Service, ServiceRecord,
+};
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    collections::{binary_heap::PeekMut, BinaryHeap, HashMap, HashSet, VecDeque},
+    net::IpAddr,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+// Services not seen for this long are removed.
+pub(crate) const EXPIRE_AFTER: Duration = Duration::from_secs(5);
+
+// How far the deadlines, with those left behind by removed services, may outnumber twice
+// the services before they are rebuilt.
+const STALE_DEADLINES: usize = 64;
+
+// The addresses a host's services keep by default; see `MdnsClientBuilder::max_addresses`.
+pub(crate) const MAX_ADDRESSES: usize = 32;
+
+// The responders a host's services were heard from, for `admits_address`.
+const MAX_RESPONDERS: usize = 4;
+
+// A service reported unreachable goes if it doesn't answer within `SUSPECT_WINDOW` of
+// the report, and further reports on it are ignored for `REPORT_INTERVAL`.
+const SUSPECT_WINDOW: Duration = Duration::from_secs(2);
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+// An instance only heard of in a PTR answer is asked after by name once it has gone this
+// long without an SRV answer, up to `FOLLOW_UPS` times `FOLLOW_UP_INTERVAL` apart, and
+// forgotten once not pointed at for `EXPIRE_AFTER`. At most `MAX_POINTERS` are kept.
+const FOLLOW_UP_DELAY: Duration = Duration::from_secs(1);
+const FOLLOW_UP_INTERVAL: Duration = Duration::from_secs(2);
+const FOLLOW_UPS: u32 = 3;
+const MAX_POINTERS: usize = 256;
+
+// Claims older than this are superseded by a cache-flush record from another responder
+// (RFC 6762, section 10.2); younger ones may be answers to the same query.
+const FLUSH_AFTER: Duration = Duration::from_secs(1);
+
+// The services gone, and not tombstones, whose availability is kept for when they come
+// back; once full, those gone for longer than the window are dropped first.
+const MAX_DEPARTED: usize = 1024;
+
+// The services found, and which of them each host name offers, so an address record
+// finds its services without a scan. Reads go to the map itself; every change goes
+// through here, keeping the index in step.
+pub(crate) struct Database {
+    services: HashMap<Service, ServiceRecord>,
+    // Every service in `services` once, under its host, the key sharing the name.
+    hosts: HashMap<Arc<str>, Host>,
+    // Every service in `services` at least once, earliest expiry first. Refreshing
+    // `last_seen_time` leaves its deadline as it was, so an entry may be early but never
+    // late; `expire` queues it again when it comes due. Removing a service leaves its
+    // entries, which are skipped, until `drop_stale_deadlines` finds too many.
+    deadlines: BinaryHeap<Deadline>,
+    // Services added, removed or handed out for changing since `drain_changed`, so
+    // `Changes` compares only those with what it last published.
+    changed: HashSet<Service>,
+    // Where each instance, by lowercase name, was last said to be, by each responder in
+    // the order they first did. Claims on services that have gone are pruned as services
+    // are removed.
+    instances: HashMap<Box<str>, Vec<Claim>>,
+    // Services that expired less than `grace` ago. `Changes` takes them to still be there,
+    // so one that comes back in time leads to no events, and one that doesn't to
+    // `Expired` once it is dropped from here.
+    tombstones: HashMap<Service, ServiceRecord>,
+    // Services asked after by `reconfirm`, with when they had last been heard then.
+    reconfirmed: HashMap<Service, Instant>,
+    // Services reported unreachable within `REPORT_INTERVAL`.
+    suspects: HashMap<Service, Suspect>,
+    // Instances, by lowercase name, that PTR answers pointed at but no SRV answer placed.
+    pointers: HashMap<Box<str>, Pointer>,
+    // Why services removed since `take_removals` went, unless it was by expiring.
+    removals: HashMap<Service, ExpiryReason>,
+    // Where the services removed as `ExpiryReason::Moved` since `take_moves` went to.
+    moves: HashMap<Service, Service>,
+    // Services `MdnsClient::pin` exempts from expiring, known yet or not.
+    pinned: HashSet<Service>,
+    // The histories for `ServiceRecord::availability` of services gone, neither listed
+    // nor tombstones. Reset with the rest.
+    departed: HashMap<Service, History>,
+    // With `MdnsClientBuilder::defer_resolution`, the services `MdnsClient::track` marked,
+    // known yet or not; `None` resolves every one.
+    tracked: Option<HashSet<Service>>,
+    // With `MdnsClientBuilder::detect_renames`, how; the instances that may have been
+    // renamed, waiting for the old one to stay quiet; and the services whose instance
+    // was renamed since `take_renames`, from and to.
+    rename_detection: Option<RenameDetection>,
+    rename_candidates: Vec<Rename>,
+    renames: HashMap<Service, (Box<str>, Box<str>)>,
+    availability_window: Duration,
+    grace: Duration,
+    max_addresses: usize,
+    // Whether `admits_address` checks anything.
+    verify_addresses: bool,
+}
+
+#[derive(Default)]
+struct Host {
+    services: Vec<Service>,
+    // The addresses `host_address` was told of, first heard first, at most
+    // `max_addresses`. May hold some its services have lost since.
+    addresses: VecDeque<IpAddr>,
+    // Where the SRV records naming the host came from, the latest last.
+    responders: Vec<IpAddr>,
+}
+
+// How many databases this thread has locked, in debug builds.
+#[cfg(debug_assertions)]
+thread_local!(static HELD: std::cell::Cell<usize> = const { std::cell::Cell::new(0) });
+
+// Whether this thread has a database locked; always false in release builds. Events are
+// only sent with none locked: a subscriber woken by one may call straight back into the
+// client, and must not find the worker holding its database.
+pub(crate) fn held() -> bool {
+    #[cfg(debug_assertions)]
+    return HELD.with(|held| held.get()) > 0;
+    #[cfg(not(debug_assertions))]
+    false
+}
+
+pub(crate) struct Guard<'a>(MutexGuard<'a, Database>);
+
+impl Deref for Guard<'_> {
+    type Target = Database;
+
+    fn deref(&self) -> &Database {
+        &self.0
+    }
+}
+
+impl DerefMut for Guard<'_> {
+    fn deref_mut(&mut self) -> &mut Database {
+        &mut self.0
+    }
+}
+
+#[cfg(any(debug_assertions, fuzzing))]
+impl Drop for Guard<'_> {
+    fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        HELD.with(|held| held.set(held.get() - 1));
+        #[cfg(fuzzing)]
+        self.0.check();
+    }
+}
+
+// The address is that of the responder, where the backend knows it; without one all
+// claims on an instance are taken to be the same responder's.
+struct Claim {
+    service: Service,
+    from: Option<IpAddr>,
+    heard: Instant,
+    // When it last said goodbye, unless heard since.
+    goodbye: Option<Instant>,
+}
+
+// An instance new on a service that another instance of the same responder had just been
+// on, see `Database::rename_candidate`.
+struct Rename {
+    service: Service,
+    old: Box<str>,
+    new: Box<str>,
+    // The old instance's responder.
+    from: Option<IpAddr>,
+    at: Instant,
+}
+
+struct Deadline {
+    at: Instant,
+    service: Service,
+}
+
+struct Suspect {
+    reported: Instant,
+    // When the service had last been heard at the report.
+    heard: Instant,
+    // By `reconfirm`.
+    asked: bool,
+}
+
+struct Pointer {
+    first_heard: Instant,
+    last_heard: Instant,
+    asked: u32,
+    last_asked: Option<Instant>,
+}
+
+impl Suspect {
+    // When it goes, unless it has answered.
+    fn deadline(&self) -> Instant {
+        self.reported + SUSPECT_WINDOW
+    }
+}
+
+// Reversed, for the heap to put the earliest first.
+impl Ord for Deadline {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.at.cmp(&self.at)
+    }
+}
+
+impl PartialOrd for Deadline {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Deadline {
+    fn eq(&self, other: &Self) -> bool {
+        self.at == other.at
+    }
+}
+
+impl Eq for Deadline {}
+
+// Keeps the history of a service that has gone, marked gone as of `now` if not already,
+// for when it comes back.
+fn depart(
+    departed: &mut HashMap<Service, History>,
+    service: &Service,
+    mut history: History,
+    now: Instant,
+) {
+    history.gone(now);
+    if departed.len() >= MAX_DEPARTED {
+        departed.retain(|_, history| !history.is_past(now));
+    }
+    if departed.len() < MAX_DEPARTED {
+        departed.insert(service.clone(), history);
+    }
+}
+
+// Records of the kinds `Database::track` has taken: addresses and TXT records.
+fn is_resolved(record_type: u16) -> bool {
+    matches!(record_type, TYPE_A | TYPE_AAAA | TYPE_TXT)
+}
+
+// Host names compare case-insensitively, like all DNS names, and responders differ on
+// the final dot, so services are kept under one form of each. Most are in it already and
+// are used as they are.
+pub(crate) fn normalize_host(host: &str) -> Cow<'_, str> {
+    let host = host.strip_suffix('.').unwrap_or(host);
+
+    if host.bytes().any(|b| b.is_ascii_uppercase()) {
+        Cow::Owned(host.to_ascii_lowercase())
+    } else {
+        Cow::Borrowed(host)
+    }
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Database::new(MAX_ADDRESSES, Duration::ZERO, true, AVAILABILITY_WINDOW)
+    }
+}
+
+impl Database {
+    pub(crate) fn new(
+        max_addresses: usize,
+        grace: Duration,
+        verify_addresses: bool,
+        availability_window: Duration,
+    ) -> Self {
+        Database {
+            services: HashMap::new(),
+            hosts: HashMap::new(),
+            deadlines: BinaryHeap::new(),
+            changed: HashSet::new(),
+            instances: HashMap::new(),
+            tombstones: HashMap::new(),
+            reconfirmed: HashMap::new(),
+            suspects: HashMap::new(),
+            pointers: HashMap::new(),
+            removals: HashMap::new(),
+            moves: HashMap::new(),
+            pinned: HashSet::new(),
+            departed: HashMap::new(),
+            tracked: None,
+            rename_detection: None,
+            rename_candidates: Vec::new(),
+            renames: HashMap::new(),
+            availability_window,
+            grace,
+            max_addresses,
+            verify_addresses,
+        }
+    }
+
+    // Locks `database`. If a panic poisoned it, the index may be out of step with the
+    // services, so everything is dropped, as if expired, and found again from the next
+    // responses.
+    pub(crate) fn lock(database: &Mutex<Database>) -> Guard<'_> {
+        #[cfg(debug_assertions)]
+        HELD.with(|held| held.set(held.get() + 1));
+
+        Guard(match database.lock() {
+            Ok(database) => database,
+            Err(poisoned) => {
+                warn!("database lock poisoned, clearing it");
+                database.clear_poison();
+
+                let mut database = poisoned.into_inner();
+                database.clear();
+                database
+            }
+        })
+    }
+
+    // Drops every service without a grace period, each reported as changed, pinned ones
+    // too, though they stay pinned. Returns how many there were.
+    pub(crate) fn clear(&mut self) -> usize {
+        let services = std::mem::take(&mut self.services);
+        let tombstones = std::mem::take(&mut self.tombstones);
+        let pinned = std::mem::take(&mut self.pinned);
+        let tracked = self.tracked.take();
+        let rename_detection = self.rename_detection;
+        let cleared = services.len();
+
+        *self = Database::new(
+            self.max_addresses,
+            self.grace,
+            self.verify_addresses,
+            self.availability_window,
+        );
+        self.pinned = pinned;
+        self.tracked = tracked;
+        self.rename_detection = rename_detection;
+        for service in services.into_keys().chain(tombstones.into_keys()) {
+            self.removals.insert(service.clone(), ExpiryReason::Reset);
+            self.changed.insert(service);
+        }
+        cleared
+    }
+
+    // A new service's host name is normalized, and shared with the services already on
+    // that host. One back within the grace period takes up its old record again.
+    pub(crate) fn get_or_insert_with(
+        &mut self,
+        mut service: Service,
+        record: impl FnOnce() -> ServiceRecord,
+    ) -> &mut ServiceRecord {
+        if let Cow::Owned(host) = normalize_host(&service.host) {
+            service.host = host.into();
+        }
+
+        if self.services.contains_key(&service) {
+            self.changed.insert(service.clone());
+            return self.services.get_mut(&service).unwrap();
+        }
+
+        // Hosts without services are removed, so there is one to share with.
+        match self.hosts.get_mut(&service.host) {
+            Some(host) => {
+                service.host = host.services[0].host.clone();
+                host.services.push(service.clone());
+            }
+            None => {
+                let host = Host {
+                    services: vec![service.clone()],
+                    ..Host::default()
+                };
+                self.hosts.insert(service.host.clone(), host);
+            }
+        }
+
+        let mut record = match self.tombstones.remove(&service) {
+            Some(mut tombstone) => {
+                let record = record();
+                tombstone.last_seen_time = record.last_seen_time;
+                tombstone.addresses.extend(record.addresses);
+                debug!(host = %service.host, port = service.port, "service back within grace");
+                tombstone
+            }
+            None => record(),
+        };
+        record.pinned = self.pinned.contains(&service);
+        if !record.history.is_started() {
+            record.history = match self.departed.remove(&service) {
+                Some(history) => history,
+                None => History::new(record.last_seen_time, self.availability_window),
+            };
+        }
+        record.history.back(record.last_seen_time);
+
+        self.deadlines.push(Deadline {
+            at: record.last_seen_time + EXPIRE_AFTER,
+            service: service.clone(),
+        });
+        self.changed.insert(service.clone());
+        self.services.insert(service.clone(), record);
+        self.drop_stale_deadlines();
+        self.services.get_mut(&service).unwrap()
+    }
+
+    // Records that `from` says `instance` is at `service`, which must be known. The
+    // service the same responder had it at before, if any other, is removed at once rather
+    // than left to expire, unless another claim is still on it: a device restarted on a
+    // new port shouldn't be listed at the dead one for seconds. Another responder placing
+    // the instance elsewhere is a conflict instead, and its service is flagged
+    // `conflicting` for as long as the first responder's claim stands.
+    pub(crate) fn instance_at(
+        &mut self,
+        instance: &str,
+        service: &Service,
+        from: Option<IpAddr>,
+        heard: Instant,
+    ) {
+        let instance = normalize_host(instance);
+
+        if let (Some(from), Some(host)) = (from, self.hosts.get_mut(&service.host)) {
+            if host.responders.last() != Some(&from) {
+                host.responders.retain(|responder| *responder != from);
+                if host.responders.len() == MAX_RESPONDERS {
+                    host.responders.remove(0);
+                }
+                host.responders.push(from);
+            }
+        }
+
+        self.pointers.remove(instance.as_ref());
+
+        if !self.instances.contains_key(instance.as_ref()) {
+            self.rename_candidate(&instance, service, from, heard);
+        }
+
+        let Some(claims) = self.instances.get_mut(instance.as_ref()) else {
+            let claim = Claim {
+                service: service.clone(),
+                from,
+                heard,
+                goodbye: None,
+            };
+            self.instances.insert(instance.into(), vec![claim]);
+            return;
+        };
+
+        let old = match claims
+            .iter_mut()
+            .find(|claim| from.is_none() || claim.from == from)
+        {
+            Some(claim) => {
+                claim.heard = heard;
+                claim.goodbye = None;
+                if claim.service == *service {
+                    return;
+                }
+                Some(std::mem::replace(&mut claim.service, service.clone()))
+            }
+            None => {
+                warn!(%instance, host = %service.host, port = service.port, from = ?from, "instance claimed by another responder");
+                claims.push(Claim {
+                    service: service.clone(),
+                    from,
+                    heard,
+                    goodbye: None,
+                });
+                None
+            }
+        };
+
+        if let Some(old) = old {
+            let claimed = |claim: &Claim| claim.service == old;
+            if !self.instances.values().flatten().any(claimed) {
+                debug!(%instance, from = %old.host, from_port = old.port, to = %service.host, to_port = service.port, "instance moved");
+                self.carry_over(&old, service);
+                self.remove(&old, ExpiryReason::Moved, heard);
+                self.moves.insert(old, service.clone());
+            }
+        }
+
+        flag_conflicts(
+            &mut self.services,
+            &mut self.changed,
+            &self.instances[instance.as_ref()],
+        );
+    }
+
+    // `from` said goodbye to `instance`, after `instance_at` was told of it.
+    pub(crate) fn instance_gone(&mut self, instance: &str, from: IpAddr, now: Instant) {
+        let instance = normalize_host(instance);
+        if let Some(claims) = self.instances.get_mut(instance.as_ref()) {
+            for claim in claims.iter_mut().filter(|claim| claim.from == Some(from)) {
+                claim.goodbye = Some(now);
+            }
+        }
+        self.settle_renames(now);
+    }
+
+    // Has a rename reported for services from now on.
+    pub(crate) fn detect_renames(&mut self, detection: RenameDetection) {
+        self.rename_detection = Some(detection);
+    }
+
+    // `instance`, new on `service`, takes the place of the one other instance there, of
+    // the same responder unless not strict, that said goodbye or was heard within the
+    // window: at once after a goodbye, else once that one stays quiet for the window.
+    // With two or more there is no telling which was renamed, and none is.
+    fn rename_candidate(
+        &mut self,
+        instance: &str,
+        service: &Service,
+        from: Option<IpAddr>,
+        now: Instant,
+    ) {
+        let Some(detection) = self.rename_detection else {
+            return;
+        };
+
+        let previous: Vec<(&Box<str>, &Claim)> = self
+            .instances
+            .iter()
+            .flat_map(|(name, claims)| claims.iter().map(move |claim| (name, claim)))
+            .filter(|(_, claim)| {
+                claim.service == *service
+                    && (!detection.same_responder || claim.from == from)
+                    && now.saturating_duration_since(claim.goodbye.unwrap_or(claim.heard))
+                        <= detection.window
+            })
+            .collect();
+        let [(old, claim)] = previous[..] else {
+            return;
+        };
+
+        let rename = Rename {
+            service: service.clone(),
+            old: old.clone(),
+            new: instance.into(),
+            from: claim.from,
+            at: now,
+        };
+        match claim.goodbye {
+            Some(_) => self.rename(rename),
+            None => self.rename_candidates.push(rename),
+        }
+    }
+
+    // Renames the candidates whose old instance said goodbye or stayed quiet for the
+    // window while the new one stayed; one heard again since is still there, even if it
+    // says goodbye later, so both are.
+    fn settle_renames(&mut self, now: Instant) {
+        let Some(detection) = self.rename_detection else {
+            return;
+        };
+
+        for candidate in std::mem::take(&mut self.rename_candidates) {
+            let same = |claim: &&Claim| claim.service == candidate.service;
+            let old = self.instances.get(&candidate.old).and_then(|claims| {
+                claims
+                    .iter()
+                    .filter(same)
+                    .find(|c| c.from == candidate.from)
+            });
+            let new = self.instances.get(&candidate.new).is_some_and(|claims| {
+                claims
+                    .iter()
+                    .any(|claim| claim.service == candidate.service && claim.goodbye.is_none())
+            });
+
+            let renamed = match old {
+                _ if !new => continue,
+                None => continue,
+                Some(claim) if claim.heard >= candidate.at => continue,
+                Some(claim) if claim.goodbye.is_some() => true,
+                Some(_) => now.saturating_duration_since(candidate.at) >= detection.window,
+            };
+
+            match renamed {
+                true => self.rename(candidate),
+                false => self.rename_candidates.push(candidate),
+            }
+        }
+    }
+
+    fn rename(&mut self, rename: Rename) {
+        debug!(old = %rename.old, new = %rename.new, host = %rename.service.host, port = rename.service.port, "instance renamed");
+
+        if let Some(claims) = self.instances.get_mut(&rename.old) {
+            claims.retain(|claim| claim.service != rename.service || claim.from != rename.from);
+            if claims.is_empty() {
+                self.instances.remove(&rename.old);
+            } else {
+                flag_conflicts(&mut self.services, &mut self.changed, claims);
+            }
+        }
+
+        // Renamed again before that was reported, it is one rename from the first name.
+        let old = match self.renames.remove(&rename.service) {
+            Some((first, _)) => first,
+            None => rename.old,
+        };
+        if old != rename.new {
+            self.renames
+                .insert(rename.service.clone(), (old, rename.new));
+        }
+        self.changed.insert(rename.service);
+    }
+
+    // The renames since the last call, by service: from and to.
+    pub(crate) fn take_renames(&mut self) -> HashMap<Service, (Box<str>, Box<str>)> {
+        std::mem::take(&mut self.renames)
+    }
+
+    // `from` sent its claim on `instance` with the cache-flush bit, saying it is the only
+    // place the instance is at: other responders' claims on it from before `FLUSH_AFTER`
+    // are dropped, and their services with them unless claimed still. The claim itself
+    // must have been made first, so its service isn't among them.
+    pub(crate) fn flush_instance(&mut self, instance: &str, from: IpAddr, now: Instant) {
+        let instance = normalize_host(instance);
+        let Some(claims) = self.instances.get_mut(instance.as_ref()) else {
+            return;
+        };
+
+        let mut flushed = Vec::new();
+        claims.retain(|claim| {
+            let stale = claim.from != Some(from) && now.duration_since(claim.heard) > FLUSH_AFTER;
+            if stale {
+                flushed.push(claim.service.clone());
+            }
+            !stale
+        });
+
+        if flushed.is_empty() {
+            return;
+        }
+
+        for service in flushed {
+            let claimed = |claim: &Claim| claim.service == service;
+            if !self.instances.values().flatten().any(claimed) {
+                debug!(%instance, host = %service.host, port = service.port, "instance flushed");
+                self.remove(&service, ExpiryReason::Flushed, now);
+            }
+        }
+
+        flag_conflicts(
+            &mut self.services,
+            &mut self.changed,
+            &self.instances[instance.as_ref()],
+        );
+    }
+
+    // Drops the claims on services that have gone, which may settle a conflict.
+    fn prune_instances(&mut self) {
+        let services = &mut self.services;
+        let changed = &mut self.changed;
+
+        self.instances.retain(|_, claims| {
+            let before = claims.len();
+            claims.retain(|claim| services.contains_key(&claim.service));
+
+            if claims.len() != before {
+                flag_conflicts(services, changed, claims);
+            }
+
+            !claims.is_empty()
+        });
+    }
+
+    // Gone as of `now`, for its availability.
+    fn remove(&mut self, service: &Service, reason: ExpiryReason, now: Instant) {
+        let Some(record) = self.services.remove(service) else {
+            return;
+        };
+        self.removals.insert(service.clone(), reason);
+        depart(&mut self.departed, service, record.history, now);
+
+        if let Some(host) = self.hosts.get_mut(&service.host) {
+            host.services.retain(|s| s != service);
+            if host.services.is_empty() {
+                self.hosts.remove(&service.host);
+            }
+        }
+
+        self.drop_stale_deadlines();
+        self.changed.insert(service.clone());
+    }
+
+    // Rebuilds the deadlines, one per service, once there are too many. Those of removed
+    // services are left behind rather than searched for, so removing one takes no time in
+    // the number of services.
+    fn drop_stale_deadlines(&mut self) {
+        if self.deadlines.len() <= 2 * self.services.len() + STALE_DEADLINES {
+            return;
+        }
+
+        self.deadlines = self
+            .services
+            .iter()
+            .map(|(service, record)| Deadline {
+                at: record.last_seen_time + EXPIRE_AFTER,
+                service: service.clone(),
+            })
+            .collect();
+    }
+
+    // Whether an address record for `host` from `from`, in a response without an SRV
+    // record naming the host, may be applied. Otherwise anyone on the network could add
+    // its own address to a service and have its traffic: only a responder the host's
+    // services were heard from may, or anyone repeating an address already known. A
+    // cache-flush record is also taken while the host has no address of its family, as
+    // the client asks for just those.
+    pub(crate) fn admits_address(
+        &self,
+        host: &str,
+        address: IpAddr,
+        from: IpAddr,
+        unique: bool,
+    ) -> bool {
+        let Some(host) = self.hosts.get(normalize_host(host).as_ref()) else {
+            return true;
+        };
+
+        !self.verify_addresses
+            || host.responders.contains(&from)
+            || host.addresses.contains(&address)
+            || unique
+                && !host
+                    .addresses
+                    .iter()
+                    .any(|known| known.is_ipv4() == address.is_ipv4())
+    }
+
+    // Notes that `host` has `address`, before it is added to the host's services, which
+    // keep no more than `max_addresses`: past that, the address heard of first is removed
+    // from them, and returned. A hostile responder could otherwise attach thousands.
+    pub(crate) fn host_address(&mut self, host: &str, address: IpAddr) -> Option<IpAddr> {
+        let host = self.hosts.get_mut(normalize_host(host).as_ref())?;
+
+        if host.addresses.contains(&address) {
+            return None;
+        }
+
+        host.addresses.push_back(address);
+        if host.addresses.len() <= self.max_addresses {
+            return None;
+        }
+
+        let evicted = host.addresses.pop_front()?;
+
+        for service in &host.services {
+            let Some(record) = self.services.get_mut(service) else {
+                continue;
+            };
+
+            let removed = match evicted {
+                IpAddr::V4(evicted) => {
+                    if record.preferred_address == Some(evicted) {
+                        record.preferred_address = None;
+                    }
+                    record.addresses.remove(&evicted)
+                }
+                IpAddr::V6(evicted) => {
+                    let before = record.ipv6_addresses.len();
+                    record
+                        .ipv6_addresses
+                        .retain(|address| address.ip != evicted);
+                    record.ipv6_addresses.len() < before
+                }
+            };
+
+            if removed {
+                self.changed.insert(service.clone());
+            }
+        }
+
+        Some(evicted)
+    }
+
+    // Calls `f` with every service on `host`.
+    pub(crate) fn for_host(&mut self, host: &str, mut f: impl FnMut(&Service, &mut ServiceRecord)) {
+        let Some(host) = self.hosts.get(normalize_host(host).as_ref()) else {
+            return;
+        };
+
+        for service in &host.services {
+            if let Some(record) = self.services.get_mut(service) {
+                if !self.changed.contains(service) {
+                    self.changed.insert(service.clone());
+                }
+                f(service, record);
+            }
+        }
+    }
+
+    // Calls `f` with every service, taking those it returns true for as changed.
+    #[cfg(feature = "neighbors")]
+    pub(crate) fn update_all(&mut self, mut f: impl FnMut(&mut ServiceRecord) -> bool) {
+        for (service, record) in &mut self.services {
+            if f(record) {
+                self.changed.insert(service.clone());
+            }
+        }
+    }
+
+    // Calls `f` with the service `from` last placed `instance` at, if it is still there.
+    pub(crate) fn for_instance(
+        &mut self,
+        instance: &str,
+        from: IpAddr,
+        f: impl FnOnce(&Service, &mut ServiceRecord),
+    ) {
+        let Some(claim) = self
+            .instances
+            .get(normalize_host(instance).as_ref())
+            .and_then(|claims| claims.iter().find(|claim| claim.from == Some(from)))
+        else {
+            return;
+        };
+
+        if let Some(record) = self.services.get_mut(&claim.service) {
+            self.changed.insert(claim.service.clone());
+            f(&claim.service, record);
+        }
+    }
+
+    // The services `keep` returns false for are gone as of `now`, as with `remove`.
+    pub(crate) fn retain(
+        &mut self,
+        reason: ExpiryReason,
+        now: Instant,
+        mut keep: impl FnMut(&Service, &mut ServiceRecord) -> bool,
+    ) {
+        let before = self.services.len();
+        let removals = &mut self.removals;
+        let departed = &mut self.departed;
+        self.changed.extend(self.services.keys().cloned());
+        self.services.retain(|service, record| {
+            let kept = keep(service, record);
+            if !kept {
+                removals.insert(service.clone(), reason);
+                depart(departed, service, std::mem::take(&mut record.history), now);
+            }
+            kept
+        });
+
+        if self.services.len() == before {
+            return;
+        }
+
+        let services = &self.services;
+        self.hosts.retain(|_, host| {
+            host.services
+                .retain(|service| services.contains_key(service));
+            !host.services.is_empty()
+        });
+        self.drop_stale_deadlines();
+        self.prune_instances();
+    }
+
+    // Exempts `service` from expiring, now or once it is found, until `unpin`. It is
+    // refreshed as ever, and flagged `pinned` for `Changes` to report.
+    pub(crate) fn pin(&mut self, service: &Service) {
+        let service = Service {
+            host: normalize_host(&service.host).into(),
+            port: service.port,
+        };
+
+        if let Some(record) = self.services.get_mut(&service) {
+            record.pinned = true;
+            self.changed.insert(service.clone());
+        }
+        self.pinned.insert(service);
+    }
+
+    // Has `service` expire as others do again, at once if it is overdue.
+    pub(crate) fn unpin(&mut self, service: &Service) {
+        let service = Service {
+            host: normalize_host(&service.host).into(),
+            port: service.port,
+        };
+        self.pinned.remove(&service);
+
+        let Some(record) = self.services.get_mut(&service) else {
+            return;
+        };
+        record.pinned = false;
+        record.withdrawn = false;
+        let at = record.last_seen_time + EXPIRE_AFTER;
+
+        // Its deadline may have been put off while pinned; that one is looked at later.
+        self.deadlines.push(Deadline {
+            at,
+            service: service.clone(),
+        });
+        self.changed.insert(service);
+        self.drop_stale_deadlines();
+    }
+
+    // Resolves only the services `track` marks from now on.
+    pub(crate) fn defer_resolution(&mut self) {
+        self.tracked.get_or_insert_with(HashSet::new);
+    }
+
+    // From the next expiry on, for services expired already too.
+    pub(crate) fn set_grace(&mut self, grace: Duration) {
+        self.grace = grace;
+    }
+
+    pub(crate) fn grace(&self) -> Duration {
+        self.grace
+    }
+
+    // For the services known, expired within the grace period and gone too.
+    pub(crate) fn set_availability_window(&mut self, window: Duration) {
+        self.availability_window = window;
+        for record in self
+            .services
+            .values_mut()
+            .chain(self.tombstones.values_mut())
+        {
+            record.history.set_window(window);
+        }
+        for history in self.departed.values_mut() {
+            history.set_window(window);
+        }
+    }
+
+    pub(crate) fn availability_window(&self) -> Duration {
+        self.availability_window
+    }
+
+    pub(crate) fn defers_resolution(&self) -> bool {
+        self.tracked.is_some()
+    }
+
+    // Has the addresses of `service`'s host taken and asked for, and its TXT record
+    // kept, until `untrack`. Returns whether that is new; never without
+    // `defer_resolution`, when every service is.
+    pub(crate) fn track(&mut self, service: &Service) -> bool {
+        let Some(tracked) = &mut self.tracked else {
+            return false;
+        };
+        tracked.insert(Service {
+            host: normalize_host(&service.host).into(),
+            port: service.port,
+        })
+    }
+
+    // `track` for every service known on `host`.
+    pub(crate) fn track_host(&mut self, host: &str) -> bool {
+        let services = match self.hosts.get(normalize_host(host).as_ref()) {
+            Some(host) => host.services.clone(),
+            None => return false,
+        };
+        let mut tracked = false;
+        for service in &services {
+            tracked |= self.track(service);
+        }
+        tracked
+    }
+
+    // Forgets the addresses and TXT records of `service`'s host once no service there is
+    // tracked, each service on it reported as changed.
+    pub(crate) fn untrack(&mut self, service: &Service) {
+        let Some(tracked) = &mut self.tracked else {
+            return;
+        };
+        let service = Service {
+            host: normalize_host(&service.host).into(),
+            port: service.port,
+        };
+        if !tracked.remove(&service) || self.resolves(&service.host) {
+            return;
+        }
+
+        if let Some(host) = self.hosts.get_mut(&service.host) {
+            host.addresses.clear();
+        }
+        self.for_host(&service.host, |service, record| {
+            // As from an SRV target that is an address, which needs no resolving.
+            let literal = service.host.parse().ok();
+            record.addresses.retain(|address| Some(*address) == literal);
+            record.ipv6_addresses.clear();
+            record.preferred_address = record.preferred_address.filter(|_| literal.is_some());
+            if record
+                .records
+                .iter()
+                .any(|record| is_resolved(record.record_type))
+            {
+                let kept: Vec<_> = record
+                    .records
+                    .iter()
+                    .filter(|record| !is_resolved(record.record_type))
+                    .cloned()
+                    .collect();
+                record.records = kept.into();
+            }
+        });
+    }
+
+    // Whether the addresses of `host` are taken: with `defer_resolution`, only if one of its
+    // services is tracked.
+    pub(crate) fn resolves(&self, host: &str) -> bool {
+        match &self.tracked {
+            None => true,
+            Some(tracked) => {
+                let host = normalize_host(host);
+                tracked.iter().any(|service| *service.host == *host)
+            }
+        }
+    }
+
+    // The service `instance` is at by the claim that stands, the first responder's.
+    pub(crate) fn instance_service(&self, instance: &str) -> Option<Service> {
+        let claims = self.instances.get(normalize_host(instance).as_ref())?;
+        claims.first().map(|claim| claim.service.clone())
+    }
+
+    // Records that a PTR answer pointed at `instance`, for `follow_ups` to ask after unless
+    // an SRV answer places it first.
+    pub(crate) fn pointed_at(&mut self, instance: &str, now: Instant) {
+        let instance = normalize_host(instance);
+        if self.instances.contains_key(instance.as_ref()) {
+            return;
+        }
+
+        if let Some(pointer) = self.pointers.get_mut(instance.as_ref()) {
+            pointer.last_heard = now;
+        } else if self.pointers.len() < MAX_POINTERS {
+            let pointer = Pointer {
+                first_heard: now,
+                last_heard: now,
+                asked: 0,
+                last_asked: None,
+            };
+            self.pointers.insert(instance.into(), pointer);
+        }
+    }
+
+    // Up to `limit` instances pointed at that are due to be asked after by name, longest
+    // waiting first, counted as asked.
+    pub(crate) fn follow_ups(&mut self, now: Instant, limit: usize) -> Vec<Box<str>> {
+        self.pointers
+            .retain(|_, pointer| now.saturating_duration_since(pointer.last_heard) < EXPIRE_AFTER);
+
+        let mut due: Vec<(&Box<str>, &mut Pointer)> = self
+            .pointers
+            .iter_mut()
+            .filter(|(_, pointer)| {
+                pointer.asked < FOLLOW_UPS
+                    && now >= pointer.first_heard + FOLLOW_UP_DELAY
+                    && pointer
+                        .last_asked
+                        .is_none_or(|at| now >= at + FOLLOW_UP_INTERVAL)
+            })
+            .collect();
+        due.sort_unstable_by_key(|(_, pointer)| pointer.first_heard);
+        due.truncate(limit);
+
+        due.into_iter()
+            .map(|(instance, pointer)| {
+                pointer.asked += 1;
+                pointer.last_asked = Some(now);
+                instance.clone()
+            })
+            .collect()
+    }
+
+    // Marks `service` as failing to connect to, for `reconfirm` to ask after first and
+    // `expire` to remove unless it answers. Returns whether it was, being known and not
+    // reported within `REPORT_INTERVAL`.
+    pub(crate) fn report_failure(&mut self, service: &Service, now: Instant) -> bool {
+        let Some(record) = self.services.get(service) else {
+            return false;
+        };
+        if self
+            .suspects
+            .get(service)
+            .is_some_and(|suspect| now < suspect.reported + REPORT_INTERVAL)
+        {
+            return false;
+        }
+
+        self.suspects.insert(
+            service.clone(),
+            Suspect {
+                reported: now,
+                heard: record.last_seen_time,
+                asked: false,
+            },
+        );
+        true
+    }
+
+    // A suspect still silent; it may have gone meanwhile.
+    fn silent(&self, service: &Service, suspect: &Suspect) -> bool {
+        self.services
+            .get(service)
+            .is_some_and(|record| record.last_seen_time == suspect.heard)
+    }
+
+    // When the next service expires or tombstone is dropped, if there are any. Deadlines
+    // that refreshes have moved are brought up to date first, so waiting for this doesn't
+    // wake for them.
+    pub(crate) fn next_expiry(&mut self) -> Option<Instant> {
+        let tombstones = self
+            .tombstones
+            .values()
+            .map(|record| record.last_seen_time + EXPIRE_AFTER + self.grace)
+            .chain(
+                self.suspects
+                    .iter()
+                    .filter(|(service, suspect)| self.silent(service, suspect))
+                    .map(|(_, suspect)| suspect.deadline()),
+            )
+            .chain(self.rename_detection.iter().flat_map(|detection| {
+                self.rename_candidates
+                    .iter()
+                    .map(move |candidate| candidate.at + detection.window)
+            }))
+            .min();
+
+        while let Some(mut deadline) = self.deadlines.peek_mut() {
+            let Some(record) = self.services.get(&deadline.service) else {
+                PeekMut::pop(deadline);
+                continue;
+            };
+            let at = record.last_seen_time + EXPIRE_AFTER;
+
+            if at <= deadline.at {
+                return Some(tombstones.map_or(deadline.at, |t| t.min(deadline.at)));
+            }
+
+            // Sifted down once `deadline` is dropped.
+            deadline.at = at;
+        }
+
+        tombstones
+    }
+
+    // Gives every service `EXPIRE_AFTER` from `now` to be heard from again, for when the
+    // clock can't say how long ago it was: after a suspend it may or may not have run on.
+    // Deadlines only get early.
+    pub(crate) fn restart_expiry(&mut self, now: Instant) {
+        for record in self.services.values_mut() {
+            record.last_seen_time = now;
+        }
+    }
+
+    // Removes the services not seen for `EXPIRE_AFTER` by `now`, and those reported
+    // unreachable that didn't answer in time, returning how many. With a grace period the
+    // former are kept as tombstones until it is over.
+    pub(crate) fn expire(&mut self, now: Instant) -> usize {
+        let mut expired = 0;
+        self.settle_renames(now);
+
+        let unconfirmed: Vec<Service> = self
+            .suspects
+            .iter()
+            .filter(|(service, suspect)| {
+                suspect.deadline() <= now
+                    && self.silent(service, suspect)
+                    && !self.pinned.contains(service)
+            })
+            .map(|(service, _)| service.clone())
+            .collect();
+        for service in unconfirmed {
+            debug!(host = %service.host, port = service.port, "reported service didn't answer");
+            self.remove(&service, ExpiryReason::Unconfirmed, now);
+            expired += 1;
+        }
+        self.suspects
+            .retain(|_, suspect| now < suspect.reported + REPORT_INTERVAL);
+
+        if !self.tombstones.is_empty() {
+            let grace = self.grace;
+            let changed = &mut self.changed;
+            let departed = &mut self.departed;
+
+            self.tombstones.retain(|service, record| {
+                let keep = record.last_seen_time + EXPIRE_AFTER + grace > now;
+                if !keep {
+                    changed.insert(service.clone());
+                    depart(departed, service, std::mem::take(&mut record.history), now);
+                }
+                keep
+            });
+        }
+
+        while let Some(deadline) = self.deadlines.peek() {
+            if deadline.at > now {
+                break;
+            }
+
+            let Deadline { service, .. } = self.deadlines.pop().unwrap();
+            let Some(record) = self.services.get(&service) else {
+                continue;
+            };
+            let at = record.last_seen_time + EXPIRE_AFTER;
+
+            if at > now {
+                self.deadlines.push(Deadline { at, service });
+                continue;
+            }
+            // Looked at again in as long, unless unpinned first.
+            if self.pinned.contains(&service) {
+                self.deadlines.push(Deadline {
+                    at: now + EXPIRE_AFTER,
+                    service,
+                });
+                continue;
+            }
+
+            let mut record = self.services.remove(&service).unwrap();
+
+            if let Some(host) = self.hosts.get_mut(&service.host) {
+                host.services.retain(|s| *s != service);
+                if host.services.is_empty() {
+                    self.hosts.remove(&service.host);
+                }
+            }
+
+            if self.grace.is_zero() {
+                depart(&mut self.departed, &service, record.history, now);
+                self.changed.insert(service);
+            } else {
+                // Gone from now, not again once the grace period is over.
+                record.history.gone(now);
+                self.tombstones.insert(service, record);
+            }
+            expired += 1;
+        }
+
+        if expired > 0 {
+            self.drop_stale_deadlines();
+            self.prune_instances();
+        }
+
+        expired
+    }
+
+    // The services reported unreachable not asked after yet, then up to `limit` services due
+    // to expire within `ahead`, soonest first, that haven't been asked after since they
+    // were last heard, each with an instance claimed on it if any. A responder that stays
+    // quiet otherwise still answers a question for its own name.
+    pub(crate) fn reconfirm(
+        &mut self,
+        now: Instant,
+        ahead: Duration,
+        limit: usize,
+    ) -> Vec<(Option<Box<str>>, Arc<str>)> {
+        let services = &self.services;
+        self.reconfirmed.retain(|service, heard| {
+            services
+                .get(service)
+                .is_some_and(|record| record.last_seen_time == *heard)
+        });
+
+        let mut due: Vec<(&Service, Instant)> = services
+            .iter()
+            .filter(|(service, record)| {
+                let at = record.last_seen_time + EXPIRE_AFTER;
+                now < at && at <= now + ahead && !self.reconfirmed.contains_key(*service)
+            })
+            .map(|(service, record)| (service, record.last_seen_time))
+            .collect();
+        due.sort_unstable_by_key(|&(_, heard)| heard);
+        due.truncate(limit);
+
+        let mut suspects: Vec<&Service> = Vec::new();
+        for (service, suspect) in self.suspects.iter_mut() {
+            if !suspect.asked && services.contains_key(service) {
+                suspect.asked = true;
+                suspects.push(service);
+            }
+        }
+        due.retain(|(service, _)| !suspects.contains(service));
+
+        let instances = &self.instances;
+        let instance = |service: &Service| {
+            instances
+                .iter()
+                .find(|(_, claims)| claims.iter().any(|claim| claim.service == *service))
+                .map(|(instance, _)| instance.clone())
+        };
+        let mut names = Vec::with_capacity(suspects.len() + due.len());
+
+        for service in suspects {
+            names.push((instance(service), service.host.clone()));
+        }
+        for (service, heard) in due {
+            self.reconfirmed.insert(service.clone(), heard);
+
+            names.push((instance(service), service.host.clone()));
+        }
+
+        names
+    }
+
+    // Calls `f` with every service changed since the last call, and its record unless it
+    // was removed; a tombstone's counts as not removed.
+    pub(crate) fn drain_changed(&mut self, mut f: impl FnMut(&Service, Option<&ServiceRecord>)) {
+        for service in self.changed.drain() {
+            let record = self.services.get(&service);
+            f(&service, record.or(self.tombstones.get(&service)));
+        }
+    }
+
+    // Under `cargo fuzz`, run whenever a database is unlocked, and by unit tests: the index
+    // is in step with the services, each has a deadline, stale deadlines are bounded, and
+    // the limits hold.
+    #[cfg(any(test, fuzzing))]
+    fn check(&self) {
+        for (service, record) in &self.services {
+            assert!(
+                matches!(normalize_host(&service.host), Cow::Borrowed(_)),
+                "host {:?} not normalized",
+                service.host
+            );
+            let host = &self.hosts[&service.host];
+            assert_eq!(host.services.iter().filter(|s| *s == service).count(), 1);
+            assert!(self.deadlines.iter().any(|d| d.service == *service));
+            assert!(!self.tombstones.contains_key(service));
+
+            if let Some(preferred) = record.preferred_address {
+                assert!(record.addresses.contains(&preferred));
+            }
+        }
+
+        for (name, host) in &self.hosts {
+            assert!(!host.services.is_empty());
+            assert!(host
+                .services
+                .iter()
+                .all(|s| s.host == *name && self.services.contains_key(s)));
+            assert!(host.addresses.len() <= self.max_addresses);
+            assert!(host.responders.len() <= MAX_RESPONDERS);
+        }
+
+        assert!(self.deadlines.len() <= 2 * self.services.len() + STALE_DEADLINES);
+    }
+
+    // Why the services removed since the last call went; those missing expired.
+    pub(crate) fn take_removals(&mut self) -> HashMap<Service, ExpiryReason> {
+        std::mem::take(&mut self.removals)
+    }
+
+    // Where each service that moved since the last call went.
+    pub(crate) fn take_moves(&mut self) -> HashMap<Service, Service> {
+        std::mem::take(&mut self.moves)
+    }
+
+    // What `to` takes over of `from`'s record when an instance moves there: the answers
+    // timed, unless `to` has more of its own than the one that may have come with it.
+    fn carry_over(&mut self, from: &Service, to: &Service) {
+        let Some(mut latency) = self.services.get(from).and_then(|record| record.latency) else {
+            return;
+        };
+        let Some(record) = self.services.get_mut(to) else {
+            return;
+        };
+
+        match record.latency {
+            Some(own) if own.samples > 1 => return,
+            Some(own) => latency.add(own.last),
+            None => (),
+        }
+        record.latency = Some(latency);
+    }
+
+    pub(crate) fn tombstones(&self) -> &HashMap<Service, ServiceRecord> {
+        &self.tombstones
+    }
+}
+
+// The first claim on an instance stands; later ones placing it at another service are
+// in conflict with it.
+fn flag_conflicts(
+    services: &mut HashMap<Service, ServiceRecord>,
+    changed: &mut HashSet<Service>,
+    claims: &[Claim],
+) {
+    for claim in claims {
+        let conflicting = claim.service != claims[0].service;
+
+        if let Some(record) = services.get_mut(&claim.service) {
+            if record.conflicting != conflicting {
+                record.conflicting = conflicting;
+                changed.insert(claim.service.clone());
+            }
+        }
+    }
+}
+
+impl Deref for Database {
+    type Target = HashMap<Service, ServiceRecord>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.services
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Years from now, so that anything reading the real clock instead is far off.
+    fn later() -> Instant {
+        Instant::now() + Duration::from_secs(10 * 365 * 24 * 3600)
+    }
+
+    fn service(port: u16) -> Service {
+        Service {
+            host: "printer.local".into(),
+            port,
+        }
+    }
+
+    fn heard(database: &mut Database, service: Service, now: Instant) {
+        database
+            .get_or_insert_with(service, || ServiceRecord::heard_at(now))
+            .last_seen_time = now;
+    }
+
+    #[test]
+    fn moves_depart_at_the_time_given() {
+        let start = later();
+        let from = Some("192.0.2.1".parse().unwrap());
+        let mut database = Database::default();
+
+        // To port 2 a second in, and back two seconds after.
+        for (port, at) in [(1, 0), (2, 1), (1, 3)] {
+            let now = start + Duration::from_secs(at);
+            heard(&mut database, service(port), now);
+            database.instance_at("Printer._ipp._tcp.local", &service(port), from, now);
+        }
+
+        let now = start + Duration::from_secs(3);
+        let availability = database[&service(1)].history.availability(now, None);
+        assert_eq!(availability.disappearances, 1);
+        assert_eq!(availability.longest_gap, Duration::from_secs(2));
+        assert!(!database.contains_key(&service(2)));
+    }
+
+    #[test]
+    fn retained_depart_at_the_time_given() {
+        let start = later();
+        let mut database = Database::default();
+
+        heard(&mut database, service(1), start);
+        database.retain(
+            ExpiryReason::InterfaceGone,
+            start + Duration::from_secs(2),
+            |_, _| false,
+        );
+        assert!(database.is_empty());
+        heard(&mut database, service(1), start + Duration::from_secs(5));
+
+        let now = start + Duration::from_secs(5);
+        let availability = database[&service(1)].history.availability(now, None);
+        assert_eq!(availability.disappearances, 1);
+        assert_eq!(availability.longest_gap, Duration::from_secs(3));
+    }
+
+    const OLD: &str = "printer._ipp._tcp.local";
+    const NEW: &str = "printer (2)._ipp._tcp.local";
+
+    // A database detecting renames within 10 seconds, with `claims` of instances on
+    // service 1 by responder and seconds in, and the renames found once it settles 10
+    // seconds after the last one.
+    fn renames(
+        same_responder: bool,
+        claims: &[(&str, &str, u64)],
+        goodbyes: &[(&str, &str, u64)],
+    ) -> Vec<(String, String)> {
+        let start = later();
+        let mut database = Database::default();
+        database.detect_renames(RenameDetection {
+            window: Duration::from_secs(10),
+            same_responder,
+        });
+
+        let mut events: Vec<_> = claims
+            .iter()
+            .map(|&(instance, from, at)| (at, instance, from, true))
+            .chain(
+                goodbyes
+                    .iter()
+                    .map(|&(instance, from, at)| (at, instance, from, false)),
+            )
+            .collect();
+        events.sort_by_key(|event| event.0);
+        for &(at, instance, from, claim) in &events {
+            let now = start + Duration::from_secs(at);
+            let from = from.parse().unwrap();
+            heard(&mut database, service(1), now);
+            match claim {
+                true => database.instance_at(instance, &service(1), Some(from), now),
+                false => database.instance_gone(instance, from, now),
+            }
+        }
+
+        let last = events.iter().map(|event| event.0).max().unwrap_or(0);
+        let now = start + Duration::from_secs(last + 10);
+        heard(&mut database, service(1), now);
+        database.expire(now);
+
+        let mut renames: Vec<_> = database
+            .take_renames()
+            .into_values()
+            .map(|(old, new)| (old.into(), new.into()))
+            .collect();
+        renames.sort();
+        renames
+    }
+
+    const A: &str = "192.0.2.1";
+    const B: &str = "192.0.2.2";
+
+    fn renamed() -> Vec<(String, String)> {
+        vec![(OLD.into(), NEW.into())]
+    }
+
+    #[test]
+    fn renames_found() {
+        // The old instance says goodbye, or stays quiet.
+        assert_eq!(
+            renames(true, &[(OLD, A, 0), (NEW, A, 1)], &[(OLD, A, 2)]),
+            renamed()
+        );
+        assert_eq!(renames(true, &[(OLD, A, 0), (NEW, A, 1)], &[]), renamed());
+        // Said goodbye just before the new name came: still within the window.
+        assert_eq!(
+            renames(true, &[(OLD, A, 0), (NEW, A, 5)], &[(OLD, A, 1)]),
+            renamed()
+        );
+        // Renamed twice before that was reported, it is one rename.
+        let third = "printer (3)._ipp._tcp.local";
+        assert_eq!(
+            renames(
+                true,
+                &[(OLD, A, 0), (third, A, 1), (NEW, A, 2)],
+                &[(OLD, A, 1), (third, A, 2)]
+            ),
+            renamed()
+        );
+        // Another responder counts when not strict.
+        assert_eq!(
+            renames(false, &[(OLD, A, 0), (NEW, B, 1)], &[(OLD, A, 2)]),
+            renamed()
+        );
+    }
+
+    #[test]
+    fn added_instances_not_renames() {
+        // Both still heard.
+        assert!(renames(true, &[(OLD, A, 0), (NEW, A, 1), (OLD, A, 5)], &[]).is_empty());
+        // Heard again, then gone.
+        let claims = [(OLD, A, 0), (NEW, A, 1), (OLD, A, 2)];
+        assert!(renames(true, &claims, &[(OLD, A, 3)]).is_empty());
+        // Two instances the new one may have been.
+        let other = "scanner._ipp._tcp.local";
+        let claims = [(OLD, A, 0), (other, A, 1), (OLD, A, 2), (NEW, A, 3)];
+        assert!(renames(true, &claims, &[(OLD, A, 4)]).is_empty());
+        // The old instance was last heard outside the window.
+        assert!(renames(true, &[(OLD, A, 0), (NEW, A, 11)], &[(OLD, A, 12)]).is_empty());
+        // Another responder, with strict detection.
+        assert!(renames(true, &[(OLD, A, 0), (NEW, B, 1)], &[(OLD, A, 2)]).is_empty());
+        // The new instance said goodbye first.
+        assert!(renames(
+            true,
+            &[(OLD, A, 0), (NEW, A, 1)],
+            &[(NEW, A, 2), (OLD, A, 3)]
+        )
+        .is_empty());
+        // Without detection, nothing is.
+        let mut database = Database::default();
+        let now = later();
+        heard(&mut database, service(1), now);
+        database.instance_at(OLD, &service(1), A.parse().ok(), now);
+        database.instance_at(NEW, &service(1), A.parse().ok(), now);
+        database.instance_gone(OLD, A.parse().unwrap(), now);
+        assert!(database.take_renames().is_empty());
+    }
+
+    #[test]
+    fn poisoned_database_cleared() {
+        let now = later();
+        let database = Mutex::new(Database::default());
+        {
+            let mut locked = Database::lock(&database);
+            heard(&mut locked, service(1), now);
+            heard(&mut locked, service(2), now);
+            locked.pin(&service(2));
+        }
+
+        std::thread::scope(|scope| {
+            let poisoner = scope.spawn(|| {
+                let _locked = Database::lock(&database);
+                panic!("poisons the database");
+            });
+            assert!(poisoner.join().is_err());
+        });
+
+        let mut locked = Database::lock(&database);
+        assert!(!database.is_poisoned());
+        assert!(locked.is_empty());
+        // Still pinned once found again.
+        heard(&mut locked, service(2), now);
+        assert!(locked[&service(2)].pinned);
+    }
+
+    // The services indexed under `host`, by port.
+    fn indexed(database: &Database, host: &str) -> Vec<u16> {
+        let mut ports: Vec<_> = database.hosts.get(host).map_or(Vec::new(), |host| {
+            host.services.iter().map(|service| service.port).collect()
+        });
+        ports.sort();
+        ports
+    }
+
+    #[test]
+    fn host_index_follows_expiry_and_flushes() {
+        let start = later();
+        let at = |secs| start + Duration::from_secs(secs);
+        let (a, b) = ("192.0.2.1".parse().ok(), "192.0.2.2".parse().ok());
+        let scanner = Service {
+            host: "scanner.local".into(),
+            port: 80,
+        };
+        let mut database = Database::default();
+
+        for service in [service(631), service(80), scanner.clone()] {
+            heard(&mut database, service, at(0));
+        }
+        database.instance_at("printer._ipp._tcp.local", &service(631), a, at(0));
+        database.instance_at("scanner._http._tcp.local", &scanner, a, at(0));
+        database.host_address("printer.local", "192.0.2.1".parse().unwrap());
+        database.check();
+        assert_eq!(indexed(&database, "printer.local"), [80, 631]);
+        assert_eq!(indexed(&database, "scanner.local"), [80]);
+
+        // Only port 631 heard from again before the others expire.
+        heard(&mut database, service(631), at(3));
+        assert_eq!(database.expire(at(6)), 2);
+        database.check();
+        assert_eq!(indexed(&database, "printer.local"), [631]);
+        assert!(indexed(&database, "scanner.local").is_empty());
+
+        // Another responder places the instance on port 80 with the cache-flush bit,
+        // which drops the first responder's claim and, unclaimed, port 631.
+        heard(&mut database, service(80), at(6));
+        database.instance_at("printer._ipp._tcp.local", &service(80), b, at(6));
+        database.check();
+        assert_eq!(indexed(&database, "printer.local"), [80, 631]);
+        database.flush_instance("printer._ipp._tcp.local", b.unwrap(), at(6));
+        database.check();
+        assert_eq!(indexed(&database, "printer.local"), [80]);
+        assert!(!database.contains_key(&service(631)));
+
+        assert_eq!(database.expire(at(11)), 1);
+        database.check();
+        assert!(database.hosts.is_empty());
+        assert!(database.is_empty());
+    }
+
+    #[test]
+    fn removed_services_deadlines_skipped() {
+        let start = later();
+        let mut database = Database::default();
+
+        // Services coming and going, each leaving its deadline behind.
+        for round in 0..100 {
+            let now = start + Duration::from_millis(round);
+            for port in 1..=10 {
+                heard(&mut database, service(port), now);
+            }
+            database.retain(ExpiryReason::InterfaceGone, now, |service, _| {
+                service.port == 1
+            });
+            database.check();
+        }
+        assert!(database.deadlines.len() <= 2 * database.len() + STALE_DEADLINES);
+
+        // Only port 1 is left, heard a round before the end.
+        let heard_at = start + Duration::from_millis(99);
+        assert_eq!(database.next_expiry(), Some(heard_at + EXPIRE_AFTER));
+        assert_eq!(database.expire(heard_at + EXPIRE_AFTER), 1);
+        database.check();
+        assert!(database.is_empty());
+        assert_eq!(database.next_expiry(), None);
+    }
+}
@@ -0,0 +1,89 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet, VecDeque},
+    hash::{Hash, Hasher},
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+// How long a datagram counts as a copy of one already received.
+const WINDOW: Duration = Duration::from_secs(1);
+
+// The datagrams received lately, so that copies heard on several sockets, e.g. through
+// interfaces bridged to the same LAN, are applied once. A copy has the same bytes and the
+// same source; anything else a host sends is new.
+#[derive(Default)]
+pub(crate) struct Recent {
+    order: VecDeque<(Instant, u64)>,
+    seen: HashSet<u64>,
+}
+
+impl Recent {
+    // False if the datagram is a copy of one received within the window.
+    pub(crate) fn insert(&mut self, datagram: &[u8], from: SocketAddr, now: Instant) -> bool {
+        while let Some(&(time, hash)) = self.order.front() {
+            if now.duration_since(time) < WINDOW {
+                break;
+            }
+
+            self.order.pop_front();
+            self.seen.remove(&hash);
+        }
+
+        let mut hasher = DefaultHasher::new();
+        datagram.hash(&mut hasher);
+        // Without the scope id, which differs between the sockets a link-local sender's
+        // copies arrive on.
+        from.ip().hash(&mut hasher);
+        from.port().hash(&mut hasher);
+        let hash = hasher.finish();
+
+        if !self.seen.insert(hash) {
+            return false;
+        }
+
+        self.order.push_back((now, hash));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATAGRAM: &[u8] = b"\0\0\x84\0\0\0\0\x01\0\0\0\0";
+
+    fn from(source: &str) -> SocketAddr {
+        source.parse().unwrap()
+    }
+
+    #[test]
+    fn copies_dropped_within_the_window() {
+        let mut recent = Recent::default();
+        let start = Instant::now();
+        let source = from("192.0.2.1:5353");
+
+        assert!(recent.insert(DATAGRAM, source, start));
+        assert!(!recent.insert(DATAGRAM, source, start));
+        assert!(!recent.insert(DATAGRAM, source, start + WINDOW / 2));
+        // The copy didn't extend the window: it ends a second after the first.
+        let end = start + WINDOW - Duration::from_nanos(1);
+        assert!(!recent.insert(DATAGRAM, source, end));
+        assert!(recent.insert(DATAGRAM, source, start + WINDOW));
+        assert!(!recent.insert(DATAGRAM, source, start + WINDOW));
+    }
+
+    #[test]
+    fn copies_need_the_same_bytes_and_source() {
+        let mut recent = Recent::default();
+        let now = Instant::now();
+
+        assert!(recent.insert(DATAGRAM, from("192.0.2.1:5353"), now));
+        assert!(recent.insert(&DATAGRAM[1..], from("192.0.2.1:5353"), now));
+        assert!(recent.insert(DATAGRAM, from("192.0.2.2:5353"), now));
+        assert!(recent.insert(DATAGRAM, from("192.0.2.1:40000"), now));
+
+        // The same link-local sender, heard through two interfaces.
+        assert!(recent.insert(DATAGRAM, from("[fe80::1%2]:5353"), now));
+        assert!(!recent.insert(DATAGRAM, from("[fe80::1%3]:5353"), now));
+    }
+}
@@ -0,0 +1,108 @@
+use crate::{lock::Lock, MdnsClient};
+use std::{
+    ops::Deref,
+    sync::{Arc, Mutex},
+};
+
+// The detached clients, never dropped unless shut down: statics aren't dropped at exit,
+// so they run for as long as the process does.
+static DETACHED: Mutex<Vec<Arc<MdnsClient>>> = Mutex::new(Vec::new());
+
+/// A client left running for the rest of the process, from `MdnsClient::detach`. It
+/// derefs to the client, so `get_services`, `subscribe` and the rest work as they did,
+/// and clones are further handles to the same client. Dropping handles doesn't stop it.
+#[derive(Clone)]
+pub struct DetachedClient {
+    client: Arc<MdnsClient>,
+}
+
+impl DetachedClient {
+    pub(crate) fn new(client: MdnsClient) -> Self {
+        let client = Arc::new(client);
+        DETACHED.locked().push(client.clone());
+        DetachedClient { client }
+    }
+
+    /// Stops the client as dropping it would have, once the other handles to it are
+    /// dropped too; at once if this is the last.
+    pub fn shutdown(self) {
+        DETACHED
+            .locked()
+            .retain(|client| !Arc::ptr_eq(client, &self.client));
+    }
+}
+
+impl Deref for DetachedClient {
+    type Target = MdnsClient;
+
+    fn deref(&self) -> &MdnsClient {
+        &self.client
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+    use std::{sync::Weak, thread};
+
+    // A client of its own, not waiting for the network.
+    fn detached() -> (DetachedClient, Weak<MdnsClient>) {
+        let client = MdnsClient::builder("_detach._tcp.local")
+            .set_up_in_background()
+            .build()
+            .unwrap()
+            .detach();
+        let weak = Arc::downgrade(&client.client);
+        (client, weak)
+    }
+
+    fn listed(weak: &Weak<MdnsClient>) -> bool {
+        DETACHED
+            .locked()
+            .iter()
+            .any(|client| Arc::as_ptr(client) == weak.as_ptr())
+    }
+
+    #[test]
+    fn dropped_handles_leave_it_running() {
+        let (client, weak) = detached();
+        let clones = vec![client.clone(); 3];
+        drop(client);
+
+        let threads: Vec<_> = clones
+            .into_iter()
+            .map(|client| thread::spawn(move || client.get_services().len()))
+            .collect();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), 0);
+        }
+
+        assert!(listed(&weak));
+        let client = weak.upgrade().unwrap();
+        DetachedClient { client }.shutdown();
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn shut_down_once_the_last_handle_goes() {
+        let (first, first_weak) = detached();
+        let (second, second_weak) = detached();
+        let first_clone = first.clone();
+
+        // Each client is shut down on its own, whichever goes first.
+        second.shutdown();
+        assert!(!listed(&second_weak));
+        assert!(second_weak.upgrade().is_none());
+        assert!(listed(&first_weak));
+
+        // A clone keeps the client running past the shutdown, but not listed.
+        first.shutdown();
+        assert!(!listed(&first_weak));
+        assert!(first_weak.upgrade().is_some());
+        first_clone.get_services();
+
+        // Shutting down again through the clone stops it, and is harmless.
+        first_clone.shutdown();
+        assert!(first_weak.upgrade().is_none());
+    }
+}
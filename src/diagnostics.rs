@@ -0,0 +1,86 @@
+use crate::{
+    ActivityEntry, Backend, BackgroundError, MdnsStats, QuerySchedule, QuerySource, Service,
+    ServiceRecord, SkippedInterface,
+};
+use std::{net::IpAddr, time::Duration};
+
+// How many services `Diagnostics::services` shows.
+pub(crate) const SAMPLE_SERVICES: usize = 5;
+
+/// The state of a client, from `MdnsClient::diagnostics()`, for bug reports: its `Debug`
+/// output is meant to be pasted whole. Fields are only ever added, never renamed. Times are
+/// relative to when it was taken.
+#[derive(Clone, Debug)]
+pub struct Diagnostics {
+    pub service: String,
+    /// The backend in use, `Backend::Auto` resolved.
+    pub backend: Backend,
+    /// As `MdnsClient::is_running`.
+    pub running: bool,
+    /// As `MdnsClient::is_ready`.
+    pub ready: bool,
+    /// As `MdnsClient::initial_scan_done`.
+    pub initial_scan_done: bool,
+    /// As set by `MdnsClientBuilder::srv_only`.
+    pub srv_only: bool,
+    /// As set by `MdnsClientBuilder::expiry_grace` or since by
+    /// `MdnsClient::set_expiry_grace` or `set_expiry_policy`.
+    pub expiry_grace: Duration,
+    /// As set by `MdnsClientBuilder::availability_window` or since by
+    /// `MdnsClient::set_expiry_policy`.
+    pub availability_window: Duration,
+    /// How long until the socket backend's next query for this client, or setup attempt
+    /// while it has no sockets; zero if it is overdue. `None` for the other backends and
+    /// until set up.
+    pub next_query_in: Option<Duration>,
+    /// Every client on the socket backend's sockets, this one among them, and when each
+    /// queries next.
+    pub questions: Vec<QuestionDiagnostics>,
+    /// The socket backend's sockets, in the order of `MdnsStats::sockets`.
+    pub interfaces: Vec<InterfaceDiagnostics>,
+    pub skipped_interfaces: Vec<SkippedInterface>,
+    /// Why the socket backend runs on one socket bound to the wildcard address and joined
+    /// on the default interface, if it does: on Windows, where no interface to set up was
+    /// found. Services on other interfaces aren't found then.
+    pub wildcard_fallback: Option<String>,
+    /// The services known, not counting those expired within `expiry_grace`.
+    pub service_count: usize,
+    /// The first five services, sorted as `get_services()` sorts them.
+    pub services: Vec<(Service, ServiceRecord)>,
+    /// As `MdnsClient::recent_errors`.
+    pub recent_errors: Vec<BackgroundError>,
+    /// As `MdnsClient::recent_activity`.
+    pub recent_activity: Vec<ActivityEntry>,
+    /// As `MdnsClient::query_sources`.
+    pub query_sources: Vec<QuerySource>,
+    pub stats: MdnsStats,
+}
+
+/// A client's question on the socket backend's sockets.
+#[derive(Clone, Debug)]
+pub struct QuestionDiagnostics {
+    pub service: String,
+    /// As set by `MdnsClientBuilder::query_schedule` or since by
+    /// `MdnsClient::set_query_schedule`.
+    pub schedule: QuerySchedule,
+    /// Zero if it is overdue.
+    pub next_query_in: Duration,
+    /// Between the next query and the one after.
+    pub next_interval: Duration,
+}
+
+/// A socket of the socket backend, as of the worker's last wakeup.
+#[derive(Clone, Debug)]
+pub struct InterfaceDiagnostics {
+    pub name: String,
+    /// Unspecified for IPv6 sockets, which aren't tied to an address.
+    pub address: IpAddr,
+    /// As `SocketStats::legacy_unicast`.
+    pub legacy_unicast: bool,
+    /// How long its sends or receives have been failing, if they are; it is set up again
+    /// once that has lasted ten seconds.
+    pub failing_for: Option<Duration>,
+    /// Sends failed in a row, and how long until it sends again after them.
+    pub send_failures: u32,
+    pub sends_resume_in: Option<Duration>,
+}
@@ -0,0 +1,323 @@
+use crate::{
+    lock::Lock,
+    sync::{Condvar, Mutex},
+    Service, ServiceRecord,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        mpsc::{RecvError, RecvTimeoutError},
+        Arc, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Debug)]
+pub enum ServiceEvent {
+    Added(Service, ServiceRecord),
+    /// The preferred address or one of the address sets changed.
+    Updated(Service, ServiceRecord),
+    Expired(Service),
+    /// Its instance was announced at another host or port, such as after the device
+    /// renamed itself: `from` is gone and `to` takes its place, with `latency` carried over.
+    Moved {
+        from: Service,
+        to: Service,
+        record: ServiceRecord,
+    },
+    /// Its instance was renamed, with `MdnsClientBuilder::detect_renames`: the host and
+    /// port stayed the same, so the service and what is known of it do too. `old` and
+    /// `new` are the instances, in lowercase.
+    Renamed {
+        service: Service,
+        old: String,
+        new: String,
+        record: ServiceRecord,
+    },
+}
+
+/// How `MdnsClientBuilder::detect_renames` tells a rename from an instance added beside
+/// another. An instance new on a service is the other one renamed if that is the only
+/// other instance there heard within `window`, and it then says goodbye or stays quiet for
+/// `window`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RenameDetection {
+    pub window: Duration,
+    /// Whether both have to come from the same address, as they do when a device renames
+    /// itself; otherwise a new responder taking over the host and port counts too.
+    pub same_responder: bool,
+}
+
+impl Default for RenameDetection {
+    fn default() -> Self {
+        RenameDetection {
+            window: Duration::from_secs(10),
+            same_responder: true,
+        }
+    }
+}
+
+impl ServiceEvent {
+    /// The service as it is now: `to` for a move.
+    pub fn service(&self) -> &Service {
+        match self {
+            ServiceEvent::Added(service, _)
+            | ServiceEvent::Updated(service, _)
+            | ServiceEvent::Expired(service)
+            | ServiceEvent::Moved { to: service, .. }
+            | ServiceEvent::Renamed { service, .. } => service,
+        }
+    }
+}
+
+// What a subscriber that hasn't seen `previous` yet should see instead of both events;
+// `None` if they cancel out.
+fn coalesce(previous: ServiceEvent, next: ServiceEvent) -> Option<ServiceEvent> {
+    match (previous, next) {
+        (ServiceEvent::Added(..), ServiceEvent::Updated(service, record)) => {
+            Some(ServiceEvent::Added(service, record))
+        }
+        (ServiceEvent::Added(..), ServiceEvent::Expired(_)) => None,
+        (ServiceEvent::Expired(_), ServiceEvent::Added(service, record)) => {
+            Some(ServiceEvent::Updated(service, record))
+        }
+        (ServiceEvent::Moved { from, to, .. }, ServiceEvent::Updated(_, record)) => {
+            Some(ServiceEvent::Moved { from, to, record })
+        }
+        // Only `from` was ever seen.
+        (ServiceEvent::Moved { from, .. }, ServiceEvent::Expired(_)) => {
+            Some(ServiceEvent::Expired(from))
+        }
+        // Seen under neither name yet.
+        (
+            ServiceEvent::Added(..),
+            ServiceEvent::Renamed {
+                service, record, ..
+            },
+        ) => Some(ServiceEvent::Added(service, record)),
+        (ServiceEvent::Moved { from, to, .. }, ServiceEvent::Renamed { record, .. }) => {
+            Some(ServiceEvent::Moved { from, to, record })
+        }
+        (
+            ServiceEvent::Renamed {
+                service, old, new, ..
+            },
+            ServiceEvent::Updated(_, record),
+        ) => Some(ServiceEvent::Renamed {
+            service,
+            old,
+            new,
+            record,
+        }),
+        // Only the first name was seen.
+        (
+            ServiceEvent::Renamed { old, .. },
+            ServiceEvent::Renamed {
+                service,
+                new,
+                record,
+                ..
+            },
+        ) => match old == new {
+            true => Some(ServiceEvent::Updated(service, record)),
+            false => Some(ServiceEvent::Renamed {
+                service,
+                old,
+                new,
+                record,
+            }),
+        },
+        (_, next) => Some(next),
+    }
+}
+
+#[derive(Default)]
+struct State {
+    // Services in the order their pending event was first queued.
+    order: VecDeque<Service>,
+    pending: HashMap<Service, ServiceEvent>,
+    closed: bool,
+}
+
+// Holds at most one event per service, and events for at most `capacity` services, so a
+// stalled subscriber costs bounded memory however long it has been stalled.
+pub(crate) struct Queue {
+    state: Mutex<State>,
+    ready: Condvar,
+    capacity: usize,
+}
+
+impl Queue {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Queue {
+            state: Mutex::default(),
+            ready: Condvar::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    // Returns whether the oldest pending event was dropped to make room.
+    pub(crate) fn push(&self, event: ServiceEvent) -> bool {
+        let mut state = self.state.locked();
+        let dropped = self.insert(&mut state, event);
+        self.ready.notify_one();
+        dropped
+    }
+
+    fn insert(&self, state: &mut State, mut event: ServiceEvent) -> bool {
+        // A move takes over what is pending for the service it leaves: it is an addition if
+        // that had not been seen yet, and one move if it had just moved there.
+        if let ServiceEvent::Moved { from, to, record } = &event {
+            if let Some(previous) = state.pending.remove(from) {
+                state.order.retain(|s| s != from);
+                let (to, record) = (to.clone(), record.clone());
+
+                event = match previous {
+                    ServiceEvent::Added(..) => ServiceEvent::Added(to, record),
+                    ServiceEvent::Moved { from: first, .. } if first == to => {
+                        ServiceEvent::Updated(to, record)
+                    }
+                    ServiceEvent::Moved { from: first, .. } => ServiceEvent::Moved {
+                        from: first,
+                        to,
+                        record,
+                    },
+                    _ => event,
+                };
+            }
+        }
+
+        let service = event.service().clone();
+        let mut dropped = false;
+
+        match state.pending.remove(&service) {
+            None => {
+                if state.pending.len() >= self.capacity {
+                    if let Some(oldest) = state.order.pop_front() {
+                        state.pending.remove(&oldest);
+                        dropped = true;
+                    }
+                }
+
+                state.order.push_back(service.clone());
+                state.pending.insert(service, event);
+            }
+            Some(previous) => match coalesce(previous, event) {
+                // Now about the service a pending move left.
+                Some(event) if *event.service() != service => {
+                    state.order.retain(|s| *s != service);
+                    dropped = self.insert(state, event);
+                }
+                Some(event) => {
+                    state.pending.insert(service, event);
+                }
+                None => state.order.retain(|s| *s != service),
+            },
+        }
+
+        dropped
+    }
+
+    pub(crate) fn close(&self) {
+        self.state.locked().closed = true;
+        self.ready.notify_all();
+    }
+}
+
+/// Receives `ServiceEvent`s from `MdnsClient::subscribe`.
+///
+/// Events for a service that haven't been received yet are merged, so a slow reader
+/// sees each service's latest state rather than every step in between. Events for at
+/// most a set number of services wait to be received; beyond that the oldest is dropped
+/// and counted in `MdnsStats::events_dropped`. The client never waits for a reader.
+pub struct Subscription {
+    queue: Arc<Queue>,
+}
+
+impl Subscription {
+    pub(crate) fn new(queue: Arc<Queue>) -> Self {
+        Subscription { queue }
+    }
+
+    /// Blocks until an event arrives. Fails once the client is dropped and every
+    /// pending event has been received.
+    pub fn recv(&self) -> Result<ServiceEvent, RecvError> {
+        // Never times out.
+        self.recv_timeout(Duration::MAX).map_err(|_| RecvError)
+    }
+
+    /// As `recv`, but gives up after `timeout`; one too long to ever pass waits as `recv`
+    /// does.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<ServiceEvent, RecvTimeoutError> {
+        let deadline = Instant::now().checked_add(timeout);
+        let mut state = self.queue.state.locked();
+        let ready = &self.queue.ready;
+
+        loop {
+            if let Some(service) = state.order.pop_front() {
+                if let Some(event) = state.pending.remove(&service) {
+                    return Ok(event);
+                }
+            } else if state.closed {
+                return Err(RecvTimeoutError::Disconnected);
+            } else if let Some(deadline) = deadline {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(RecvTimeoutError::Timeout);
+                }
+
+                state = ready
+                    .wait_timeout(state, deadline - now)
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .0;
+            } else {
+                state = ready.wait(state).unwrap_or_else(PoisonError::into_inner);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn poisoned_queue_still_delivers() {
+        let queue = Arc::new(Queue::new(8));
+        let subscription = Subscription::new(queue.clone());
+        let service = |port| Service {
+            host: "printer.local".into(),
+            port,
+        };
+
+        thread::scope(|scope| {
+            let poisoner = scope.spawn(|| {
+                let _state = queue.state.locked();
+                panic!("poisons the queue");
+            });
+            assert!(poisoner.join().is_err());
+        });
+        assert!(queue.state.is_poisoned());
+
+        queue.push(ServiceEvent::Expired(service(1)));
+        assert!(matches!(
+            subscription.recv_timeout(Duration::ZERO),
+            Ok(ServiceEvent::Expired(expired)) if expired == service(1)
+        ));
+
+        // Waiting on the poisoned queue too.
+        thread::scope(|scope| {
+            let waiter = scope.spawn(|| subscription.recv_timeout(Duration::from_secs(20)));
+            thread::sleep(Duration::from_millis(50));
+            queue.push(ServiceEvent::Expired(service(2)));
+            assert!(matches!(
+                waiter.join().unwrap(),
+                Ok(ServiceEvent::Expired(expired)) if expired == service(2)
+            ));
+        });
+
+        queue.close();
+        assert!(subscription.recv().is_err());
+    }
+}
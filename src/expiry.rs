@@ -0,0 +1,35 @@
+use crate::availability::AVAILABILITY_WINDOW;
+use std::{error::Error, time::Duration};
+
+// The longest a grace period or availability window may be, so that every deadline and
+// window start taken from them is an `Instant`.
+pub(crate) const MAX_EXPIRY: Duration = Duration::from_secs(365 * 24 * 3600);
+
+/// How a client lets services go, see `MdnsClient::set_expiry_policy`. By default no
+/// grace period and a day of availability.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExpiryPolicy {
+    /// How long an expired service is remembered, as `MdnsClientBuilder::expiry_grace`.
+    pub grace: Duration,
+    /// How far back `ServiceRecord::availability` looks, as
+    /// `MdnsClientBuilder::availability_window`.
+    pub availability_window: Duration,
+}
+
+impl ExpiryPolicy {
+    pub(crate) fn check(&self) -> Result<(), Box<dyn Error>> {
+        if self.grace > MAX_EXPIRY || self.availability_window > MAX_EXPIRY {
+            return Err(format!("invalid expiry policy {:?}", self).into());
+        }
+        Ok(())
+    }
+}
+
+impl Default for ExpiryPolicy {
+    fn default() -> Self {
+        ExpiryPolicy {
+            grace: Duration::ZERO,
+            availability_window: AVAILABILITY_WINDOW,
+        }
+    }
+}
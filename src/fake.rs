@@ -0,0 +1,213 @@
+use crate::{
+    lock::Lock,
+    packet::Packet,
+    responder::{Advert, Responder},
+    socket, MULTICAST_ADDR, MULTICAST_PORT,
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    io,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+// How often the thread checks whether to stop, and sends announcements that are due.
+const TICK: Duration = Duration::from_millis(100);
+
+/// A responder for integration tests that shouldn't depend on what is on the network:
+/// it answers PTR, SRV, TXT and A questions for the services added to it, on port 5353 of
+/// one interface, where a client on the same host hears it through multicast loopback.
+/// It announces each service as it is added, like `MdnsClient::advertise`. Dropping it
+/// stops answering without goodbyes, as a responder that went away would. Only behind the
+/// `test-util` feature.
+pub struct FakeResponder {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct Shared {
+    socket: UdpSocket,
+    state: Mutex<State>,
+    stop: AtomicBool,
+}
+
+struct State {
+    responder: Responder,
+    // The ids of the services, by instance name as given.
+    ids: HashMap<String, u64>,
+    delay: Duration,
+    malformed: bool,
+}
+
+impl FakeResponder {
+    /// Joins the group on the interface with `address`, which the services' hosts resolve
+    /// to. The loopback interface won't do: clients leave it out.
+    pub fn new(address: Ipv4Addr) -> io::Result<FakeResponder> {
+        let socket = socket::multicast(
+            address,
+            0,
+            socket::Options {
+                device: None,
+                receive_buffer: None,
+                bind_group: false,
+            },
+        )?;
+        socket.set_read_timeout(Some(TICK))?;
+
+        let mut responder = Responder::default();
+        responder.set_addresses(vec![address]);
+
+        let shared = Arc::new(Shared {
+            socket,
+            state: Mutex::new(State {
+                responder,
+                ids: HashMap::new(),
+                delay: Duration::ZERO,
+                malformed: false,
+            }),
+            stop: AtomicBool::new(false),
+        });
+
+        let thread = thread::spawn({
+            let shared = shared.clone();
+            move || shared.run()
+        });
+
+        Ok(FakeResponder {
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    /// Answers for `instance` of `service_type` on `port`, as `MdnsClient::advertise`
+    /// would, from a host named after the instance.
+    pub fn add(
+        &self,
+        instance: &str,
+        service_type: &str,
+        port: u16,
+        txt: &[(&str, &str)],
+    ) -> Result<(), Box<dyn Error>> {
+        let advert = Advert::new(instance, service_type, port, txt)?;
+
+        let mut state = self.shared.state.locked();
+        let id = state.responder.add(advert);
+        if let Some(previous) = state.ids.insert(instance.to_string(), id) {
+            state.responder.withdraw(previous);
+        }
+        Ok(())
+    }
+
+    /// Sends goodbye packets for `instance` and stops answering for it.
+    pub fn goodbye(&self, instance: &str) {
+        let mut state = self.shared.state.locked();
+
+        if let Some(id) = state.ids.remove(instance) {
+            state.responder.withdraw(id);
+        }
+    }
+
+    /// Waits this long before each answer, as a slow responder would. Zero at first.
+    pub fn set_delay(&self, delay: Duration) {
+        self.shared.state.locked().delay = delay;
+    }
+
+    /// Whether announcements and answers to PTR questions carry the PTR record alone, as
+    /// some responders', so only asking for an instance by name finds its SRV and TXT
+    /// records. Off at first.
+    pub fn set_bare_pointers(&self, bare: bool) {
+        self.shared.state.locked().responder.set_bare_pointers(bare);
+    }
+
+    /// Whether answers and announcements also carry an A record with a three-byte address,
+    /// which the DNS parser rejects. Off at first.
+    pub fn set_malformed(&self, malformed: bool) {
+        self.shared.state.locked().malformed = malformed;
+    }
+}
+
+impl Drop for FakeResponder {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+        self.thread.take().map(JoinHandle::join);
+    }
+}
+
+impl Shared {
+    fn run(&self) {
+        let group = SocketAddr::V4(SocketAddrV4::new(MULTICAST_ADDR, MULTICAST_PORT));
+        let mut buffer = [0; 9000];
+
+        while !self.stop.load(Ordering::Relaxed) {
+            let (due, malformed) = {
+                let mut state = self.state.locked();
+                (state.responder.due(Instant::now()), state.malformed)
+            };
+            for packet in due {
+                self.send(packet, group, malformed);
+            }
+
+            let (len, from) = match self.socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(err)
+                    if err.kind() == io::ErrorKind::WouldBlock
+                        || err.kind() == io::ErrorKind::TimedOut =>
+                {
+                    continue
+                }
+                Err(_err) => {
+                    warn!(error = %_err, "fake responder failed to receive");
+                    thread::sleep(TICK);
+                    continue;
+                }
+            };
+
+            let Ok(query) = Packet::parse(&buffer[..len]) else {
+                continue;
+            };
+            if !query.header.query {
+                continue;
+            }
+
+            let (answer, delay, malformed) = {
+                let state = self.state.locked();
+                let answer = state.responder.answer(&query, from, group);
+                (answer, state.delay, state.malformed)
+            };
+
+            if let Some((packet, to)) = answer {
+                thread::sleep(delay);
+                self.send(packet, to, malformed);
+            }
+        }
+    }
+
+    fn send(&self, mut packet: Vec<u8>, to: SocketAddr, malformed: bool) {
+        if malformed {
+            malform(&mut packet);
+        }
+
+        if let Err(_err) = self.socket.send_to(&packet, to) {
+            warn!(error = %_err, "fake responder failed to send");
+        }
+    }
+}
+
+// Adds an additional record the parser rejects, after those that are fine.
+fn malform(packet: &mut Vec<u8>) {
+    let additionals = u16::from_be_bytes([packet[10], packet[11]]);
+    packet[10..12].copy_from_slice(&(additionals + 1).to_be_bytes());
+
+    packet.extend(crate::encode_dns_name("malformed.local"));
+    packet.extend_from_slice(&1u16.to_be_bytes()); // A
+    packet.extend_from_slice(&1u16.to_be_bytes()); // IN
+    packet.extend_from_slice(&120u32.to_be_bytes());
+    packet.extend_from_slice(&3u16.to_be_bytes());
+    packet.extend_from_slice(&[10, 0, 0]);
+}
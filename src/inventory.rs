@@ -0,0 +1,136 @@
+use crate::{
+    database::Database,
+    error_response,
+    lock::Lock,
+    matcher::Filter,
+    metrics::Metrics,
+    packet::{Packet, TYPE_SRV},
+    responder::OTHER_TTL,
+    updates::Updates,
+    AddressFamily, ExpiryReason, Service, ServiceRecord,
+};
+use std::{
+    net::SocketAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// At most this many services are kept, as anything on the network can announce; more
+// are left out until some expire.
+const MAX_SERVICES: usize = 1024;
+
+// How long a service lasts without an SRV record kept to take the TTL from, that of SRV
+// records RFC 6762 section 10 recommends.
+const DEFAULT_TTL: Duration = Duration::from_secs(120);
+
+// The longest an SRV record's TTL is taken to be, the 75 minutes RFC 6762 section 10
+// recommends for records other than a host's: a responder announcing years would
+// otherwise hold its place among `MAX_SERVICES` for good.
+const MAX_TTL: Duration = Duration::from_secs(OTHER_TTL as u64);
+
+// How often expired services are dropped as responses arrive.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+// Every service heard of, whatever its type, for `MdnsClientBuilder::inventory`: what the
+// clients' questions take from responses, with the filter letting everything through.
+// Nothing asks after these services, so each lasts as long as its SRV record's TTL
+// rather than `EXPIRE_AFTER`.
+pub(crate) struct Inventory {
+    database: Mutex<Database>,
+    // For `Updates::apply` alone; the client's own counters are for what it browses.
+    metrics: Metrics,
+    family: AddressFamily,
+    swept_at: Mutex<Instant>,
+}
+
+impl Inventory {
+    pub(crate) fn new(family: AddressFamily) -> Self {
+        let metrics = Metrics::new(Vec::new());
+        metrics.activity().set_capacity(0);
+
+        Inventory {
+            database: Mutex::new(Database::default()),
+            metrics,
+            family,
+            swept_at: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub(crate) fn record(&self, packet: &Packet, from: SocketAddr, now: Instant) {
+        if packet.header.query || error_response(packet) {
+            return;
+        }
+
+        // Only an IPv6 source says which interface it arrived on.
+        let interface = match from {
+            SocketAddr::V6(from) => from.scope_id(),
+            SocketAddr::V4(_) => 0,
+        };
+
+        let mut updates = Updates::default();
+        updates.gather(
+            packet,
+            from,
+            interface,
+            now,
+            Filter::everything(),
+            self.family,
+        );
+
+        let mut database = Database::lock(&self.database);
+        let mut swept_at = self.swept_at.locked();
+        if now.saturating_duration_since(*swept_at) >= SWEEP_INTERVAL {
+            sweep(&mut database, now);
+            *swept_at = now;
+        }
+
+        let mut room = MAX_SERVICES.saturating_sub(database.len());
+        updates.apply(&mut database, &self.metrics, None, || {
+            let admitted = room > 0;
+            room = room.saturating_sub(1);
+            admitted
+        });
+        forget_changes(&mut database);
+    }
+
+    // Sorted by service, as `MdnsClient::get_services` sorts them.
+    pub(crate) fn services(&self, now: Instant) -> Vec<(Service, ServiceRecord)> {
+        let mut database = Database::lock(&self.database);
+        sweep(&mut database, now);
+
+        let mut services: Vec<_> = database
+            .iter()
+            .map(|(service, record)| (service.clone(), record.clone()))
+            .collect();
+        services.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        services
+    }
+}
+
+// Drops the services whose SRV record's TTL has run out since they were last heard.
+fn sweep(database: &mut Database, now: Instant) {
+    database.retain(ExpiryReason::NotHeard, now, |_, record| {
+        now < record.last_seen_time + ttl(record)
+    });
+    forget_changes(database);
+}
+
+// The longest TTL of its SRV records, as one host and port may serve several instances,
+// up to `MAX_TTL`.
+fn ttl(record: &ServiceRecord) -> Duration {
+    record
+        .records()
+        .iter()
+        .filter(|record| record.record_type == TYPE_SRV)
+        .map(|record| Duration::from_secs(record.ttl.into()))
+        .max()
+        .unwrap_or(DEFAULT_TTL)
+        .min(MAX_TTL)
+}
+
+// No one follows the inventory's changes, so they aren't kept for anyone.
+fn forget_changes(database: &mut Database) {
+    database.drain_changed(|_, _| ());
+    database.take_removals();
+    database.take_moves();
+}
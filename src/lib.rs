@@ -1,26 +1,126 @@
-use dns_parser::{
-    rdata::{Srv, A},
-    Packet, RData, ResourceRecord,
-};
+#[macro_use]
+mod trace;
+
+mod activity;
+mod address;
+#[cfg(all(target_os = "linux", feature = "avahi"))]
+mod avahi;
+mod availability;
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench;
+#[cfg(all(target_os = "macos", feature = "bonjour"))]
+mod bonjour;
+mod capture;
+mod changes;
+mod connect;
+mod daemon;
+mod database;
+mod dedup;
+mod detach;
+mod diagnostics;
+mod events;
+mod expiry;
+#[cfg(feature = "test-util")]
+mod fake;
+mod inventory;
+mod limit;
+mod lint;
+mod lock;
+mod matcher;
+mod metrics;
+#[cfg(all(test, loom))]
+mod models;
+mod ndjson;
+#[cfg(feature = "neighbors")]
+mod neighbor;
+mod observe;
+mod packet;
+mod query_options;
+mod record;
+mod resolver;
+mod responder;
+#[cfg(feature = "test-util")]
+mod response;
+mod route;
+mod scan;
+mod schedule;
+mod seed;
+mod service_type;
+#[cfg(feature = "url")]
+mod service_url;
+mod share;
+mod socket;
+mod state;
+mod store;
+mod subnet;
+mod sync;
+mod updates;
+#[cfg(all(target_os = "windows", feature = "windows-dns"))]
+mod windows_dns;
+
 use std::{
+    any::Any,
     collections::{HashMap, HashSet},
     error::Error,
-    io,
-    net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket},
-    sync::{
-        mpsc::{sync_channel, RecvTimeoutError, SyncSender},
-        Arc, Mutex,
-    },
-    thread::{self, JoinHandle},
-    time::{Duration, Instant},
+    fmt,
+    io::{self, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket},
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc::SyncSender, Arc, Mutex},
+    thread::JoinHandle,
+    time::{Duration, Instant, SystemTime},
 };
 
-#[cfg(not(target_os = "windows"))]
-use net2::unix::UnixUdpBuilderExt;
+use availability::{History, AVAILABILITY_WINDOW};
+use capture::Capture;
+use changes::Changes;
+use database::{Database, EXPIRE_AFTER};
+use inventory::Inventory;
+use lint::Lint;
+use matcher::Filter;
+use metrics::Metrics;
+use observe::Observer;
+use packet::{Packet, ParseError};
+use scan::{Scan, SCAN_ROUNDS, SCAN_WARM_UP};
+use updates::Updates;
+
+pub use activity::{Activity, ActivityEntry, ExpiryReason};
+pub use address::{MacAddr, ScopedIpv6Addr};
+pub use availability::Availability;
+pub use daemon::{NoUsableInterfaces, SkippedInterface};
+pub use detach::DetachedClient;
+pub use diagnostics::{Diagnostics, InterfaceDiagnostics, QuestionDiagnostics};
+pub use events::{RenameDetection, ServiceEvent, Subscription};
+pub use expiry::ExpiryPolicy;
+#[cfg(feature = "test-util")]
+pub use fake::FakeResponder;
+pub use lint::{LintCode, LintFinding};
+pub use matcher::{InstanceMatcher, ServiceMatcher, ServiceName, ServiceTypeMatcher};
+pub use metrics::{BackgroundError, FailedOperation, MdnsStats, SocketStats};
+pub use observe::{ObservedQuery, QuerySource};
+pub use query_options::QueryOptions;
+pub use record::OwnedRecord;
+pub use resolver::CacheResolver;
+pub use responder::Advertisement;
+#[cfg(feature = "test-util")]
+pub use response::ResponseBuilder;
+pub use route::rank_addresses;
+pub use schedule::QuerySchedule;
+pub use seed::SeedError;
+pub use service_type::{InvalidServiceType, Protocol, ServiceType};
+#[cfg(feature = "url")]
+pub use service_url::service_url;
+pub use state::MdnsState;
+pub use store::{MemoryStore, ServiceStore};
 
 const MULTICAST_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MULTICAST_ADDR_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
 const MULTICAST_PORT: u16 = 5353;
 
+// Services whose events wait for a subscriber, unless it asks for another limit.
+const DEFAULT_EVENT_CAPACITY: usize = 1024;
+
 // DNS header flags
 const OPCODE_QUERY: u16 = 0x0000;
 
@@ -57,6 +157,8 @@ impl DnsHeader {
     }
 }
 
+// Labels as they are between the dots, without escapes, as names are everywhere else in
+// the crate; `dns_name_error` says whether they fit.
 fn encode_dns_name(name: &str) -> Vec<u8> {
     let mut bytes = Vec::new();
     for part in name.split('.') {
@@ -67,127 +169,471 @@ fn encode_dns_name(name: &str) -> Vec<u8> {
     bytes
 }
 
-#[cfg(not(target_os = "windows"))]
-fn create_socket(addr: Ipv4Addr) -> io::Result<std::net::UdpSocket> {
-    net2::UdpBuilder::new_v4()?
-        .reuse_address(true)?
-        .reuse_port(true)?
-        .bind((addr, MULTICAST_PORT))
+// Why `name`, without the final dot, can't be encoded, if it can't: an empty label or one
+// longer than 63 bytes, or more than 255 bytes encoded.
+fn dns_name_error(name: &str) -> Option<String> {
+    if name
+        .split('.')
+        .any(|label| label.is_empty() || label.len() > 63)
+    {
+        return Some(format!("{:?} is not a valid DNS name", name));
+    }
+    if name.len() + 2 > 255 {
+        return Some(format!("{:?} is longer than 255 bytes encoded", name));
+    }
+    None
 }
 
-#[cfg(target_os = "windows")]
-fn create_socket(addr: Ipv4Addr) -> io::Result<std::net::UdpSocket> {
-    net2::UdpBuilder::new_v4()?
-        .reuse_address(true)?
-        .bind((addr, MULTICAST_PORT))
+// Hosts known from SRV answers that are still missing addresses of a family in use, of
+// those the database resolves. A target that is an IPv4 address is all there is to know,
+// and isn't asked about.
+fn unresolved_hosts(database: &Database, family: AddressFamily) -> Vec<Arc<str>> {
+    let mut hosts: Vec<Arc<str>> = database
+        .iter()
+        .filter(|(service, record)| {
+            ((family.v4() && record.addresses.is_empty())
+                || (family.v6() && record.ipv6_addresses.is_empty()))
+                && service.host.parse::<Ipv4Addr>().is_err()
+                && database.resolves(&service.host)
+        })
+        .map(|(service, _)| service.host.clone())
+        .collect();
+
+    hosts.sort();
+    hosts.dedup();
+    hosts
 }
 
-fn send_mdns_query(socket: &UdpSocket, service_name: &str) -> Result<(), Box<dyn Error>> {
-    // Create DNS header
-    let header = DnsHeader::new_query();
+// Hosts asked for by address in one query, at most, so that SRV answers naming many hosts
+// don't make it outgrow a datagram; past that, each query asks for the next ones in turn.
+const UNRESOLVED_LIMIT: usize = 16;
+
+// A question's query packet, built once and reused for every socket and every tick until
+// what it is built from changes. `Changes::generation` is the invalidation hook: it moves
+// whenever the services known or their addresses change, which is all that decides the
+// hosts asked for; anything else a query comes to depend on should move it too. With more
+// than `UNRESOLVED_LIMIT` hosts, it is built anew for each query.
+#[derive(Default)]
+struct QueryCache {
+    packet: Vec<u8>,
+    built_for: Option<u64>,
+    // Where the next query's hosts start among those `hosts` gives, while there are more
+    // than fit.
+    next_host: usize,
+}
+
+impl QueryCache {
+    fn get(
+        &mut self,
+        generation: u64,
+        hosts: impl FnOnce() -> Vec<Arc<str>>,
+        build: impl FnOnce(&mut Vec<u8>, &[Arc<str>]),
+    ) -> &[u8] {
+        if self.built_for != Some(generation) {
+            let hosts = hosts();
+            let all = hosts.len() <= UNRESOLVED_LIMIT;
+            let hosts = self.in_turn(hosts);
+
+            self.packet.clear();
+            build(&mut self.packet, &hosts);
+            self.built_for = all.then_some(generation);
+        }
 
-    // Build the query packet
-    let mut packet = Vec::new();
+        &self.packet
+    }
+
+    // The next `UNRESOLVED_LIMIT` of `hosts`, wrapping around.
+    fn in_turn(&mut self, hosts: Vec<Arc<str>>) -> Vec<Arc<str>> {
+        if hosts.len() <= UNRESOLVED_LIMIT {
+            return hosts;
+        }
+
+        let start = self.next_host % hosts.len();
+        self.next_host = start + UNRESOLVED_LIMIT;
+        hosts
+            .iter()
+            .cycle()
+            .skip(start)
+            .take(UNRESOLVED_LIMIT)
+            .cloned()
+            .collect()
+    }
+}
+
+fn build_mdns_query(
+    packet: &mut Vec<u8>,
+    filter: Filter,
+    hosts: &[Arc<str>],
+    family: AddressFamily,
+    unicast_response: bool,
+) {
+    // Create DNS header
+    let mut header = DnsHeader::new_query();
+    header.num_questions = 0;
 
     // Add header
     packet.extend(header.to_bytes());
 
-    // Add question section
-    packet.extend(encode_dns_name(service_name));
+    // Add question section, a PTR question for each service type
+    for service_name in filter.service_types() {
+        packet.extend(encode_dns_name(&service_name));
 
-    // Add QTYPE (PTR = 12) and QCLASS (IN = 1) with QU bit set
-    packet.extend_from_slice(&(12u16).to_be_bytes()); // QTYPE
-    packet.extend_from_slice(&(1u16).to_be_bytes()); // QCLASS
+        // Add QTYPE (PTR = 12) and QCLASS (IN = 1), with the top bit for a unicast answer
+        let qclass = if unicast_response { 0x8001u16 } else { 1u16 };
+        packet.extend_from_slice(&(12u16).to_be_bytes()); // QTYPE
+        packet.extend_from_slice(&qclass.to_be_bytes()); // QCLASS
+        header.num_questions += 1;
+    }
 
-    let mdns_addr = SocketAddrV4::new(MULTICAST_ADDR, MULTICAST_PORT);
-    socket.send_to(&packet, mdns_addr)?;
+    // Ask for addresses directly, so a host that answered SRV over one family but only
+    // has records of the other still gets them.
+    header.num_questions += address_questions(packet, hosts, family);
+
+    packet[4..6].copy_from_slice(&header.num_questions.to_be_bytes());
+}
+
+// Asks after services about to expire by name, as RFC 6762 section 10.4 suggests before
+// giving up on a record: the SRV record of an instance claimed on each, and the addresses
+// of their hosts.
+// The addresses only of hosts `resolves` names.
+fn build_reconfirm_query(
+    packet: &mut Vec<u8>,
+    names: &[(Option<Box<str>>, Arc<str>)],
+    family: AddressFamily,
+    resolves: impl Fn(&str) -> bool,
+) {
+    let mut header = DnsHeader::new_query();
+    header.num_questions = 0;
+    packet.extend(header.to_bytes());
+
+    for instance in names.iter().filter_map(|(instance, _)| instance.as_deref()) {
+        packet.extend(encode_dns_name(instance));
+        packet.extend_from_slice(&(33u16).to_be_bytes()); // SRV
+        packet.extend_from_slice(&(1u16).to_be_bytes());
+        header.num_questions += 1;
+    }
+
+    let mut hosts: Vec<Arc<str>> = names
+        .iter()
+        .map(|(_, host)| host.clone())
+        .filter(|host| resolves(host) && host.parse::<Ipv4Addr>().is_err())
+        .collect();
+    hosts.sort();
+    hosts.dedup();
+    header.num_questions += address_questions(packet, &hosts, family);
+
+    packet[4..6].copy_from_slice(&header.num_questions.to_be_bytes());
+}
+
+// Asks for the SRV and TXT records of instances only PTR answers have named so far, the
+// SRV records alone without `txt`.
+fn build_follow_up_query(packet: &mut Vec<u8>, instances: &[Box<str>], txt: bool) {
+    let mut header = DnsHeader::new_query();
+    header.num_questions = 0;
+    packet.extend(header.to_bytes());
+
+    let qtypes: &[u16] = match txt {
+        true => &[33, 16],
+        false => &[33],
+    };
+    for instance in instances {
+        for &qtype in qtypes {
+            packet.extend(encode_dns_name(instance));
+            packet.extend_from_slice(&qtype.to_be_bytes()); // SRV or TXT
+            packet.extend_from_slice(&(1u16).to_be_bytes());
+            header.num_questions += 1;
+        }
+    }
+
+    packet[4..6].copy_from_slice(&header.num_questions.to_be_bytes());
+}
+
+// Adds A or AAAA questions, or both, for `hosts`, and returns how many.
+fn address_questions(packet: &mut Vec<u8>, hosts: &[Arc<str>], family: AddressFamily) -> u16 {
+    let mut questions = 0;
+
+    for host in hosts {
+        for (qtype, wanted) in [(1u16, family.v4()), (28u16, family.v6())] {
+            if wanted {
+                packet.extend(encode_dns_name(host));
+                packet.extend_from_slice(&qtype.to_be_bytes()); // A or AAAA
+                packet.extend_from_slice(&(1u16).to_be_bytes());
+                questions += 1;
+            }
+        }
+    }
+
+    questions
+}
+
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+fn send_mdns_query(
+    socket: &UdpSocket,
+    group: SocketAddr,
+    packet: &[u8],
+    service_name: &str,
+    capture: Option<&Capture>,
+) -> Result<(), Box<dyn Error>> {
+    socket.send_to(packet, group)?;
+
+    if let Some(capture) = capture {
+        capture.sent(socket, group, packet);
+    }
+
+    debug!(socket = ?socket.local_addr().ok(), service = service_name, "query sent");
 
     Ok(())
 }
 
+// Responses with a non-zero RCODE must be ignored (RFC 6762, 18.11): what some gateways
+// and captive portals send to the group that way is junk.
+fn error_response(packet: &Packet) -> bool {
+    !packet.header.query && packet.header.response_code != 0
+}
+
+#[allow(clippy::too_many_arguments)]
 fn handle_response(
     packet: &Packet,
     from: SocketAddr,
-    service: &str,
-    database: &Mutex<HashMap<Service, ServiceRecord>>,
+    filter: Filter,
+    family: AddressFamily,
+    database: &Mutex<Database>,
+    metrics: &Metrics,
+    changes: &Changes,
+    now: Instant,
+    queried: Option<Instant>,
 ) {
-    if packet.header.query {
+    if error_response(packet) {
+        metrics.error_response();
         return;
     }
 
-    let mut database = database.lock().unwrap();
+    // Only an IPv6 source says which interface it arrived on.
+    let interface = match from {
+        SocketAddr::V6(from) => from.scope_id(),
+        SocketAddr::V4(_) => 0,
+    };
 
-    for answer in &packet.answers {
-        if let ResourceRecord {
-            name,
-            data: RData::SRV(Srv { target, port, .. }),
-            ..
-        } = answer
-        {
-            if name.to_string().contains(service) {
-                let service = Service {
-                    host: target.to_string(),
-                    port: *port,
-                };
-
-                database
-                    .entry(service)
-                    .and_modify(|e| e.last_seen_time = Instant::now())
-                    .or_insert_with(|| ServiceRecord {
-                        last_seen_time: Instant::now(),
-                        preferred_address: None,
-                        addresses: HashSet::new(),
-                    });
+    let mut updates = Updates::default();
+    updates.gather(packet, from, interface, now, filter, family);
+
+    let mut database = Database::lock(database);
+    updates.apply(&mut database, metrics, queried, || true);
+
+    metrics.database_size(database.len());
+    changes.update(database, now);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn process_packet(
+    data: &[u8],
+    from: SocketAddr,
+    filter: Filter,
+    family: AddressFamily,
+    database: &Mutex<Database>,
+    metrics: &Metrics,
+    changes: &Changes,
+    now: Instant,
+    queried: Option<Instant>,
+) -> Result<(), ParseError> {
+    metrics.packet_received();
+
+    match Packet::parse(data) {
+        Ok(packet) => {
+            if packet.skipped > 0 {
+                debug!(%from, skipped = packet.skipped, "skipped records that failed to parse");
+                metrics.packet_salvaged();
             }
+            handle_response(
+                &packet, from, filter, family, database, metrics, changes, now, queried,
+            );
+            Ok(())
+        }
+        Err(err) => {
+            warn!(%from, error = %err, "failed to parse packet");
+            metrics.parse_failure();
+            Err(err)
         }
     }
+}
 
-    for answer in &packet.answers {
-        if let ResourceRecord {
-            name,
-            data: RData::A(A(addr)),
-            ..
-        } = answer
-        {
-            for (k, v) in database.iter_mut() {
-                if k.host == name.to_string() {
-                    if let SocketAddr::V4(from) = from {
-                        if from.ip() == addr {
-                            v.preferred_address = Some(*addr);
-                        }
-                    }
+// "Instance._http._tcp.local" is browsed as "_http._tcp" in "local" by the daemon
+// backends; subtypes such as "_printer._sub._http._tcp" are kept whole.
+fn split_service_type(service: &str) -> Option<(String, String)> {
+    let labels: Vec<&str> = service.trim_end_matches('.').split('.').collect();
+    let protocol = labels.iter().position(|l| *l == "_tcp" || *l == "_udp")?;
 
-                    v.addresses.insert(*addr);
-                }
-            }
-        }
+    let start = labels[..protocol]
+        .iter()
+        .rposition(|l| !l.starts_with('_'))
+        .map_or(0, |i| i + 1);
+
+    if start == protocol {
+        return None;
     }
+
+    let domain = match labels[protocol + 1..].join(".") {
+        domain if domain.is_empty() => "local".to_string(),
+        domain => domain,
+    };
+
+    Some((labels[start..=protocol].join("."), domain))
 }
 
-fn receive_response(
-    socket: &UdpSocket,
-    service: &str,
-    database: &Mutex<HashMap<Service, ServiceRecord>>,
-) -> Result<(), Box<dyn Error>> {
-    let mut buffer: [u8; 2048] = [0; 2048];
+/// Returned by `MdnsClient::new` and `MdnsClientBuilder::build` for a service name that
+/// could never match anything, before anything is started. `reason` says what is wrong.
+#[derive(Clone, Debug)]
+pub struct InvalidServiceName {
+    pub service: String,
+    pub reason: String,
+}
 
-    loop {
-        let (count, from) = socket.recv_from(&mut buffer)?;
+impl fmt::Display for InvalidServiceName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid service name {:?}: {}",
+            self.service, self.reason
+        )
+    }
+}
 
-        if let Ok(packet) = dns_parser::Packet::parse(&buffer[..count]) {
-            handle_response(&packet, from, service, database);
-        }
+impl Error for InvalidServiceName {}
+
+// An instance ("My Device._http._tcp.local") or a service type ("_http._tcp.local",
+// "_printer._sub._http._tcp.local"), optionally with the final dot: labels of 1 to 63
+// bytes, 253 in all, and a `_tcp` or `_udp` label after a `_name` one, then the domain.
+fn check_service_name(service: &str) -> Result<(), InvalidServiceName> {
+    let invalid = |reason: String| InvalidServiceName {
+        service: service.to_string(),
+        reason,
+    };
+    let name = service.strip_suffix('.').unwrap_or(service);
+
+    if name.is_empty() {
+        return Err(invalid(
+            "it is empty; give a service type such as `_http._tcp.local`".into(),
+        ));
+    }
+
+    if name.len() > 253 {
+        return Err(invalid("it is longer than 253 bytes".into()));
     }
+
+    let labels: Vec<&str> = name.split('.').collect();
+
+    if labels.iter().any(|label| label.is_empty()) {
+        return Err(invalid("it has an empty label, between two dots".into()));
+    }
+
+    if let Some(label) = labels.iter().find(|label| label.len() > 63) {
+        return Err(invalid(format!(
+            "label {:?} is longer than 63 bytes",
+            label
+        )));
+    }
+
+    let protocol = labels.iter().rposition(|label| {
+        label.eq_ignore_ascii_case("_tcp") || label.eq_ignore_ascii_case("_udp")
+    });
+
+    let Some(protocol) = protocol else {
+        return Err(invalid(
+            "there is no `_tcp` or `_udp` label; give a service type such as \
+             `_http._tcp.local` or an instance such as `My Device._http._tcp.local`"
+                .into(),
+        ));
+    };
+
+    if protocol == 0 || labels[protocol - 1].len() < 2 || !labels[protocol - 1].starts_with('_') {
+        return Err(invalid(format!(
+            "`{}` must follow a service label such as `_http`",
+            labels[protocol]
+        )));
+    }
+
+    if protocol == labels.len() - 1 {
+        return Err(invalid(format!(
+            "there is no domain after `{}`; did you mean `{}.local`?",
+            labels[protocol], name
+        )));
+    }
+
+    Ok(())
 }
 
-fn remove_old_entries(database: &Mutex<HashMap<Service, ServiceRecord>>) {
-    let mut database = database.lock().unwrap();
-    database.retain(|_, v| v.last_seen_time.elapsed() < Duration::from_secs(5));
+// Has `mark` track services for `MdnsClientBuilder::defer_resolution`; if it did any, the
+// query is built again to ask for their addresses, and sent right away.
+fn track(
+    database: &Mutex<Database>,
+    changes: &Changes,
+    command_tx: &SyncSender<Command>,
+    mark: impl FnOnce(&mut Database) -> bool,
+) {
+    if mark(&mut Database::lock(database)) {
+        changes.invalidate();
+        // A full channel means a query is already pending.
+        command_tx.try_send(Command::Query).ok();
+    }
 }
 
-#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+// Returns whether the last services expired.
+fn remove_old_entries(
+    database: &Mutex<Database>,
+    metrics: &Metrics,
+    changes: &Changes,
+    now: Instant,
+) -> bool {
+    let mut database = Database::lock(database);
+    let expired = database.expire(now);
+    if expired > 0 {
+        debug!(count = expired, "expired services");
+    }
+
+    let emptied = expired > 0 && database.is_empty();
+    metrics.expired(expired);
+    metrics.database_size(database.len());
+    changes.update(database, now);
+    emptied
+}
+
+// Runs a background thread's work. A panic ends it as if it had returned, and is passed
+// to `report` for `MdnsClient::last_error` instead of going unnoticed.
+fn catch_panic(work: impl FnOnce(), report: impl FnOnce(&str)) {
+    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(work)) {
+        let message = match payload.downcast_ref::<&str>() {
+            Some(message) => message,
+            None => payload
+                .downcast_ref::<String>()
+                .map_or("panicked", String::as_str),
+        };
+
+        warn!(%message, "background thread panicked");
+        report(message);
+    }
+}
+
+enum Command {
+    Exit,
+    Query,
+    // A query right away, as after everything expired, which the socket backend limits to
+    // one every `daemon::RECOVERY_INTERVAL`; the others take it as `Query`.
+    Recover,
+    // Only for the socket backend's worker, which sleeps until its next deadline: something
+    // it has to act on, such as a new advertisement, was added meanwhile.
+    Wake,
+    // Sent by `MdnsClient::reset` once the database is cleared: the socket backend's worker
+    // closes its sockets and sets them up again; the others take it as `Query`.
+    Reset,
+}
+
+/// Ordered by host, then port.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Service {
-    pub host: String,
+    /// Lowercase and without the final dot, however responders spell it, so a host's
+    /// service is only listed once. Shared by the services on the same host, so cloning one
+    /// doesn't copy the name.
+    pub host: Arc<str>,
     pub port: u16,
 }
 
@@ -196,115 +642,1881 @@ pub struct ServiceRecord {
     pub last_seen_time: Instant,
     pub preferred_address: Option<Ipv4Addr>,
     pub addresses: HashSet<Ipv4Addr>,
+    /// Only filled in when the client uses `AddressFamily::V6` or `AddressFamily::Both`.
+    /// A link-local address heard on several interfaces is listed once for each, with
+    /// that interface's scope.
+    pub ipv6_addresses: HashSet<ScopedIpv6Addr>,
+    /// Another responder announced this service's instance first, at a different host or
+    /// port, and still does: a misconfigured clone, or the same device after its address
+    /// changed. `CacheResolver::resolve` lists these last; cleared once the first one stops
+    /// answering.
+    pub conflicting: bool,
+    /// How quickly this service answered the socket backend's queries, from the SRV
+    /// answers that arrived within 500 ms of one; `None` until one has. Announcements
+    /// heard at other times don't count, and changes don't send `Updated` events.
+    pub latency: Option<Latency>,
+    /// Whether the SRV answers this service was heard in came as answers to the socket
+    /// backend's queries or unasked. Changes don't send `Updated` events.
+    pub provenance: Provenance,
+    /// Set with `MdnsClient::pin`: the service doesn't expire, however long it goes
+    /// unheard; `staleness` says how long that is.
+    pub pinned: bool,
+    /// A pinned service's responder said goodbye, a TTL of zero, since it was last heard.
+    /// It is kept rather than dropped, and cleared when it is heard again.
+    pub withdrawn: bool,
+    /// Loaded from `MdnsClientBuilder::seed_file` rather than, or as well as, heard. A
+    /// seeded service is pinned for as long as the file lists it.
+    pub seeded: bool,
+    /// The hardware address of the service's preferred address, or else of another of
+    /// its addresses, from the OS neighbor table, with `MdnsClientBuilder::mac_addresses`;
+    /// kept once known, though the table drops its entry. `None` where the table has no
+    /// entry or can't be read, as by platform.
+    pub mac_address: Option<MacAddr>,
+    records: Arc<[OwnedRecord]>,
+    history: History,
+}
+
+impl ServiceRecord {
+    /// How long since the service was last heard, as of now. Only a pinned service goes
+    /// unheard for longer than five seconds.
+    pub fn staleness(&self) -> Duration {
+        self.last_seen_time.elapsed()
+    }
+
+    /// The records this service was taken from, as last received, if
+    /// `MdnsClientBuilder::keep_records` is set; none otherwise.
+    pub fn records(&self) -> &[OwnedRecord] {
+        &self.records
+    }
+
+    /// How reliably the service was listed, as of now: the share of the time since it was
+    /// first heard, how often it went and its longest gap. Kept while it is gone, for
+    /// when it comes back. Changes don't send `Updated` events.
+    pub fn availability(&self) -> Availability {
+        let now = Instant::now();
+        let expires_at = self.last_seen_time + EXPIRE_AFTER;
+        let expired = (!self.pinned && expires_at <= now).then_some(expires_at);
+        self.history.availability(now, expired)
+    }
+}
+
+// A service heard at `now` with nothing known of it yet, for the tests to hand the
+// database.
+#[cfg(test)]
+impl ServiceRecord {
+    pub(crate) fn heard_at(now: Instant) -> ServiceRecord {
+        ServiceRecord {
+            last_seen_time: now,
+            preferred_address: None,
+            addresses: HashSet::new(),
+            ipv6_addresses: HashSet::new(),
+            conflicting: false,
+            latency: None,
+            provenance: Provenance::default(),
+            pinned: false,
+            withdrawn: false,
+            seeded: false,
+            mac_address: None,
+            records: Arc::default(),
+            history: History::default(),
+        }
+    }
+}
+
+/// The time from a query to its answer, see `ServiceRecord::latency`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Latency {
+    /// That of the answer that last refreshed the service.
+    pub last: Duration,
+    pub min: Duration,
+    pub max: Duration,
+    pub mean: Duration,
+    /// The answers these are taken from.
+    pub samples: u32,
+}
+
+impl Latency {
+    fn new(latency: Duration) -> Latency {
+        Latency {
+            last: latency,
+            min: latency,
+            max: latency,
+            mean: latency,
+            samples: 1,
+        }
+    }
+
+    fn add(&mut self, latency: Duration) {
+        let samples = self.samples.saturating_add(1);
+        let total = self.mean * self.samples + latency;
+
+        *self = Latency {
+            last: latency,
+            min: self.min.min(latency),
+            max: self.max.max(latency),
+            mean: total / samples,
+            samples,
+        };
+    }
+}
+
+/// How a service's SRV answers reached the client, see `ServiceRecord::provenance`: those
+/// arriving within 500 ms of a query, as timed for `ServiceRecord::latency`, count as
+/// solicited, the rest, such as announcements overheard, as unsolicited. Other backends
+/// count none.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Provenance {
+    /// Whether the latest answer was solicited; `None` until one is counted.
+    pub last_solicited: Option<bool>,
+    pub solicited: u32,
+    pub unsolicited: u32,
 }
 
+impl Provenance {
+    fn first(solicited: bool) -> Provenance {
+        let mut provenance = Provenance::default();
+        provenance.heard(solicited);
+        provenance
+    }
+
+    fn heard(&mut self, solicited: bool) {
+        self.last_solicited = Some(solicited);
+        if solicited {
+            self.solicited = self.solicited.saturating_add(1);
+        } else {
+            self.unsolicited = self.unsolicited.saturating_add(1);
+        }
+    }
+}
+
+/// A service from `MdnsClient::get_services_including_recent()`.
+#[derive(Clone, Debug)]
+pub struct RecentService {
+    pub service: Service,
+    pub record: ServiceRecord,
+    /// When the service expired, if it has: it is kept for the grace period set with
+    /// `MdnsClientBuilder::expiry_grace` after that.
+    pub expired_at: Option<Instant>,
+}
+
+/// The IP versions the socket backend uses: which sockets it opens, which address records
+/// it asks for and which it stores.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AddressFamily {
+    /// IPv4 sockets and A records.
+    #[default]
+    V4,
+    /// IPv6 sockets on FF02::FB and AAAA records.
+    V6,
+    /// Both, merged per host whichever socket heard them.
+    Both,
+}
+
+impl AddressFamily {
+    fn v4(self) -> bool {
+        self != AddressFamily::V6
+    }
+
+    fn v6(self) -> bool {
+        self != AddressFamily::V4
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// Multicast sockets owned by this crate.
+    #[default]
+    Sockets,
+    /// The Avahi daemon over D-Bus. Needs Linux and the `avahi` feature.
+    Avahi,
+    /// The Windows DNS-SD API (`DnsServiceBrowse`). Needs Windows 10 or later and the
+    /// `windows-dns` feature.
+    WindowsDns,
+    /// Bonjour through the `dns_sd.h` API. Needs macOS and the `bonjour` feature.
+    Bonjour,
+    /// The system daemon when one is compiled in and running, sockets otherwise.
+    Auto,
+}
+
+// Called with every datagram the socket backend sends or receives, and its peer.
+type PacketHook = Arc<dyn Fn(&[u8], SocketAddr, Direction) + Send + Sync>;
+
+// Sets up the socket for an interface, given its name, address and index.
+type SocketFactory = Arc<dyn Fn(&str, IpAddr, u32) -> io::Result<UdpSocket> + Send + Sync>;
+
+/// Which way a datagram passed to `MdnsClientBuilder::on_packet` went.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+pub struct MdnsClientBuilder {
+    service: String,
+    backend: Backend,
+    #[cfg(feature = "pcap")]
+    capture: Option<std::path::PathBuf>,
+    #[cfg(feature = "pcap")]
+    capture_limit: Option<u64>,
+    family: AddressFamily,
+    devices: Vec<String>,
+    indices: Vec<u32>,
+    addresses: Vec<Ipv4Addr>,
+    all_interfaces: bool,
+    legacy_unicast_fallback: bool,
+    socket_factory: Option<SocketFactory>,
+    on_packet: Option<PacketHook>,
+    lint_responders: bool,
+    observe_queries: bool,
+    inventory: bool,
+    receive_buffer: Option<usize>,
+    bind_to_group: bool,
+    source_rate_limit: Option<u32>,
+    allowed_sources: Vec<String>,
+    denied_sources: Vec<String>,
+    discovery_rate_limit: Option<u32>,
+    suspend_threshold: Option<Duration>,
+    max_addresses: Option<usize>,
+    accept_unverified_addresses: bool,
+    initial_scan: Option<(u32, Duration)>,
+    silence_threshold: Option<u32>,
+    group_rejoin_interval: Option<Duration>,
+    expiry_grace: Option<Duration>,
+    availability_window: Option<Duration>,
+    activity_log: Option<usize>,
+    matcher: Option<Arc<dyn ServiceMatcher>>,
+    store: Option<Arc<dyn ServiceStore>>,
+    srv_only: bool,
+    defer_resolution: bool,
+    rename_detection: Option<RenameDetection>,
+    keep_records: bool,
+    query_schedule: Option<QuerySchedule>,
+    route_aware: bool,
+    connect_stagger: Option<Duration>,
+    set_up_in_background: bool,
+    share_cache: Option<std::path::PathBuf>,
+    seed_file: Option<std::path::PathBuf>,
+    watch_seed_file: bool,
+    attach_shared: Option<std::path::PathBuf>,
+    #[cfg(feature = "neighbors")]
+    mac_addresses: bool,
+    held: Option<Box<dyn Any + Send + Sync>>,
+}
+
+impl MdnsClientBuilder {
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// `AddressFamily::V4` by default. With IPv6, one socket per interface listens and
+    /// queries on FF02::FB, for responders that only speak mDNS over IPv6. Only applies to
+    /// the socket backend.
+    pub fn address_family(mut self, family: AddressFamily) -> Self {
+        self.family = family;
+        self
+    }
+
+    /// Ties the socket backend's sockets to the named network devices with
+    /// `SO_BINDTODEVICE`, e.g. `["br-lan"]` on a router with VRFs, so queries and answers
+    /// stay in the device's routing domain. Only those devices are used. Needs Linux or
+    /// Android, and `CAP_NET_RAW` on kernels before 5.7.
+    pub fn bind_to_devices<S: Into<String>>(mut self, names: impl IntoIterator<Item = S>) -> Self {
+        self.devices = names.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Binds the socket backend's IPv4 sockets to 224.0.0.251 rather than the wildcard
+    /// address, so the kernel only hands them datagrams sent to the group, not unicast
+    /// ones to port 5353 or other traffic for the port, which would all be parsed. Unicast
+    /// answers aren't heard then, so it can't go with `QuerySchedule::unicast_response`,
+    /// and queries sent straight to this host go unanswered; sockets that fell back to
+    /// `legacy_unicast_fallback` hear their answers as before. Needs Linux or Android.
+    pub fn bind_to_group(mut self) -> Self {
+        self.bind_to_group = true;
+        self
+    }
+
+    /// Restricts the socket backend to the interface with this index, e.g. the Wi-Fi
+    /// interface's `NetworkInterface.getIndex()` on Android. May be called more than once.
+    pub fn interface_index(mut self, index: u32) -> Self {
+        self.indices.push(index);
+        self
+    }
+
+    /// Restricts the socket backend to the interfaces holding these local IPv4 addresses,
+    /// e.g. to keep mDNS off a management network. IPv6 sockets are only opened on those
+    /// interfaces too. Building fails if none of the addresses exist.
+    pub fn interface_addresses(mut self, addresses: impl IntoIterator<Item = Ipv4Addr>) -> Self {
+        self.addresses = addresses.into_iter().collect();
+        self
+    }
+
+    /// Also uses the interfaces the socket backend leaves out by default: those that are
+    /// down, link-local IPv4 addresses (169.254/16, which Windows gives adapters when DHCP
+    /// fails) unless passed to `interface_addresses`, and on Windows the Hyper-V Default
+    /// Switch and WSL adapters, unless chosen by device or index. The interfaces used are
+    /// listed in `MdnsStats::sockets`.
+    pub fn all_interfaces(mut self) -> Self {
+        self.all_interfaces = true;
+        self
+    }
+
+    /// Where another program holds port 5353 without sharing it, as Bonjour for Windows
+    /// can, queries from an ephemeral port instead of failing. Responders answer such
+    /// legacy unicast queries (RFC 6762 section 6.7) directly, so services are still found
+    /// when queried, but announcements and goodbyes aren't heard, nor can this client
+    /// advertise on those interfaces. `SocketStats::legacy_unicast` tells which sockets
+    /// fell back. Only applies to the socket backend.
+    pub fn legacy_unicast_fallback(mut self) -> Self {
+        self.legacy_unicast_fallback = true;
+        self
+    }
+
+    /// Has `factory` set up each socket instead, for options this crate doesn't offer,
+    /// such as `SO_MARK` or an attached BPF program. It is called with the interface's
+    /// name, address and index whenever the socket backend would open a socket on it: at
+    /// first, as interfaces come and go, to recreate a broken socket and on
+    /// `MdnsClient::reset`. The socket must be bound to port 5353 and have joined
+    /// 224.0.0.251, or FF02::FB for an IPv6 address, on that interface, with the interface
+    /// set for sending; the client only makes it blocking with a read timeout, and leaves
+    /// the group before closing it. An error skips the interface, as when the crate's own
+    /// setup fails. `receive_buffer`, `bind_to_devices` and `legacy_unicast_fallback` are
+    /// then up to the factory. Only applies to the socket backend.
+    pub fn socket_factory(
+        mut self,
+        factory: impl Fn(&str, IpAddr, u32) -> io::Result<UdpSocket> + Send + Sync + 'static,
+    ) -> Self {
+        self.socket_factory = Some(Arc::new(factory));
+        self
+    }
+
+    /// Calls `hook` with every datagram the socket backend sends or receives, as it is on
+    /// the wire, and the address it went to or came from, e.g. to log traffic on a box
+    /// without Wireshark. Received datagrams include the client's own queries looped back,
+    /// but not those dropped by `allow_sources`, `deny_sources` or `source_rate_limit`.
+    /// The hook runs on the client's threads, though with none of its locks held: a slow
+    /// one holds up receiving on that socket or the worker, so it should hand work off
+    /// rather than block. Only applies to the socket backend.
+    pub fn on_packet(
+        mut self,
+        hook: impl Fn(&[u8], SocketAddr, Direction) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_packet = Some(Arc::new(hook));
+        self
+    }
+
+    /// Checks what responders send against RFC 6762 and RFC 6763 as it arrives, such as
+    /// unique records without the cache-flush bit or responses from a port other than
+    /// 5353, and keeps what each source got wrong; see `MdnsClient::lint_findings`. Off
+    /// by default, as it looks at every record of every datagram. Only applies to the
+    /// socket backend and `MdnsClient::process_packet`.
+    pub fn lint_responders(mut self) -> Self {
+        self.lint_responders = true;
+        self
+    }
+
+    /// Keeps the questions other hosts ask, by source, name, type and QU bit, with how
+    /// many known answers went along; see `MdnsClient::observed_queries` and
+    /// `MdnsClient::query_sources`. For seeing who browses for what. Off by default; what
+    /// is kept is capped, and each source's queries are only looked at 20 times a second.
+    /// Only applies to the socket backend and `MdnsClient::process_packet`.
+    pub fn observe_queries(mut self) -> Self {
+        self.observe_queries = true;
+        self
+    }
+
+    /// Also keeps every service heard of, whatever its type and `matcher`, with its
+    /// records, for `MdnsClient::get_inventory`: what is on the network, from the
+    /// responses and announcements the client hears anyway. Nothing is asked after, so
+    /// each service lasts as long as its SRV record's TTL says; at most 1024 are kept.
+    /// `get_services()` and the events are unchanged. Only applies to the socket backend
+    /// and `MdnsClient::process_packet`.
+    pub fn inventory(mut self) -> Self {
+        self.inventory = true;
+        self
+    }
+
+    /// Asks for a receive buffer of `bytes` on each of the socket backend's sockets
+    /// (`SO_RCVBUF`), so bursts of announcements aren't dropped between reads. The size in
+    /// effect and the kernel's drops are in `SocketStats`.
+    pub fn receive_buffer(mut self, bytes: usize) -> Self {
+        self.receive_buffer = Some(bytes);
+        self
+    }
+
+    /// Drops datagrams from any one address beyond `packets` a second, 50 by default,
+    /// before they are parsed, so a host flooding the network can't keep the socket
+    /// backend busy. Bursts of up to as many at once get through. Counted in
+    /// `MdnsStats::rate_limited`. Only applies to the socket backend.
+    pub fn source_rate_limit(mut self, packets: u32) -> Self {
+        self.source_rate_limit = Some(packets);
+        self
+    }
+
+    /// Only takes datagrams from sources in these ranges, such as `192.168.1.0/24`, `fd00::/8`
+    /// or a single address, e.g. to ignore multicast reflected from other networks. Others
+    /// are dropped before they are parsed, queries to the client's advertisements among
+    /// them, and counted in `MdnsStats::sources_rejected`; a range that doesn't parse fails
+    /// `build`. Every source is taken by default. Only applies to the socket backend.
+    pub fn allow_sources<S: Into<String>>(mut self, ranges: impl IntoIterator<Item = S>) -> Self {
+        self.allowed_sources
+            .extend(ranges.into_iter().map(Into::into));
+        self
+    }
+
+    /// Drops datagrams from sources in these ranges as `allow_sources` drops the others,
+    /// even where an allowed range holds them. Only applies to the socket backend.
+    pub fn deny_sources<S: Into<String>>(mut self, ranges: impl IntoIterator<Item = S>) -> Self {
+        self.denied_sources
+            .extend(ranges.into_iter().map(Into::into));
+        self
+    }
+
+    /// Adds at most `services` new services a second, 200 by default, so fake
+    /// announcements can't grow the database without bound; the rest are added once they
+    /// answer again. Services already known are refreshed regardless. Counted in
+    /// `MdnsStats::discoveries_limited`. Only applies to the socket backend.
+    pub fn discovery_rate_limit(mut self, services: u32) -> Self {
+        self.discovery_rate_limit = Some(services);
+        self
+    }
+
+    /// How long the worker may seem not to have run, by the monotonic or the wall clock,
+    /// before it takes the system to have been suspended, 10 seconds by default. It then
+    /// queries at once and gives every service known the usual five seconds to answer,
+    /// whatever the clocks say about when it was last seen. Only applies to the socket
+    /// backend.
+    pub fn suspend_threshold(mut self, gap: Duration) -> Self {
+        self.suspend_threshold = Some(gap);
+        self
+    }
+
+    /// Keeps at most `addresses` IPv4 and IPv6 addresses, at least one, for the services
+    /// on each host, 32 by default, so a responder can't grow a record without bound.
+    /// Past that the one heard of first is dropped, counted in
+    /// `MdnsStats::addresses_evicted`. Only applies to the socket backend.
+    pub fn max_addresses(mut self, addresses: usize) -> Self {
+        self.max_addresses = Some(addresses.max(1));
+        self
+    }
+
+    /// Applies every address record for a host with known services, as announced by
+    /// anyone. By default one only adds to them if it came with an SRV record naming the
+    /// host, or from a responder the host's services were heard from, or is a cache-flush
+    /// record while the host has no address of its family yet; the rest are counted in
+    /// `MdnsStats::addresses_rejected`. For networks where hosts have their addresses
+    /// announced by another device. Only applies to the socket backend.
+    pub fn accept_unverified_addresses(mut self) -> Self {
+        self.accept_unverified_addresses = true;
+        self
+    }
+
+    /// When `MdnsClient::wait_for_initial_scan` takes the first scan to be done: once
+    /// `rounds` queries, 2 by default, have each had half a second to be answered, or
+    /// `warm_up` after building, 3 seconds by default, whichever comes first. Only the
+    /// socket backend counts rounds; the platform backends wait for the warm-up.
+    pub fn initial_scan(mut self, rounds: u32, warm_up: Duration) -> Self {
+        self.initial_scan = Some((rounds, warm_up));
+        self
+    }
+
+    /// After how many query cycles (seconds) in a row without a datagram from another
+    /// host the client warns, 10 by default: a network with other mDNS hosts on it is
+    /// never that quiet, but a firewall dropping inbound UDP port 5353 makes it so. Shown
+    /// by `MdnsStats::silent` and `MdnsClient::last_error()` until something arrives. Only
+    /// applies to the socket backend.
+    pub fn silence_threshold(mut self, cycles: u32) -> Self {
+        self.silence_threshold = Some(cycles.max(1));
+        self
+    }
+
+    /// How often each socket leaves the multicast group and joins it again, two minutes by
+    /// default. Joining sends a fresh membership report, for managed switches whose IGMP
+    /// snooping otherwise stops forwarding the group after a few minutes when the OS
+    /// doesn't answer their queries. Also done once a client's `silence_threshold` is
+    /// reached; zero turns off both. Counted in `SocketStats::group_rejoins`. Only applies
+    /// to the socket backend.
+    pub fn group_rejoin_interval(mut self, interval: Duration) -> Self {
+        self.group_rejoin_interval = Some(interval);
+        self
+    }
+
+    /// How long an expired service is remembered, none by default. One that answers again
+    /// within this long comes back without an `Expired` and `Added` event, or a new
+    /// generation, so a responder missing a query or two doesn't flap; one that doesn't is
+    /// only reported expired once it is over. Until then it is left out of
+    /// `get_services()` but listed by `get_services_including_recent()`. Services that
+    /// moved or were forgotten go at once. Building fails for more than a year, and
+    /// `MdnsClient::set_expiry_policy` changes it later. Only applies to the socket backend.
+    pub fn expiry_grace(mut self, grace: Duration) -> Self {
+        self.expiry_grace = Some(grace);
+        self
+    }
+
+    /// How far back `ServiceRecord::availability` looks, a day by default; what is older
+    /// no longer counts, and services gone for longer are forgotten. Building fails for
+    /// more than a year, and `MdnsClient::set_expiry_policy` changes it later. Only applies
+    /// to the socket backend.
+    pub fn availability_window(mut self, window: Duration) -> Self {
+        self.availability_window = Some(window);
+        self
+    }
+
+    /// How many entries `MdnsClient::recent_activity()` keeps, 256 by default; zero turns
+    /// the log off.
+    pub fn activity_log(mut self, entries: usize) -> Self {
+        self.activity_log = Some(entries);
+        self
+    }
+
+    /// Takes the services whose SRV records `matcher` matches, rather than those whose
+    /// instance name contains the service type: particular instances with an
+    /// `InstanceMatcher`, several service types with a `ServiceTypeMatcher`, or whatever a
+    /// closure taking a `&ServiceName` says. The matcher's `service_types` are queried
+    /// besides the client's own; building fails if one isn't a valid service name.
+    /// Applies to the socket backend and `MdnsClient::process_packet`.
+    pub fn matcher(mut self, matcher: impl ServiceMatcher + 'static) -> Self {
+        self.matcher = Some(Arc::new(matcher));
+        self
+    }
+
+    /// Takes services from their SRV records alone, for callers that resolve host names
+    /// themselves: address records are ignored and no addresses are asked for, so
+    /// `ServiceRecord::addresses` is only ever set for an SRV target that is itself an
+    /// IPv4 address, and `CacheResolver` finds nothing. Services are added, refreshed and
+    /// expired as usual. Applies to the socket backend and `MdnsClient::process_packet`.
+    pub fn srv_only(mut self) -> Self {
+        self.srv_only = true;
+        self
+    }
+
+    /// Browses as `srv_only` does, for networks with many instances of which only a few
+    /// are ever used: addresses and, with `keep_records`, TXT records are only asked for
+    /// and taken for the services `MdnsClient::track` marks, or that `connect_to_service`
+    /// or `CacheResolver::resolve` look up. Others are kept from their SRV records alone,
+    /// and instances only PTR answers named are asked for their SRV records without the
+    /// TXT. Only applies to the socket backend.
+    pub fn defer_resolution(mut self) -> Self {
+        self.defer_resolution = true;
+        self
+    }
+
+    /// Reports an instance renamed on the same host and port, such as a printer renamed in
+    /// its web interface, as `ServiceEvent::Renamed`; see `RenameDetection` for how it is
+    /// told from a second instance. Without this, such a rename changes nothing about the
+    /// service, which is kept by host and port, and raises no event. Only applies to the
+    /// socket backend.
+    pub fn detect_renames(mut self, detection: RenameDetection) -> Self {
+        self.rename_detection = Some(detection);
+        self
+    }
+
+    /// Keeps the resource records behind each service as last received, for
+    /// `ServiceRecord::records`: its SRV, TXT and PTR records and its host's address
+    /// records, the latest of each type and name. Costs a copy of those records per
+    /// service. Applies to the socket backend and `MdnsClient::process_packet`.
+    pub fn keep_records(mut self) -> Self {
+        self.keep_records = true;
+        self
+    }
+
+    /// When this client's queries go out, instead of once a second: clients sharing the
+    /// sockets each keep their own schedule, and queries due within 50 ms of each other go
+    /// out in one datagram. Services already found are still asked after by name before
+    /// they expire, so a slow schedule doesn't lose them. Restarts when the interfaces
+    /// change, after `reset` and after the host was suspended. Building fails if the
+    /// interval is zero, the backoff is zero or the maximum is below the interval or over
+    /// a day. `MdnsClient::set_query_schedule` changes it later. Only applies to the
+    /// socket backend.
+    pub fn query_schedule(mut self, schedule: QuerySchedule) -> Self {
+        self.query_schedule = Some(schedule);
+        self
+    }
+
+    /// Has `CacheResolver::resolve` list first the IPv4 addresses on the same subnet as one
+    /// of this host's interfaces, as `rank_addresses` orders them, and
+    /// `MdnsClient::connect_to_service` try them first, so connecting to a device
+    /// advertised on several subnets tries one it can reach first.
+    pub fn route_aware_addresses(mut self) -> Self {
+        self.route_aware = true;
+        self
+    }
+
+    /// Has `MdnsClient::connect_to_service` start on the next address once an attempt has
+    /// gone `delay` without connecting, keeping the earlier ones going, as Happy Eyeballs
+    /// (RFC 8305) suggests 250 ms for; by default each address is only tried once the one
+    /// before has failed.
+    pub fn connect_stagger(mut self, delay: Duration) -> Self {
+        self.connect_stagger = Some(delay);
+        self
+    }
+
+    /// Also keeps `store` in step with the services, e.g. to have them land in an
+    /// application's own map rather than be polled for with `get_services()`. The
+    /// services it already holds are taken up, and those found before `build()` returns
+    /// are inserted at once.
+    pub fn service_store(mut self, store: impl ServiceStore + 'static) -> Self {
+        self.store = Some(Arc::new(store));
+        self
+    }
+
+    /// Makes `build()` return at once, listing the interfaces and setting up their sockets
+    /// on a thread of its own, for callers such as a GUI thread that can't wait for that.
+    /// `MdnsClient::is_ready()` tells when it is done; until then the client is empty. If
+    /// setting up fails, the client stays empty and `MdnsClient::last_error()` has what
+    /// `build()` would have returned, as `FailedOperation::Setup`. Such a client has
+    /// sockets of its own rather than sharing those of other clients. Only applies to the
+    /// socket backend.
+    pub fn set_up_in_background(mut self) -> Self {
+        self.set_up_in_background = true;
+        self
+    }
+
+    /// Publishes the client's services to `path` for other processes on the host, which
+    /// follow them with `attach_shared` rather than each opening sockets and querying. The
+    /// file is rewritten whenever the services change and at least every second, and
+    /// removed when the client is dropped; failing to write it is reported in
+    /// `last_error()` as `FailedOperation::ShareCache`. The directory should be one only
+    /// trusted processes can write to, as attached clients take what is in it as heard.
+    pub fn share_cache(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.share_cache = Some(path.into());
+        self
+    }
+
+    /// Lists the services of the file at `path`, for devices on segments where multicast
+    /// doesn't get through, from when `build()` returns, which fails if the file can't be
+    /// read. It holds a `[[service]]` table for each, in TOML:
+    /// `instance = "Printer"`, `host = "printer.local"`, `port = 631` and
+    /// `addresses = ["192.168.1.20", "fe80::1%3"]`, each on a line of its own, and
+    /// optionally `txt = ["rp=ipp/print"]`. Seeded services are pinned, see
+    /// `MdnsClient::pin`, flagged `ServiceRecord::seeded`, and refreshed as usual if also
+    /// heard; their instance names work with `connect_to_instance`, and their TXT entries
+    /// are in `ServiceRecord::records`. A service with a line that can't be taken is left
+    /// out, the others kept, and the lines are in `MdnsClient::seed_errors()` and
+    /// `last_error()` as `FailedOperation::Seed`. They stay through `MdnsClient::reset`.
+    pub fn seed_file(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.seed_file = Some(path.into());
+        self
+    }
+
+    /// Loads the `seed_file` again whenever it is modified, looking every second: services
+    /// added are listed, and those taken out are no longer pinned and expire unless heard.
+    /// If it can't be read, the services stay as they were.
+    pub fn watch_seed_file(mut self) -> Self {
+        self.watch_seed_file = true;
+        self
+    }
+
+    /// Follows the services another process publishes to `path` with `share_cache`, if it
+    /// does for this service type, instead of setting up sockets. When the owner stops
+    /// rewriting the file, such as when it exits, the client sets up sockets of its own in
+    /// the background and finds the services itself, as with `set_up_in_background`; with
+    /// no such file it does from the start. While attached, `MdnsClient::is_ready()` is
+    /// false and queries are left to the owner. Only applies to the socket backend.
+    pub fn attach_shared(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.attach_shared = Some(path.into());
+        self
+    }
+
+    /// Keeps `value` until the client is dropped and has stopped. On Android, pass a guard
+    /// that releases a `WifiManager.MulticastLock` when dropped: without the lock most
+    /// Wi-Fi drivers filter out multicast and the client sees little or nothing.
+    pub fn hold_while_running(mut self, value: impl Any + Send + Sync) -> Self {
+        self.held = Some(Box::new(value));
+        self
+    }
+
+    /// Writes every datagram sent and received by the socket backend to a pcap file.
+    #[cfg(feature = "pcap")]
+    pub fn capture_to(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.capture = Some(path.into());
+        self
+    }
+
+    /// Caps the capture file at roughly `bytes`; when full it is moved to `<path>.1`,
+    /// replacing any older one, and a new file is started.
+    #[cfg(feature = "pcap")]
+    pub fn capture_limit(mut self, bytes: u64) -> Self {
+        self.capture_limit = Some(bytes);
+        self
+    }
+
+    /// Sets `ServiceRecord::mac_address` from the OS neighbor table, looked up as services
+    /// change and every 30 seconds: the ARP table on Linux and Android, so only for IPv4
+    /// addresses, and both families on Windows. A host only has entries for neighbors on
+    /// its own links it has talked to; where the table can't be read, services have none.
+    #[cfg(feature = "neighbors")]
+    pub fn mac_addresses(mut self) -> Self {
+        self.mac_addresses = true;
+        self
+    }
+
+    pub fn build(mut self) -> Result<MdnsClient, Box<dyn Error>> {
+        check_service_name(&self.service)?;
+        if let Some(matcher) = &self.matcher {
+            for service_type in matcher.service_types() {
+                check_service_name(&service_type)?;
+            }
+        }
+        if let Some(schedule) = &self.query_schedule {
+            schedule.check()?;
+        }
+        ExpiryPolicy {
+            grace: self.expiry_grace.unwrap_or(Duration::ZERO),
+            availability_window: self.availability_window.unwrap_or(AVAILABILITY_WINDOW),
+        }
+        .check()?;
+
+        // Queried as given, which takes the name without its final dot.
+        if self.service.ends_with('.') {
+            self.service.pop();
+        }
+
+        let held = self.held.take();
+        let matcher = self.matcher.clone();
+        let srv_only = self.srv_only;
+        let keep_records = self.keep_records;
+        let route_aware = self.route_aware;
+        let connect_stagger = self.connect_stagger;
+        let share_cache = self.share_cache.take();
+        let seed_file = self.seed_file.take();
+        let watch_seed_file = self.watch_seed_file;
+        #[cfg(feature = "neighbors")]
+        let mac_addresses = self.mac_addresses;
+        let store = self.store.take();
+        let activity_log = self.activity_log.unwrap_or(activity::ACTIVITY_LOG);
+        let (rounds, warm_up) = self.initial_scan.unwrap_or((SCAN_ROUNDS, SCAN_WARM_UP));
+
+        let mut client = match self.backend {
+            Backend::Sockets => daemon::spawn(self),
+            #[cfg(all(target_os = "linux", feature = "avahi"))]
+            Backend::Avahi => avahi::spawn(&self.service),
+            #[cfg(not(all(target_os = "linux", feature = "avahi")))]
+            Backend::Avahi => Err("the Avahi backend needs Linux and the `avahi` feature".into()),
+            #[cfg(all(target_os = "windows", feature = "windows-dns"))]
+            Backend::WindowsDns => windows_dns::spawn(&self.service),
+            #[cfg(not(all(target_os = "windows", feature = "windows-dns")))]
+            Backend::WindowsDns => {
+                Err("the Windows DNS-SD backend needs Windows and the `windows-dns` feature".into())
+            }
+            #[cfg(all(target_os = "macos", feature = "bonjour"))]
+            Backend::Bonjour => bonjour::spawn(&self.service),
+            #[cfg(not(all(target_os = "macos", feature = "bonjour")))]
+            Backend::Bonjour => {
+                Err("the Bonjour backend needs macOS and the `bonjour` feature".into())
+            }
+            #[cfg(all(target_os = "linux", feature = "avahi"))]
+            Backend::Auto if avahi::is_available() => avahi::spawn(&self.service),
+            #[cfg(all(target_os = "windows", feature = "windows-dns"))]
+            Backend::Auto if windows_dns::is_available() => windows_dns::spawn(&self.service),
+            #[cfg(all(target_os = "macos", feature = "bonjour"))]
+            Backend::Auto if bonjour::is_available() => bonjour::spawn(&self.service),
+            Backend::Auto => daemon::spawn(self),
+        }?;
+
+        client.held = held;
+        client.matcher = matcher;
+        client.srv_only = srv_only;
+        client.keep_records = keep_records;
+        client.route_aware = route_aware;
+        client.connect_stagger = connect_stagger;
+        client.metrics.activity().set_capacity(activity_log);
+        client.scan.configure(rounds, warm_up);
+        if let Some(store) = store {
+            client.changes.set_store(store.clone());
+            store::load(&*store, &client.database, &client.changes);
+        }
+        if let Some(path) = seed_file {
+            client.seed = Some(seed::Seeder::start(
+                path,
+                watch_seed_file,
+                client.service.clone(),
+                client.database.clone(),
+                client.changes.clone(),
+                client.metrics.clone(),
+            )?);
+        }
+        if let Some(path) = share_cache {
+            client.share = Some(share::Writer::start(
+                path,
+                client.service.clone(),
+                client.changes.clone(),
+                client.metrics.clone(),
+            ));
+        }
+        #[cfg(feature = "neighbors")]
+        if mac_addresses {
+            client.neighbors = Some(neighbor::Lookup::start(
+                client.database.clone(),
+                client.changes.clone(),
+                client.metrics.clone(),
+            ));
+        }
+        Ok(client)
+    }
+}
+
+/// Browses for one service type. `Send` and `Sync`: one client may be shared between
+/// threads, e.g. in an `Arc`, and any of its methods called from any of them.
 pub struct MdnsClient {
-    database: Arc<Mutex<HashMap<Service, ServiceRecord>>>,
-    exit_tx: SyncSender<()>,
+    service: String,
+    database: Arc<Mutex<Database>>,
+    metrics: Arc<Metrics>,
+    changes: Arc<Changes>,
+    scan: Arc<Scan>,
+    command_tx: SyncSender<Command>,
     thread: Option<JoinHandle<()>>,
+    registration: Option<daemon::Registration>,
+    family: AddressFamily,
+    backend: Backend,
+    matcher: Option<Arc<dyn ServiceMatcher>>,
+    srv_only: bool,
+    keep_records: bool,
+    route_aware: bool,
+    connect_stagger: Option<Duration>,
+    lint: Option<Arc<Lint>>,
+    observer: Option<Arc<Observer>>,
+    inventory: Option<Arc<Inventory>>,
+    share: Option<share::Writer>,
+    seed: Option<seed::Seeder>,
+    #[cfg(feature = "neighbors")]
+    neighbors: Option<neighbor::Lookup>,
+    // Declared last, so it outlives the registration.
+    held: Option<Box<dyn Any + Send + Sync>>,
 }
 
+// Kept so by the compiler rather than by chance: a field that isn't would break
+// clients shared between threads.
+const _: fn() = || {
+    fn send_sync<T: Send + Sync>() {}
+
+    send_sync::<MdnsClient>();
+    send_sync::<Service>();
+    send_sync::<ServiceRecord>();
+    send_sync::<ServiceEvent>();
+    send_sync::<Subscription>();
+    send_sync::<CacheResolver>();
+    send_sync::<Advertisement>();
+    send_sync::<DetachedClient>();
+};
+
 impl MdnsClient {
-    pub fn new(service: &str) -> Result<MdnsClient, Box<dyn Error>> {
-        let database = Arc::new(Mutex::new(HashMap::new()));
+    /// For `service`, a string such as `_http._tcp.local` or a `ServiceType`.
+    pub fn new(service: impl AsRef<str>) -> Result<MdnsClient, Box<dyn Error>> {
+        Self::builder(service).build()
+    }
 
-        let mut sockets = Vec::new();
+    /// A client with `MdnsClientBuilder::attach_shared`.
+    pub fn attach_shared(
+        service: impl AsRef<str>,
+        path: impl Into<std::path::PathBuf>,
+    ) -> Result<MdnsClient, Box<dyn Error>> {
+        Self::builder(service).attach_shared(path).build()
+    }
 
-        #[cfg(target_os = "windows")]
-        {
-            use if_addrs::IfAddr;
+    /// As `MdnsClient::new`.
+    pub fn builder(service: impl AsRef<str>) -> MdnsClientBuilder {
+        MdnsClientBuilder {
+            service: service.as_ref().to_string(),
+            backend: Backend::default(),
+            #[cfg(feature = "pcap")]
+            capture: None,
+            #[cfg(feature = "pcap")]
+            capture_limit: None,
+            family: AddressFamily::default(),
+            devices: Vec::new(),
+            indices: Vec::new(),
+            addresses: Vec::new(),
+            all_interfaces: false,
+            legacy_unicast_fallback: false,
+            socket_factory: None,
+            on_packet: None,
+            lint_responders: false,
+            observe_queries: false,
+            inventory: false,
+            receive_buffer: None,
+            bind_to_group: false,
+            source_rate_limit: None,
+            allowed_sources: Vec::new(),
+            denied_sources: Vec::new(),
+            discovery_rate_limit: None,
+            suspend_threshold: None,
+            max_addresses: None,
+            accept_unverified_addresses: false,
+            initial_scan: None,
+            silence_threshold: None,
+            group_rejoin_interval: None,
+            expiry_grace: None,
+            availability_window: None,
+            activity_log: None,
+            matcher: None,
+            store: None,
+            srv_only: false,
+            defer_resolution: false,
+            rename_detection: None,
+            keep_records: false,
+            query_schedule: None,
+            route_aware: false,
+            connect_stagger: None,
+            set_up_in_background: false,
+            share_cache: None,
+            seed_file: None,
+            watch_seed_file: false,
+            attach_shared: None,
+            #[cfg(feature = "neighbors")]
+            mac_addresses: false,
+            held: None,
+        }
+    }
 
-            for iface in if_addrs::get_if_addrs()?
-                .into_iter()
-                .filter(|i| !i.addr.is_loopback())
-                .filter_map(|i| {
-                    if let IfAddr::V4(v4_addr) = i.addr {
-                        Some(v4_addr)
-                    } else {
-                        None
-                    }
-                })
-            {
-                let socket = create_socket(iface.ip)?;
+    /// A copy of `snapshot()`, sorted by service.
+    pub fn get_services(&self) -> Vec<(Service, ServiceRecord)> {
+        let mut services: Vec<_> = self
+            .snapshot()
+            .iter()
+            .map(|(service, record)| (service.clone(), record.clone()))
+            .collect();
+        services.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        services
+    }
+
+    /// Every service `MdnsClientBuilder::inventory` heard of, of any type, sorted by
+    /// service. Empty unless that is set.
+    pub fn get_inventory(&self) -> Vec<(Service, ServiceRecord)> {
+        self.inventory
+            .as_ref()
+            .map(|inventory| inventory.services(Instant::now()))
+            .unwrap_or_default()
+    }
+
+    /// `get_services()` and the services expired within the grace period set with
+    /// `MdnsClientBuilder::expiry_grace`, as last published, sorted by service.
+    pub fn get_services_including_recent(&self) -> Vec<RecentService> {
+        let now = Instant::now();
+        let mut services: Vec<_> = self
+            .changes
+            .published()
+            .load_recent()
+            .iter()
+            .map(|(service, record)| {
+                let expires_at = record.last_seen_time + EXPIRE_AFTER;
+                RecentService {
+                    service: service.clone(),
+                    record: record.clone(),
+                    expired_at: Some(expires_at).filter(|at| !record.pinned && *at <= now),
+                }
+            })
+            .collect();
+        services.sort_unstable_by(|a, b| a.service.cmp(&b.service));
+        services
+    }
+
+    /// The services as last published, shared rather than copied and never waiting for
+    /// packets being applied. Published on every change to the services or their
+    /// addresses; `last_seen_time` refreshes alone are published at most once a second.
+    /// Never holds a service not seen for five seconds, even before the worker has removed
+    /// it; the snapshot is copied without it then.
+    pub fn snapshot(&self) -> Arc<HashMap<Service, ServiceRecord>> {
+        self.changes.published().load(Instant::now())
+    }
 
-                socket.set_multicast_loop_v4(true)?;
-                socket.join_multicast_v4(&MULTICAST_ADDR, &iface.ip)?;
-                socket.set_nonblocking(true)?;
+    pub fn resolver(&self) -> CacheResolver {
+        CacheResolver::new(
+            self.database.clone(),
+            self.changes.clone(),
+            self.command_tx.clone(),
+            self.route_aware,
+        )
+    }
+
+    /// Tells the client that connecting to `service` failed, so what it has may be stale: it
+    /// asks after the service by name right away, as RFC 6762 section 10.4 suggests, and
+    /// drops it with an `Expired` event unless it answers within two seconds. Reports on a
+    /// service within ten seconds of the last one taken are ignored, so a reconnect loop
+    /// can't flood the network. Only applies to the socket backend.
+    pub fn report_failure(&self, service: &Service) {
+        if self.backend != Backend::Sockets {
+            return;
+        }
 
-                sockets.push(socket);
+        if Database::lock(&self.database).report_failure(service, Instant::now()) {
+            debug!(host = %service.host, port = service.port, "failure reported, reconfirming");
+            // Wakes the worker to ask, leaving every client's query schedule as it is; a
+            // full channel means it is about to wake anyway.
+            self.command_tx.try_send(Command::Wake).ok();
+        }
+    }
+
+    /// Keeps `service` in `get_services` however long it goes unheard, until `unpin`, for
+    /// peers that mustn't disappear over a network hiccup: it is refreshed and updated as
+    /// any other, but doesn't expire, isn't dropped after `report_failure` or when its
+    /// interface goes, and a goodbye marks it `withdrawn` instead of refreshing it.
+    /// `ServiceRecord::staleness` says how long ago it was last heard. May be called
+    /// before the service is found; pins survive `reset`.
+    pub fn pin(&self, service: &Service) {
+        let mut database = Database::lock(&self.database);
+        database.pin(service);
+        self.changes.update(database, Instant::now());
+    }
+
+    /// The lines of `MdnsClientBuilder::seed_file` that couldn't be taken when it was last
+    /// loaded, in order; none without a seed file.
+    pub fn seed_errors(&self) -> Vec<SeedError> {
+        self.seed
+            .as_ref()
+            .map_or_else(Vec::new, |seed| seed.errors())
+    }
+
+    /// Has `service` expire as others do again, straight away if it has gone unheard for
+    /// longer than five seconds.
+    pub fn unpin(&self, service: &Service) {
+        let mut database = Database::lock(&self.database);
+        database.unpin(service);
+        self.changes.update(database, Instant::now());
+    }
+
+    /// With `MdnsClientBuilder::defer_resolution`, has the addresses of `service`'s host
+    /// asked for and taken, and its TXT record kept, until `untrack`; they are asked for
+    /// right away. `connect_to_service` and `CacheResolver::resolve` call it on what they
+    /// look up. Does nothing otherwise, as every service is resolved.
+    pub fn track(&self, service: &Service) {
+        track(
+            &self.database,
+            &self.changes,
+            &self.command_tx,
+            |database| database.track(service),
+        );
+    }
+
+    /// Stops resolving `service` after `track`, forgetting the addresses and TXT records of
+    /// its host unless another service there is tracked.
+    pub fn untrack(&self, service: &Service) {
+        let mut database = Database::lock(&self.database);
+        database.untrack(service);
+        self.changes.update(database, Instant::now());
+    }
+
+    /// Connects over TCP to `service`, trying its addresses in the order
+    /// `CacheResolver::resolve` lists them, each for up to `timeout`, and returns the
+    /// stream and the address that took it. If none does, the service is reported with
+    /// `report_failure` and the last attempt's error returned; `NotFound` if the service or
+    /// any address for it isn't known.
+    pub fn connect_to_service(
+        &self,
+        service: &Service,
+        timeout: Duration,
+    ) -> io::Result<(TcpStream, SocketAddr)> {
+        let not_found = |what| io::Error::new(io::ErrorKind::NotFound, what);
+
+        self.track(service);
+        let snapshot = self.snapshot();
+        let record = snapshot
+            .get(service)
+            .ok_or_else(|| not_found("the service isn't known"))?;
+
+        let subnets = self.route_aware.then(route::local_subnets);
+        let addresses = resolver::service_addresses(service, record, subnets.as_deref());
+        if addresses.is_empty() {
+            return Err(not_found("no addresses are known for the service"));
+        }
+
+        let connected = connect::connect(&addresses, timeout, self.connect_stagger);
+        if connected.is_err() {
+            self.report_failure(service);
+        }
+        connected
+    }
+
+    /// `connect_to_service` for the service the instance, such as
+    /// `Printer._ipp._tcp.local`, was last said to be at; where several responders claim
+    /// it, the first one's.
+    pub fn connect_to_instance(
+        &self,
+        instance: &str,
+        timeout: Duration,
+    ) -> io::Result<(TcpStream, SocketAddr)> {
+        let service = Database::lock(&self.database)
+            .instance_service(instance)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "the instance isn't known"))?;
+
+        self.connect_to_service(&service, timeout)
+    }
+
+    /// Runs `data` through the same parse-and-apply path as datagrams received from
+    /// `source` on the network, e.g. to replay a capture.
+    pub fn process_packet(&self, data: &[u8], source: SocketAddr) -> Result<(), Box<dyn Error>> {
+        self.process_packet_at(data, source, Instant::now())
+    }
+
+    /// As `process_packet`, for a datagram received at `received`, such as by a kernel
+    /// timestamp: what it says counts as heard then rather than now.
+    pub fn process_packet_at(
+        &self,
+        data: &[u8],
+        source: SocketAddr,
+        received: Instant,
+    ) -> Result<(), Box<dyn Error>> {
+        if let Ok(packet) = Packet::parse(data) {
+            if let Some(lint) = &self.lint {
+                lint.check(&packet, source, received);
+            }
+            if let Some(observer) = &self.observer {
+                observer.observe(&packet, source.ip(), received);
             }
+            if let Some(inventory) = &self.inventory {
+                inventory.record(&packet, source, received);
+            }
+        }
+
+        process_packet(
+            data,
+            source,
+            Filter::new(
+                &self.service,
+                self.matcher.as_ref(),
+                self.srv_only,
+                self.keep_records,
+            ),
+            self.family,
+            &self.database,
+            &self.metrics,
+            &self.changes,
+            received,
+            None,
+        )?;
+        Ok(())
+    }
+
+    /// Answers queries for `instance` of `service_type` (e.g. `_http._tcp`) on this client's
+    /// sockets until the returned handle is dropped. The host name announced is derived
+    /// from `instance`, with every non-loopback IPv4 address. Needs the socket backend.
+    pub fn advertise(
+        &self,
+        instance: &str,
+        service_type: &str,
+        port: u16,
+        txt: &[(&str, &str)],
+    ) -> Result<Advertisement, Box<dyn Error>> {
+        let registration = self
+            .registration
+            .as_ref()
+            .ok_or("advertising needs the socket backend")?;
+
+        let advert = responder::Advert::new(instance, service_type, port, txt)?;
+        registration.advertise(advert)
+    }
+
+    /// Increases whenever services are added or removed or their addresses change.
+    pub fn generation(&self) -> u64 {
+        self.changes.generation()
+    }
+
+    /// Blocks until `generation()` is other than `last`, returning it, or for at most
+    /// `timeout`, returning `None`; at once for any generation but the current one. As for
+    /// `generation()`, services only heard from again don't count.
+    pub fn wait_for_change(&self, last: u64, timeout: Duration) -> Option<u64> {
+        self.changes.wait_for_change(last, timeout)
+    }
+
+    /// The current services, sorted, updated only when `generation()` changes. Receivers that
+    /// fall behind see just the latest snapshot; once the client is dropped, `changed()`
+    /// returns an error after the last one. Unlike `snapshot()`, a value may hold a service
+    /// that has just expired until its removal is published.
+    #[cfg(feature = "watch")]
+    pub fn watch(&self) -> tokio::sync::watch::Receiver<Arc<Vec<(Service, ServiceRecord)>>> {
+        self.changes.watch()
+    }
+
+    /// `subscribe_with_capacity(1024)`.
+    pub fn subscribe(&self) -> Subscription {
+        self.subscribe_with_capacity(DEFAULT_EVENT_CAPACITY)
+    }
+
+    /// Events for up to `capacity` services wait to be received before the oldest is
+    /// dropped; see `Subscription`. They are sent once the worker has let go of the
+    /// services, so whoever receives one may call any method of the client straight away.
+    /// The events of one change, such as one response, come in service order, as do the
+    /// `Added` events a subscriber starts with.
+    ///
+    /// The first subscriber finding no services yet has a query sent right away.
+    pub fn subscribe_with_capacity(&self, capacity: usize) -> Subscription {
+        if !self.changes.has_subscribers() && self.snapshot().is_empty() {
+            self.command_tx.try_send(Command::Recover).ok();
         }
 
-        #[cfg(target_os = "linux")]
+        self.changes.subscribe(capacity, Instant::now())
+    }
+
+    /// Writes every event as one JSON object per line, starting with an `added` line per
+    /// known service. Returns when writing fails, e.g. because the reader went away.
+    pub fn run_ndjson_bridge(&self, mut writer: impl Write) -> io::Result<()> {
+        let subscription = self.subscribe();
+
+        while let Ok(event) = subscription.recv() {
+            writeln!(writer, "{}", ndjson::line(&event, SystemTime::now()))?;
+            writer.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Interfaces the socket backend left out because their socket couldn't be set up,
+    /// with the reason. Retried whenever the interfaces change.
+    pub fn skipped_interfaces(&self) -> Vec<SkippedInterface> {
+        self.registration
+            .as_ref()
+            .map(|r| r.skipped_interfaces())
+            .unwrap_or_default()
+    }
+
+    pub fn stats(&self) -> MdnsStats {
+        self.metrics.snapshot()
+    }
+
+    /// Forgets every service, each reported as expired without waiting for
+    /// `MdnsClientBuilder::expiry_grace`, and starts discovery over, including the scan of
+    /// `wait_for_initial_scan`. Subscriptions stay attached.
+    ///
+    /// The socket backend also closes its sockets and sets them up again in the background
+    /// for the interfaces there are now, with no backoff left over from earlier failures,
+    /// then queries at once. Clients sharing them keep their services. Where no socket can be
+    /// set up, the client keeps running without any and retries every second, with
+    /// `skipped_interfaces()` and `last_error()` as `FailedOperation::Setup` telling why.
+    pub fn reset(&self) {
         {
-            let socket = create_socket(Ipv4Addr::UNSPECIFIED)?;
+            let mut database = Database::lock(&self.database);
+            let cleared = database.clear();
+            info!(services = cleared, "resetting discovery");
+            if let Some(seed) = &self.seed {
+                seed.reapply(&mut database);
+            }
 
-            socket.set_multicast_loop_v4(true)?;
-            socket.join_multicast_v4(&MULTICAST_ADDR, &Ipv4Addr::UNSPECIFIED)?;
-            socket.set_nonblocking(true)?;
+            self.metrics.expired(cleared);
+            self.metrics.database_size(database.len());
+            self.changes.update(database, Instant::now());
+        }
 
-            sockets.push(socket);
+        self.scan.restart();
+        // Waits for room behind a pending command rather than being coalesced with it.
+        self.command_tx.send(Command::Reset).ok();
+    }
+
+    /// Has this client's queries follow `schedule` from now on, as if built with
+    /// `MdnsClientBuilder::query_schedule`, keeping what it found: the next query goes out
+    /// when it was due, or an interval of the new schedule from now if that is sooner.
+    /// Fails as building would for the same schedule, or on another backend.
+    pub fn set_query_schedule(&self, schedule: QuerySchedule) -> Result<(), Box<dyn Error>> {
+        self.registration
+            .as_ref()
+            .ok_or("query schedules need the socket backend")?
+            .set_schedule(schedule)
+    }
+
+    /// `set_query_schedule(QuerySchedule::fixed(interval))`.
+    pub fn set_query_interval(&self, interval: Duration) -> Result<(), Box<dyn Error>> {
+        self.set_query_schedule(QuerySchedule::fixed(interval))
+    }
+
+    /// Remembers expired services for `grace` from now on, as if built with
+    /// `MdnsClientBuilder::expiry_grace`; those expired already are kept or reported gone by
+    /// the new grace period. Fails as building would for more than a year, or on another
+    /// backend.
+    pub fn set_expiry_grace(&self, grace: Duration) -> Result<(), Box<dyn Error>> {
+        self.registration
+            .as_ref()
+            .ok_or("expiry policies need the socket backend")?;
+        let policy = ExpiryPolicy {
+            grace,
+            ..ExpiryPolicy::default()
+        };
+        policy.check()?;
+
+        Database::lock(&self.database).set_grace(grace);
+        // Its next expiry may be sooner now.
+        self.command_tx.try_send(Command::Wake).ok();
+        Ok(())
+    }
+
+    /// Lets services go by `policy` from now on, as if built with its `expiry_grace` and
+    /// `availability_window`, keeping what was found: the grace period applies as with
+    /// `set_expiry_grace`, and availability looks back the new window, for services known
+    /// and gone alike. Fails as building would for the same values, or on another backend.
+    pub fn set_expiry_policy(&self, policy: ExpiryPolicy) -> Result<(), Box<dyn Error>> {
+        self.registration
+            .as_ref()
+            .ok_or("expiry policies need the socket backend")?;
+        policy.check()?;
+
+        let mut database = Database::lock(&self.database);
+        database.set_grace(policy.grace);
+        database.set_availability_window(policy.availability_window);
+        drop(database);
+
+        self.command_tx.try_send(Command::Wake).ok();
+        Ok(())
+    }
+
+    /// Leaves this client running until the process exits or `DetachedClient::shutdown`,
+    /// for programs that browse for as long as they run without a client to pass around:
+    /// the handle returned reads it as the client did, and dropping it or its clones
+    /// doesn't stop it.
+    pub fn detach(self) -> DetachedClient {
+        DetachedClient::new(self)
+    }
+
+    /// Whether the sockets are set up, as they are once `build()` returns unless
+    /// `MdnsClientBuilder::set_up_in_background` was used.
+    pub fn is_ready(&self) -> bool {
+        self.registration.as_ref().is_none_or(|r| r.is_ready())
+    }
+
+    /// Whether the first scan is done, see `wait_for_initial_scan`.
+    pub fn initial_scan_done(&self) -> bool {
+        self.scan.is_done()
+    }
+
+    /// Waits up to `timeout` for the first scan to be done, as set by
+    /// `MdnsClientBuilder::initial_scan`, and returns whether it is. Services not listed
+    /// by then most likely aren't there, rather than not having been asked for long
+    /// enough; an empty `get_services()` right after building tells nothing.
+    pub fn wait_for_initial_scan(&self, timeout: Duration) -> bool {
+        self.scan.wait(timeout)
+    }
+
+    /// Whether the background worker is still running. It only stops on its own by
+    /// panicking, reported by `last_error` as `FailedOperation::Panic`; the services are no
+    /// longer updated after that.
+    pub fn is_running(&self) -> bool {
+        match (&self.thread, &self.registration) {
+            (Some(thread), _) => !thread.is_finished(),
+            (None, Some(registration)) => registration.is_running(),
+            (None, None) => false,
         }
+    }
 
-        let (exit_tx, exit_rx) = sync_channel(0);
+    /// The most recent error the background threads ran into, which they otherwise only
+    /// log: a socket that can't send or receive looks much like a quiet network.
+    pub fn last_error(&self) -> Option<BackgroundError> {
+        self.metrics.last_error()
+    }
 
-        let thread = thread::spawn({
-            let service = service.to_string();
-            let database = database.clone();
+    /// The last 16 errors, oldest first.
+    pub fn recent_errors(&self) -> Vec<BackgroundError> {
+        self.metrics.errors()
+    }
 
-            move || {
-                for socket in &sockets {
-                    send_mdns_query(socket, &service).ok();
-                }
+    /// What `MdnsClientBuilder::lint_responders` found responders doing against the RFCs,
+    /// by source, then code, one line each when displayed, e.g. for a vendor bug report.
+    /// Empty unless that is set.
+    pub fn lint_findings(&self) -> Vec<LintFinding> {
+        self.lint
+            .as_ref()
+            .map(|lint| lint.findings())
+            .unwrap_or_default()
+    }
 
-                match exit_rx.recv_timeout(Duration::from_millis(50)) {
-                    Ok(()) | Err(RecvTimeoutError::Disconnected) => return,
-                    Err(RecvTimeoutError::Timeout) => (),
-                }
+    /// The questions `MdnsClientBuilder::observe_queries` saw other hosts ask, by source,
+    /// then name and type. Empty unless that is set.
+    pub fn observed_queries(&self) -> Vec<ObservedQuery> {
+        self.observer
+            .as_ref()
+            .map(|observer| observer.queries())
+            .unwrap_or_default()
+    }
 
-                for socket in &sockets {
-                    receive_response(socket, &service, &database).ok();
-                }
+    /// The hosts `MdnsClientBuilder::observe_queries` saw asking, most queries first.
+    /// Empty unless that is set.
+    pub fn query_sources(&self) -> Vec<QuerySource> {
+        self.observer
+            .as_ref()
+            .map(|observer| observer.sources())
+            .unwrap_or_default()
+    }
+
+    /// What the client did and found of late, oldest first, as many entries as
+    /// `MdnsClientBuilder::activity_log` says: the queries sent per interface, services
+    /// added, updated, refreshed and expired with why, errors and interface changes. A
+    /// timeline for when a device went missing and counters can't say when.
+    pub fn recent_activity(&self) -> Vec<ActivityEntry> {
+        self.metrics.activity().entries()
+    }
 
-                loop {
-                    match exit_rx.recv_timeout(Duration::from_secs(1)) {
-                        Ok(()) | Err(RecvTimeoutError::Disconnected) => break,
-                        Err(RecvTimeoutError::Timeout) => {
-                            for socket in &sockets {
-                                send_mdns_query(socket, &service).ok();
-                            }
+    /// What the client is doing, for a bug report; `{:#?}` renders all of it, while the
+    /// client's own `Debug` output is a one-line summary.
+    pub fn diagnostics(&self) -> Diagnostics {
+        let (next_query_in, questions, interfaces) = self
+            .registration
+            .as_ref()
+            .map(|r| r.diagnostics(Instant::now()))
+            .unwrap_or_default();
+        let services = self.get_services();
+        let (expiry_grace, availability_window) = {
+            let database = Database::lock(&self.database);
+            (database.grace(), database.availability_window())
+        };
 
-                            for socket in &sockets {
-                                receive_response(socket, &service, &database).ok();
-                            }
+        Diagnostics {
+            service: self.service.clone(),
+            backend: self.backend,
+            running: self.is_running(),
+            ready: self.is_ready(),
+            initial_scan_done: self.initial_scan_done(),
+            srv_only: self.srv_only,
+            expiry_grace,
+            availability_window,
+            next_query_in,
+            questions,
+            interfaces,
+            skipped_interfaces: self.skipped_interfaces(),
+            wildcard_fallback: self
+                .registration
+                .as_ref()
+                .and_then(|r| r.wildcard_fallback()),
+            service_count: services.len(),
+            services: services
+                .into_iter()
+                .take(diagnostics::SAMPLE_SERVICES)
+                .collect(),
+            recent_errors: self.recent_errors(),
+            recent_activity: self.recent_activity(),
+            query_sources: self.query_sources(),
+            stats: self.stats(),
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn render_prometheus_text(&self) -> String {
+        self.metrics.render()
+    }
+}
 
-                            remove_old_entries(&database);
+impl fmt::Debug for MdnsClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let interfaces: Vec<String> = self
+            .metrics
+            .snapshot()
+            .sockets
+            .into_iter()
+            .map(|socket| socket.interface)
+            .collect();
+
+        f.debug_struct("MdnsClient")
+            .field("service", &self.service)
+            .field("backend", &self.backend)
+            .field("running", &self.is_running())
+            .field("services", &self.snapshot().len())
+            .field("interfaces", &interfaces)
+            .field("last_error", &self.last_error().map(|e| e.message))
+            .finish()
+    }
+}
+
+impl Drop for MdnsClient {
+    fn drop(&mut self) {
+        // A shared daemon is stopped by its last registration instead.
+        if let Some(thread) = self.thread.take() {
+            self.command_tx.send(Command::Exit).ok();
+            thread.join().ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        matcher::{Filter, ServiceMatcher, ServiceTypeMatcher},
+        packet::{TYPE_A, TYPE_AAAA, TYPE_PTR, TYPE_SRV, TYPE_TXT},
+        responder::Advert,
+    };
+    use proptest::prelude::*;
+
+    // A label of up to 63 bytes: plain, with backslashes and spaces as they would be
+    // escaped elsewhere, the longest there is, or any characters but the dot.
+    fn label() -> impl Strategy<Value = String> {
+        prop_oneof![
+            "[a-z0-9-]{1,20}",
+            r"[a-zA-Z \\()]{1,20}",
+            "[a-z]{63}",
+            prop::collection::vec(any::<char>().prop_filter("a dot", |&c| c != '.'), 1..63)
+                .prop_map(|chars| {
+                    let mut label = String::new();
+                    for c in chars {
+                        if label.len() + c.len_utf8() > 63 {
+                            break;
                         }
+                        label.push(c);
                     }
-                }
-            }
-        });
+                    label
+                })
+                .prop_filter("empty", |label| !label.is_empty()),
+        ]
+    }
 
-        Ok(MdnsClient {
-            database,
-            exit_tx,
-            thread: Some(thread),
+    // Labels that fit in a name together, the last dropped until they do.
+    fn name() -> impl Strategy<Value = Vec<String>> {
+        prop::collection::vec(label(), 1..8).prop_map(|mut labels| {
+            while labels.iter().map(|label| label.len() + 1).sum::<usize>() + 1 > 255 {
+                labels.pop();
+            }
+            labels
         })
     }
 
-    pub fn get_services(&self) -> Vec<(Service, ServiceRecord)> {
-        self.database
-            .lock()
-            .unwrap()
+    // A name under `.local`, for a host.
+    fn host() -> impl Strategy<Value = Arc<str>> {
+        "[a-z0-9-]{1,20}".prop_map(|label| format!("{}.local", label).into())
+    }
+
+    fn family() -> impl Strategy<Value = AddressFamily> {
+        prop_oneof![
+            Just(AddressFamily::V4),
+            Just(AddressFamily::V6),
+            Just(AddressFamily::Both),
+        ]
+    }
+
+    // The questions of a built query, as `(name, qtype, prefer_unicast)`, after checking
+    // its header is that of a query and nothing else is in it.
+    fn questions(packet: &[u8]) -> Vec<(String, u16, bool)> {
+        let parsed = Packet::parse(packet).expect("a built query doesn't parse");
+        assert!(parsed.header.query);
+        assert_eq!(parsed.header.id, 0);
+        assert_eq!(parsed.header.response_code, 0);
+        assert_eq!(u16::from_be_bytes([packet[2], packet[3]]), OPCODE_QUERY);
+        assert_eq!(&packet[6..12], &[0; 6]);
+        assert!(parsed.answers.is_empty());
+
+        parsed
+            .questions
             .iter()
-            .map(|(service, record)| (service.clone(), record.clone()))
+            .map(|q| (q.qname.to_string(), q.qtype, q.prefer_unicast))
             .collect()
     }
-}
 
-impl Drop for MdnsClient {
-    fn drop(&mut self) {
-        self.exit_tx.send(()).ok();
-        self.thread.take().map(JoinHandle::join);
+    // `encoded` read back as the name of a question, label by label.
+    fn decode(encoded: &[u8]) -> Result<Vec<Vec<u8>>, packet::ParseError> {
+        let mut packet = DnsHeader::new_query().to_bytes();
+        packet.extend_from_slice(encoded);
+        packet.extend_from_slice(&TYPE_A.to_be_bytes());
+        packet.extend_from_slice(&1u16.to_be_bytes());
+
+        let parsed = Packet::parse(&packet)?;
+        Ok(parsed.questions[0]
+            .qname
+            .labels()
+            .map(|label| label.to_vec())
+            .collect())
+    }
+
+    fn address_qtypes(family: AddressFamily) -> Vec<u16> {
+        match family {
+            AddressFamily::V4 => vec![TYPE_A],
+            AddressFamily::V6 => vec![TYPE_AAAA],
+            AddressFamily::Both => vec![TYPE_A, TYPE_AAAA],
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn names_round_trip(labels in name()) {
+            let name = labels.join(".");
+            prop_assert_eq!(dns_name_error(&name), None);
+
+            let encoded = encode_dns_name(&name);
+            prop_assert!(encoded.len() <= 255);
+            let decoded = decode(&encoded).unwrap();
+            let expected: Vec<Vec<u8>> = labels.iter().map(|label| label.as_bytes().to_vec()).collect();
+            prop_assert_eq!(decoded, expected);
+        }
+
+        #[test]
+        fn queries_parse(
+            extra in prop::collection::btree_set("_[a-z]{1,10}", 0..4),
+            hosts in prop::collection::vec(host(), 0..6),
+            family in family(),
+            unicast_response in any::<bool>(),
+        ) {
+            let service_types: Vec<String> =
+                extra.iter().map(|kind| format!("{}._tcp.local", kind)).collect();
+            let matcher: Arc<dyn ServiceMatcher> =
+                Arc::new(ServiceTypeMatcher::new(service_types.clone()));
+            let filter = Filter::new("_main._tcp.local", Some(&matcher), false, false);
+
+            let mut packet = Vec::new();
+            build_mdns_query(&mut packet, filter, &hosts, family, unicast_response);
+
+            let mut expected: Vec<(String, u16, bool)> = vec![("_main._tcp.local".into(), TYPE_PTR, unicast_response)];
+            expected.extend(service_types.into_iter().map(|kind| (kind, TYPE_PTR, unicast_response)));
+            for host in &hosts {
+                for qtype in address_qtypes(family) {
+                    expected.push((host.to_string(), qtype, false));
+                }
+            }
+            prop_assert_eq!(questions(&packet), expected);
+        }
+
+        #[test]
+        fn reconfirm_queries_parse(
+            names in prop::collection::vec(
+                (prop::option::of(name()), host()),
+                0..6,
+            ),
+            family in family(),
+        ) {
+            let names: Vec<(Option<Box<str>>, Arc<str>)> = names
+                .into_iter()
+                .map(|(instance, host)| (instance.map(|labels| labels.join(".").into()), host))
+                .collect();
+
+            let mut packet = Vec::new();
+            build_reconfirm_query(&mut packet, &names, family, |host| !host.starts_with('x'));
+
+            let mut expected: Vec<(Vec<Vec<u8>>, u16)> = names
+                .iter()
+                .filter_map(|(instance, _)| instance.as_deref())
+                .map(|instance| (instance.split('.').map(|label| label.as_bytes().to_vec()).collect(), TYPE_SRV))
+                .collect();
+            let mut hosts: Vec<&Arc<str>> =
+                names.iter().map(|(_, host)| host).filter(|host| !host.starts_with('x')).collect();
+            hosts.sort();
+            hosts.dedup();
+            for host in hosts {
+                for qtype in address_qtypes(family) {
+                    expected.push((host.split('.').map(|label| label.as_bytes().to_vec()).collect(), qtype));
+                }
+            }
+
+            let parsed = Packet::parse(&packet).unwrap();
+            let asked: Vec<(Vec<Vec<u8>>, u16)> = parsed
+                .questions
+                .iter()
+                .map(|q| (q.qname.labels().map(|label| label.to_vec()).collect(), q.qtype))
+                .collect();
+            prop_assert_eq!(asked, expected);
+            prop_assert!(parsed.questions.iter().all(|q| !q.prefer_unicast));
+            questions(&packet);
+        }
+
+        #[test]
+        fn follow_up_queries_parse(
+            instances in prop::collection::vec(name(), 0..6),
+            txt in any::<bool>(),
+        ) {
+            let instances: Vec<Box<str>> =
+                instances.iter().map(|labels| labels.join(".").into()).collect();
+
+            let mut packet = Vec::new();
+            build_follow_up_query(&mut packet, &instances, txt);
+
+            let qtypes: &[u16] = if txt { &[TYPE_SRV, TYPE_TXT] } else { &[TYPE_SRV] };
+            let expected: Vec<(Vec<u8>, u16)> = instances
+                .iter()
+                .flat_map(|instance| qtypes.iter().map(move |&qtype| (encode_dns_name(instance), qtype)))
+                .collect();
+
+            let parsed = Packet::parse(&packet).unwrap();
+            let asked: Vec<(Vec<u8>, u16)> = parsed
+                .questions
+                .iter()
+                .map(|q| {
+                    let mut encoded = Vec::new();
+                    q.qname.encode(&mut encoded);
+                    (encoded, q.qtype)
+                })
+                .collect();
+            prop_assert_eq!(asked, expected);
+            questions(&packet);
+        }
+    }
+
+    #[test]
+    fn invalid_names_rejected() {
+        let long_label = "a".repeat(64);
+        for name in [
+            String::new(),
+            "a..local".into(),
+            ".local".into(),
+            format!("{}.local", long_label),
+            vec!["a".repeat(63); 4].join("."),
+        ] {
+            assert!(dns_name_error(&name).is_some(), "{:?} accepted", name);
+        }
+
+        // The longest there are: a label of 63 bytes, and 255 bytes encoded.
+        assert_eq!(dns_name_error(&format!("{}.local", "a".repeat(63))), None);
+        let longest = [
+            &*"a".repeat(63),
+            &"a".repeat(63),
+            &"a".repeat(63),
+            &"a".repeat(61),
+        ]
+        .join(".");
+        assert_eq!(encode_dns_name(&longest).len(), 255);
+        assert_eq!(dns_name_error(&longest), None);
+    }
+
+    #[test]
+    fn invalid_names_unparsed() {
+        // A length byte over 63 is no label.
+        assert!(decode(&encode_dns_name(&format!("{}.local", "a".repeat(64)))).is_err());
+        // Cut short, before the root or within a label.
+        assert!(decode(b"\x05local").is_err());
+        assert!(decode(b"\x09local\x00").is_err());
+        // A compression pointer to itself, and one past the end.
+        assert!(decode(&[0xc0, 12]).is_err());
+        assert!(decode(&[0xc0, 0xff]).is_err());
+    }
+
+    #[test]
+    fn invalid_adverts_rejected() {
+        assert!(Advert::new("", "_http._tcp", 80, &[]).is_err());
+        assert!(Advert::new(&"a".repeat(64), "_http._tcp", 80, &[]).is_err());
+        assert!(Advert::new("Printer", "_http", 80, &[]).is_err());
+
+        let domain = vec!["a".repeat(63); 3].join(".");
+        let service_type = format!("_http._tcp.{}", domain);
+        assert!(Advert::new(&"a".repeat(63), &service_type, 80, &[]).is_err());
+        assert!(Advert::new("Printer", &service_type, 80, &[]).is_ok());
+    }
+
+    // Up to `UNRESOLVED_LIMIT` hosts a query, the rest in turn, and a query reused only
+    // while every host fits in it.
+    #[test]
+    fn hosts_asked_in_turn() {
+        let query = |cache: &mut QueryCache, hosts: &[Arc<str>], family| {
+            let mut built = false;
+            let packet = cache.get(
+                1,
+                || hosts.to_vec(),
+                |packet, hosts| {
+                    built = true;
+                    let filter = Filter::new("_main._tcp.local", None, false, false);
+                    build_mdns_query(packet, filter, hosts, family, false);
+                },
+            );
+            let asked: Vec<String> = questions(packet)
+                .into_iter()
+                .filter(|(_, qtype, _)| *qtype != TYPE_PTR)
+                .map(|(name, _, _)| name)
+                .collect();
+            (asked, packet.len(), built)
+        };
+
+        let hosts: Vec<Arc<str>> = (0..40).map(|i| format!("h{:02}.local", i).into()).collect();
+        let mut cache = QueryCache::default();
+        let mut asked = HashSet::new();
+        for _ in 0..3 {
+            let (names, _, built) = query(&mut cache, &hosts, AddressFamily::V4);
+            assert!(built);
+            assert_eq!(names.len(), UNRESOLVED_LIMIT);
+            asked.extend(names);
+        }
+        assert_eq!(asked.len(), hosts.len());
+
+        let mut cache = QueryCache::default();
+        let few = &hosts[..UNRESOLVED_LIMIT];
+        assert!(query(&mut cache, few, AddressFamily::V4).2);
+        assert!(!query(&mut cache, few, AddressFamily::V4).2);
+
+        // The longest names, asked for over both families, still fit in a datagram.
+        let label = "a".repeat(63);
+        let long: Vec<Arc<str>> = (0..2 * UNRESOLVED_LIMIT)
+            .map(|i| format!("{}.{}.{}.{:03}.local", label, label, label, i).into())
+            .collect();
+        let mut cache = QueryCache::default();
+        let (names, len, _) = query(&mut cache, &long, AddressFamily::Both);
+        assert_eq!(names.len(), 2 * UNRESOLVED_LIMIT);
+        assert!(len <= socket::MAX_DATAGRAM, "{} bytes", len);
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn expiry_changes_checked() {
+        let client = MdnsClient::builder("_http._tcp.local")
+            .backend(Backend::Sockets)
+            .set_up_in_background()
+            .build()
+            .unwrap();
+        let year = Duration::from_secs(365 * 24 * 3600);
+
+        assert!(client.set_expiry_grace(year).is_ok());
+        assert!(client
+            .set_expiry_grace(year + Duration::from_secs(1))
+            .is_err());
+        assert!(client.set_expiry_grace(Duration::MAX).is_err());
+        // A grace period refused leaves the last one.
+        assert_eq!(Database::lock(&client.database).grace(), year);
+
+        let policy = ExpiryPolicy {
+            grace: Duration::from_secs(60),
+            availability_window: Duration::MAX,
+        };
+        assert!(client.set_expiry_policy(policy).is_err());
+        assert_eq!(Database::lock(&client.database).grace(), year);
+    }
+
+    #[test]
+    fn service_names_checked() {
+        for name in [
+            "_http._tcp.local",
+            "_http._tcp.local.",
+            "_HTTP._TCP.local",
+            "_printer._sub._ipp._tcp.local",
+            "_sleep-proxy._udp.example.com",
+            "My Device._http._tcp.local",
+            "Büro (2. Stock)._ipp._tcp.local",
+        ] {
+            assert!(check_service_name(name).is_ok(), "{:?} rejected", name);
+        }
+
+        let long = format!("{}._http._tcp.local", vec!["a".repeat(60); 4].join("."));
+        let long_label = format!("{}._http._tcp.local", "a".repeat(64));
+        for (name, reason) in [
+            ("", "it is empty"),
+            (".", "it is empty"),
+            (&long, "longer than 253 bytes"),
+            (&long_label, "is longer than 63 bytes"),
+            ("_http.._tcp.local", "empty label"),
+            ("no-dots-here", "no `_tcp` or `_udp` label"),
+            ("_http._tls.local", "no `_tcp` or `_udp` label"),
+            ("http._tcp.local", "`_tcp` must follow a service label"),
+            ("_._tcp.local", "`_tcp` must follow a service label"),
+            ("_udp.local", "`_udp` must follow a service label"),
+            ("_http._tcp", "did you mean `_http._tcp.local`?"),
+            ("_http._tcp.", "did you mean `_http._tcp.local`?"),
+        ] {
+            let err = check_service_name(name).unwrap_err();
+            assert!(err.reason.contains(reason), "{:?}: {}", name, err);
+            assert_eq!(err.service, name);
+        }
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn invalid_responses_rejected() {
+        let long_label = "a".repeat(64);
+        assert!(ResponseBuilder::new()
+            .a("a..local", Ipv4Addr::LOCALHOST)
+            .build()
+            .is_err());
+        assert!(ResponseBuilder::new()
+            .srv(
+                "Printer._http._tcp.local",
+                &format!("{}.local", long_label),
+                80
+            )
+            .build()
+            .is_err());
+        let long_name = vec!["a".repeat(63); 4].join(".");
+        assert!(ResponseBuilder::new()
+            .ptr("_http._tcp.local", &long_name)
+            .build()
+            .is_err());
+        assert!(ResponseBuilder::new()
+            .txt("Printer._http._tcp.local", &[("key", &*"v".repeat(255))])
+            .build()
+            .is_err());
     }
 }
@@ -0,0 +1,78 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+// How often sources that have been quiet long enough to have their whole budget back
+// are forgotten.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+// A token bucket: `rate` a second on average, and bursts of up to as many at once.
+pub(crate) struct Bucket {
+    rate: f64,
+    tokens: f64,
+    at: Instant,
+}
+
+impl Bucket {
+    pub(crate) fn new(rate: u32, now: Instant) -> Self {
+        Bucket {
+            rate: rate as f64,
+            tokens: rate as f64,
+            at: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.at = now;
+    }
+
+    pub(crate) fn take(&mut self, now: Instant) -> bool {
+        self.refill(now);
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+// A budget of datagrams per source address, checked before anything else is done with
+// them, so one host flooding the network costs little more than receiving.
+pub(crate) struct Sources {
+    rate: u32,
+    buckets: HashMap<IpAddr, Bucket>,
+    swept_at: Instant,
+}
+
+impl Sources {
+    pub(crate) fn new(rate: u32) -> Self {
+        Sources {
+            rate,
+            buckets: HashMap::new(),
+            swept_at: Instant::now(),
+        }
+    }
+
+    // False if `from` has used up its budget.
+    pub(crate) fn allow(&mut self, from: IpAddr, now: Instant) -> bool {
+        if now.saturating_duration_since(self.swept_at) >= SWEEP_INTERVAL {
+            self.buckets.retain(|_, bucket| {
+                bucket.refill(now);
+                bucket.tokens < bucket.rate
+            });
+            self.swept_at = now;
+        }
+
+        let rate = self.rate;
+        self.buckets
+            .entry(from)
+            .or_insert_with(|| Bucket::new(rate, now))
+            .take(now)
+    }
+}
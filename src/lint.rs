@@ -0,0 +1,408 @@
+use crate::{
+    lock::Lock,
+    packet::{Packet, RData, ResourceRecord, TYPE_A, TYPE_AAAA, TYPE_PTR, TYPE_SRV, TYPE_TXT},
+    MULTICAST_PORT,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+// RFC 6762 section 6 asks for a second between multicasts of a record; half that leaves
+// room for answers to the socket backend's own queries, which responders may delay.
+const MIN_REPEAT: Duration = Duration::from_millis(500);
+
+// Caps on what is kept, as anything on the network can send: findings, and records
+// timed for `AnnouncementRate` per source.
+const MAX_FINDINGS: usize = 1024;
+const MAX_TIMED: usize = 256;
+
+/// A way a responder strays from RFC 6762 or RFC 6763, see `MdnsClient::lint_findings`.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LintCode {
+    /// Answers in a message with no questions and the QR bit clear, a response sent as a
+    /// query (RFC 6762 section 18.2).
+    AnswersInQuery,
+    /// An SRV, TXT, A or AAAA record, which only its responder holds, sent from port 5353
+    /// without the cache-flush bit (RFC 6762 section 10.2).
+    MissingCacheFlush,
+    /// A record given a TTL of 0 and another one in the same response, withdrawn and
+    /// announced at once (RFC 6762 section 10.1).
+    ZeroTtl,
+    /// An SRV record without an A or AAAA record for its target in the same response
+    /// (RFC 6763 section 12.2).
+    SrvWithoutAddress,
+    /// A response from a port other than 5353 (RFC 6762 section 6).
+    SourcePort,
+    /// A record sent again less than half a second after the last time; RFC 6762 section
+    /// 6 asks for a second.
+    AnnouncementRate,
+}
+
+impl LintCode {
+    /// A name that stays the same from release to release, such as `missing-cache-flush`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            LintCode::AnswersInQuery => "answers-in-query",
+            LintCode::MissingCacheFlush => "missing-cache-flush",
+            LintCode::ZeroTtl => "zero-ttl",
+            LintCode::SrvWithoutAddress => "srv-without-address",
+            LintCode::SourcePort => "source-port",
+            LintCode::AnnouncementRate => "announcement-rate",
+        }
+    }
+}
+
+/// What one responder did wrong one way, see `MdnsClient::lint_findings`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LintFinding {
+    pub source: IpAddr,
+    pub code: LintCode,
+    /// In how many datagrams.
+    pub count: u64,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+    /// The last datagram's case, such as `Printer._ipp._tcp.local SRV`.
+    pub example: String,
+}
+
+/// One line: source, code, count and example.
+impl fmt::Display for LintFinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} x{}: {}",
+            self.source,
+            self.code.code(),
+            self.count,
+            self.example
+        )
+    }
+}
+
+// The findings of a client built with `MdnsClientBuilder::lint_responders`.
+#[derive(Default)]
+pub(crate) struct Lint {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    findings: HashMap<(IpAddr, LintCode), LintFinding>,
+    // When each source last sent each record.
+    sent: HashMap<IpAddr, HashMap<Sent, Instant>>,
+}
+
+// A record by lowercase name, type and data.
+type Sent = (String, u16, Vec<u8>);
+
+impl Lint {
+    pub(crate) fn check(&self, packet: &Packet, from: SocketAddr, now: Instant) {
+        let additionals = packet.additionals();
+        let records: Vec<_> = packet.answers.iter().chain(&additionals).collect();
+        let mut found = Vec::new();
+
+        if packet.header.query {
+            if packet.questions.is_empty() && !packet.answers.is_empty() {
+                let answer = &packet.answers[0];
+                found.push((
+                    LintCode::AnswersInQuery,
+                    example(&answer.name.to_string(), answer.kind),
+                ));
+            }
+        } else {
+            found.extend(check_response(&records, from));
+        }
+
+        let mut state = self.state.locked();
+        if !packet.header.query {
+            found.extend(state.repeated(&records, from.ip(), now));
+        }
+
+        let time = SystemTime::now();
+        for (code, example) in found {
+            let key = (from.ip(), code);
+            if let Some(finding) = state.findings.get_mut(&key) {
+                finding.count += 1;
+                finding.last_seen = time;
+                finding.example = example;
+            } else if state.findings.len() < MAX_FINDINGS {
+                state.findings.insert(
+                    key,
+                    LintFinding {
+                        source: from.ip(),
+                        code,
+                        count: 1,
+                        first_seen: time,
+                        last_seen: time,
+                        example,
+                    },
+                );
+            }
+        }
+    }
+
+    // By source, then code.
+    pub(crate) fn findings(&self) -> Vec<LintFinding> {
+        let mut findings: Vec<_> = self.state.locked().findings.values().cloned().collect();
+        findings.sort_by_key(|finding| (finding.source, finding.code));
+        findings
+    }
+}
+
+impl State {
+    // The first record `source` sent again too soon, if any.
+    fn repeated(
+        &mut self,
+        records: &[&ResourceRecord],
+        source: IpAddr,
+        now: Instant,
+    ) -> Option<(LintCode, String)> {
+        // Once each, as a record may be in both the answers and the additional records.
+        let records: HashSet<_> = records
+            .iter()
+            .filter(|record| record.ttl > 0)
+            .map(|record| {
+                let mut name = record.name.to_string();
+                name.make_ascii_lowercase();
+                (name, record.kind, record.expanded_rdata())
+            })
+            .collect();
+
+        let recent = |at: &Instant| now.saturating_duration_since(*at) < MIN_REPEAT;
+        if !self.sent.contains_key(&source) {
+            // Sources that have sent nothing lately go, so their number stays bounded.
+            self.sent.retain(|_, sent| {
+                sent.retain(|_, at| recent(at));
+                !sent.is_empty()
+            });
+        }
+
+        let sent = self.sent.entry(source).or_default();
+        sent.retain(|_, at| recent(at));
+
+        let mut found = None;
+        for record in records {
+            if let Some(at) = sent.get_mut(&record) {
+                found = found.or_else(|| Some(example(&record.0, record.1)));
+                *at = now;
+            } else if sent.len() < MAX_TIMED {
+                sent.insert(record, now);
+            }
+        }
+
+        found.map(|example| (LintCode::AnnouncementRate, example))
+    }
+}
+
+// Each code once, with its first case, for a response's answers and additional records.
+fn check_response(records: &[&ResourceRecord], from: SocketAddr) -> Vec<(LintCode, String)> {
+    let mut found: Vec<(LintCode, String)> = Vec::new();
+    let mut push = |code, example: String| {
+        if !found.iter().any(|(known, _)| *known == code) {
+            found.push((code, example));
+        }
+    };
+
+    if from.port() != MULTICAST_PORT {
+        push(LintCode::SourcePort, format!("from port {}", from.port()));
+    }
+
+    for record in records {
+        let name = record.name.to_string();
+        let unique = matches!(record.kind, TYPE_SRV | TYPE_TXT | TYPE_A | TYPE_AAAA);
+
+        // Legacy unicast answers go without the bit (RFC 6762 section 6.7).
+        if unique && !record.multicast_unique && from.port() == MULTICAST_PORT {
+            push(LintCode::MissingCacheFlush, example(&name, record.kind));
+        }
+
+        if record.ttl == 0
+            && records.iter().any(|other| {
+                other.ttl > 0
+                    && other.kind == record.kind
+                    && other.expanded_rdata() == record.expanded_rdata()
+                    && other.name.to_string().eq_ignore_ascii_case(&name)
+            })
+        {
+            push(LintCode::ZeroTtl, example(&name, record.kind));
+        }
+
+        if let RData::Srv { target, .. } = &record.data {
+            let target = target.to_string();
+            let literal = target.trim_end_matches('.').parse::<Ipv4Addr>().is_ok();
+            let addressed = records.iter().any(|other| {
+                matches!(other.data, RData::A(_) | RData::Aaaa(_))
+                    && other.name.to_string().eq_ignore_ascii_case(&target)
+            });
+
+            if record.ttl > 0 && !literal && !addressed {
+                push(LintCode::SrvWithoutAddress, example(&name, record.kind));
+            }
+        }
+    }
+
+    found
+}
+
+fn example(name: &str, kind: u16) -> String {
+    let kind = match kind {
+        TYPE_A => "A".to_string(),
+        TYPE_AAAA => "AAAA".to_string(),
+        TYPE_SRV => "SRV".to_string(),
+        TYPE_TXT => "TXT".to_string(),
+        TYPE_PTR => "PTR".to_string(),
+        kind => format!("TYPE{}", kind),
+    };
+    format!("{} {}", name, kind)
+}
+
+#[cfg(all(test, feature = "test-util"))]
+mod tests {
+    use super::*;
+    use crate::ResponseBuilder;
+
+    const SERVICE: &str = "_ipp._tcp.local";
+    const INSTANCE: &str = "Printer._ipp._tcp.local";
+
+    fn from() -> SocketAddr {
+        "192.0.2.10:5353".parse().unwrap()
+    }
+
+    // A complete announcement, which nothing is found wrong with.
+    fn announcement() -> ResponseBuilder {
+        announce(ResponseBuilder::new())
+    }
+
+    fn announce(response: ResponseBuilder) -> ResponseBuilder {
+        response
+            .ptr(SERVICE, INSTANCE)
+            .srv(INSTANCE, "printer.local", 631)
+            .txt(INSTANCE, &[("rp", "ipp/print")])
+            .a("printer.local", Ipv4Addr::new(192, 0, 2, 10))
+    }
+
+    // The codes of what `lint` has found, after checking `data` as heard at `now`.
+    fn check(lint: &Lint, data: &[u8], from: SocketAddr, now: Instant) -> Vec<&'static str> {
+        lint.check(&Packet::parse(data).unwrap(), from, now);
+        lint.findings()
+            .iter()
+            .map(|finding| finding.code.code())
+            .collect()
+    }
+
+    fn codes(response: ResponseBuilder, from: SocketAddr) -> Vec<&'static str> {
+        check(
+            &Lint::default(),
+            &response.build().unwrap(),
+            from,
+            Instant::now(),
+        )
+    }
+
+    #[test]
+    fn announcements_pass() {
+        assert!(codes(announcement(), from()).is_empty());
+        // A literal address needs no address record.
+        let literal = ResponseBuilder::new().srv(INSTANCE, "192.0.2.10", 631);
+        assert!(codes(literal, from()).is_empty());
+    }
+
+    #[test]
+    fn each_deviation_found() {
+        let mut query = announcement().build().unwrap();
+        query[2] &= 0x7f;
+        assert_eq!(
+            check(&Lint::default(), &query, from(), Instant::now()),
+            ["answers-in-query"]
+        );
+
+        assert_eq!(
+            codes(announce(ResponseBuilder::new().cache_flush(false)), from()),
+            ["missing-cache-flush"]
+        );
+        // Legacy unicast replies don't set it, and come from another port.
+        assert_eq!(
+            codes(
+                announce(ResponseBuilder::new().cache_flush(false)),
+                "192.0.2.10:40000".parse().unwrap()
+            ),
+            ["source-port"]
+        );
+
+        let withdrawn_and_announced = announcement()
+            .ttl(0)
+            .a("printer.local", Ipv4Addr::new(192, 0, 2, 10));
+        assert_eq!(codes(withdrawn_and_announced, from()), ["zero-ttl"]);
+
+        let unaddressed =
+            ResponseBuilder::new()
+                .ptr(SERVICE, INSTANCE)
+                .srv(INSTANCE, "printer.local", 631);
+        assert_eq!(codes(unaddressed, from()), ["srv-without-address"]);
+    }
+
+    #[test]
+    fn repeats_counted_per_source() {
+        let lint = Lint::default();
+        let data = announcement().build().unwrap();
+        let now = Instant::now();
+        let other = "192.0.2.11:5353".parse().unwrap();
+
+        assert!(check(&lint, &data, from(), now).is_empty());
+        assert!(check(&lint, &data, other, now).is_empty());
+        let soon = now + MIN_REPEAT / 2;
+        assert_eq!(check(&lint, &data, from(), soon), ["announcement-rate"]);
+        assert_eq!(
+            check(&lint, &data, from(), soon + MIN_REPEAT / 4),
+            ["announcement-rate"]
+        );
+        // More than half a second after the last time isn't counted.
+        check(&lint, &data, from(), soon + MIN_REPEAT * 2);
+
+        let findings = lint.findings();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].source, from().ip());
+        assert_eq!(findings[0].count, 2);
+        // Whichever of its records was looked at first.
+        let sent = [
+            "_ipp._tcp.local PTR",
+            "printer._ipp._tcp.local SRV",
+            "printer._ipp._tcp.local TXT",
+            "printer.local A",
+        ];
+        assert!(
+            sent.contains(&&*findings[0].example),
+            "{}",
+            findings[0].example
+        );
+    }
+
+    #[test]
+    fn codes_stay_the_same() {
+        let codes = [
+            LintCode::AnswersInQuery,
+            LintCode::MissingCacheFlush,
+            LintCode::ZeroTtl,
+            LintCode::SrvWithoutAddress,
+            LintCode::SourcePort,
+            LintCode::AnnouncementRate,
+        ];
+        assert_eq!(
+            codes.iter().map(LintCode::code).collect::<Vec<_>>(),
+            [
+                "answers-in-query",
+                "missing-cache-flush",
+                "zero-ttl",
+                "srv-without-address",
+                "source-port",
+                "announcement-rate",
+            ]
+        );
+    }
+}
@@ -0,0 +1,61 @@
+use std::sync::PoisonError;
+
+// A panic while one of the client's locks is held shouldn't make every later call panic
+// too: what they guard is either a cache or counters, and the guard is taken as it was
+// left. The database is reset instead, see `Database::lock`.
+pub(crate) trait Lock<T> {
+    type Guard<'a>
+    where
+        Self: 'a;
+
+    fn locked(&self) -> Self::Guard<'_>;
+}
+
+impl<T> Lock<T> for std::sync::Mutex<T> {
+    type Guard<'a>
+        = std::sync::MutexGuard<'a, T>
+    where
+        T: 'a;
+
+    fn locked(&self) -> Self::Guard<'_> {
+        self.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// For the state `sync` builds on loom's primitives.
+#[cfg(loom)]
+impl<T> Lock<T> for loom::sync::Mutex<T> {
+    type Guard<'a>
+        = loom::sync::MutexGuard<'a, T>
+    where
+        T: 'a;
+
+    fn locked(&self) -> Self::Guard<'_> {
+        self.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+    use std::{sync::Mutex, thread};
+
+    #[test]
+    fn poisoned_locks_taken_as_left() {
+        let counter = Mutex::new(1);
+
+        thread::scope(|scope| {
+            let poisoner = scope.spawn(|| {
+                let mut guard = counter.locked();
+                *guard += 1;
+                panic!("poisons the lock");
+            });
+            assert!(poisoner.join().is_err());
+        });
+
+        assert!(counter.is_poisoned());
+        assert_eq!(*counter.locked(), 2);
+        *counter.locked() += 1;
+        assert_eq!(*counter.locked(), 3);
+    }
+}
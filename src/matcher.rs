@@ -0,0 +1,198 @@
+use std::sync::Arc;
+
+/// Decides which SRV answers a client takes for its services; see
+/// `MdnsClientBuilder::matcher`.
+pub trait ServiceMatcher: Send + Sync {
+    fn matches(&self, name: &ServiceName<'_>) -> bool;
+
+    /// Service types to query for besides the client's own, e.g. `_ipp._tcp.local`. None
+    /// by default.
+    fn service_types(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// Any closure taking the name, with the client's own service type queried.
+impl<F> ServiceMatcher for F
+where
+    F: Fn(&ServiceName<'_>) -> bool + Send + Sync,
+{
+    fn matches(&self, name: &ServiceName<'_>) -> bool {
+        self(name)
+    }
+}
+
+/// The name an SRV record is for, e.g. `Printer._ipp._tcp.local`, in the case it arrived
+/// in and without a final dot.
+#[derive(Clone, Copy, Debug)]
+pub struct ServiceName<'a> {
+    name: &'a str,
+}
+
+impl<'a> ServiceName<'a> {
+    pub(crate) fn new(name: &'a str) -> Self {
+        ServiceName { name }
+    }
+
+    pub fn as_str(&self) -> &'a str {
+        self.name
+    }
+
+    /// `Printer`, everything before the service type; an instance label may hold dots.
+    pub fn instance(&self) -> &'a str {
+        self.split().map_or("", |(instance, _)| instance)
+    }
+
+    /// `_ipp._tcp.local`, from the label before `_tcp` or `_udp` on; the whole name if
+    /// there is none.
+    pub fn service_type(&self) -> &'a str {
+        self.split()
+            .map_or(self.name, |(_, service_type)| service_type)
+    }
+
+    // Before and after the dot ending the instance.
+    fn split(&self) -> Option<(&'a str, &'a str)> {
+        let mut service = None;
+        let mut previous = None;
+        let mut start = 0;
+
+        for label in self.name.split('.') {
+            if label.eq_ignore_ascii_case("_tcp") || label.eq_ignore_ascii_case("_udp") {
+                service = previous.or(service);
+            }
+            previous = Some(start);
+            start += label.len() + 1;
+        }
+
+        match service? {
+            0 => None,
+            at => Some((&self.name[..at - 1], &self.name[at..])),
+        }
+    }
+}
+
+/// Matches these instances alone, by full name such as `Printer._ipp._tcp.local`, and
+/// queries for their service types.
+pub struct InstanceMatcher {
+    instances: Vec<String>,
+}
+
+impl InstanceMatcher {
+    pub fn new<S: Into<String>>(instances: impl IntoIterator<Item = S>) -> Self {
+        InstanceMatcher {
+            instances: instances.into_iter().map(|i| trimmed(i.into())).collect(),
+        }
+    }
+}
+
+impl ServiceMatcher for InstanceMatcher {
+    fn matches(&self, name: &ServiceName<'_>) -> bool {
+        self.instances
+            .iter()
+            .any(|instance| instance.eq_ignore_ascii_case(name.as_str()))
+    }
+
+    fn service_types(&self) -> Vec<String> {
+        self.instances
+            .iter()
+            .map(|instance| ServiceName::new(instance).service_type().to_string())
+            .collect()
+    }
+}
+
+/// Matches every instance of these service types, such as `_ipp._tcp.local`, and queries
+/// for them all.
+pub struct ServiceTypeMatcher {
+    service_types: Vec<String>,
+}
+
+impl ServiceTypeMatcher {
+    pub fn new<S: Into<String>>(service_types: impl IntoIterator<Item = S>) -> Self {
+        ServiceTypeMatcher {
+            service_types: service_types
+                .into_iter()
+                .map(|t| trimmed(t.into()))
+                .collect(),
+        }
+    }
+}
+
+impl ServiceMatcher for ServiceTypeMatcher {
+    fn matches(&self, name: &ServiceName<'_>) -> bool {
+        let service_type = name.service_type();
+        self.service_types
+            .iter()
+            .any(|t| t.eq_ignore_ascii_case(service_type))
+    }
+
+    fn service_types(&self) -> Vec<String> {
+        self.service_types.clone()
+    }
+}
+
+fn trimmed(mut name: String) -> String {
+    while name.ends_with('.') {
+        name.pop();
+    }
+    name
+}
+
+// What a question takes from responses and asks about: by default the services whose
+// name contains its service type, and that type alone; with `srv_only`, no addresses; with
+// `records`, the resource records themselves too.
+#[derive(Clone, Copy)]
+pub(crate) struct Filter<'a> {
+    service: &'a str,
+    matcher: Option<&'a dyn ServiceMatcher>,
+    pub(crate) srv_only: bool,
+    pub(crate) records: bool,
+}
+
+impl<'a> Filter<'a> {
+    pub(crate) fn new(
+        service: &'a str,
+        matcher: Option<&'a Arc<dyn ServiceMatcher>>,
+        srv_only: bool,
+        records: bool,
+    ) -> Self {
+        Filter {
+            service,
+            matcher: matcher.map(|matcher| &**matcher),
+            srv_only,
+            records,
+        }
+    }
+
+    // Every service, with its records, for `MdnsClientBuilder::inventory`.
+    pub(crate) fn everything() -> Self {
+        Filter {
+            service: "",
+            matcher: None,
+            srv_only: false,
+            records: true,
+        }
+    }
+
+    // `name` as the SRV record has it.
+    pub(crate) fn matches(&self, name: &str) -> bool {
+        match self.matcher {
+            Some(matcher) => matcher.matches(&ServiceName::new(name)),
+            // Also for `everything`, as every name contains the empty one.
+            None => name.contains(self.service),
+        }
+    }
+
+    // The PTR questions to ask, the question's own service type first.
+    pub(crate) fn service_types(&self) -> Vec<String> {
+        let mut types = vec![self.service.to_string()];
+
+        for service_type in self.matcher.map(|m| m.service_types()).unwrap_or_default() {
+            let service_type = trimmed(service_type);
+            if !types.iter().any(|t| t.eq_ignore_ascii_case(&service_type)) {
+                types.push(service_type);
+            }
+        }
+
+        types
+    }
+}
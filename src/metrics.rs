@@ -0,0 +1,803 @@
+use crate::{
+    activity::{Activity, ActivityLog},
+    lock::Lock,
+};
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MdnsStats {
+    pub sockets: Vec<SocketStats>,
+    pub datagrams_received: u64,
+    /// Datagrams ignored as copies of one just received on another socket, e.g. through
+    /// interfaces bridged to the same network. Not counted in `datagrams_received`.
+    pub duplicates: u64,
+    /// Receive calls that returned datagrams. On Linux and Android one call takes up to 16;
+    /// `datagrams_received + duplicates` over this is the average.
+    pub receive_batches: u64,
+    /// The most datagrams a single receive call returned.
+    pub largest_receive_batch: u64,
+    /// Datagrams dropped unread because their source sent more than its budget; see
+    /// `MdnsClientBuilder::source_rate_limit`.
+    pub rate_limited: u64,
+    /// Datagrams dropped unread for their source address; see
+    /// `MdnsClientBuilder::allow_sources` and `deny_sources`.
+    pub sources_rejected: u64,
+    /// New services not added because more than the budget arrived in a second; see
+    /// `MdnsClientBuilder::discovery_rate_limit`.
+    pub discoveries_limited: u64,
+    pub parse_failures: u64,
+    /// Datagrams used although some of their records couldn't be parsed, which were
+    /// skipped.
+    pub packets_salvaged: u64,
+    /// Responses ignored for a non-zero response code, such as SERVFAIL or NXDOMAIN from a
+    /// gateway that answers on the mDNS group with junk.
+    pub error_responses: u64,
+    /// Addresses dropped, the oldest first, from hosts announcing more than
+    /// `MdnsClientBuilder::max_addresses`.
+    pub addresses_evicted: u64,
+    /// Address records ignored because neither the response they came in nor their sender
+    /// had anything to do with the host; see `MdnsClientBuilder::accept_unverified_addresses`.
+    pub addresses_rejected: u64,
+    pub answers_applied: u64,
+    /// Of those, SRV answers that arrived within 500 ms of a query, whose latency is in
+    /// `ServiceRecord::latency`.
+    pub answers_timed: u64,
+    /// The latency of those summed; over `answers_timed` it is the mean.
+    pub answer_latency_total: Duration,
+    pub entries_expired: u64,
+    /// Services asked after by name because they were about to expire.
+    pub services_reconfirmed: u64,
+    /// Instances asked after by name because PTR answers pointed at them alone.
+    pub instances_followed_up: u64,
+    /// Events dropped because a subscriber's queue was full.
+    pub events_dropped: u64,
+    /// How often the interface set changed after startup, each time followed by a rescan.
+    pub interface_changes: u64,
+    pub last_received: Option<SystemTime>,
+    /// Nothing arrived from other hosts for `MdnsClientBuilder::silence_threshold` query
+    /// cycles in a row, as when a firewall drops inbound UDP port 5353; cleared once
+    /// something does. Only the socket backend sets it.
+    pub silent: bool,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SocketStats {
+    pub interface: String,
+    pub queries_sent: u64,
+    pub errors: u64,
+    /// How often the socket kept failing and was closed and set up again.
+    pub recreations: u64,
+    /// How often the socket left the multicast group and joined it again, see
+    /// `MdnsClientBuilder::group_rejoin_interval`.
+    pub group_rejoins: u64,
+    /// The receive buffer in effect, in bytes, as the OS reports it.
+    pub receive_buffer: Option<u64>,
+    /// Datagrams the kernel dropped because the receive buffer was full. Only known on
+    /// Linux, and counted from when the socket was last set up.
+    pub receive_drops: Option<u64>,
+    /// Sends that have failed in a row, as of the last refresh. While there are any, the
+    /// socket only sends again after a pause that doubles up to ten seconds.
+    pub send_failures: u64,
+    /// Whether the socket queries from an ephemeral port because another program holds
+    /// port 5353, see `MdnsClientBuilder::legacy_unicast_fallback`.
+    pub legacy_unicast: bool,
+    /// Datagrams sent from the socket: queries, answers and announcements.
+    pub datagrams_sent: u64,
+    /// Datagrams the socket received, this host's own and those rejected included, so a
+    /// socket that stays at zero while others count up isn't getting multicast at all.
+    pub datagrams_received: u64,
+    pub last_received: Option<SystemTime>,
+    /// The last of the socket's errors, as kept for `MdnsClient::recent_errors`.
+    pub last_error: Option<BackgroundError>,
+}
+
+/// Something the client's background threads failed at, see `MdnsClient::last_error`.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BackgroundError {
+    pub time: SystemTime,
+    pub operation: FailedOperation,
+    /// The socket's interface, named as in `SocketStats::interface`, if the error was one
+    /// socket's.
+    pub interface: Option<String>,
+    pub message: String,
+}
+
+/// What failed, for `BackgroundError`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FailedOperation {
+    /// Sending the question.
+    Query,
+    Receive,
+    /// Answering a query for an advertised service.
+    Answer,
+    /// Announcing an advertised service or its withdrawal.
+    Announce,
+    /// Setting up a broken socket again.
+    Recreate,
+    /// Leaving the multicast group and joining it again, see
+    /// `MdnsClientBuilder::group_rejoin_interval`.
+    Rejoin,
+    ListInterfaces,
+    /// Browsing or resolving through Avahi, Bonjour or the Windows DNS API.
+    Backend,
+    /// No datagram arrived from other hosts for a while; see `MdnsStats::silent`.
+    NoTraffic,
+    /// Setting up the sockets of a client built with
+    /// `MdnsClientBuilder::set_up_in_background`, which then stays without them, or after
+    /// `MdnsClient::reset`, which is retried every second.
+    Setup,
+    /// Writing the file of `MdnsClientBuilder::share_cache`.
+    ShareCache,
+    /// Reading the file of `MdnsClientBuilder::seed_file`, or lines of it, see
+    /// `MdnsClient::seed_errors`.
+    Seed,
+    /// A background thread panicked. The worker stops, see `MdnsClient::is_running`; a
+    /// socket's receive thread is started again with a new socket.
+    Panic,
+}
+
+// How many errors `MdnsClient::recent_errors` keeps.
+const RECENT_ERRORS: usize = 16;
+
+struct SocketCounters {
+    interface: String,
+    queries_sent: AtomicU64,
+    errors: AtomicU64,
+    recreations: AtomicU64,
+    rejoins: AtomicU64,
+    // UNKNOWN until first read, or where the OS doesn't tell.
+    receive_buffer: AtomicU64,
+    receive_drops: AtomicU64,
+    send_failures: AtomicU64,
+    // 1 for legacy unicast.
+    legacy_unicast: AtomicU64,
+    datagrams_sent: AtomicU64,
+    datagrams_received: AtomicU64,
+    // As `Metrics::last_received`.
+    last_received: AtomicU64,
+    last_error: Option<BackgroundError>,
+}
+
+impl SocketCounters {
+    fn new(interface: String) -> Self {
+        SocketCounters {
+            interface,
+            queries_sent: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            recreations: AtomicU64::new(0),
+            rejoins: AtomicU64::new(0),
+            receive_buffer: AtomicU64::new(UNKNOWN),
+            receive_drops: AtomicU64::new(UNKNOWN),
+            send_failures: AtomicU64::new(0),
+            legacy_unicast: AtomicU64::new(0),
+            datagrams_sent: AtomicU64::new(0),
+            datagrams_received: AtomicU64::new(0),
+            last_received: AtomicU64::new(0),
+            last_error: None,
+        }
+    }
+}
+
+const UNKNOWN: u64 = u64::MAX;
+
+fn known(value: &AtomicU64) -> Option<u64> {
+    match value.load(Ordering::Relaxed) {
+        UNKNOWN => None,
+        value => Some(value),
+    }
+}
+
+fn unix_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |t| t.as_nanos() as u64)
+}
+
+// The time stored by `unix_nanos`, with 0 for none.
+fn since_epoch(value: &AtomicU64) -> Option<SystemTime> {
+    match value.load(Ordering::Relaxed) {
+        0 => None,
+        nanos => Some(UNIX_EPOCH + Duration::from_nanos(nanos)),
+    }
+}
+
+// Relaxed atomics throughout: these are independent counters, updated on every packet.
+pub(crate) struct Metrics {
+    // In the order of the daemon's sockets, which follow the interfaces as they change.
+    sockets: Mutex<Vec<SocketCounters>>,
+    datagrams_received: AtomicU64,
+    duplicates: AtomicU64,
+    receive_batches: AtomicU64,
+    largest_receive_batch: AtomicU64,
+    rate_limited: AtomicU64,
+    sources_rejected: AtomicU64,
+    discoveries_limited: AtomicU64,
+    parse_failures: AtomicU64,
+    packets_salvaged: AtomicU64,
+    error_responses: AtomicU64,
+    addresses_evicted: AtomicU64,
+    addresses_rejected: AtomicU64,
+    answers_applied: AtomicU64,
+    answers_timed: AtomicU64,
+    // In nanoseconds.
+    answer_latency_total: AtomicU64,
+    database_size: AtomicU64,
+    expirations: AtomicU64,
+    reconfirmations: AtomicU64,
+    follow_ups: AtomicU64,
+    events_dropped: AtomicU64,
+    interface_changes: AtomicU64,
+    // Nanoseconds since the Unix epoch, 0 until the first datagram.
+    last_received: AtomicU64,
+    silent: AtomicBool,
+    // The last `RECENT_ERRORS`, oldest first.
+    errors: Mutex<VecDeque<BackgroundError>>,
+    activity: ActivityLog,
+}
+
+impl Metrics {
+    pub(crate) fn new(interfaces: impl IntoIterator<Item = String>) -> Self {
+        Metrics {
+            sockets: Mutex::new(interfaces.into_iter().map(SocketCounters::new).collect()),
+            datagrams_received: AtomicU64::new(0),
+            duplicates: AtomicU64::new(0),
+            receive_batches: AtomicU64::new(0),
+            largest_receive_batch: AtomicU64::new(0),
+            rate_limited: AtomicU64::new(0),
+            sources_rejected: AtomicU64::new(0),
+            discoveries_limited: AtomicU64::new(0),
+            parse_failures: AtomicU64::new(0),
+            packets_salvaged: AtomicU64::new(0),
+            error_responses: AtomicU64::new(0),
+            addresses_evicted: AtomicU64::new(0),
+            addresses_rejected: AtomicU64::new(0),
+            answers_applied: AtomicU64::new(0),
+            answers_timed: AtomicU64::new(0),
+            answer_latency_total: AtomicU64::new(0),
+            database_size: AtomicU64::new(0),
+            expirations: AtomicU64::new(0),
+            reconfirmations: AtomicU64::new(0),
+            follow_ups: AtomicU64::new(0),
+            events_dropped: AtomicU64::new(0),
+            interface_changes: AtomicU64::new(0),
+            last_received: AtomicU64::new(0),
+            silent: AtomicBool::new(false),
+            errors: Mutex::default(),
+            activity: ActivityLog::default(),
+        }
+    }
+
+    pub(crate) fn activity(&self) -> &ActivityLog {
+        &self.activity
+    }
+
+    // Interfaces that remain keep their counters.
+    pub(crate) fn interfaces_changed(&self, interfaces: impl IntoIterator<Item = String>) {
+        let interfaces: Vec<String> = interfaces.into_iter().collect();
+        self.set_interfaces(interfaces.iter().cloned());
+        self.interface_changes.fetch_add(1, Ordering::Relaxed);
+
+        self.activity
+            .record(|| Activity::InterfacesChanged { interfaces });
+    }
+
+    // Counters for interfaces that had sockets before are kept.
+    pub(crate) fn set_interfaces(&self, interfaces: impl IntoIterator<Item = String>) {
+        let mut sockets = self.sockets.locked();
+        let mut previous = std::mem::take(&mut *sockets);
+
+        for interface in interfaces {
+            match previous.iter().position(|s| s.interface == interface) {
+                Some(i) => sockets.push(previous.swap_remove(i)),
+                None => sockets.push(SocketCounters::new(interface)),
+            }
+        }
+    }
+
+    pub(crate) fn packet_received(&self) {
+        self.datagrams_received.fetch_add(1, Ordering::Relaxed);
+        self.last_received.store(unix_nanos(), Ordering::Relaxed);
+    }
+
+    // For the receive threads, as `socket_error_on`.
+    pub(crate) fn received_on(&self, interface: &str, count: usize) {
+        let sockets = self.sockets.locked();
+
+        if let Some(socket) = sockets.iter().find(|s| s.interface == interface) {
+            socket
+                .datagrams_received
+                .fetch_add(count as u64, Ordering::Relaxed);
+            socket.last_received.store(unix_nanos(), Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn sent_on(&self, interface: &str) {
+        let sockets = self.sockets.locked();
+
+        if let Some(socket) = sockets.iter().find(|s| s.interface == interface) {
+            socket.datagrams_sent.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn duplicates(&self, count: usize) {
+        self.duplicates.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn receive_batch(&self, size: usize) {
+        self.receive_batches.fetch_add(1, Ordering::Relaxed);
+        self.largest_receive_batch
+            .fetch_max(size as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn rate_limited(&self, count: usize) {
+        self.rate_limited.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn sources_rejected(&self, count: usize) {
+        self.sources_rejected
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn discovery_limited(&self) {
+        self.discoveries_limited.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn parse_failure(&self) {
+        self.parse_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn packet_salvaged(&self) {
+        self.packets_salvaged.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn error_response(&self) {
+        self.error_responses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn address_evicted(&self) {
+        self.addresses_evicted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn address_rejected(&self) {
+        self.addresses_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_silent(&self, silent: bool) {
+        self.silent.store(silent, Ordering::Relaxed);
+    }
+
+    pub(crate) fn answer_applied(&self) {
+        self.answers_applied.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn answer_timed(&self, latency: Duration) {
+        self.answers_timed.fetch_add(1, Ordering::Relaxed);
+        self.answer_latency_total
+            .fetch_add(latency.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn query_sent(&self, socket: usize) {
+        if let Some(socket) = self.sockets.locked().get(socket) {
+            socket.queries_sent.fetch_add(1, Ordering::Relaxed);
+            self.activity.record(|| Activity::QuerySent {
+                interface: socket.interface.clone(),
+            });
+        }
+    }
+
+    pub(crate) fn database_size(&self, size: usize) {
+        self.database_size.store(size as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn expired(&self, count: usize) {
+        self.expirations.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn reconfirmed(&self, count: usize) {
+        self.reconfirmations
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn followed_up(&self, count: usize) {
+        self.follow_ups.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn events_dropped(&self, count: usize) {
+        self.events_dropped
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn socket_error(&self, socket: usize) {
+        if let Some(socket) = self.sockets.locked().get(socket) {
+            socket.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // For a receive thread, which knows its interface but not where its socket is in the list.
+    pub(crate) fn socket_error_on(&self, interface: &str) {
+        let sockets = self.sockets.locked();
+
+        if let Some(socket) = sockets.iter().find(|s| s.interface == interface) {
+            socket.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn error(
+        &self,
+        operation: FailedOperation,
+        interface: Option<String>,
+        error: &dyn Display,
+    ) {
+        let mut errors = self.errors.locked();
+
+        if errors.len() == RECENT_ERRORS {
+            errors.pop_front();
+        }
+
+        let error = BackgroundError {
+            time: SystemTime::now(),
+            operation,
+            interface,
+            message: error.to_string(),
+        };
+        self.activity.record(|| Activity::Error {
+            operation,
+            interface: error.interface.clone(),
+            message: error.message.clone(),
+        });
+        if let Some(interface) = &error.interface {
+            let mut sockets = self.sockets.locked();
+
+            if let Some(socket) = sockets.iter_mut().find(|s| s.interface == *interface) {
+                socket.last_error = Some(error.clone());
+            }
+        }
+        errors.push_back(error);
+    }
+
+    pub(crate) fn errors(&self) -> Vec<BackgroundError> {
+        self.errors.locked().iter().cloned().collect()
+    }
+
+    pub(crate) fn last_error(&self) -> Option<BackgroundError> {
+        self.errors.locked().back().cloned()
+    }
+
+    pub(crate) fn socket_recreated(&self, socket: usize) {
+        if let Some(socket) = self.sockets.locked().get(socket) {
+            socket.recreations.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn group_rejoined(&self, socket: usize) {
+        if let Some(socket) = self.sockets.locked().get(socket) {
+            socket.rejoins.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn send_failures(&self, socket: usize, failures: u32) {
+        if let Some(socket) = self.sockets.locked().get(socket) {
+            socket
+                .send_failures
+                .store(failures as u64, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn legacy_unicast(&self, socket: usize, legacy_unicast: bool) {
+        if let Some(socket) = self.sockets.locked().get(socket) {
+            socket
+                .legacy_unicast
+                .store(legacy_unicast as u64, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn receive_state(&self, socket: usize, buffer: Option<usize>, drops: Option<u64>) {
+        if let Some(socket) = self.sockets.locked().get(socket) {
+            let buffer = buffer.map_or(UNKNOWN, |buffer| buffer as u64);
+            socket.receive_buffer.store(buffer, Ordering::Relaxed);
+            socket
+                .receive_drops
+                .store(drops.unwrap_or(UNKNOWN), Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> MdnsStats {
+        MdnsStats {
+            sockets: self
+                .sockets
+                .locked()
+                .iter()
+                .map(|socket| SocketStats {
+                    interface: socket.interface.clone(),
+                    queries_sent: socket.queries_sent.load(Ordering::Relaxed),
+                    errors: socket.errors.load(Ordering::Relaxed),
+                    recreations: socket.recreations.load(Ordering::Relaxed),
+                    group_rejoins: socket.rejoins.load(Ordering::Relaxed),
+                    receive_buffer: known(&socket.receive_buffer),
+                    receive_drops: known(&socket.receive_drops),
+                    send_failures: socket.send_failures.load(Ordering::Relaxed),
+                    legacy_unicast: socket.legacy_unicast.load(Ordering::Relaxed) != 0,
+                    datagrams_sent: socket.datagrams_sent.load(Ordering::Relaxed),
+                    datagrams_received: socket.datagrams_received.load(Ordering::Relaxed),
+                    last_received: since_epoch(&socket.last_received),
+                    last_error: socket.last_error.clone(),
+                })
+                .collect(),
+            datagrams_received: self.datagrams_received.load(Ordering::Relaxed),
+            duplicates: self.duplicates.load(Ordering::Relaxed),
+            receive_batches: self.receive_batches.load(Ordering::Relaxed),
+            largest_receive_batch: self.largest_receive_batch.load(Ordering::Relaxed),
+            rate_limited: self.rate_limited.load(Ordering::Relaxed),
+            sources_rejected: self.sources_rejected.load(Ordering::Relaxed),
+            discoveries_limited: self.discoveries_limited.load(Ordering::Relaxed),
+            parse_failures: self.parse_failures.load(Ordering::Relaxed),
+            packets_salvaged: self.packets_salvaged.load(Ordering::Relaxed),
+            error_responses: self.error_responses.load(Ordering::Relaxed),
+            addresses_evicted: self.addresses_evicted.load(Ordering::Relaxed),
+            addresses_rejected: self.addresses_rejected.load(Ordering::Relaxed),
+            answers_applied: self.answers_applied.load(Ordering::Relaxed),
+            answers_timed: self.answers_timed.load(Ordering::Relaxed),
+            answer_latency_total: Duration::from_nanos(
+                self.answer_latency_total.load(Ordering::Relaxed),
+            ),
+            entries_expired: self.expirations.load(Ordering::Relaxed),
+            services_reconfirmed: self.reconfirmations.load(Ordering::Relaxed),
+            instances_followed_up: self.follow_ups.load(Ordering::Relaxed),
+            events_dropped: self.events_dropped.load(Ordering::Relaxed),
+            interface_changes: self.interface_changes.load(Ordering::Relaxed),
+            last_received: since_epoch(&self.last_received),
+            silent: self.silent.load(Ordering::Relaxed),
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    pub(crate) fn render(&self) -> String {
+        use std::fmt::Write;
+
+        const PREFIX: &str = "simple_mdns_client";
+
+        let mut out = String::new();
+
+        let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+            writeln!(out, "# HELP {}_{} {}", PREFIX, name, help).ok();
+            writeln!(out, "# TYPE {}_{} {}", PREFIX, name, kind).ok();
+            writeln!(out, "{}_{} {}", PREFIX, name, value).ok();
+        };
+
+        let load = |value: &AtomicU64| value.load(Ordering::Relaxed);
+        let sockets = self.sockets.locked();
+
+        metric(
+            "packets_received_total",
+            "counter",
+            "Datagrams received on the mDNS sockets.",
+            load(&self.datagrams_received),
+        );
+        metric(
+            "duplicates_total",
+            "counter",
+            "Datagrams ignored as copies of one just received on another socket.",
+            load(&self.duplicates),
+        );
+        metric(
+            "receive_batches_total",
+            "counter",
+            "Receive calls that returned datagrams, each returning one or more.",
+            load(&self.receive_batches),
+        );
+        metric(
+            "largest_receive_batch",
+            "gauge",
+            "The most datagrams a single receive call returned.",
+            load(&self.largest_receive_batch),
+        );
+        metric(
+            "rate_limited_total",
+            "counter",
+            "Datagrams dropped because their source exceeded its rate limit.",
+            load(&self.rate_limited),
+        );
+        metric(
+            "sources_rejected_total",
+            "counter",
+            "Datagrams dropped because of their source address.",
+            load(&self.sources_rejected),
+        );
+        metric(
+            "discoveries_limited_total",
+            "counter",
+            "New services not added because of the discovery rate limit.",
+            load(&self.discoveries_limited),
+        );
+        metric(
+            "parse_failures_total",
+            "counter",
+            "Datagrams that could not be parsed as DNS packets.",
+            load(&self.parse_failures),
+        );
+        metric(
+            "packets_salvaged_total",
+            "counter",
+            "Datagrams used with the records that could not be parsed skipped.",
+            load(&self.packets_salvaged),
+        );
+        metric(
+            "error_responses_total",
+            "counter",
+            "Responses ignored for a non-zero response code.",
+            load(&self.error_responses),
+        );
+        metric(
+            "addresses_evicted_total",
+            "counter",
+            "Addresses dropped from hosts announcing more than the limit.",
+            load(&self.addresses_evicted),
+        );
+        metric(
+            "addresses_rejected_total",
+            "counter",
+            "Address records ignored for coming from a responder unrelated to the host.",
+            load(&self.addresses_rejected),
+        );
+        metric(
+            "queries_sent_total",
+            "counter",
+            "Queries sent, summed over all sockets.",
+            sockets.iter().map(|s| load(&s.queries_sent)).sum(),
+        );
+        metric(
+            "answers_applied_total",
+            "counter",
+            "Answers applied to the database.",
+            load(&self.answers_applied),
+        );
+        metric(
+            "silent",
+            "gauge",
+            "1 while no datagram has arrived from other hosts for the silence threshold.",
+            self.silent.load(Ordering::Relaxed) as u64,
+        );
+        metric(
+            "database_size",
+            "gauge",
+            "Services currently in the database.",
+            load(&self.database_size),
+        );
+        metric(
+            "expirations_total",
+            "counter",
+            "Services removed from the database because they were not seen recently.",
+            load(&self.expirations),
+        );
+        metric(
+            "reconfirmations_total",
+            "counter",
+            "Services asked after by name because they were about to expire.",
+            load(&self.reconfirmations),
+        );
+        metric(
+            "follow_ups_total",
+            "counter",
+            "Instances asked after by name because PTR answers pointed at them alone.",
+            load(&self.follow_ups),
+        );
+        metric(
+            "events_dropped_total",
+            "counter",
+            "Events dropped because a subscriber's queue was full.",
+            load(&self.events_dropped),
+        );
+        metric(
+            "interface_changes_total",
+            "counter",
+            "Changes to the set of interfaces the sockets are bound to.",
+            load(&self.interface_changes),
+        );
+
+        let name = "answer_latency_seconds";
+        let help = "Time from a query to the SRV answers that arrived within 500 ms of it.";
+        writeln!(out, "# HELP {}_{} {}", PREFIX, name, help).ok();
+        writeln!(out, "# TYPE {}_{} summary", PREFIX, name).ok();
+        writeln!(
+            out,
+            "{}_{}_sum {}",
+            PREFIX,
+            name,
+            Duration::from_nanos(load(&self.answer_latency_total)).as_secs_f64()
+        )
+        .ok();
+        writeln!(
+            out,
+            "{}_{}_count {}",
+            PREFIX,
+            name,
+            load(&self.answers_timed)
+        )
+        .ok();
+
+        let mut per_socket =
+            |name: &str, kind: &str, help: &str, value: fn(&SocketCounters) -> &AtomicU64| {
+                writeln!(out, "# HELP {}_{} {}", PREFIX, name, help).ok();
+                writeln!(out, "# TYPE {}_{} {}", PREFIX, name, kind).ok();
+                for socket in sockets.iter() {
+                    if let Some(value) = known(value(socket)) {
+                        writeln!(
+                            out,
+                            "{}_{}{{interface=\"{}\"}} {}",
+                            PREFIX, name, socket.interface, value
+                        )
+                        .ok();
+                    }
+                }
+            };
+
+        per_socket(
+            "socket_errors_total",
+            "counter",
+            "Send and receive errors per socket.",
+            |s| &s.errors,
+        );
+        per_socket(
+            "socket_recreations_total",
+            "counter",
+            "Sockets closed and set up again after failing persistently.",
+            |s| &s.recreations,
+        );
+        per_socket(
+            "socket_group_rejoins_total",
+            "counter",
+            "Times a socket left the multicast group and joined it again.",
+            |s| &s.rejoins,
+        );
+        per_socket(
+            "socket_receive_buffer_bytes",
+            "gauge",
+            "Receive buffer size in effect per socket.",
+            |s| &s.receive_buffer,
+        );
+        per_socket(
+            "socket_receive_drops_total",
+            "counter",
+            "Datagrams dropped by the kernel because the receive buffer was full.",
+            |s| &s.receive_drops,
+        );
+        per_socket(
+            "socket_send_failures",
+            "gauge",
+            "Sends failed in a row per socket.",
+            |s| &s.send_failures,
+        );
+        per_socket(
+            "socket_legacy_unicast",
+            "gauge",
+            "1 where the socket queries from an ephemeral port because 5353 is taken.",
+            |s| &s.legacy_unicast,
+        );
+        per_socket(
+            "socket_datagrams_sent_total",
+            "counter",
+            "Queries, answers and announcements sent per socket.",
+            |s| &s.datagrams_sent,
+        );
+        per_socket(
+            "socket_datagrams_received_total",
+            "counter",
+            "Datagrams received per socket, before any are rejected or deduplicated.",
+            |s| &s.datagrams_received,
+        );
+
+        out
+    }
+}
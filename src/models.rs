@@ -0,0 +1,222 @@
+// Loom models of the threads sharing `Changes` and the event queues: workers publishing
+// updates, readers of the published services and the generation, subscribers and the
+// client shutting down. Loom runs each one in every interleaving `sync`'s primitives
+// allow, and reports a deadlock if a wakeup is ever lost.
+use crate::{
+    availability::History, changes::Changes, database::Database, metrics::Metrics, Provenance,
+    Service, ServiceEvent, ServiceRecord,
+};
+use loom::thread;
+use std::{
+    collections::HashSet,
+    sync::{mpsc::RecvTimeoutError, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+fn service() -> Service {
+    Service {
+        host: "printer.local".into(),
+        port: 631,
+    }
+}
+
+fn record() -> ServiceRecord {
+    ServiceRecord {
+        last_seen_time: Instant::now(),
+        preferred_address: None,
+        addresses: HashSet::new(),
+        ipv6_addresses: HashSet::new(),
+        conflicting: false,
+        latency: None,
+        provenance: Provenance::default(),
+        pinned: false,
+        withdrawn: false,
+        seeded: false,
+        mac_address: None,
+        records: Arc::default(),
+        history: History::default(),
+    }
+}
+
+fn changes() -> Changes {
+    Changes::new(Arc::new(Metrics::new([])))
+}
+
+// As a worker does after handling a packet that added `service()`. The database is
+// std's: only the thread calling this locks it.
+fn add(changes: &Changes, database: &Mutex<Database>) {
+    let mut database = Database::lock(database);
+    database.get_or_insert_with(service(), record);
+    changes.update(database, Instant::now());
+}
+
+// A reader that sees the generation moved sees what moved it.
+#[test]
+fn publish_read() {
+    loom::model(|| {
+        let changes = Arc::new(changes());
+        let database = Mutex::new(Database::default());
+
+        let reader = thread::spawn({
+            let changes = changes.clone();
+            move || {
+                let generation = changes.generation();
+                let services = changes.published().load(Instant::now());
+                if generation > 0 {
+                    assert!(services.contains_key(&service()));
+                }
+            }
+        });
+
+        add(&changes, &database);
+        assert_eq!(changes.generation(), 1);
+        assert!(changes
+            .published()
+            .load(Instant::now())
+            .contains_key(&service()));
+        reader.join().unwrap();
+    });
+}
+
+// A waiter is woken by a change made just as it starts waiting.
+#[test]
+fn wait_for_change() {
+    loom::model(|| {
+        let changes = Arc::new(changes());
+
+        let waiter = thread::spawn({
+            let changes = changes.clone();
+            move || changes.wait_for_change(0, Duration::from_secs(3600))
+        });
+
+        changes.invalidate();
+        assert_eq!(waiter.join().unwrap(), Some(1));
+    });
+}
+
+// A generation already moved on from is returned without waiting, which with nothing
+// else to wake the waiter is the only way for the model to end.
+#[test]
+fn wait_for_stale_generation() {
+    loom::model(|| {
+        let changes = changes();
+
+        changes.invalidate();
+        assert_eq!(
+            changes.wait_for_change(0, Duration::from_secs(3600)),
+            Some(1)
+        );
+    });
+}
+
+// A service only heard from again leaves waiters waiting, for the change after it.
+#[test]
+fn wait_through_refresh() {
+    loom::model(|| {
+        let changes = Arc::new(changes());
+        let database = Mutex::new(Database::default());
+        add(&changes, &database);
+
+        let waiter = thread::spawn({
+            let changes = changes.clone();
+            move || changes.wait_for_change(1, Duration::from_secs(3600))
+        });
+
+        let mut locked = Database::lock(&database);
+        locked.get_or_insert_with(service(), record).last_seen_time += Duration::from_secs(1);
+        changes.update(locked, Instant::now());
+        assert_eq!(changes.generation(), 1);
+
+        changes.invalidate();
+        assert_eq!(waiter.join().unwrap(), Some(2));
+    });
+}
+
+// Woken without a change, a waiter waits on rather than returning.
+#[test]
+fn wait_through_spurious_wake() {
+    loom::model(|| {
+        let changes = Arc::new(changes());
+
+        let waiter = thread::spawn({
+            let changes = changes.clone();
+            move || changes.wait_for_change(0, Duration::from_secs(3600))
+        });
+
+        changes.wake_waiters();
+        changes.invalidate();
+        assert_eq!(waiter.join().unwrap(), Some(1));
+    });
+}
+
+// A subscriber that joins during an update is told of the service once, from either the
+// state it joins with or the update's events.
+#[test]
+fn subscribe_during_update() {
+    loom::model(|| {
+        let changes = Arc::new(changes());
+        let database = Mutex::new(Database::default());
+
+        let subscriber = thread::spawn({
+            let changes = changes.clone();
+            move || changes.subscribe(16, Instant::now())
+        });
+
+        add(&changes, &database);
+        let subscription = subscriber.join().unwrap();
+        drop(changes);
+
+        match subscription.recv_timeout(Duration::from_secs(3600)) {
+            Ok(ServiceEvent::Added(added, _)) => assert_eq!(added, service()),
+            other => panic!("expected the service added, got {:?}", other),
+        }
+        assert!(matches!(
+            subscription.recv_timeout(Duration::from_secs(3600)),
+            Err(RecvTimeoutError::Disconnected)
+        ));
+    });
+}
+
+// The client shutting down right after an update, while the subscriber waits: the
+// subscriber gets the update's event first, then is told the client is gone, and
+// doesn't wait on forever.
+#[test]
+fn shutdown_during_update() {
+    loom::model(|| {
+        let changes = changes();
+        let database = Mutex::new(Database::default());
+        let subscription = changes.subscribe(16, Instant::now());
+
+        let worker = thread::spawn(move || {
+            add(&changes, &database);
+            drop(changes);
+        });
+
+        match subscription.recv_timeout(Duration::from_secs(3600)) {
+            Ok(ServiceEvent::Added(added, _)) => assert_eq!(added, service()),
+            other => panic!("expected the service added, got {:?}", other),
+        }
+        assert!(matches!(
+            subscription.recv_timeout(Duration::from_secs(3600)),
+            Err(RecvTimeoutError::Disconnected)
+        ));
+        worker.join().unwrap();
+    });
+}
+
+// A subscriber dropped while an update is pushing to it is let go, and the update
+// neither fails nor waits for it.
+#[test]
+fn unsubscribe_during_update() {
+    loom::model(|| {
+        let changes = Arc::new(changes());
+        let database = Mutex::new(Database::default());
+        let subscription = changes.subscribe(16, Instant::now());
+
+        let dropper = thread::spawn(move || drop(subscription));
+
+        add(&changes, &database);
+        dropper.join().unwrap();
+        assert!(!changes.has_subscribers());
+    });
+}
@@ -0,0 +1,143 @@
+use crate::{OwnedRecord, ServiceEvent};
+use std::{
+    fmt::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// Bumped whenever a field is renamed or removed; additions keep the version.
+const SCHEMA_VERSION: u32 = 1;
+
+fn string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn array(out: &mut String, key: &str, values: &[impl std::fmt::Display]) {
+    write!(out, ",\"{}\":[", key).unwrap();
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write!(out, "\"{}\"", value).unwrap();
+    }
+    out.push(']');
+}
+
+// With `MdnsClientBuilder::keep_records`, e.g. ,"records":[{"name":"a._http._tcp.local",
+// "type":16,"class":1,"cache_flush":true,"ttl":4500,"data":"00"}], the data in hex.
+fn records(out: &mut String, records: &[OwnedRecord]) {
+    out.push_str(",\"records\":[");
+    for (i, record) in records.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        out.push_str("{\"name\":");
+        string(out, &record.name);
+        write!(
+            out,
+            ",\"type\":{},\"class\":{},\"cache_flush\":{},\"ttl\":{},\"data\":\"",
+            record.record_type, record.class, record.cache_flush, record.ttl
+        )
+        .unwrap();
+        for byte in &record.data {
+            write!(out, "{:02x}", byte).unwrap();
+        }
+        out.push_str("\"}");
+    }
+    out.push(']');
+}
+
+// One line, without the newline, e.g.
+// {"schema":1,"event":"added","timestamp_ms":1700000000000,"host":"a.local","port":80,
+//  "preferred_address":null,"addresses":["192.168.1.2"],"ipv6_addresses":[],
+//  "conflicting":false,"availability":{"uptime":1.0000,"disappearances":0,
+//  "longest_gap_ms":0,"observed_ms":1200}}
+// A "moved" event also has "from_host" and "from_port", and a "renamed" event
+// "old_instance" and "new_instance".
+pub(crate) fn line(event: &ServiceEvent, time: SystemTime) -> String {
+    let (kind, record) = match event {
+        ServiceEvent::Added(_, record) => ("added", Some(record)),
+        ServiceEvent::Updated(_, record) => ("updated", Some(record)),
+        ServiceEvent::Expired(_) => ("expired", None),
+        ServiceEvent::Moved { record, .. } => ("moved", Some(record)),
+        ServiceEvent::Renamed { record, .. } => ("renamed", Some(record)),
+    };
+
+    let timestamp = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |t| t.as_millis() as u64);
+
+    let mut out = String::new();
+    write!(
+        out,
+        "{{\"schema\":{},\"event\":\"{}\",\"timestamp_ms\":{},\"host\":",
+        SCHEMA_VERSION, kind, timestamp
+    )
+    .unwrap();
+    string(&mut out, &event.service().host);
+    write!(out, ",\"port\":{}", event.service().port).unwrap();
+
+    if let Some(record) = record {
+        match record.preferred_address {
+            Some(address) => write!(out, ",\"preferred_address\":\"{}\"", address).unwrap(),
+            None => out.push_str(",\"preferred_address\":null"),
+        }
+
+        let mut addresses: Vec<_> = record.addresses.iter().collect();
+        addresses.sort();
+        array(&mut out, "addresses", &addresses);
+
+        let mut addresses: Vec<_> = record.ipv6_addresses.iter().collect();
+        addresses.sort();
+        array(&mut out, "ipv6_addresses", &addresses);
+
+        write!(out, ",\"conflicting\":{}", record.conflicting).unwrap();
+        if record.seeded {
+            out.push_str(",\"seeded\":true");
+        }
+        if let Some(mac) = record.mac_address {
+            write!(out, ",\"mac_address\":\"{}\"", mac).unwrap();
+        }
+
+        let availability = record.availability();
+        write!(
+            out,
+            ",\"availability\":{{\"uptime\":{:.4},\"disappearances\":{},\"longest_gap_ms\":{},\"observed_ms\":{}}}",
+            availability.uptime,
+            availability.disappearances,
+            availability.longest_gap.as_millis(),
+            availability.observed.as_millis()
+        )
+        .unwrap();
+
+        if !record.records().is_empty() {
+            records(&mut out, record.records());
+        }
+    }
+
+    if let ServiceEvent::Moved { from, .. } = event {
+        out.push_str(",\"from_host\":");
+        string(&mut out, &from.host);
+        write!(out, ",\"from_port\":{}", from.port).unwrap();
+    }
+    if let ServiceEvent::Renamed { old, new, .. } = event {
+        out.push_str(",\"old_instance\":");
+        string(&mut out, old);
+        out.push_str(",\"new_instance\":");
+        string(&mut out, new);
+    }
+
+    out.push('}');
+    out
+}
@@ -0,0 +1,214 @@
+// The MAC addresses of `MdnsClientBuilder::mac_addresses`, from the OS neighbor table:
+// /proc/net/arp on Linux and Android, which only holds IPv4 neighbors, and GetIpNetTable2
+// on Windows. Elsewhere, or where the table can't be read, services get none.
+use crate::{
+    catch_panic,
+    changes::Changes,
+    database::Database,
+    metrics::{FailedOperation, Metrics},
+    MacAddr, ServiceRecord,
+};
+use std::{
+    collections::HashMap,
+    io,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+// How often services are looked up again when nothing changed, as a neighbor may answer
+// for an address before the kernel's table has it.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// Sets `ServiceRecord::mac_address` for as long as it is kept.
+pub(crate) struct Lookup {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Lookup {
+    pub(crate) fn start(
+        database: Arc<Mutex<Database>>,
+        changes: Arc<Changes>,
+        metrics: Arc<Metrics>,
+    ) -> Lookup {
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread = thread::spawn({
+            let stop = stop.clone();
+
+            move || {
+                catch_panic(
+                    || look_up_while(&database, &changes, &stop),
+                    |message| metrics.error(FailedOperation::Panic, None, &message),
+                );
+            }
+        });
+
+        Lookup {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Lookup {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+// Whenever the services change, at most every `CHECK_INTERVAL`, and every
+// `REFRESH_INTERVAL` otherwise.
+fn look_up_while(database: &Mutex<Database>, changes: &Changes, stop: &AtomicBool) {
+    let mut looked_up: Option<(u64, Instant)> = None;
+    let mut failing = false;
+
+    while !stop.load(Ordering::Relaxed) {
+        let generation = changes.generation();
+        let due = looked_up
+            .is_none_or(|(last, at)| last != generation || at.elapsed() >= REFRESH_INTERVAL);
+
+        if due {
+            looked_up = Some((generation, Instant::now()));
+
+            match table() {
+                Ok(table) => {
+                    failing = false;
+
+                    let mut database = Database::lock(database);
+                    database.update_all(|record| {
+                        // Kept while the table has none, as entries age out of it.
+                        let mac = mac_of(record, &table);
+                        if mac.is_some() && mac != record.mac_address {
+                            record.mac_address = mac;
+                            true
+                        } else {
+                            false
+                        }
+                    });
+                    changes.update(database, Instant::now());
+                }
+                // Only logged, once until it works again: without the table there are
+                // just no MAC addresses.
+                Err(_err) if !failing => {
+                    debug!(error = %_err, "failed to read the neighbor table");
+                    failing = true;
+                }
+                Err(_) => (),
+            }
+        }
+
+        thread::sleep(CHECK_INTERVAL);
+    }
+}
+
+// That of the preferred address, else of the first other address, in order.
+fn mac_of(record: &ServiceRecord, table: &HashMap<IpAddr, MacAddr>) -> Option<MacAddr> {
+    let mut addresses: Vec<IpAddr> = record.addresses.iter().map(|&a| a.into()).collect();
+    addresses.sort();
+    let mut ipv6_addresses: Vec<IpAddr> =
+        record.ipv6_addresses.iter().map(|a| a.ip.into()).collect();
+    ipv6_addresses.sort();
+
+    record
+        .preferred_address
+        .map(IpAddr::from)
+        .into_iter()
+        .chain(addresses)
+        .chain(ipv6_addresses)
+        .find_map(|address| table.get(&address).copied())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn table() -> io::Result<HashMap<IpAddr, MacAddr>> {
+    // IP address  HW type  Flags  HW address  Mask  Device, after a header line.
+    let text = std::fs::read_to_string("/proc/net/arp")?;
+    let table = text
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [address, _, flags, mac, ..] = fields[..] else {
+                return None;
+            };
+            // Without ATF_COM, the entry is still being resolved.
+            let flags = u32::from_str_radix(flags.trim_start_matches("0x"), 16).ok()?;
+            if flags & 0x2 == 0 {
+                return None;
+            }
+            Some((address.parse().ok()?, parse_mac(mac)?))
+        })
+        .collect();
+    Ok(table)
+}
+
+#[cfg(target_os = "windows")]
+fn table() -> io::Result<HashMap<IpAddr, MacAddr>> {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use windows_sys::Win32::{
+        NetworkManagement::IpHelper::{FreeMibTable, GetIpNetTable2, MIB_IPNET_TABLE2},
+        Networking::WinSock::{NlnsIncomplete, NlnsUnreachable, AF_INET, AF_INET6, AF_UNSPEC},
+    };
+
+    let mut table: *mut MIB_IPNET_TABLE2 = std::ptr::null_mut();
+    match unsafe { GetIpNetTable2(AF_UNSPEC, &mut table) } {
+        0 => (),
+        code => return Err(io::Error::from_raw_os_error(code as i32)),
+    }
+
+    let mut neighbors = HashMap::new();
+    // Valid until freed, with `NumEntries` rows from `Table` on.
+    unsafe {
+        let rows =
+            std::slice::from_raw_parts((*table).Table.as_ptr(), (*table).NumEntries as usize);
+        for row in rows {
+            if row.State == NlnsIncomplete
+                || row.State == NlnsUnreachable
+                || row.PhysicalAddressLength != 6
+            {
+                continue;
+            }
+            let address: IpAddr = match row.Address.si_family {
+                AF_INET => {
+                    Ipv4Addr::from(u32::from_be(row.Address.Ipv4.sin_addr.S_un.S_addr)).into()
+                }
+                AF_INET6 => Ipv6Addr::from(row.Address.Ipv6.sin6_addr.u.Byte).into(),
+                _ => continue,
+            };
+            let mut mac = [0; 6];
+            mac.copy_from_slice(&row.PhysicalAddress[..6]);
+            if mac != [0; 6] {
+                neighbors.insert(address, MacAddr(mac));
+            }
+        }
+        FreeMibTable(table as *const _);
+    }
+    Ok(neighbors)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "windows")))]
+fn table() -> io::Result<HashMap<IpAddr, MacAddr>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "no neighbor table on this platform",
+    ))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn parse_mac(text: &str) -> Option<MacAddr> {
+    let mut mac = [0; 6];
+    let mut parts = text.split(':');
+    for byte in &mut mac {
+        *byte = u8::from_str_radix(parts.next()?, 16).ok()?;
+    }
+    (parts.next().is_none() && mac != [0; 6]).then_some(MacAddr(mac))
+}
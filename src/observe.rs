@@ -0,0 +1,187 @@
+use crate::{
+    limit::Sources,
+    lock::Lock,
+    packet::{Packet, TYPE_ANY},
+};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Instant, SystemTime},
+};
+
+// Caps on what is kept, as anything on the network can send queries: questions overall
+// and per source, sources, and datagrams a second looked at per source. What goes over
+// is only counted, in `QuerySource::dropped` where the source is kept.
+const MAX_QUERIES: usize = 1024;
+const MAX_QUERIES_PER_SOURCE: usize = 64;
+const MAX_SOURCES: usize = 256;
+const SOURCE_RATE: u32 = 20;
+
+/// A question other hosts were seen asking, see `MdnsClient::observed_queries`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ObservedQuery {
+    pub source: IpAddr,
+    /// As last asked; names are compared ignoring case.
+    pub name: String,
+    pub record_type: u16,
+    /// The QU bit, asking for a unicast answer (RFC 6762 section 5.4).
+    pub unicast_response: bool,
+    /// Known answers for the name sent along the last time (RFC 6762 section 7.1).
+    pub known_answers: usize,
+    /// In how many datagrams.
+    pub count: u64,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+}
+
+/// What one host was seen asking altogether, see `MdnsClient::query_sources`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QuerySource {
+    pub source: IpAddr,
+    /// Datagrams with questions.
+    pub queries: u64,
+    /// Distinct questions among `MdnsClient::observed_queries`.
+    pub questions: usize,
+    /// Datagrams over the rate looked at per source, or with questions beyond the caps.
+    pub dropped: u64,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+}
+
+// The questions of a client built with `MdnsClientBuilder::observe_queries`.
+pub(crate) struct Observer {
+    state: Mutex<State>,
+}
+
+struct State {
+    // By source, lowercase name, type and QU bit.
+    queries: HashMap<(IpAddr, String, u16, bool), ObservedQuery>,
+    sources: HashMap<IpAddr, QuerySource>,
+    rate: Sources,
+}
+
+impl Default for Observer {
+    fn default() -> Self {
+        Observer {
+            state: Mutex::new(State {
+                queries: HashMap::new(),
+                sources: HashMap::new(),
+                rate: Sources::new(SOURCE_RATE),
+            }),
+        }
+    }
+}
+
+impl Observer {
+    pub(crate) fn observe(&self, packet: &Packet, from: IpAddr, now: Instant) {
+        if !packet.header.query || packet.questions.is_empty() {
+            return;
+        }
+
+        let mut state = self.state.locked();
+        let time = SystemTime::now();
+
+        if !state.sources.contains_key(&from) && state.sources.len() >= MAX_SOURCES {
+            return;
+        }
+        let allowed = state.rate.allow(from, now);
+
+        let source = state.sources.entry(from).or_insert(QuerySource {
+            source: from,
+            queries: 0,
+            questions: 0,
+            dropped: 0,
+            first_seen: time,
+            last_seen: time,
+        });
+        source.last_seen = time;
+        if !allowed {
+            source.dropped += 1;
+            return;
+        }
+        source.queries += 1;
+
+        let mut dropped = false;
+        for question in &packet.questions {
+            let name = question.qname.to_string();
+            let known_answers = packet
+                .answers
+                .iter()
+                .filter(|answer| {
+                    (question.qtype == TYPE_ANY || answer.kind == question.qtype)
+                        && answer.name.to_string().eq_ignore_ascii_case(&name)
+                })
+                .count();
+            let key = (
+                from,
+                name.to_ascii_lowercase(),
+                question.qtype,
+                question.prefer_unicast,
+            );
+
+            if let Some(query) = state.queries.get_mut(&key) {
+                query.name = name;
+                query.known_answers = known_answers;
+                query.count += 1;
+                query.last_seen = time;
+                continue;
+            }
+
+            let questions = state
+                .sources
+                .get(&from)
+                .map_or(0, |source| source.questions);
+            if state.queries.len() >= MAX_QUERIES || questions >= MAX_QUERIES_PER_SOURCE {
+                dropped = true;
+                continue;
+            }
+
+            state.queries.insert(
+                key,
+                ObservedQuery {
+                    source: from,
+                    name,
+                    record_type: question.qtype,
+                    unicast_response: question.prefer_unicast,
+                    known_answers,
+                    count: 1,
+                    first_seen: time,
+                    last_seen: time,
+                },
+            );
+            if let Some(source) = state.sources.get_mut(&from) {
+                source.questions += 1;
+            }
+        }
+
+        if dropped {
+            if let Some(source) = state.sources.get_mut(&from) {
+                source.dropped += 1;
+            }
+        }
+    }
+
+    // By source, then name and type.
+    pub(crate) fn queries(&self) -> Vec<ObservedQuery> {
+        let mut queries: Vec<_> = self.state.locked().queries.values().cloned().collect();
+        queries.sort_by_cached_key(|query| {
+            (
+                query.source,
+                query.name.to_ascii_lowercase(),
+                query.record_type,
+                query.unicast_response,
+            )
+        });
+        queries
+    }
+
+    // Most queries first.
+    pub(crate) fn sources(&self) -> Vec<QuerySource> {
+        let mut sources: Vec<_> = self.state.locked().sources.values().cloned().collect();
+        sources.sort_by_key(|source| (std::cmp::Reverse(source.queries), source.source));
+        sources
+    }
+}
@@ -0,0 +1,354 @@
+// A reader for what mDNS uses of DNS messages. General DNS parsers fail a whole packet
+// over one record they can't decode, a type or class they don't know such as HTTPS, or
+// malformed data, as some devices send next to perfectly good SRV and A records. Here those
+// records are kept as `RData::Other`, the malformed ones counted in `Packet::skipped`, and
+// a record whose name or length is broken ends the packet there. Only a broken header or
+// question fails it. Public only for `bench`.
+use std::{
+    convert::TryFrom,
+    error::Error,
+    fmt,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+const HEADER: usize = 12;
+const UNIQUE: u16 = 0x8000;
+const POINTER: u8 = 0xc0;
+
+pub(crate) const TYPE_A: u16 = 1;
+pub(crate) const TYPE_PTR: u16 = 12;
+pub(crate) const TYPE_TXT: u16 = 16;
+pub(crate) const TYPE_AAAA: u16 = 28;
+pub(crate) const TYPE_SRV: u16 = 33;
+pub(crate) const TYPE_ANY: u16 = 255;
+
+#[derive(Debug)]
+pub struct ParseError(&'static str);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl Error for ParseError {}
+
+#[derive(Debug)]
+pub struct Packet<'a> {
+    pub header: Header,
+    pub questions: Vec<Question<'a>>,
+    /// The answers alone; authority and additional records are only read on demand.
+    pub answers: Vec<ResourceRecord<'a>>,
+    /// Records whose data was malformed, or that were dropped with the rest of the packet
+    /// after one whose name or length was.
+    pub skipped: usize,
+    data: &'a [u8],
+    // Where the authority and additional records start, and how many were read.
+    rest: (usize, usize),
+}
+
+#[derive(Debug)]
+pub struct Header {
+    pub id: u16,
+    pub query: bool,
+    pub response_code: u8,
+}
+
+#[derive(Debug)]
+pub struct Question<'a> {
+    pub qname: Name<'a>,
+    pub qtype: u16,
+    /// The QU bit, asking for a unicast answer.
+    pub prefer_unicast: bool,
+}
+
+#[derive(Debug)]
+pub struct ResourceRecord<'a> {
+    pub name: Name<'a>,
+    pub kind: u16,
+    /// Without the cache-flush bit.
+    pub class: u16,
+    /// The cache-flush bit.
+    pub multicast_unique: bool,
+    pub ttl: u32,
+    pub data: RData<'a>,
+    /// The data as sent, names in it possibly compressed.
+    pub rdata: &'a [u8],
+    rdata_at: usize,
+}
+
+#[derive(Debug)]
+pub enum RData<'a> {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Srv { port: u16, target: Name<'a> },
+    Other,
+}
+
+// A name where it starts in the packet, checked when read; written out without the root's
+// trailing dot, labels as UTF-8.
+#[derive(Clone, Copy)]
+pub struct Name<'a> {
+    packet: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Packet<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Packet<'a>, ParseError> {
+        if data.len() < HEADER {
+            return Err(ParseError("packet shorter than a header"));
+        }
+
+        let flags = read(data, 2);
+        let header = Header {
+            id: read(data, 0),
+            query: flags & 0x8000 == 0,
+            response_code: (flags & 0x000f) as u8,
+        };
+        let question_count = read(data, 4);
+        let record_count: usize = [6, 8, 10].iter().map(|&at| read(data, at) as usize).sum();
+
+        let mut offset = HEADER;
+        let mut questions = Vec::with_capacity(question_count.min(64) as usize);
+
+        for _ in 0..question_count {
+            let (qname, end) = Name::scan(data, offset)?;
+            if end + 4 > data.len() {
+                return Err(ParseError("question cut short"));
+            }
+
+            questions.push(Question {
+                qname,
+                qtype: read(data, end),
+                prefer_unicast: read(data, end + 2) & UNIQUE != 0,
+            });
+            offset = end + 4;
+        }
+
+        let answer_count = read(data, 6) as usize;
+        let mut answers = Vec::with_capacity(answer_count.min(64));
+        let mut skipped = 0;
+        let mut rest = (offset, 0);
+
+        for i in 0..record_count {
+            let Some((record, malformed, end)) = record(data, offset) else {
+                skipped += record_count - i;
+                break;
+            };
+
+            skipped += malformed as usize;
+            if i < answer_count {
+                answers.push(record);
+                rest.0 = end;
+            } else {
+                rest.1 += 1;
+            }
+            offset = end;
+        }
+
+        Ok(Packet {
+            header,
+            questions,
+            answers,
+            skipped,
+            data,
+            rest,
+        })
+    }
+
+    // The authority and additional records, read again, which `parse` only skips over.
+    pub(crate) fn additionals(&self) -> Vec<ResourceRecord<'a>> {
+        let (mut offset, count) = self.rest;
+        let mut records = Vec::with_capacity(count);
+
+        for _ in 0..count {
+            let Some((record, _, end)) = record(self.data, offset) else {
+                break;
+            };
+            records.push(record);
+            offset = end;
+        }
+
+        records
+    }
+}
+
+// The record at `offset`, whether its data was malformed, and where it ends; `None` if its
+// name or length is broken.
+fn record(data: &[u8], offset: usize) -> Option<(ResourceRecord<'_>, bool, usize)> {
+    let (name, end) = Name::scan(data, offset).ok()?;
+    if end + 10 > data.len() {
+        return None;
+    }
+
+    let kind = read(data, end);
+    let class = read(data, end + 2);
+    let rdata_end = end + 10 + read(data, end + 8) as usize;
+    let rdata = data.get(end + 10..rdata_end)?;
+
+    let parsed = match kind {
+        TYPE_A => <[u8; 4]>::try_from(rdata)
+            .ok()
+            .map(|octets| RData::A(octets.into())),
+        TYPE_AAAA => <[u8; 16]>::try_from(rdata)
+            .ok()
+            .map(|octets| RData::Aaaa(octets.into())),
+        // Priority, weight, port and the target, which has to be within the data.
+        TYPE_SRV if rdata.len() > 6 => Name::scan(data, end + 16)
+            .ok()
+            .filter(|(_, target_end)| *target_end <= rdata_end)
+            .map(|(target, _)| RData::Srv {
+                port: read(data, end + 14),
+                target,
+            }),
+        TYPE_SRV => None,
+        _ => Some(RData::Other),
+    };
+
+    let malformed = parsed.is_none();
+    let record = ResourceRecord {
+        name,
+        kind,
+        class: class & !UNIQUE,
+        multicast_unique: class & UNIQUE != 0,
+        ttl: u32::from(read(data, end + 4)) << 16 | u32::from(read(data, end + 6)),
+        data: parsed.unwrap_or(RData::Other),
+        rdata,
+        rdata_at: end + 10,
+    };
+    Some((record, malformed, rdata_end))
+}
+
+impl<'a> ResourceRecord<'a> {
+    // The instance a PTR record points at, if it does within its data.
+    pub(crate) fn ptr_target(&self) -> Option<Name<'a>> {
+        if self.kind != TYPE_PTR {
+            return None;
+        }
+
+        let (target, end) = Name::scan(self.name.packet, self.rdata_at).ok()?;
+        (end <= self.rdata_at + self.rdata.len()).then_some(target)
+    }
+
+    // `rdata` with the names of PTR and SRV records written out in full, so it means the
+    // same outside the packet.
+    pub(crate) fn expanded_rdata(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.rdata.len());
+
+        match &self.data {
+            RData::Srv { target, .. } => {
+                data.extend_from_slice(&self.rdata[..6]);
+                target.encode(&mut data);
+            }
+            _ => match self.ptr_target() {
+                Some(target) => target.encode(&mut data),
+                None => data.extend_from_slice(self.rdata),
+            },
+        }
+
+        data
+    }
+}
+
+impl<'a> Name<'a> {
+    // The name at `offset` and where it ends there. Each compression pointer has to point
+    // before the one followed last, so following them always ends.
+    fn scan(packet: &'a [u8], offset: usize) -> Result<(Name<'a>, usize), ParseError> {
+        let mut at = offset;
+        let mut end = None;
+        let mut limit = packet.len();
+
+        loop {
+            let length = *packet.get(at).ok_or(ParseError("name cut short"))?;
+
+            match length & POINTER {
+                0 if length == 0 => {
+                    return Ok((Name { packet, offset }, end.unwrap_or(at + 1)));
+                }
+                0 => {
+                    at += 1 + length as usize;
+                }
+                POINTER => {
+                    let low = *packet.get(at + 1).ok_or(ParseError("name cut short"))?;
+                    let target = u16::from_be_bytes([length & !POINTER, low]) as usize;
+                    if target >= limit {
+                        return Err(ParseError("compression pointer loop"));
+                    }
+
+                    end.get_or_insert(at + 2);
+                    limit = target;
+                    at = target;
+                }
+                _ => return Err(ParseError("unknown label type")),
+            }
+        }
+    }
+
+    // Its labels in order, without the root, compression pointers followed.
+    pub(crate) fn labels(&self) -> Labels<'a> {
+        Labels {
+            packet: self.packet,
+            at: self.offset,
+        }
+    }
+
+    // Uncompressed, as labels and the root.
+    pub(crate) fn encode(&self, out: &mut Vec<u8>) {
+        for label in self.labels() {
+            out.push(label.len() as u8);
+            out.extend_from_slice(label);
+        }
+        out.push(0);
+    }
+}
+
+pub(crate) struct Labels<'a> {
+    packet: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Iterator for Labels<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        // Checked by `scan`: every label is in the packet and the pointers lead somewhere.
+        loop {
+            let length = self.packet[self.at];
+
+            if length & POINTER == POINTER {
+                self.at =
+                    u16::from_be_bytes([length & !POINTER, self.packet[self.at + 1]]) as usize;
+                continue;
+            }
+            if length == 0 {
+                return None;
+            }
+
+            let label = &self.packet[self.at + 1..self.at + 1 + length as usize];
+            self.at += 1 + length as usize;
+            return Some(label);
+        }
+    }
+}
+
+impl fmt::Display for Name<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, label) in self.labels().enumerate() {
+            if i > 0 {
+                f.write_str(".")?;
+            }
+            write!(f, "{}", String::from_utf8_lossy(label))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Name<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.to_string())
+    }
+}
+
+fn read(data: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([data[at], data[at + 1]])
+}
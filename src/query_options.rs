@@ -0,0 +1,207 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+// How often the cache is looked at again while waiting for a first answer.
+const POLL_INTERVAL: Duration = Duration::from_millis(25);
+
+/// How a lookup such as `CacheResolver::resolve_with_options` queries and waits. The
+/// default waits up to three seconds for a first answer, querying three times a second
+/// apart, and returns at once what the cache already has.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueryOptions {
+    /// How long to wait for answers at most. Zero looks in the cache alone, without
+    /// querying, whatever `use_cache` says.
+    pub timeout: Duration,
+    /// Queries sent after the first while nothing is found; those that would go out at
+    /// or after the timeout aren't sent.
+    pub retransmissions: u32,
+    /// Between one query and the next.
+    pub interval: Duration,
+    /// Whether to return as soon as anything is found, rather than with what was found by
+    /// the timeout.
+    pub first_answer: bool,
+    /// Whether what the cache already holds counts; otherwise only what is heard after
+    /// the lookup starts does.
+    pub use_cache: bool,
+}
+
+impl QueryOptions {
+    /// What the cache holds, without querying.
+    pub fn cache_only() -> Self {
+        QueryOptions {
+            timeout: Duration::ZERO,
+            ..QueryOptions::default()
+        }
+    }
+
+    // Looks up with `lookup`, which is given the time from which answers count, if not
+    // all of them do, and sends each query with `query`. A timeout or interval too long to
+    // end is waited out without a deadline, as if it never ended.
+    pub(crate) fn run<T>(
+        &self,
+        mut query: impl FnMut(),
+        mut lookup: impl FnMut(Option<Instant>) -> Vec<T>,
+    ) -> Vec<T> {
+        let start = Instant::now();
+        if self.timeout.is_zero() {
+            return lookup(None);
+        }
+
+        let since = (!self.use_cache).then_some(start);
+        let deadline = start.checked_add(self.timeout);
+        let mut next_query = Some(start);
+        let mut sent = 0;
+
+        loop {
+            if self.first_answer {
+                let found = lookup(since);
+                if !found.is_empty() {
+                    return found;
+                }
+            }
+
+            let now = Instant::now();
+            if deadline.is_some_and(|deadline| now >= deadline) {
+                return lookup(since);
+            }
+
+            if next_query.is_some_and(|at| now >= at) {
+                query();
+                sent += 1;
+                next_query = (sent <= self.retransmissions)
+                    .then(|| now.checked_add(self.interval))
+                    .flatten()
+                    .filter(|at| deadline.is_none_or(|deadline| *at < deadline));
+            }
+
+            let poll = self
+                .first_answer
+                .then(|| now.checked_add(POLL_INTERVAL))
+                .flatten();
+            match [next_query, deadline, poll].iter().flatten().min() {
+                Some(wake) => thread::sleep(wake.saturating_duration_since(now)),
+                // Nothing left to do but wait for good.
+                None => thread::park(),
+            }
+        }
+    }
+}
+
+impl Default for QueryOptions {
+    fn default() -> Self {
+        QueryOptions {
+            timeout: Duration::from_secs(3),
+            retransmissions: 2,
+            interval: Duration::from_secs(1),
+            first_answer: true,
+            use_cache: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    // Runs `options`, counting the queries, with `lookup` told how many times it was
+    // called before.
+    fn run(options: QueryOptions, mut lookup: impl FnMut(u32) -> Vec<()>) -> (usize, u32) {
+        let queries = Cell::new(0);
+        let mut lookups = 0;
+        let found = options.run(
+            || queries.set(queries.get() + 1),
+            |_| {
+                lookups += 1;
+                lookup(lookups - 1)
+            },
+        );
+        (found.len(), queries.get())
+    }
+
+    #[test]
+    fn zero_timeout_only_looks() {
+        let options = QueryOptions {
+            use_cache: false,
+            ..QueryOptions::cache_only()
+        };
+        let since = Cell::new(Some(Instant::now()));
+        let found = options.run(
+            || panic!("queried"),
+            |at| {
+                since.set(at);
+                vec![()]
+            },
+        );
+
+        assert_eq!(found.len(), 1);
+        assert_eq!(since.get(), None);
+    }
+
+    #[test]
+    fn retries_stop_at_the_timeout() {
+        let options = QueryOptions {
+            timeout: Duration::from_millis(150),
+            retransmissions: 5,
+            interval: Duration::from_millis(100),
+            first_answer: false,
+            use_cache: true,
+        };
+        let start = Instant::now();
+
+        let (found, queries) = run(options, |_| Vec::new());
+        assert!(start.elapsed() >= options.timeout);
+        assert_eq!(found, 0);
+        // The one due at 200 ms is past the timeout.
+        assert!((1..=2).contains(&queries), "{} queries", queries);
+    }
+
+    #[test]
+    fn retries_stop_at_their_count() {
+        let options = QueryOptions {
+            timeout: Duration::from_millis(300),
+            retransmissions: 2,
+            interval: Duration::from_millis(10),
+            first_answer: false,
+            use_cache: true,
+        };
+
+        assert_eq!(run(options, |_| Vec::new()), (0, 3));
+    }
+
+    #[test]
+    fn first_answer_returns_early() {
+        let options = QueryOptions {
+            timeout: Duration::from_secs(10),
+            ..QueryOptions::default()
+        };
+        let start = Instant::now();
+
+        let (found, queries) = run(options, |before| match before {
+            0 => Vec::new(),
+            _ => vec![()],
+        });
+        assert_eq!((found, queries), (1, 1));
+        assert!(start.elapsed() < options.timeout);
+    }
+
+    #[test]
+    fn durations_too_long_to_end() {
+        let options = QueryOptions {
+            timeout: Duration::MAX,
+            retransmissions: u32::MAX,
+            interval: Duration::MAX,
+            first_answer: true,
+            use_cache: false,
+        };
+
+        // One query, as the next would never be due, and waiting on until an answer.
+        let (found, queries) = run(options, |before| match before {
+            0..=3 => Vec::new(),
+            _ => vec![()],
+        });
+        assert_eq!((found, queries), (1, 1));
+    }
+}
@@ -0,0 +1,65 @@
+use crate::packet::ResourceRecord;
+use std::sync::Arc;
+
+/// A resource record as it was last received for a service, see `ServiceRecord::records`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OwnedRecord {
+    /// In the case it arrived in, without the final dot.
+    pub name: String,
+    /// E.g. 33 for SRV.
+    pub record_type: u16,
+    /// Without the cache-flush bit, so 1 for IN.
+    pub class: u16,
+    pub cache_flush: bool,
+    /// In seconds, as sent.
+    pub ttl: u32,
+    /// As sent, except that the names in PTR and SRV records are written out in full
+    /// rather than compressed.
+    pub data: Vec<u8>,
+}
+
+impl OwnedRecord {
+    pub(crate) fn new(record: &ResourceRecord) -> Self {
+        OwnedRecord {
+            name: record.name.to_string(),
+            record_type: record.kind,
+            class: record.class,
+            cache_flush: record.multicast_unique,
+            ttl: record.ttl,
+            data: record.expanded_rdata(),
+        }
+    }
+}
+
+// The strings of a TXT record's data, as UTF-8 where they aren't, up to one whose length
+// runs past the end.
+pub(crate) fn txt_entries(data: &[u8]) -> Vec<String> {
+    let mut entries = Vec::new();
+    let mut data = data;
+
+    while let Some((&len, rest)) = data.split_first() {
+        let Some(entry) = rest.get(..len as usize) else {
+            break;
+        };
+        entries.push(String::from_utf8_lossy(entry).into_owned());
+        data = &rest[len as usize..];
+    }
+    entries
+}
+
+// Puts `record` in place of the one of its type and name in `records`, if any; so there
+// is only ever the latest of each. Copied on write, as the records are shared with every
+// published copy of the service.
+pub(crate) fn keep(records: &mut Arc<[OwnedRecord]>, record: &OwnedRecord) {
+    let same = |kept: &OwnedRecord| {
+        kept.record_type == record.record_type && kept.name.eq_ignore_ascii_case(&record.name)
+    };
+    if records.iter().any(|kept| kept == record) {
+        return;
+    }
+
+    let mut kept: Vec<OwnedRecord> = records.iter().filter(|kept| !same(kept)).cloned().collect();
+    kept.push(record.clone());
+    *records = kept.into();
+}
@@ -0,0 +1,338 @@
+use crate::{
+    changes::{Changes, Published},
+    database::{normalize_host, Database},
+    packet::TYPE_TXT,
+    record, route, track, Command, QueryOptions, Service, ServiceRecord,
+};
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{mpsc::SyncSender, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Resolves `.local` host names from the client's cache instead of the OS resolver.
+///
+/// Obtained through `MdnsClient::resolver`. Keeps working (from a frozen cache) after the
+/// client is dropped.
+#[derive(Clone)]
+pub struct CacheResolver {
+    services: Arc<Published>,
+    command_tx: SyncSender<Command>,
+    miss_timeout: Option<Duration>,
+    route_aware: bool,
+    // For `MdnsClient::track` to be called on the hosts looked up.
+    database: Arc<Mutex<Database>>,
+    changes: Arc<Changes>,
+}
+
+impl CacheResolver {
+    pub(crate) fn new(
+        database: Arc<Mutex<Database>>,
+        changes: Arc<Changes>,
+        command_tx: SyncSender<Command>,
+        route_aware: bool,
+    ) -> Self {
+        CacheResolver {
+            services: changes.published(),
+            command_tx,
+            miss_timeout: None,
+            route_aware,
+            database,
+            changes,
+        }
+    }
+
+    /// On a cache miss, send a query right away and wait up to `timeout` for the host to
+    /// appear instead of failing immediately.
+    pub fn query_on_miss(mut self, timeout: Duration) -> Self {
+        self.miss_timeout = Some(timeout);
+        self
+    }
+
+    /// Addresses of every discovered service whose SRV target is `host`, combined with that
+    /// service's port. Services whose instance another responder claimed first
+    /// (`ServiceRecord::conflicting`) come after the rest; within each, the preferred
+    /// address first, IPv6 addresses last, global ones before link-local ones, which carry
+    /// the scope of the interface they were heard on. With
+    /// `MdnsClientBuilder::route_aware_addresses`, IPv4 addresses are ordered by
+    /// `rank_addresses` instead.
+    pub fn resolve(&self, host: &str) -> Vec<SocketAddr> {
+        self.resolve_with_options(host, &self.miss_options())
+    }
+
+    // A lookup as `query_on_miss` says: one query, with what the cache holds counting.
+    fn miss_options(&self) -> QueryOptions {
+        match self.miss_timeout {
+            Some(timeout) => QueryOptions {
+                timeout,
+                retransmissions: 0,
+                ..QueryOptions::default()
+            },
+            None => QueryOptions::cache_only(),
+        }
+    }
+
+    /// `resolve`, querying and waiting as `options` say rather than as `query_on_miss`
+    /// does. With `QueryOptions::use_cache` off, only services heard from since the call
+    /// count.
+    pub fn resolve_with_options(&self, host: &str, options: &QueryOptions) -> Vec<SocketAddr> {
+        track(
+            &self.database,
+            &self.changes,
+            &self.command_tx,
+            |database| database.track_host(host),
+        );
+
+        options.run(
+            // A full channel means a query is already pending, which is just as good.
+            || {
+                self.command_tx.try_send(Command::Query).ok();
+            },
+            |since| self.lookup(host, since),
+        )
+    }
+
+    /// The services discovered, sorted by service, as `MdnsClient::get_services` lists
+    /// them; on finding none, querying and waiting as `query_on_miss` says.
+    pub fn discover(&self) -> Vec<(Service, ServiceRecord)> {
+        self.discover_with_options(&self.miss_options())
+    }
+
+    /// `discover`, querying and waiting as `options` say.
+    pub fn discover_with_options(&self, options: &QueryOptions) -> Vec<(Service, ServiceRecord)> {
+        options.run(
+            || {
+                self.command_tx.try_send(Command::Query).ok();
+            },
+            |since| {
+                let mut services: Vec<_> = self
+                    .services
+                    .load(Instant::now())
+                    .iter()
+                    .filter(|(_, record)| heard_since(record, since))
+                    .map(|(service, record)| (service.clone(), record.clone()))
+                    .collect();
+                services.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+                services
+            },
+        )
+    }
+
+    /// The addresses of `host`, in the order `resolve` gives them, each once.
+    pub fn resolve_host(&self, host: &str) -> Vec<IpAddr> {
+        self.resolve_host_with_options(host, &self.miss_options())
+    }
+
+    /// `resolve_host`, querying and waiting as `options` say.
+    pub fn resolve_host_with_options(&self, host: &str, options: &QueryOptions) -> Vec<IpAddr> {
+        let mut addresses: Vec<IpAddr> = Vec::new();
+
+        for address in self.resolve_with_options(host, options) {
+            if !addresses.contains(&address.ip()) {
+                addresses.push(address.ip());
+            }
+        }
+        addresses
+    }
+
+    /// The entries of the TXT record of `instance`, e.g. `My Printer._ipp._tcp.local`, as
+    /// last received, such as `path=/`. Only `MdnsClientBuilder::keep_records` keeps TXT
+    /// records; without it there are none.
+    pub fn resolve_txt(&self, instance: &str) -> Vec<String> {
+        self.resolve_txt_with_options(instance, &self.miss_options())
+    }
+
+    /// `resolve_txt`, querying and waiting as `options` say.
+    pub fn resolve_txt_with_options(&self, instance: &str, options: &QueryOptions) -> Vec<String> {
+        let instance = instance.strip_suffix('.').unwrap_or(instance);
+
+        options.run(
+            || {
+                self.command_tx.try_send(Command::Query).ok();
+            },
+            |since| {
+                let services = self.services.load(Instant::now());
+                let txt = services
+                    .values()
+                    .filter(|record| heard_since(record, since))
+                    .flat_map(|record| record.records())
+                    .find(|record| {
+                        record.record_type == TYPE_TXT && record.name.eq_ignore_ascii_case(instance)
+                    });
+
+                txt.map(|txt| record::txt_entries(&txt.data))
+                    .unwrap_or_default()
+            },
+        )
+    }
+
+    fn lookup(&self, host: &str, since: Option<Instant>) -> Vec<SocketAddr> {
+        let host = normalize_host(host);
+        let services = self.services.load(Instant::now());
+
+        let mut matching: Vec<_> = services
+            .iter()
+            .filter(|(s, record)| *s.host == *host && heard_since(record, since))
+            .collect();
+        matching.sort_by_key(|(_, record)| record.conflicting);
+
+        let subnets = match self.route_aware && !matching.is_empty() {
+            true => Some(route::local_subnets()),
+            false => None,
+        };
+        let mut addrs = Vec::new();
+
+        for (service, record) in matching {
+            addrs.extend(service_addresses(service, record, subnets.as_deref()));
+        }
+
+        addrs
+    }
+}
+
+// Whether `record` counts for a lookup only counting what was heard from `since` on.
+fn heard_since(record: &ServiceRecord, since: Option<Instant>) -> bool {
+    since.is_none_or(|since| record.last_seen_time >= since)
+}
+
+// A service's addresses in the order `CacheResolver::resolve` gives them: by `rank` if
+// there are `subnets` to rank IPv4 addresses by, else the preferred address first, then
+// IPv6 addresses, link-local ones last.
+pub(crate) fn service_addresses(
+    service: &Service,
+    record: &ServiceRecord,
+    subnets: Option<&[(Ipv4Addr, Ipv4Addr)]>,
+) -> Vec<SocketAddr> {
+    let mut addrs = Vec::new();
+
+    if let Some(subnets) = subnets {
+        addrs.extend(
+            route::rank(record, subnets)
+                .into_iter()
+                .map(|addr| SocketAddr::from((addr, service.port))),
+        );
+    } else {
+        let preferred = record.preferred_address;
+
+        addrs.extend(preferred.map(|addr| SocketAddr::from((addr, service.port))));
+        addrs.extend(
+            record
+                .addresses
+                .iter()
+                .filter(|addr| Some(**addr) != preferred)
+                .map(|addr| SocketAddr::from((*addr, service.port))),
+        );
+    }
+
+    let mut ipv6: Vec<_> = record.ipv6_addresses.iter().collect();
+    ipv6.sort_by_key(|addr| (addr.is_link_local(), **addr));
+    addrs.extend(
+        ipv6.into_iter()
+            .map(|addr| SocketAddr::from(addr.socket_addr(service.port))),
+    );
+
+    addrs
+}
+
+#[cfg(feature = "reqwest")]
+impl reqwest::dns::Resolve for CacheResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let resolver = self.clone();
+
+        Box::pin(async move {
+            // Waiting out a cache miss sleeps, so keep it off the async workers.
+            let host = name.as_str().to_string();
+            let addrs = tokio::task::spawn_blocking(move || resolver.resolve(&host)).await?;
+
+            if addrs.is_empty() {
+                return Err(format!("{} not found in the mDNS cache", name.as_str()).into());
+            }
+
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+    use crate::{metrics::Metrics, record::OwnedRecord};
+    use std::sync::mpsc::{sync_channel, Receiver};
+
+    const INSTANCE: &str = "Printer._ipp._tcp.local";
+
+    // A resolver over a printer on two ports of one host, the first with its TXT record.
+    fn resolver() -> (CacheResolver, Receiver<Command>) {
+        let metrics = Arc::new(Metrics::new([]));
+        let changes = Arc::new(Changes::new(metrics));
+        let database = Arc::new(Mutex::new(Database::default()));
+        let now = Instant::now();
+
+        let mut locked = Database::lock(&database);
+        for port in [631, 80] {
+            let service = Service {
+                host: "printer.local".into(),
+                port,
+            };
+            let record = locked.get_or_insert_with(service, || ServiceRecord::heard_at(now));
+            record.addresses.insert(Ipv4Addr::new(192, 0, 2, 20));
+            if port == 631 {
+                record.records = Arc::new([OwnedRecord {
+                    name: INSTANCE.into(),
+                    record_type: TYPE_TXT,
+                    class: 1,
+                    cache_flush: true,
+                    ttl: 4500,
+                    data: b"\x07path=/a\x03x=y".to_vec(),
+                }]);
+            }
+        }
+        changes.update(locked, now);
+
+        let (command_tx, command_rx) = sync_channel(2);
+        let resolver = CacheResolver::new(database, changes, command_tx, false);
+        (resolver, command_rx)
+    }
+
+    #[test]
+    fn discovers_what_the_cache_has() {
+        let (resolver, command_rx) = resolver();
+
+        let services = resolver.discover();
+        let ports: Vec<u16> = services.iter().map(|(service, _)| service.port).collect();
+        assert_eq!(ports, [80, 631]);
+        assert!(command_rx.try_recv().is_err());
+
+        // Heard before the lookup started, so not counted; a query goes out instead.
+        let options = QueryOptions {
+            timeout: Duration::from_millis(50),
+            use_cache: false,
+            ..QueryOptions::default()
+        };
+        assert!(resolver.discover_with_options(&options).is_empty());
+        assert!(matches!(command_rx.try_recv(), Ok(Command::Query)));
+    }
+
+    #[test]
+    fn resolves_a_host_once_per_address() {
+        let (resolver, _command_rx) = resolver();
+
+        assert_eq!(resolver.resolve("Printer.local.").len(), 2);
+        assert_eq!(
+            resolver.resolve_host("Printer.local."),
+            [IpAddr::from([192, 0, 2, 20])]
+        );
+        assert!(resolver.resolve_host("scanner.local").is_empty());
+    }
+
+    #[test]
+    fn resolves_txt_entries() {
+        let (resolver, _command_rx) = resolver();
+
+        assert_eq!(
+            resolver.resolve_txt("printer._ipp._tcp.local."),
+            ["path=/a", "x=y"]
+        );
+        assert!(resolver.resolve_txt("Scanner._ipp._tcp.local").is_empty());
+    }
+}
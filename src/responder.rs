@@ -0,0 +1,417 @@
+use crate::{
+    daemon::Daemon,
+    dns_name_error, encode_dns_name,
+    packet::{Packet, TYPE_A, TYPE_ANY, TYPE_PTR, TYPE_SRV, TYPE_TXT},
+    MULTICAST_PORT,
+};
+use std::{
+    error::Error,
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+const CLASS_IN: u16 = 1;
+const CACHE_FLUSH: u16 = 0x8000;
+
+const SERVICES: &str = "_services._dns-sd._udp.local";
+
+// TTLs recommended by RFC 6762 section 10; legacy unicast answers are capped at 10s.
+pub(crate) const HOST_TTL: u32 = 120;
+pub(crate) const OTHER_TTL: u32 = 4500;
+const LEGACY_TTL: u32 = 10;
+
+// Two unsolicited announcements one second apart, as in RFC 6762 section 8.3.
+const ANNOUNCEMENTS: u8 = 2;
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A service answered for and announced on the client's sockets. Dropping it sends
+/// goodbye packets.
+pub struct Advertisement {
+    daemon: Arc<Daemon>,
+    id: u64,
+}
+
+impl Advertisement {
+    pub(crate) fn new(daemon: Arc<Daemon>, id: u64) -> Self {
+        Advertisement { daemon, id }
+    }
+}
+
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        self.daemon.withdraw(self.id);
+    }
+}
+
+pub(crate) struct Advert {
+    id: u64,
+    instance: String,
+    service_type: String,
+    host: String,
+    port: u16,
+    txt: Vec<u8>,
+    announcements_left: u8,
+    next_announcement: Instant,
+    withdrawn: bool,
+}
+
+impl Advert {
+    pub(crate) fn new(
+        instance: &str,
+        service_type: &str,
+        port: u16,
+        txt: &[(&str, &str)],
+    ) -> Result<Self, Box<dyn Error>> {
+        if instance.is_empty() || instance.len() > 63 {
+            return Err(format!("instance name {:?} must be 1 to 63 bytes", instance).into());
+        }
+
+        let (kind, domain) = crate::split_service_type(service_type)
+            .ok_or_else(|| format!("no service type such as `_http._tcp` in {:?}", service_type))?;
+        let service_type = format!("{}.{}", kind, domain);
+
+        // The host name is derived from the instance so no platform API is needed.
+        let label: String = instance
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .collect();
+
+        let instance = format!("{}.{}", instance, service_type);
+        if let Some(err) = dns_name_error(&instance) {
+            return Err(err.into());
+        }
+
+        Ok(Advert {
+            id: 0,
+            instance,
+            host: format!("{}.{}", label.trim_matches('-'), domain),
+            service_type,
+            port,
+            txt: encode_txt(txt)?,
+            announcements_left: ANNOUNCEMENTS,
+            next_announcement: Instant::now(),
+            withdrawn: false,
+        })
+    }
+
+    fn ptr(&self, section: &mut Section) {
+        section.record(
+            &self.service_type,
+            TYPE_PTR,
+            false,
+            OTHER_TTL,
+            &encode_dns_name(&self.instance),
+        );
+    }
+
+    fn enumeration(&self, section: &mut Section) {
+        section.record(
+            SERVICES,
+            TYPE_PTR,
+            false,
+            OTHER_TTL,
+            &encode_dns_name(&self.service_type),
+        );
+    }
+
+    fn srv(&self, section: &mut Section) {
+        let mut rdata = Vec::new();
+        rdata.extend_from_slice(&0u16.to_be_bytes()); // priority
+        rdata.extend_from_slice(&0u16.to_be_bytes()); // weight
+        rdata.extend_from_slice(&self.port.to_be_bytes());
+        rdata.extend(encode_dns_name(&self.host));
+
+        section.record(&self.instance, TYPE_SRV, true, HOST_TTL, &rdata);
+    }
+
+    fn txt(&self, section: &mut Section) {
+        section.record(&self.instance, TYPE_TXT, true, OTHER_TTL, &self.txt);
+    }
+
+    fn addresses(&self, section: &mut Section, addresses: &[Ipv4Addr]) {
+        for address in addresses {
+            section.record(&self.host, TYPE_A, true, HOST_TTL, &address.octets());
+        }
+    }
+}
+
+// "key=value" strings, or an empty one if there are none, as RFC 6763 section 6.1 asks.
+pub(crate) fn encode_txt(txt: &[(&str, &str)]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut encoded = Vec::new();
+    for (key, value) in txt {
+        let entry = format!("{}={}", key, value);
+        if entry.len() > 255 {
+            return Err(format!("TXT entry for {:?} is longer than 255 bytes", key).into());
+        }
+        encoded.push(entry.len() as u8);
+        encoded.extend_from_slice(entry.as_bytes());
+    }
+    if encoded.is_empty() {
+        encoded.push(0);
+    }
+    Ok(encoded)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Mode {
+    Multicast,
+    Legacy,
+    Goodbye,
+}
+
+pub(crate) struct Section {
+    mode: Mode,
+    bytes: Vec<u8>,
+    count: u16,
+}
+
+impl Section {
+    pub(crate) fn new(mode: Mode) -> Self {
+        Section {
+            mode,
+            bytes: Vec::new(),
+            count: 0,
+        }
+    }
+
+    pub(crate) fn record(&mut self, name: &str, kind: u16, unique: bool, ttl: u32, rdata: &[u8]) {
+        // Legacy resolvers don't know the cache-flush bit.
+        let class = match self.mode {
+            Mode::Multicast | Mode::Goodbye if unique => CLASS_IN | CACHE_FLUSH,
+            _ => CLASS_IN,
+        };
+
+        let ttl = match self.mode {
+            Mode::Multicast => ttl,
+            Mode::Legacy => ttl.min(LEGACY_TTL),
+            Mode::Goodbye => 0,
+        };
+
+        self.bytes.extend(encode_dns_name(name));
+        self.bytes.extend_from_slice(&kind.to_be_bytes());
+        self.bytes.extend_from_slice(&class.to_be_bytes());
+        self.bytes.extend_from_slice(&ttl.to_be_bytes());
+        self.bytes
+            .extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        self.bytes.extend_from_slice(rdata);
+        self.count += 1;
+    }
+}
+
+pub(crate) fn response(
+    id: u16,
+    questions: &Section,
+    answers: &Section,
+    additionals: &Section,
+) -> Vec<u8> {
+    let mut packet = Vec::new();
+
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&0x8400u16.to_be_bytes()); // response, authoritative
+    packet.extend_from_slice(&questions.count.to_be_bytes());
+    packet.extend_from_slice(&answers.count.to_be_bytes());
+    packet.extend_from_slice(&0u16.to_be_bytes());
+    packet.extend_from_slice(&additionals.count.to_be_bytes());
+    packet.extend_from_slice(&questions.bytes);
+    packet.extend_from_slice(&answers.bytes);
+    packet.extend_from_slice(&additionals.bytes);
+    packet
+}
+
+#[derive(Default)]
+pub(crate) struct Responder {
+    next_id: u64,
+    adverts: Vec<Advert>,
+    addresses: Vec<Ipv4Addr>,
+    // PTR answers and announcements carry the PTR record alone, as `FakeResponder` may.
+    bare_pointers: bool,
+}
+
+impl Responder {
+    pub(crate) fn add(&mut self, mut advert: Advert) -> u64 {
+        self.next_id += 1;
+        advert.id = self.next_id;
+        self.adverts.push(advert);
+        self.next_id
+    }
+
+    pub(crate) fn withdraw(&mut self, id: u64) {
+        if let Some(advert) = self.adverts.iter_mut().find(|a| a.id == id) {
+            advert.withdrawn = true;
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.adverts.is_empty()
+    }
+
+    // Every advertisement is announced again after an address change.
+    pub(crate) fn set_addresses(&mut self, mut addresses: Vec<Ipv4Addr>) {
+        addresses.sort();
+
+        if addresses != self.addresses {
+            debug!(?addresses, "advertised addresses changed");
+
+            self.addresses = addresses;
+
+            for advert in &mut self.adverts {
+                advert.announcements_left = ANNOUNCEMENTS;
+                advert.next_announcement = Instant::now();
+            }
+        }
+    }
+
+    // Announcements that are due and goodbyes for withdrawn services, which are then
+    // forgotten.
+    #[cfg(feature = "test-util")]
+    pub(crate) fn set_bare_pointers(&mut self, bare: bool) {
+        self.bare_pointers = bare;
+    }
+
+    pub(crate) fn due(&mut self, now: Instant) -> Vec<Vec<u8>> {
+        let mut packets = Vec::new();
+
+        for advert in &mut self.adverts {
+            if advert.withdrawn {
+                packets.push(announcement(advert, &self.addresses, Mode::Goodbye, false));
+            } else if advert.announcements_left > 0 && advert.next_announcement <= now {
+                let bare = self.bare_pointers;
+                packets.push(announcement(advert, &self.addresses, Mode::Multicast, bare));
+                advert.announcements_left -= 1;
+                advert.next_announcement = now + ANNOUNCE_INTERVAL;
+            }
+        }
+
+        self.adverts.retain(|advert| !advert.withdrawn);
+        packets
+    }
+
+    // When `due` next has anything to send: at once for a withdrawn service.
+    pub(crate) fn next_due(&self) -> Option<Instant> {
+        self.adverts
+            .iter()
+            .filter_map(|advert| {
+                if advert.withdrawn {
+                    Some(Instant::now())
+                } else if advert.announcements_left > 0 {
+                    Some(advert.next_announcement)
+                } else {
+                    None
+                }
+            })
+            .min()
+    }
+
+    pub(crate) fn goodbyes(&mut self) -> Vec<Vec<u8>> {
+        let addresses = &self.addresses;
+
+        self.adverts
+            .drain(..)
+            .map(|advert| announcement(&advert, addresses, Mode::Goodbye, false))
+            .collect()
+    }
+
+    // The response to a query, if it asks about anything advertised, and where to send it:
+    // back to `from` or to `group`, the group of the socket it arrived on.
+    pub(crate) fn answer(
+        &self,
+        query: &Packet,
+        from: SocketAddr,
+        group: SocketAddr,
+    ) -> Option<(Vec<u8>, SocketAddr)> {
+        // Queries not from port 5353 are one-shot legacy unicast queries (RFC 6762
+        // section 6.7), answered directly with the question repeated.
+        let legacy = from.port() != MULTICAST_PORT;
+        let mode = if legacy {
+            Mode::Legacy
+        } else {
+            Mode::Multicast
+        };
+
+        let mut questions = Section::new(mode);
+        let mut answers = Section::new(mode);
+        let mut additionals = Section::new(mode);
+        let mut unicast = legacy;
+
+        for question in &query.questions {
+            let name = question.qname.to_string();
+            let is = |kind: u16| question.qtype == kind || question.qtype == TYPE_ANY;
+            let before = answers.count;
+
+            let mut enumerated: Vec<&str> = Vec::new();
+
+            for advert in self.adverts.iter().filter(|a| !a.withdrawn) {
+                if name.eq_ignore_ascii_case(SERVICES)
+                    && is(TYPE_PTR)
+                    && !enumerated.contains(&advert.service_type.as_str())
+                {
+                    enumerated.push(&advert.service_type);
+                    advert.enumeration(&mut answers);
+                }
+
+                if name.eq_ignore_ascii_case(&advert.service_type) && is(TYPE_PTR) {
+                    advert.ptr(&mut answers);
+                    if !self.bare_pointers {
+                        advert.srv(&mut additionals);
+                        advert.txt(&mut additionals);
+                        advert.addresses(&mut additionals, &self.addresses);
+                    }
+                }
+
+                if name.eq_ignore_ascii_case(&advert.instance) {
+                    if is(TYPE_SRV) {
+                        advert.srv(&mut answers);
+                        advert.addresses(&mut additionals, &self.addresses);
+                    }
+                    if is(TYPE_TXT) {
+                        advert.txt(&mut answers);
+                    }
+                }
+
+                if name.eq_ignore_ascii_case(&advert.host) && is(TYPE_A) {
+                    advert.addresses(&mut answers, &self.addresses);
+                }
+            }
+
+            if answers.count > before {
+                unicast |= question.prefer_unicast;
+
+                if legacy {
+                    questions.bytes.extend(encode_dns_name(&name));
+                    questions
+                        .bytes
+                        .extend_from_slice(&question.qtype.to_be_bytes());
+                    questions.bytes.extend_from_slice(&CLASS_IN.to_be_bytes());
+                    questions.count += 1;
+                }
+            }
+        }
+
+        if answers.count == 0 {
+            return None;
+        }
+
+        let id = if legacy { query.header.id } else { 0 };
+        let to = if unicast { from } else { group };
+
+        Some((response(id, &questions, &answers, &additionals), to))
+    }
+}
+
+fn announcement(advert: &Advert, addresses: &[Ipv4Addr], mode: Mode, bare: bool) -> Vec<u8> {
+    let mut answers = Section::new(mode);
+
+    advert.ptr(&mut answers);
+    if !bare {
+        advert.srv(&mut answers);
+        advert.txt(&mut answers);
+        advert.addresses(&mut answers, addresses);
+    }
+
+    if mode != Mode::Goodbye {
+        advert.enumeration(&mut answers);
+    }
+
+    response(0, &Section::new(mode), &answers, &Section::new(mode))
+}
@@ -0,0 +1,148 @@
+use crate::{
+    dns_name_error, encode_dns_name,
+    packet::{TYPE_A, TYPE_AAAA, TYPE_PTR, TYPE_SRV, TYPE_TXT},
+    responder::{encode_txt, response, Mode, Section, HOST_TTL, OTHER_TTL},
+};
+use std::{
+    error::Error,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+/// Assembles an mDNS response datagram, for tests of code that handles what the client
+/// discovers, e.g. through `MdnsState::handle_packet` or `MdnsClient::process_packet`.
+/// Records go to the answer section until `additionals()` is called. Unless `ttl` or
+/// `cache_flush` say otherwise, they have the TTLs RFC 6762 section 10 recommends and all
+/// but PTR records the cache-flush bit, as the crate's own responder sends them. Names are
+/// written out in full, without compression. Only behind the `test-util` feature.
+pub struct ResponseBuilder {
+    id: u16,
+    answers: Section,
+    additionals: Section,
+    in_additionals: bool,
+    ttl: Option<u32>,
+    cache_flush: Option<bool>,
+    // The first invalid name or TXT entry, returned by `build`.
+    error: Option<String>,
+}
+
+impl Default for ResponseBuilder {
+    fn default() -> Self {
+        ResponseBuilder::new()
+    }
+}
+
+impl ResponseBuilder {
+    pub fn new() -> ResponseBuilder {
+        ResponseBuilder {
+            id: 0,
+            answers: Section::new(Mode::Multicast),
+            additionals: Section::new(Mode::Multicast),
+            in_additionals: false,
+            ttl: None,
+            cache_flush: None,
+            error: None,
+        }
+    }
+
+    /// The message id, zero at first as multicast responses have it.
+    pub fn id(mut self, id: u16) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// Records added after this go to the additional section.
+    pub fn additionals(mut self) -> Self {
+        self.in_additionals = true;
+        self
+    }
+
+    /// Records added after this have this TTL in seconds; zero makes them goodbyes.
+    pub fn ttl(mut self, ttl: u32) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Whether records added after this have the cache-flush bit set.
+    pub fn cache_flush(mut self, cache_flush: bool) -> Self {
+        self.cache_flush = Some(cache_flush);
+        self
+    }
+
+    /// Points `service_type`, e.g. `_http._tcp.local`, at `instance`, its full name.
+    pub fn ptr(mut self, service_type: &str, instance: &str) -> Self {
+        let rdata = self.name(instance);
+        self.record(service_type, TYPE_PTR, false, OTHER_TTL, rdata)
+    }
+
+    pub fn srv(mut self, instance: &str, host: &str, port: u16) -> Self {
+        let mut rdata = Vec::new();
+        rdata.extend_from_slice(&0u16.to_be_bytes()); // priority
+        rdata.extend_from_slice(&0u16.to_be_bytes()); // weight
+        rdata.extend_from_slice(&port.to_be_bytes());
+        rdata.extend(self.name(host));
+
+        self.record(instance, TYPE_SRV, true, HOST_TTL, rdata)
+    }
+
+    /// Entries are written as `key=value`; none makes the single empty string RFC 6763
+    /// asks for.
+    pub fn txt(mut self, instance: &str, txt: &[(&str, &str)]) -> Self {
+        let rdata = match encode_txt(txt) {
+            Ok(rdata) => rdata,
+            Err(err) => {
+                self.error.get_or_insert(err.to_string());
+                return self;
+            }
+        };
+
+        self.record(instance, TYPE_TXT, true, OTHER_TTL, rdata)
+    }
+
+    pub fn a(self, host: &str, address: Ipv4Addr) -> Self {
+        self.record(host, TYPE_A, true, HOST_TTL, address.octets().to_vec())
+    }
+
+    pub fn aaaa(self, host: &str, address: Ipv6Addr) -> Self {
+        self.record(host, TYPE_AAAA, true, HOST_TTL, address.octets().to_vec())
+    }
+
+    /// Fails if a name had an empty label or one longer than 63 bytes, or was longer than
+    /// 255 bytes encoded, or a TXT entry was longer than 255.
+    pub fn build(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        if let Some(err) = &self.error {
+            return Err(err.clone().into());
+        }
+
+        Ok(response(
+            self.id,
+            &Section::new(Mode::Multicast),
+            &self.answers,
+            &self.additionals,
+        ))
+    }
+
+    fn record(mut self, name: &str, kind: u16, unique: bool, ttl: u32, rdata: Vec<u8>) -> Self {
+        let name = name.strip_suffix('.').unwrap_or(name);
+        self.name(name);
+
+        let unique = self.cache_flush.unwrap_or(unique);
+        let ttl = self.ttl.unwrap_or(ttl);
+        let section = if self.in_additionals {
+            &mut self.additionals
+        } else {
+            &mut self.answers
+        };
+
+        section.record(name, kind, unique, ttl, &rdata);
+        self
+    }
+
+    // Encoded, with the first invalid name kept for `build`.
+    fn name(&mut self, name: &str) -> Vec<u8> {
+        let name = name.strip_suffix('.').unwrap_or(name);
+        if let Some(err) = dns_name_error(name) {
+            self.error.get_or_insert(err);
+        }
+        encode_dns_name(name)
+    }
+}
@@ -0,0 +1,125 @@
+use crate::ServiceRecord;
+use if_addrs::IfAddr;
+use std::net::Ipv4Addr;
+
+/// `record`'s IPv4 addresses, those on the same subnet as one of this host's interfaces
+/// first, as a device advertising addresses on several subnets may only be reachable on
+/// some. Otherwise in the usual order: the preferred address first, then the rest, lowest
+/// first. Reads the interface table each time; without one, just the usual order.
+pub fn rank_addresses(record: &ServiceRecord) -> Vec<Ipv4Addr> {
+    rank(record, &local_subnets())
+}
+
+// This host's IPv4 addresses and their netmasks.
+pub(crate) fn local_subnets() -> Vec<(Ipv4Addr, Ipv4Addr)> {
+    if_addrs::get_if_addrs()
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|i| match i.addr {
+            IfAddr::V4(addr) => Some((addr.ip, addr.netmask)),
+            IfAddr::V6(_) => None,
+        })
+        .collect()
+}
+
+pub(crate) fn rank(record: &ServiceRecord, subnets: &[(Ipv4Addr, Ipv4Addr)]) -> Vec<Ipv4Addr> {
+    let preferred = record.preferred_address;
+
+    let mut others: Vec<Ipv4Addr> = record
+        .addresses
+        .iter()
+        .copied()
+        .filter(|address| Some(*address) != preferred)
+        .collect();
+    others.sort_unstable();
+
+    let mut ranked: Vec<Ipv4Addr> = preferred.into_iter().chain(others).collect();
+    // Stable, so each half keeps the usual order.
+    ranked.sort_by_key(|address| !on_link(*address, subnets));
+    ranked
+}
+
+fn on_link(address: Ipv4Addr, subnets: &[(Ipv4Addr, Ipv4Addr)]) -> bool {
+    subnets.iter().any(|(ip, netmask)| {
+        let mask = u32::from(*netmask);
+        mask != 0 && u32::from(address) & mask == u32::from(*ip) & mask
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn record(preferred: Option<[u8; 4]>, addresses: &[[u8; 4]]) -> ServiceRecord {
+        let mut record = ServiceRecord::heard_at(Instant::now());
+        record.preferred_address = preferred.map(Ipv4Addr::from);
+        record.addresses = addresses.iter().copied().map(Ipv4Addr::from).collect();
+        record
+    }
+
+    fn subnet(ip: [u8; 4], netmask: [u8; 4]) -> (Ipv4Addr, Ipv4Addr) {
+        (ip.into(), netmask.into())
+    }
+
+    fn ranked(record: &ServiceRecord, subnets: &[(Ipv4Addr, Ipv4Addr)]) -> Vec<[u8; 4]> {
+        rank(record, subnets)
+            .into_iter()
+            .map(|address| address.octets())
+            .collect()
+    }
+
+    #[test]
+    fn on_link_addresses_first() {
+        let record = record(
+            Some([10, 0, 0, 5]),
+            &[
+                [10, 0, 0, 5],
+                [192, 168, 1, 20],
+                [172, 16, 0, 9],
+                [192, 168, 1, 3],
+            ],
+        );
+        let lan = subnet([192, 168, 1, 100], [255, 255, 255, 0]);
+
+        // The usual order within each half, the preferred address first among the rest.
+        assert_eq!(
+            ranked(&record, &[lan]),
+            [
+                [192, 168, 1, 3],
+                [192, 168, 1, 20],
+                [10, 0, 0, 5],
+                [172, 16, 0, 9]
+            ]
+        );
+        // Two interfaces, one of them on a wider subnet.
+        let vpn = subnet([172, 16, 3, 1], [255, 255, 0, 0]);
+        assert_eq!(
+            ranked(&record, &[vpn, lan]),
+            [
+                [172, 16, 0, 9],
+                [192, 168, 1, 3],
+                [192, 168, 1, 20],
+                [10, 0, 0, 5]
+            ]
+        );
+    }
+
+    #[test]
+    fn usual_order_without_a_match() {
+        let record = record(Some([10, 0, 0, 5]), &[[192, 168, 1, 20], [10, 0, 0, 5]]);
+        let usual = [[10, 0, 0, 5], [192, 168, 1, 20]];
+
+        assert_eq!(ranked(&record, &[]), usual);
+        assert_eq!(
+            ranked(&record, &[subnet([203, 0, 113, 1], [255, 255, 255, 0])]),
+            usual
+        );
+        // A zero netmask, as some tunnels report, matches nothing rather than everything.
+        let any = subnet([192, 168, 1, 1], [0, 0, 0, 0]);
+        assert_eq!(ranked(&record, &[any]), usual);
+        // A host route only matches its own address.
+        let host = subnet([192, 168, 1, 20], [255, 255, 255, 255]);
+        assert_eq!(ranked(&record, &[host]), [[192, 168, 1, 20], [10, 0, 0, 5]]);
+    }
+}
@@ -0,0 +1,128 @@
+use crate::lock::Lock;
+use std::{
+    sync::{Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+// When the first scan is taken to be done, unless the builder says otherwise: after this
+// many query rounds, or this long after the client was built, whichever comes first.
+pub(crate) const SCAN_ROUNDS: u32 = 2;
+pub(crate) const SCAN_WARM_UP: Duration = Duration::from_secs(3);
+
+// A query round counts as answered once the next goes out at least this much later;
+// responders may delay answers by up to 500 ms (RFC 6762, section 6).
+const ROUND_ANSWERED: Duration = Duration::from_millis(500);
+
+// Tells "nothing on the network" apart from "not asked long enough yet", for
+// `MdnsClient::wait_for_initial_scan`. Only the socket backend counts rounds; the others
+// have the warm-up alone.
+pub(crate) struct Scan {
+    state: Mutex<State>,
+    done: Condvar,
+}
+
+struct State {
+    started: Instant,
+    rounds: u32,
+    last_query: Option<Instant>,
+    target: u32,
+    warm_up: Duration,
+}
+
+impl State {
+    fn is_done(&self, now: Instant) -> bool {
+        self.rounds >= self.target || self.warmed_up().is_some_and(|at| now >= at)
+    }
+
+    // `None` if the warm-up is too long to end.
+    fn warmed_up(&self) -> Option<Instant> {
+        self.started.checked_add(self.warm_up)
+    }
+}
+
+impl Default for Scan {
+    fn default() -> Self {
+        Scan {
+            state: Mutex::new(State {
+                started: Instant::now(),
+                rounds: 0,
+                last_query: None,
+                target: SCAN_ROUNDS,
+                warm_up: SCAN_WARM_UP,
+            }),
+            done: Condvar::new(),
+        }
+    }
+}
+
+impl Scan {
+    // Set once built; rounds already counted still count.
+    pub(crate) fn configure(&self, rounds: u32, warm_up: Duration) {
+        let mut state = self.state.locked();
+        state.target = rounds;
+        state.warm_up = warm_up;
+        self.done.notify_all();
+    }
+
+    // For `MdnsClient::reset`: the scan starts over, as if the client had just been built.
+    pub(crate) fn restart(&self) {
+        let mut state = self.state.locked();
+        state.started = Instant::now();
+        state.rounds = 0;
+        state.last_query = None;
+    }
+
+    pub(crate) fn queried(&self, now: Instant) {
+        let mut state = self.state.locked();
+
+        if state
+            .last_query
+            .is_some_and(|at| now >= at + ROUND_ANSWERED)
+        {
+            state.rounds += 1;
+            if state.rounds == state.target {
+                self.done.notify_all();
+            }
+        }
+
+        state.last_query = Some(now);
+    }
+
+    // When the last query went out, which answers are timed from.
+    pub(crate) fn last_query(&self) -> Option<Instant> {
+        self.state.locked().last_query
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        self.state.locked().is_done(Instant::now())
+    }
+
+    // Without end for a `timeout` too long to have one.
+    pub(crate) fn wait(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now().checked_add(timeout);
+        let mut state = self.state.locked();
+
+        loop {
+            let now = Instant::now();
+            if state.is_done(now) {
+                return true;
+            }
+            if deadline.is_some_and(|deadline| now >= deadline) {
+                return false;
+            }
+
+            state = match deadline.into_iter().chain(state.warmed_up()).min() {
+                Some(until) => {
+                    self.done
+                        .wait_timeout(state, until - now)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .0
+                }
+                None => self
+                    .done
+                    .wait(state)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()),
+            };
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use std::{error::Error, time::Duration};
+
+// RFC 6762 section 5.2: a second between the first two queries, at least doubling after,
+// and intervals may be capped at an hour.
+const RFC_FIRST_INTERVAL: Duration = Duration::from_secs(1);
+const RFC_MAX_INTERVAL: Duration = Duration::from_secs(3600);
+
+// The longest interval a schedule may have, well beyond any a responder would wait for,
+// and short enough that the next query's time is always an `Instant`.
+const MAX_INTERVAL: Duration = Duration::from_secs(24 * 3600);
+
+/// When a client's queries go out, see `MdnsClientBuilder::query_schedule`. By default a
+/// query every second.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QuerySchedule {
+    /// Between the first query and the second.
+    pub interval: Duration,
+    /// Each interval after is the last times this; 1 keeps it as it is.
+    pub backoff: u32,
+    /// What intervals grow to at most, a day at the most.
+    pub max_interval: Duration,
+    /// Whether its PTR questions ask for unicast answers (QU) rather than multicast ones
+    /// (QM), as RFC 6762 section 5.4 describes.
+    pub unicast_response: bool,
+}
+
+impl QuerySchedule {
+    /// A query every `interval`.
+    pub fn fixed(interval: Duration) -> Self {
+        QuerySchedule {
+            interval,
+            backoff: 1,
+            max_interval: interval,
+            unicast_response: false,
+        }
+    }
+
+    /// A second between the first two queries, doubling up to an hour, as RFC 6762 section
+    /// 5.2 describes.
+    pub fn rfc_backoff() -> Self {
+        QuerySchedule {
+            interval: RFC_FIRST_INTERVAL,
+            backoff: 2,
+            max_interval: RFC_MAX_INTERVAL,
+            unicast_response: false,
+        }
+    }
+
+    pub(crate) fn check(&self) -> Result<(), Box<dyn Error>> {
+        if self.interval.is_zero()
+            || self.backoff == 0
+            || self.max_interval < self.interval
+            || self.max_interval > MAX_INTERVAL
+        {
+            return Err(format!("invalid query schedule {:?}", self).into());
+        }
+        Ok(())
+    }
+
+    // The interval after `interval`.
+    pub(crate) fn next(&self, interval: Duration) -> Duration {
+        interval.saturating_mul(self.backoff).min(self.max_interval)
+    }
+}
+
+impl Default for QuerySchedule {
+    fn default() -> Self {
+        QuerySchedule::fixed(crate::daemon::REFRESH_INTERVAL)
+    }
+}
@@ -0,0 +1,531 @@
+// The services of `MdnsClientBuilder::seed_file`, in a subset of TOML: a `[[service]]`
+// table per service, each line `key = value` with strings in double quotes, the port a
+// number and the addresses and TXT entries arrays of strings on one line.
+//
+//   [[service]]
+//   instance = "Printer"
+//   host = "printer.local"
+//   port = 631
+//   addresses = ["192.168.1.20", "fe80::1%3"]
+//   txt = ["rp=ipp/print"]
+//
+// A service with a line that can't be taken is left out and the line reported, the rest
+// are kept.
+use crate::{
+    availability::History,
+    changes::Changes,
+    database::{normalize_host, Database},
+    lock::Lock,
+    metrics::{FailedOperation, Metrics},
+    packet::TYPE_TXT,
+    record::{self, OwnedRecord},
+    responder::encode_txt,
+    Provenance, ScopedIpv6Addr, Service, ServiceRecord,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt, fs,
+    net::{IpAddr, Ipv4Addr},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime},
+};
+
+// How often a watched file's modification time is looked at.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// That of seeded TXT records, as responders give them (RFC 6762 section 10).
+const TXT_TTL: u32 = 4500;
+
+/// A line of `MdnsClientBuilder::seed_file` that couldn't be taken, see
+/// `MdnsClient::seed_errors`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SeedError {
+    /// Counted from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+/// `line 3: port 70000 is out of range`.
+impl fmt::Display for SeedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Entry {
+    instance: String,
+    host: String,
+    port: u16,
+    addresses: Vec<Ipv4Addr>,
+    ipv6_addresses: Vec<ScopedIpv6Addr>,
+    txt: Option<Vec<(String, String)>>,
+}
+
+// The services of a client built with `MdnsClientBuilder::seed_file`, for as long as it
+// is kept, and the thread watching the file if it is watched.
+pub(crate) struct Seeder {
+    shared: Arc<Shared>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct Shared {
+    path: PathBuf,
+    // The client's, which instance names without one are taken to be of.
+    service_type: String,
+    database: Arc<Mutex<Database>>,
+    changes: Arc<Changes>,
+    metrics: Arc<Metrics>,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    // What each seeded service was last given, by service as the database has it.
+    applied: HashMap<Service, Entry>,
+    modified: Option<SystemTime>,
+    errors: Vec<SeedError>,
+    // Whether reading the file failed last time, so it is reported once.
+    unreadable: bool,
+}
+
+impl Seeder {
+    // Seeds the services of `path` now, failing if it can't be read.
+    pub(crate) fn start(
+        path: PathBuf,
+        watch: bool,
+        service_type: String,
+        database: Arc<Mutex<Database>>,
+        changes: Arc<Changes>,
+        metrics: Arc<Metrics>,
+    ) -> Result<Seeder, Box<dyn Error>> {
+        let shared = Arc::new(Shared {
+            path,
+            service_type,
+            database,
+            changes,
+            metrics,
+            state: Mutex::default(),
+        });
+        shared
+            .load()
+            .map_err(|err| format!("failed to read {}: {}", shared.path.display(), err))?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = watch.then(|| {
+            let shared = shared.clone();
+            let stop = stop.clone();
+
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    thread::sleep(POLL_INTERVAL);
+                    shared.reload();
+                }
+            })
+        });
+
+        Ok(Seeder {
+            shared,
+            stop,
+            thread,
+        })
+    }
+
+    pub(crate) fn errors(&self) -> Vec<SeedError> {
+        self.shared.state.locked().errors.clone()
+    }
+
+    // Seeds the services again into `database`, after `MdnsClient::reset` cleared it.
+    pub(crate) fn reapply(&self, database: &mut Database) {
+        let mut state = self.shared.state.locked();
+        let entries: Vec<Entry> = state.applied.drain().map(|(_, entry)| entry).collect();
+        state.applied = self.shared.apply(database, entries, HashMap::new());
+    }
+}
+
+impl Drop for Seeder {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+impl Shared {
+    // A file that is there but can't be read keeps the services it had.
+    fn reload(&self) {
+        let modified = fs::metadata(&self.path).and_then(|metadata| metadata.modified());
+        let changed = match &modified {
+            Ok(modified) => self.state.locked().modified != Some(*modified),
+            Err(_) => true,
+        };
+        if !changed {
+            return;
+        }
+
+        let result = self.load();
+        let mut state = self.state.locked();
+        match result {
+            Ok(()) => state.unreadable = false,
+            Err(err) if !state.unreadable => {
+                warn!(path = %self.path.display(), error = %err, "failed to read the seed file");
+                let message = format!("failed to read {}: {}", self.path.display(), err);
+                self.metrics.error(FailedOperation::Seed, None, &message);
+                state.unreadable = true;
+            }
+            Err(_) => (),
+        }
+    }
+
+    fn load(&self) -> Result<(), std::io::Error> {
+        let modified = fs::metadata(&self.path)?.modified().ok();
+        let text = fs::read_to_string(&self.path)?;
+        let (entries, errors) = parse(&text, &self.service_type);
+
+        for _error in &errors {
+            warn!(path = %self.path.display(), error = %_error, "seed file line not taken");
+        }
+        if let Some(first) = errors.first() {
+            let message = format!(
+                "{} of {} not taken, the first {}",
+                plural(errors.len()),
+                self.path.display(),
+                first
+            );
+            self.metrics.error(FailedOperation::Seed, None, &message);
+        }
+
+        let mut database = Database::lock(&self.database);
+        let mut state = self.state.locked();
+        let applied = std::mem::take(&mut state.applied);
+        state.applied = self.apply(&mut database, entries, applied);
+        state.modified = modified;
+        state.errors = errors;
+        let _seeded = state.applied.len();
+        drop(state);
+
+        info!(path = %self.path.display(), services = _seeded, "seeded services");
+        self.metrics.database_size(database.len());
+        self.changes.update(database, Instant::now());
+        Ok(())
+    }
+
+    // Seeds `entries`, and stops seeding the services of `applied` not among them any
+    // longer, which then expire as others do unless heard. Returns what was seeded.
+    fn apply(
+        &self,
+        database: &mut Database,
+        entries: Vec<Entry>,
+        mut applied: HashMap<Service, Entry>,
+    ) -> HashMap<Service, Entry> {
+        let now = Instant::now();
+        let mut seeded = HashMap::new();
+
+        for entry in entries {
+            let service = Service {
+                host: normalize_host(&entry.host).into(),
+                port: entry.port,
+            };
+            let previous = applied.remove(&service);
+            database.pin(&service);
+
+            let record = database.get_or_insert_with(service.clone(), || ServiceRecord {
+                last_seen_time: now,
+                preferred_address: None,
+                addresses: HashSet::new(),
+                ipv6_addresses: HashSet::new(),
+                conflicting: false,
+                latency: None,
+                provenance: Provenance::default(),
+                pinned: true,
+                withdrawn: false,
+                seeded: true,
+                mac_address: None,
+                records: Arc::default(),
+                history: History::default(),
+            });
+
+            // Addresses the file no longer gives go, those heard as well stay until they
+            // are not.
+            if let Some(previous) = &previous {
+                for address in &previous.addresses {
+                    if !entry.addresses.contains(address) {
+                        record.addresses.remove(address);
+                    }
+                }
+                for address in &previous.ipv6_addresses {
+                    if !entry.ipv6_addresses.contains(address) {
+                        record.ipv6_addresses.remove(address);
+                    }
+                }
+            }
+            record.addresses.extend(&entry.addresses);
+            record.ipv6_addresses.extend(&entry.ipv6_addresses);
+            if record
+                .preferred_address
+                .is_none_or(|preferred| !record.addresses.contains(&preferred))
+            {
+                record.preferred_address = entry.addresses.first().copied();
+            }
+            record.seeded = true;
+
+            if let Some(txt) = &entry.txt {
+                let txt: Vec<(&str, &str)> = txt
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value.as_str()))
+                    .collect();
+                if let Ok(data) = encode_txt(&txt) {
+                    let txt = OwnedRecord {
+                        name: entry.instance.clone(),
+                        record_type: TYPE_TXT,
+                        class: 1,
+                        cache_flush: true,
+                        ttl: TXT_TTL,
+                        data,
+                    };
+                    record::keep(&mut record.records, &txt);
+                }
+            }
+
+            database.instance_at(&entry.instance, &service, None, now);
+            seeded.insert(service, entry);
+        }
+
+        for service in applied.into_keys() {
+            database.unpin(&service);
+            database.for_host(&service.host.clone(), |known, record| {
+                if *known == service {
+                    record.seeded = false;
+                }
+            });
+        }
+        seeded
+    }
+}
+
+fn plural(lines: usize) -> String {
+    match lines {
+        1 => "1 line".to_string(),
+        lines => format!("{} lines", lines),
+    }
+}
+
+// The services of `text` that could be taken, and the lines that couldn't. Instance names
+// not ending in a service type are taken to be of `service_type`.
+fn parse(text: &str, service_type: &str) -> (Vec<Entry>, Vec<SeedError>) {
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+    // The service being read, with the line its table started on and whether a line of
+    // it couldn't be taken.
+    let mut current: Option<(Entry, usize, bool)> = None;
+
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let result = if line.starts_with('[') {
+            finish(current.take(), &mut entries, &mut errors);
+            match strip_comment(line) {
+                "[[service]]" => {
+                    current = Some((Entry::default(), number, false));
+                    Ok(())
+                }
+                _ => Err(format!("unknown table {}", line)),
+            }
+        } else {
+            match (line.split_once('='), &mut current) {
+                (None, _) => Err("expected key = value".to_string()),
+                (Some(_), None) => Err("outside a [[service]] table".to_string()),
+                (Some((key, value)), Some((entry, _, _))) => {
+                    set(entry, key.trim(), value.trim(), service_type)
+                }
+            }
+        };
+
+        if let Err(message) = result {
+            if let Some((_, _, broken)) = &mut current {
+                *broken = true;
+            }
+            errors.push(SeedError {
+                line: number,
+                message,
+            });
+        }
+    }
+    finish(current, &mut entries, &mut errors);
+
+    errors.sort_by_key(|error| error.line);
+    (entries, errors)
+}
+
+// Takes the service read, unless a line of it couldn't be, which was reported, or it
+// lacks a key.
+fn finish(
+    current: Option<(Entry, usize, bool)>,
+    entries: &mut Vec<Entry>,
+    errors: &mut Vec<SeedError>,
+) {
+    let Some((entry, line, false)) = current else {
+        return;
+    };
+    let missing = [
+        ("instance", entry.instance.is_empty()),
+        ("host", entry.host.is_empty()),
+        ("port", entry.port == 0),
+        (
+            "addresses",
+            entry.addresses.is_empty() && entry.ipv6_addresses.is_empty(),
+        ),
+    ];
+
+    if let Some((key, _)) = missing.iter().find(|(_, missing)| *missing) {
+        errors.push(SeedError {
+            line,
+            message: format!("service without {}", key),
+        });
+    } else {
+        entries.push(entry);
+    }
+}
+
+fn set(entry: &mut Entry, key: &str, value: &str, service_type: &str) -> Result<(), String> {
+    match key {
+        "instance" => {
+            let instance = string(value)?;
+            let suffix = format!(".{}", service_type);
+            let len = instance.len();
+            entry.instance = match len > suffix.len()
+                && instance.is_char_boundary(len - suffix.len())
+                && instance[len - suffix.len()..].eq_ignore_ascii_case(&suffix)
+            {
+                true => instance,
+                false => format!("{}{}", instance, suffix),
+            };
+        }
+        "host" => entry.host = string(value)?,
+        "port" => {
+            let port = strip_comment(value);
+            entry.port = match port.parse() {
+                Ok(0) | Err(_) => return Err(format!("invalid port {}", port)),
+                Ok(port) => port,
+            };
+        }
+        "addresses" => {
+            for address in array(value)? {
+                match parse_address(&address) {
+                    Some(Address::V4(address)) => entry.addresses.push(address),
+                    Some(Address::V6(address)) => entry.ipv6_addresses.push(address),
+                    None => return Err(format!("invalid address {:?}", address)),
+                }
+            }
+        }
+        "txt" => {
+            let mut txt = Vec::new();
+            for item in array(value)? {
+                let (key, value) = item.split_once('=').unwrap_or((&item, ""));
+                if key.is_empty() {
+                    return Err(format!("TXT entry {:?} without a key", item));
+                }
+                txt.push((key.to_string(), value.to_string()));
+            }
+            let pairs: Vec<(&str, &str)> = txt.iter().map(|(k, v)| (&**k, &**v)).collect();
+            encode_txt(&pairs).map_err(|err| err.to_string())?;
+            entry.txt = Some(txt);
+        }
+        key => return Err(format!("unknown key {}", key)),
+    }
+    Ok(())
+}
+
+enum Address {
+    V4(Ipv4Addr),
+    V6(ScopedIpv6Addr),
+}
+
+// An IPv4 or IPv6 address, the latter with a `%` and scope if link-local.
+fn parse_address(text: &str) -> Option<Address> {
+    let (ip, scope_id) = match text.split_once('%') {
+        Some((ip, scope_id)) => (ip, scope_id.parse().ok()?),
+        None => (text, 0),
+    };
+    match ip.parse().ok()? {
+        IpAddr::V4(ip) if scope_id == 0 => Some(Address::V4(ip)),
+        IpAddr::V4(_) => None,
+        IpAddr::V6(ip) => Some(Address::V6(ScopedIpv6Addr { ip, scope_id })),
+    }
+}
+
+// A value with what follows it, past a `#`, taken off.
+fn strip_comment(value: &str) -> &str {
+    let value = match value.split_once('#') {
+        Some((value, _)) => value,
+        None => value,
+    };
+    value.trim()
+}
+
+// A quoted string, with `\"` and `\\` escapes, and its length in `text`.
+fn quoted(text: &str) -> Result<(String, usize), String> {
+    let mut chars = text.char_indices();
+    if chars.next().map(|(_, c)| c) != Some('"') {
+        return Err(format!("expected a quoted string at {}", text));
+    }
+
+    let mut out = String::new();
+    while let Some((at, c)) = chars.next() {
+        match c {
+            '"' => return Ok((out, at + 1)),
+            '\\' => match chars.next() {
+                Some((_, c @ ('"' | '\\'))) => out.push(c),
+                _ => return Err("unknown escape in string".to_string()),
+            },
+            c => out.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+fn string(value: &str) -> Result<String, String> {
+    let (string, len) = quoted(value)?;
+    match strip_comment(&value[len..]) {
+        "" => Ok(string),
+        rest => Err(format!("unexpected {} after string", rest)),
+    }
+}
+
+fn array(value: &str) -> Result<Vec<String>, String> {
+    let Some(mut rest) = value.strip_prefix('[') else {
+        return Err(format!("expected an array of strings, got {}", value));
+    };
+
+    let mut items = Vec::new();
+    loop {
+        rest = rest.trim_start();
+        if let Some(after) = rest.strip_prefix(']') {
+            return match strip_comment(after) {
+                "" => Ok(items),
+                after => Err(format!("unexpected {} after array", after)),
+            };
+        }
+
+        let (item, len) = quoted(rest)?;
+        items.push(item);
+        rest = rest[len..].trim_start();
+        match rest.strip_prefix(',') {
+            Some(after) => rest = after,
+            None if rest.starts_with(']') => (),
+            None => return Err("expected , or ] in array".to_string()),
+        }
+    }
+}
@@ -0,0 +1,241 @@
+use std::{error::Error, fmt};
+
+/// The transport of a `ServiceType`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl Protocol {
+    fn label(self) -> &'static str {
+        match self {
+            Protocol::Tcp => "_tcp",
+            Protocol::Udp => "_udp",
+        }
+    }
+}
+
+/// A service type checked as it is built, for `MdnsClient::new` and `MdnsClient::builder`
+/// instead of a string: `ServiceType::new("http", Protocol::Tcp)` is `_http._tcp.local`,
+/// and `.with_subtype("printer")` makes it `_printer._sub._http._tcp.local`. It turns
+/// into the string form with `Display` or `String::from`, e.g. for a
+/// `ServiceTypeMatcher`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ServiceType {
+    name: String,
+    protocol: Protocol,
+    subtype: Option<String>,
+    // `MdnsClient::new` takes it as a string.
+    full: String,
+}
+
+impl ServiceType {
+    /// `name` as registered with IANA (RFC 6335 section 5.1), with or without its
+    /// leading underscore: 1 to 15 letters, digits and hyphens, at least one a letter,
+    /// and no hyphen at either end or next to another.
+    pub fn new(name: &str, protocol: Protocol) -> Result<ServiceType, InvalidServiceType> {
+        let name = name.strip_prefix('_').unwrap_or(name);
+        check_name(name)?;
+
+        Ok(ServiceType {
+            name: name.to_string(),
+            protocol,
+            subtype: None,
+            full: format!("_{}.{}.local", name, protocol.label()),
+        })
+    }
+
+    /// Only the instances registered with `subtype` (RFC 6763 section 7.1), given with
+    /// or without its leading underscore: 1 to 63 bytes without dots or control
+    /// characters.
+    pub fn with_subtype(self, subtype: &str) -> Result<ServiceType, InvalidServiceType> {
+        let subtype = subtype.strip_prefix('_').unwrap_or(subtype);
+        if subtype.is_empty()
+            || subtype.len() > 63
+            || subtype.chars().any(|c| c == '.' || c.is_control())
+        {
+            return Err(InvalidServiceType::InvalidSubtype(subtype.to_string()));
+        }
+
+        Ok(ServiceType {
+            full: format!(
+                "_{}._sub._{}.{}.local",
+                subtype,
+                self.name,
+                self.protocol.label()
+            ),
+            subtype: Some(subtype.to_string()),
+            ..self
+        })
+    }
+
+    /// `http`, without the underscore.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// Without the underscore.
+    pub fn subtype(&self) -> Option<&str> {
+        self.subtype.as_deref()
+    }
+}
+
+/// `_http._tcp.local`.
+impl fmt::Display for ServiceType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.full)
+    }
+}
+
+impl AsRef<str> for ServiceType {
+    fn as_ref(&self) -> &str {
+        &self.full
+    }
+}
+
+impl From<ServiceType> for String {
+    fn from(service_type: ServiceType) -> String {
+        service_type.full
+    }
+}
+
+/// Why `ServiceType::new` or `ServiceType::with_subtype` refused a name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InvalidServiceType {
+    Empty,
+    /// The name, longer than 15 characters.
+    TooLong(String),
+    /// One that is not a letter, digit or hyphen.
+    InvalidCharacter(char),
+    /// The name has no letter, so it could be taken for a port number.
+    NoLetter,
+    /// At the start or end of the name, or next to another.
+    MisplacedHyphen,
+    /// The subtype, empty, longer than 63 bytes or holding a dot or control character.
+    InvalidSubtype(String),
+}
+
+impl fmt::Display for InvalidServiceType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidServiceType::Empty => write!(f, "the service name is empty"),
+            InvalidServiceType::TooLong(name) => {
+                write!(f, "service name {:?} is longer than 15 characters", name)
+            }
+            InvalidServiceType::InvalidCharacter(c) => write!(
+                f,
+                "service names only hold letters, digits and hyphens, not {:?}",
+                c
+            ),
+            InvalidServiceType::NoLetter => write!(f, "service names need a letter"),
+            InvalidServiceType::MisplacedHyphen => write!(
+                f,
+                "service names can't start or end with a hyphen, or have two in a row"
+            ),
+            InvalidServiceType::InvalidSubtype(subtype) => write!(
+                f,
+                "subtype {:?} must be 1 to 63 bytes without dots or control characters",
+                subtype
+            ),
+        }
+    }
+}
+
+impl Error for InvalidServiceType {}
+
+fn check_name(name: &str) -> Result<(), InvalidServiceType> {
+    if name.is_empty() {
+        return Err(InvalidServiceType::Empty);
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| !c.is_ascii_alphanumeric() && *c != '-')
+    {
+        return Err(InvalidServiceType::InvalidCharacter(c));
+    }
+    if name.len() > 15 {
+        return Err(InvalidServiceType::TooLong(name.to_string()));
+    }
+    if !name.chars().any(|c| c.is_ascii_alphabetic()) {
+        return Err(InvalidServiceType::NoLetter);
+    }
+    if name.starts_with('-') || name.ends_with('-') || name.contains("--") {
+        return Err(InvalidServiceType::MisplacedHyphen);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn service_types_built() {
+        let http = ServiceType::new("http", Protocol::Tcp).unwrap();
+        assert_eq!(http.to_string(), "_http._tcp.local");
+        assert_eq!(ServiceType::new("_http", Protocol::Tcp).unwrap(), http);
+        assert_eq!(
+            ServiceType::new("sleep-proxy", Protocol::Udp)
+                .unwrap()
+                .to_string(),
+            "_sleep-proxy._udp.local"
+        );
+
+        let printer = ServiceType::new("ipp", Protocol::Tcp)
+            .unwrap()
+            .with_subtype("_printer")
+            .unwrap();
+        assert_eq!(
+            String::from(printer.clone()),
+            "_printer._sub._ipp._tcp.local"
+        );
+        assert_eq!(
+            (printer.name(), printer.protocol(), printer.subtype()),
+            ("ipp", Protocol::Tcp, Some("printer"))
+        );
+
+        // And the client takes them.
+        for service_type in [http, printer] {
+            assert!(crate::check_service_name(service_type.as_ref()).is_ok());
+        }
+    }
+
+    #[test]
+    fn invalid_service_types_rejected() {
+        let new = |name: &str| ServiceType::new(name, Protocol::Tcp).unwrap_err();
+
+        assert_eq!(new(""), InvalidServiceType::Empty);
+        assert_eq!(new("_"), InvalidServiceType::Empty);
+        assert_eq!(
+            new("a-very-long-name"),
+            InvalidServiceType::TooLong("a-very-long-name".into())
+        );
+        assert_eq!(new("my app"), InvalidServiceType::InvalidCharacter(' '));
+        assert_eq!(new("my_app"), InvalidServiceType::InvalidCharacter('_'));
+        assert_eq!(new("http.local"), InvalidServiceType::InvalidCharacter('.'));
+        assert_eq!(new("7000"), InvalidServiceType::NoLetter);
+        for name in ["-http", "http-", "my--app"] {
+            assert_eq!(new(name), InvalidServiceType::MisplacedHyphen, "{}", name);
+        }
+        // 15 characters is the most there may be.
+        assert!(ServiceType::new("abcdefghijklmno", Protocol::Tcp).is_ok());
+
+        let http = ServiceType::new("http", Protocol::Tcp).unwrap();
+        for subtype in ["", "_", "a.b", "tab\t", &"a".repeat(64)] {
+            assert!(
+                matches!(
+                    http.clone().with_subtype(subtype),
+                    Err(InvalidServiceType::InvalidSubtype(_))
+                ),
+                "{:?} accepted",
+                subtype
+            );
+        }
+        assert!(http.with_subtype("Büro Drucker").is_ok());
+    }
+}
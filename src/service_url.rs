@@ -0,0 +1,192 @@
+use crate::{packet::TYPE_TXT, record, resolver, Service, ServiceRecord};
+use std::{error::Error, net::SocketAddr};
+
+/// A URL for `service` of `service_type`, such as `http://192.168.1.20:8080/admin`: at
+/// the first address `CacheResolver::resolve` would give, IPv6 ones in brackets, with the
+/// port left out where it is the scheme's default and the TXT `path` entry as the path if
+/// `MdnsClientBuilder::keep_records` kept one (RFC 6763 section 6). The scheme is
+/// `scheme` if given, else the service type's name, so `http` for `_http._tcp.local`.
+/// Link-local IPv6 addresses are skipped, as a URL can't carry their scope.
+pub fn service_url(
+    service_type: &str,
+    service: &Service,
+    record: &ServiceRecord,
+    scheme: Option<&str>,
+) -> Result<url::Url, Box<dyn Error>> {
+    let scheme = match scheme {
+        Some(scheme) => scheme.to_ascii_lowercase(),
+        None => scheme_of(service_type)
+            .ok_or_else(|| format!("no scheme for service type {:?}", service_type))?,
+    };
+
+    let address = resolver::service_addresses(service, record, None)
+        .into_iter()
+        .find(|address| match address {
+            SocketAddr::V4(_) => true,
+            SocketAddr::V6(address) => address.scope_id() == 0,
+        })
+        .ok_or_else(|| format!("no address usable in a URL for {}", service.host))?;
+
+    let mut url = url::Url::parse(&format!("{}://{}", scheme, address))
+        .map_err(|err| format!("invalid URL for scheme {:?}: {}", scheme, err))?;
+    if let Some(path) = txt_value(record, "path") {
+        match path.starts_with('/') {
+            true => url.set_path(&path),
+            false => url.set_path(&format!("/{}", path)),
+        }
+    }
+    Ok(url)
+}
+
+// The service name of a type such as `_printer._sub._ipp._tcp.local`, here `ipp`.
+fn scheme_of(service_type: &str) -> Option<String> {
+    let labels: Vec<&str> = service_type.split('.').collect();
+    let protocol = labels.iter().position(|label| {
+        label.eq_ignore_ascii_case("_tcp") || label.eq_ignore_ascii_case("_udp")
+    })?;
+    let name = labels.get(protocol.checked_sub(1)?)?.strip_prefix('_')?;
+    (!name.is_empty()).then(|| name.to_ascii_lowercase())
+}
+
+// The value of the first entry for `key` in the service's TXT record, keys compared
+// ignoring case; an entry without `=` has an empty one.
+fn txt_value(record: &ServiceRecord, key: &str) -> Option<String> {
+    let txt = record
+        .records()
+        .iter()
+        .find(|record| record.record_type == TYPE_TXT)?;
+
+    record::txt_entries(&txt.data)
+        .into_iter()
+        .find_map(|entry| {
+            let (name, value) = entry.split_once('=').unwrap_or((&entry, ""));
+            name.eq_ignore_ascii_case(key).then(|| value.to_string())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{address::ScopedIpv6Addr, record::OwnedRecord};
+    use std::{net::SocketAddrV6, sync::Arc, time::Instant};
+
+    fn service(port: u16) -> Service {
+        Service {
+            host: "printer.local".into(),
+            port,
+        }
+    }
+
+    // A record with `v4` and `v6` addresses and, if given, a TXT record of `txt` entries.
+    fn record(v4: &[&str], v6: &[&str], txt: Option<&[&str]>) -> ServiceRecord {
+        let mut record = ServiceRecord::heard_at(Instant::now());
+        record.addresses = v4.iter().map(|ip| ip.parse().unwrap()).collect();
+        record.ipv6_addresses = v6
+            .iter()
+            .map(|ip| {
+                let address: SocketAddrV6 = ip.parse().unwrap();
+                ScopedIpv6Addr::heard(*address.ip(), address.scope_id())
+            })
+            .collect();
+        if let Some(txt) = txt {
+            let mut data = Vec::new();
+            for entry in txt {
+                data.push(entry.len() as u8);
+                data.extend_from_slice(entry.as_bytes());
+            }
+            record.records = Arc::new([OwnedRecord {
+                name: "Printer._http._tcp.local".into(),
+                record_type: TYPE_TXT,
+                class: 1,
+                cache_flush: true,
+                ttl: 4500,
+                data,
+            }]);
+        }
+        record
+    }
+
+    fn url(
+        service_type: &str,
+        port: u16,
+        record: &ServiceRecord,
+        scheme: Option<&str>,
+    ) -> Result<String, Box<dyn Error>> {
+        service_url(service_type, &service(port), record, scheme).map(String::from)
+    }
+
+    const V4: &[&str] = &["192.0.2.20"];
+
+    #[test]
+    fn default_ports_left_out() {
+        let record = record(V4, &[], None);
+
+        assert_eq!(
+            url("_http._tcp.local", 80, &record, None).unwrap(),
+            "http://192.0.2.20/"
+        );
+        assert_eq!(
+            url("_http._tcp.local", 8080, &record, None).unwrap(),
+            "http://192.0.2.20:8080/"
+        );
+        assert_eq!(
+            url("_http._tcp.local", 443, &record, Some("HTTPS")).unwrap(),
+            "https://192.0.2.20/"
+        );
+        // Schemes the url crate doesn't know keep every port.
+        assert_eq!(
+            url("_printer._sub._ipp._tcp.local.", 631, &record, None).unwrap(),
+            "ipp://192.0.2.20:631"
+        );
+    }
+
+    #[test]
+    fn ipv6_bracketed_when_not_link_local() {
+        let both = record(&[], &["[fe80::1%3]:0", "[2001:db8::1]:0"], None);
+        assert_eq!(
+            url("_http._tcp.local", 8080, &both, None).unwrap(),
+            "http://[2001:db8::1]:8080/"
+        );
+
+        let link_local = record(&[], &["[fe80::1%3]:0"], None);
+        assert!(url("_http._tcp.local", 8080, &link_local, None).is_err());
+        assert!(url("_http._tcp.local", 8080, &record(&[], &[], None), None).is_err());
+    }
+
+    #[test]
+    fn txt_path_used() {
+        for (txt, path) in [
+            (&["path=/admin"][..], "/admin"),
+            (&["PATH=admin"], "/admin"),
+            (&["x", "Path=/a b", "path=/second"], "/a%20b"),
+            (&["path"], "/"),
+            (&["paths=/no"], "/"),
+        ] {
+            let record = record(V4, &[], Some(txt));
+            assert_eq!(
+                url("_http._tcp.local", 80, &record, None).unwrap(),
+                format!("http://192.0.2.20{}", path),
+                "{:?}",
+                txt
+            );
+        }
+    }
+
+    #[test]
+    fn schemes_needed() {
+        let record = record(V4, &[], None);
+
+        for service_type in ["_tcp.local", "udp.local", "_._tcp.local", "local", ""] {
+            assert!(
+                url(service_type, 80, &record, None).is_err(),
+                "{:?}",
+                service_type
+            );
+        }
+        assert!(url("_http._tcp.local", 80, &record, Some("not a scheme")).is_err());
+        assert_eq!(
+            url("_HTTP._TCP.local", 80, &record, None).unwrap(),
+            "http://192.0.2.20/"
+        );
+    }
+}
@@ -0,0 +1,408 @@
+// The cache shared with other processes on the host, see
+// `MdnsClientBuilder::share_cache`: the owner rewrites a snapshot file whenever its
+// services change and at least every `HEARTBEAT`, replacing it whole so a reader never
+// sees half of one, and removes it when dropped. A file not rewritten for `STALE_AFTER`
+// is taken to be left by an owner that died.
+//
+//   simple_mdns_client cache 1
+//   service _http._tcp.local
+//   pid 4242
+//   written 1700000000000
+//   <host> <port> <age ms> <preferred|-> <IPv4,...|-> <IPv6%scope,...|-> <flags|-> <records|->
+//
+// The flags are c, p, w and s for conflicting, pinned, withdrawn and seeded; records are
+// name:type:class:flush:ttl:data, the name and data in hex. Fields are separated by tabs.
+use crate::{
+    availability::History,
+    catch_panic,
+    changes::Changes,
+    database::Database,
+    metrics::{FailedOperation, Metrics},
+    Command, ExpiryReason, OwnedRecord, Provenance, ScopedIpv6Addr, Service, ServiceRecord,
+};
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs, io,
+    net::{Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Receiver,
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const FORMAT: &str = "simple_mdns_client cache 1";
+
+const CHECK_INTERVAL: Duration = Duration::from_millis(250);
+const HEARTBEAT: Duration = Duration::from_secs(1);
+const STALE_AFTER: Duration = Duration::from_secs(3);
+
+// Rewrites the snapshot at `path` for as long as it is kept.
+pub(crate) struct Writer {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Writer {
+    pub(crate) fn start(
+        path: PathBuf,
+        service: String,
+        changes: Arc<Changes>,
+        metrics: Arc<Metrics>,
+    ) -> Writer {
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread = thread::spawn({
+            let stop = stop.clone();
+
+            move || {
+                catch_panic(
+                    || write_while(&path, &service, &changes, &metrics, &stop),
+                    |message| metrics.error(FailedOperation::Panic, None, &message),
+                );
+                fs::remove_file(&path).ok();
+            }
+        });
+
+        Writer {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+fn write_while(
+    path: &Path,
+    service: &str,
+    changes: &Changes,
+    metrics: &Metrics,
+    stop: &AtomicBool,
+) {
+    let mut written: Option<(u64, Instant)> = None;
+    let mut failing = false;
+
+    while !stop.load(Ordering::Relaxed) {
+        let generation = changes.generation();
+        let due = written.is_none_or(|(last, at)| last != generation || at.elapsed() >= HEARTBEAT);
+
+        if due {
+            match write(path, service, &changes.published().load(Instant::now())) {
+                Ok(()) => {
+                    written = Some((generation, Instant::now()));
+                    failing = false;
+                }
+                // Reported once until it works again; it is tried every check.
+                Err(err) if !failing => {
+                    warn!(path = %path.display(), error = %err, "failed to write the shared cache");
+                    metrics.error(FailedOperation::ShareCache, None, &err);
+                    failing = true;
+                }
+                Err(_) => (),
+            }
+        }
+
+        thread::sleep(CHECK_INTERVAL);
+    }
+}
+
+fn write(path: &Path, service: &str, services: &HashMap<Service, ServiceRecord>) -> io::Result<()> {
+    let now = Instant::now();
+    let mut out = String::new();
+
+    writeln!(out, "{}", FORMAT).unwrap();
+    writeln!(out, "service {}", service).unwrap();
+    writeln!(out, "pid {}", std::process::id()).unwrap();
+    writeln!(out, "written {}", unix_millis(SystemTime::now())).unwrap();
+
+    for (service, record) in services {
+        // Only a hostile responder sends such names, which the format can't hold.
+        if service.host.chars().any(char::is_control) {
+            continue;
+        }
+        line(&mut out, service, record, now);
+    }
+
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    fs::write(&temporary, out)?;
+    fs::rename(&temporary, path)
+}
+
+fn line(out: &mut String, service: &Service, record: &ServiceRecord, now: Instant) {
+    let list = |out: &mut String, items: Vec<String>| {
+        out.push('\t');
+        match items.is_empty() {
+            true => out.push('-'),
+            false => out.push_str(&items.join(",")),
+        }
+    };
+
+    let age = now.saturating_duration_since(record.last_seen_time);
+    write!(
+        out,
+        "{}\t{}\t{}",
+        service.host,
+        service.port,
+        age.as_millis()
+    )
+    .unwrap();
+    match record.preferred_address {
+        Some(address) => write!(out, "\t{}", address).unwrap(),
+        None => out.push_str("\t-"),
+    }
+
+    let mut addresses: Vec<_> = record.addresses.iter().collect();
+    addresses.sort();
+    list(out, addresses.iter().map(|a| a.to_string()).collect());
+
+    let mut addresses: Vec<_> = record.ipv6_addresses.iter().collect();
+    addresses.sort();
+    list(
+        out,
+        addresses
+            .iter()
+            .map(|a| format!("{}%{}", a.ip, a.scope_id))
+            .collect(),
+    );
+
+    let flags = [
+        (record.conflicting, 'c'),
+        (record.pinned, 'p'),
+        (record.withdrawn, 'w'),
+        (record.seeded, 's'),
+    ];
+    list(
+        out,
+        flags
+            .iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, flag)| flag.to_string())
+            .collect(),
+    );
+
+    list(
+        out,
+        record
+            .records()
+            .iter()
+            .map(|r| {
+                format!(
+                    "{}:{}:{}:{}:{}:{}",
+                    hex(r.name.as_bytes()),
+                    r.record_type,
+                    r.class,
+                    r.cache_flush as u8,
+                    r.ttl,
+                    hex(&r.data)
+                )
+            })
+            .collect(),
+    );
+    out.push('\n');
+}
+
+// A snapshot as read back: for `service`, written at `written`.
+struct Snapshot {
+    service: String,
+    written: SystemTime,
+    services: HashMap<Service, ServiceRecord>,
+}
+
+impl Snapshot {
+    fn read(path: &Path) -> Option<Snapshot> {
+        let text = fs::read_to_string(path).ok()?;
+        let mut lines = text.lines();
+        let mut header = |key: &str| lines.next()?.strip_prefix(key).map(str::to_string);
+
+        header(FORMAT).filter(String::is_empty)?;
+        let service = header("service ")?;
+        header("pid ")?;
+        let written = header("written ")?.parse().ok()?;
+        let written = UNIX_EPOCH + Duration::from_millis(written);
+
+        // How long ago it was written, by the wall clock, as ages are from then.
+        let since = SystemTime::now()
+            .duration_since(written)
+            .unwrap_or_default();
+        let now = Instant::now();
+        let services = lines
+            .filter_map(|line| parse(line, now.checked_sub(since)?))
+            .collect();
+
+        Some(Snapshot {
+            service,
+            written,
+            services,
+        })
+    }
+
+    // Left by an owner that died, or for another service type.
+    fn usable(&self, service: &str) -> bool {
+        let age = SystemTime::now()
+            .duration_since(self.written)
+            .unwrap_or_default();
+        age < STALE_AFTER && self.service.eq_ignore_ascii_case(service)
+    }
+}
+
+fn parse(line: &str, written: Instant) -> Option<(Service, ServiceRecord)> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    let [host, port, age, preferred, addresses, ipv6_addresses, flags, records] = fields[..] else {
+        return None;
+    };
+
+    let age = Duration::from_millis(age.parse().ok()?);
+
+    let ipv6_addresses = items(ipv6_addresses)
+        .map(|item| {
+            let (ip, scope_id) = item.split_once('%')?;
+            Some(ScopedIpv6Addr {
+                ip: ip.parse::<Ipv6Addr>().ok()?,
+                scope_id: scope_id.parse().ok()?,
+            })
+        })
+        .collect::<Option<_>>()?;
+    let records: Vec<OwnedRecord> = items(records)
+        .map(|item| {
+            let fields: Vec<&str> = item.split(':').collect();
+            let [name, record_type, class, cache_flush, ttl, data] = fields[..] else {
+                return None;
+            };
+            Some(OwnedRecord {
+                name: String::from_utf8(unhex(name)?).ok()?,
+                record_type: record_type.parse().ok()?,
+                class: class.parse().ok()?,
+                cache_flush: cache_flush == "1",
+                ttl: ttl.parse().ok()?,
+                data: unhex(data)?,
+            })
+        })
+        .collect::<Option<_>>()?;
+
+    let service = Service {
+        host: host.into(),
+        port: port.parse().ok()?,
+    };
+    let record = ServiceRecord {
+        last_seen_time: written.checked_sub(age)?,
+        preferred_address: match preferred {
+            "-" => None,
+            preferred => Some(preferred.parse().ok()?),
+        },
+        addresses: items(addresses)
+            .map(|item| item.parse::<Ipv4Addr>().ok())
+            .collect::<Option<_>>()?,
+        ipv6_addresses,
+        conflicting: flags.contains('c'),
+        latency: None,
+        provenance: Provenance::default(),
+        pinned: flags.contains('p'),
+        withdrawn: flags.contains('w'),
+        seeded: flags.contains('s'),
+        mac_address: None,
+        records: records.into(),
+        history: History::default(),
+    };
+    Some((service, record))
+}
+
+// A field's items, none for `-`.
+fn items(field: &str) -> impl Iterator<Item = &str> {
+    field.split(',').filter(|item| *item != "-")
+}
+
+// Whether `path` holds a snapshot of `service` that its owner keeps up to date.
+pub(crate) fn is_live(path: &Path, service: &str) -> bool {
+    Snapshot::read(path).is_some_and(|snapshot| snapshot.usable(service))
+}
+
+// Keeps `database` in step with the snapshot at `path` until it goes stale, when the
+// client is to fall back to sockets of its own, returning true, or until `gone` says
+// the client was dropped, returning false. Meanwhile it takes the client's `commands`. Services the owner pinned stay pinned, as
+// do those pinned here.
+pub(crate) fn follow(
+    path: &Path,
+    service: &str,
+    database: &Mutex<Database>,
+    metrics: &Metrics,
+    changes: &Changes,
+    commands: &Receiver<Command>,
+    gone: impl Fn() -> bool,
+) -> bool {
+    let mut last_written = None;
+
+    while !gone() {
+        let snapshot = Snapshot::read(path).filter(|snapshot| snapshot.usable(service));
+        let Some(snapshot) = snapshot else {
+            info!(path = %path.display(), "shared cache gone stale, setting up sockets");
+            return true;
+        };
+
+        if last_written != Some(snapshot.written) {
+            last_written = Some(snapshot.written);
+
+            let now = Instant::now();
+            let mut database = Database::lock(database);
+            let mut services = snapshot.services;
+            database.retain(ExpiryReason::NotHeard, now, |service, _| {
+                services.contains_key(service)
+            });
+            for (service, mut record) in services.drain() {
+                let known = database.get_or_insert_with(service, || record.clone());
+                record.pinned |= known.pinned;
+                record.seeded |= known.seeded;
+                // Kept by each client for itself.
+                record.history = std::mem::take(&mut known.history);
+                record.mac_address = known.mac_address;
+                *known = record;
+            }
+
+            metrics.database_size(database.len());
+            changes.update(database, now);
+        }
+
+        // Taken so senders never wait on a full channel; queries are the owner's to send,
+        // and after `MdnsClient::reset` cleared the database the snapshot is applied again.
+        if let Ok(Command::Reset) = commands.recv_timeout(CHECK_INTERVAL) {
+            last_written = None;
+        }
+    }
+    false
+}
+
+fn unix_millis(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(out, "{:02x}", byte).unwrap();
+    }
+    out
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(text.get(at..at + 2)?, 16).ok())
+        .collect()
+}
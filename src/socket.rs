@@ -0,0 +1,771 @@
+use crate::{MULTICAST_ADDR, MULTICAST_ADDR_V6, MULTICAST_PORT};
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket},
+    time::Instant,
+};
+
+// The largest mDNS message, RFC 6762 section 17. Longer datagrams are received truncated.
+pub(crate) const MAX_DATAGRAM: usize = 9000;
+
+// How many datagrams one receive call takes at most.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const BATCH: usize = 16;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const BATCH: usize = 1;
+
+// A kernel timestamp further back than this, as after the wall clock it is taken from was
+// set back, is ignored for the time the datagram is taken in.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const MAX_TIMESTAMP_AGE: std::time::Duration = std::time::Duration::from_secs(10);
+
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Options<'a> {
+    // Tie the socket to this device with SO_BINDTODEVICE.
+    pub(crate) device: Option<&'a str>,
+    // SO_RCVBUF, in bytes.
+    pub(crate) receive_buffer: Option<usize>,
+    // Bind IPv4 sockets to 224.0.0.251 rather than the wildcard address, so the kernel
+    // only delivers group traffic to them. Only set on Linux and Android.
+    pub(crate) bind_group: bool,
+}
+
+// Every socket the socket backend uses is set up here, one per interface, bound to port
+// 5353 next to whatever other responder runs on the host.
+//
+// Windows binds to the interface address itself and joins and sends by the adapter's
+// `index` where it has one: joined by address, the membership is tied to that address, so
+// another address of the same adapter fails to join and a changed one loses it.
+// Elsewhere the socket is bound to the wildcard address and joins and sends on the one
+// interface alone: the BSDs only deliver group traffic to sockets that joined on the
+// receiving interface, and Linux does the same once IP_MULTICAST_ALL is off. Given the
+// unspecified address, the kernel picks the interface instead; see
+// `daemon::JOINS_PER_INTERFACE`.
+//
+// The `options` are applied before binding. Sockets are left blocking, for their receive
+// threads.
+#[cfg(any(target_os = "windows", unix))]
+#[cfg_attr(unix, allow(unused_variables))]
+pub(crate) fn multicast(
+    interface: Ipv4Addr,
+    index: u32,
+    options: Options,
+) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+
+    configure(&socket, options)?;
+
+    socket.set_reuse_address(true)?;
+
+    // SO_REUSEADDR lets multicast sockets share the port on Linux, Solaris and illumos.
+    // Linux would also put our sockets into one SO_REUSEPORT group and hand each
+    // sender's datagrams to whichever member the hash picks, regardless of the interface
+    // it joined on; Solaris and illumos have no SO_REUSEPORT.
+    #[cfg(all(
+        unix,
+        not(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "solaris",
+            target_os = "illumos"
+        ))
+    ))]
+    socket.set_reuse_port(true)?;
+
+    socket
+        .bind(&SocketAddrV4::new(bind_address(interface, options), MULTICAST_PORT).into())
+        .map_err(port_taken)?;
+
+    #[cfg(target_os = "linux")]
+    socket.set_multicast_all_v4(false)?;
+    #[cfg(target_os = "android")]
+    disable_multicast_all(&socket, libc::IPPROTO_IP, libc::IP_MULTICAST_ALL)?;
+
+    socket.set_multicast_loop_v4(true)?;
+
+    #[cfg(target_os = "windows")]
+    {
+        socket.join_multicast_v4_n(&MULTICAST_ADDR, &membership(interface, index))?;
+        // An address of 0.0.0.x stands for interface index x.
+        if index != 0 {
+            socket.set_multicast_if_v4(&Ipv4Addr::from(index))?;
+        }
+    }
+    #[cfg(unix)]
+    {
+        socket.join_multicast_v4(&MULTICAST_ADDR, &interface)?;
+        socket.set_multicast_if_v4(&interface)?;
+    }
+
+    Ok(socket.into())
+}
+
+// What `multicast` binds to. Sockets bound to the group address still send from the
+// interface's, that of IP_MULTICAST_IF, but unicast datagrams to port 5353 no longer reach
+// them.
+#[cfg(any(target_os = "windows", unix))]
+#[cfg_attr(unix, allow(unused_variables))]
+fn bind_address(interface: Ipv4Addr, options: Options) -> Ipv4Addr {
+    match options.bind_group {
+        true => MULTICAST_ADDR,
+        #[cfg(target_os = "windows")]
+        false => interface,
+        #[cfg(unix)]
+        false => Ipv4Addr::UNSPECIFIED,
+    }
+}
+
+#[cfg(not(any(target_os = "windows", unix)))]
+pub(crate) fn multicast(
+    _interface: Ipv4Addr,
+    _index: u32,
+    _options: Options,
+) -> io::Result<UdpSocket> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "multicast sockets are not supported on this platform",
+    ))
+}
+
+// The IPv6 counterpart, identified by interface index. Bound to the wildcard address on
+// every platform; link-local sources keep their scope id, so replies find their way back.
+#[cfg(any(target_os = "windows", unix))]
+pub(crate) fn multicast_v6(index: u32, options: Options) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+
+    configure(&socket, options)?;
+
+    socket.set_only_v6(true)?;
+    socket.set_reuse_address(true)?;
+
+    #[cfg(all(
+        unix,
+        not(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "solaris",
+            target_os = "illumos"
+        ))
+    ))]
+    socket.set_reuse_port(true)?;
+
+    socket
+        .bind(&SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, MULTICAST_PORT, 0, 0).into())
+        .map_err(port_taken)?;
+
+    // Kernels before 4.20 lack the option; their sockets also hear the other interfaces'
+    // group traffic, which only costs duplicate parsing.
+    #[cfg(target_os = "linux")]
+    socket.set_multicast_all_v6(false).ok();
+    #[cfg(target_os = "android")]
+    disable_multicast_all(&socket, libc::IPPROTO_IPV6, libc::IPV6_MULTICAST_ALL).ok();
+
+    socket.set_multicast_loop_v6(true)?;
+    socket.join_multicast_v6(&MULTICAST_ADDR_V6, index)?;
+    socket.set_multicast_if_v6(index)?;
+
+    Ok(socket.into())
+}
+
+#[cfg(not(any(target_os = "windows", unix)))]
+pub(crate) fn multicast_v6(_index: u32, options: Options) -> io::Result<UdpSocket> {
+    multicast(Ipv4Addr::UNSPECIFIED, 0, options)
+}
+
+// Legacy unicast, RFC 6762 section 6.7, for when another program holds port 5353: queries
+// go to the group from an ephemeral port, and responders answer them by unicast to that
+// port. Nothing sent to the group is heard, neither announcements nor others' queries.
+#[cfg(any(target_os = "windows", unix))]
+pub(crate) fn unicast(interface: IpAddr, index: u32, options: Options) -> io::Result<UdpSocket> {
+    let socket = match interface {
+        IpAddr::V4(ip) => {
+            let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+            configure(&socket, options)?;
+
+            // Bound to the interface address, which answers are sent to.
+            socket.bind(&SocketAddrV4::new(ip, 0).into())?;
+            socket.set_multicast_if_v4(&ip)?;
+            socket
+        }
+        IpAddr::V6(_) => {
+            let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+            configure(&socket, options)?;
+
+            socket.set_only_v6(true)?;
+            socket.bind(&SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, 0, 0, 0).into())?;
+            socket.set_multicast_if_v6(index)?;
+            socket
+        }
+    };
+
+    Ok(socket.into())
+}
+
+#[cfg(not(any(target_os = "windows", unix)))]
+pub(crate) fn unicast(_interface: IpAddr, _index: u32, options: Options) -> io::Result<UdpSocket> {
+    multicast(Ipv4Addr::UNSPECIFIED, 0, options)
+}
+
+pub(crate) fn is_unicast(socket: &UdpSocket) -> bool {
+    socket
+        .local_addr()
+        .is_ok_and(|address| address.port() != MULTICAST_PORT)
+}
+
+// Another program bound to port 5353 without SO_REUSEADDR, or on Windows with
+// SO_EXCLUSIVEADDRUSE, which fails with access denied instead. Kept as AddrInUse, for
+// `daemon::open_socket` to fall back on.
+#[cfg(any(target_os = "windows", unix))]
+fn port_taken(err: io::Error) -> io::Error {
+    let taken = err.kind() == io::ErrorKind::AddrInUse
+        || cfg!(target_os = "windows") && err.kind() == io::ErrorKind::PermissionDenied;
+
+    if !taken {
+        return err;
+    }
+
+    io::Error::new(
+        io::ErrorKind::AddrInUse,
+        format!(
+            "port {} is held exclusively by another mDNS stack, such as Bonjour for Windows \
+             or a responder bound without address reuse; see \
+             MdnsClientBuilder::legacy_unicast_fallback ({})",
+            MULTICAST_PORT, err
+        ),
+    )
+}
+
+#[cfg(any(target_os = "windows", unix))]
+fn configure(socket: &Socket, options: Options) -> io::Result<()> {
+    if let Some(device) = options.device {
+        bind_device(socket, device)?;
+    }
+
+    #[cfg(target_os = "windows")]
+    ignore_connection_reset(SockRef::from(socket))?;
+
+    if let Some(size) = options.receive_buffer {
+        socket.set_recv_buffer_size(size).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("setting the receive buffer to {} bytes: {}", size, err),
+            )
+        })?;
+    }
+
+    Ok(())
+}
+
+// Windows fails the next receive with WSAECONNRESET after a send drew an ICMP port
+// unreachable, e.g. an answer to a legacy querier that has gone, which would pass for a
+// failing socket. SIO_UDP_CONNRESET turns that off. Also for the sockets of
+// `MdnsClientBuilder::socket_factory`.
+#[cfg(target_os = "windows")]
+pub(crate) fn ignore_connection_reset(socket: SockRef<'_>) -> io::Result<()> {
+    use std::{os::windows::io::AsRawSocket, ptr};
+    use windows_sys::Win32::Networking::WinSock::{
+        WSAGetLastError, WSAIoctl, SIO_UDP_CONNRESET, SOCKET, SOCKET_ERROR,
+    };
+
+    let report: i32 = 0; // FALSE
+    let mut returned = 0;
+
+    let result = unsafe {
+        WSAIoctl(
+            socket.as_raw_socket() as SOCKET,
+            SIO_UDP_CONNRESET,
+            &report as *const i32 as *const _,
+            std::mem::size_of::<i32>() as u32,
+            ptr::null_mut(),
+            0,
+            &mut returned,
+            ptr::null_mut(),
+            None,
+        )
+    };
+
+    if result == SOCKET_ERROR {
+        return Err(io::Error::from_raw_os_error(unsafe { WSAGetLastError() }));
+    }
+    Ok(())
+}
+
+// The receive buffer size in effect, which the OS may have rounded or capped; Linux also
+// reports twice the requested size, the rest being its bookkeeping.
+pub(crate) fn receive_buffer(socket: &UdpSocket) -> Option<usize> {
+    SockRef::from(socket).recv_buffer_size().ok()
+}
+
+// Datagrams the kernel dropped for each socket because its receive buffer was full, from
+// the `drops` column of /proc/net/udp that SO_RXQ_OVFL also reports. `None` where unknown.
+#[cfg(target_os = "linux")]
+pub(crate) fn receive_drops(sockets: &[&UdpSocket]) -> Vec<Option<u64>> {
+    use std::{collections::HashMap, os::unix::fs::MetadataExt, os::unix::io::AsRawFd};
+
+    let mut drops = HashMap::new();
+
+    for table in ["/proc/net/udp", "/proc/net/udp6"] {
+        let Ok(table) = std::fs::read_to_string(table) else {
+            continue;
+        };
+
+        for line in table.lines().skip(1) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            if let (Some(inode), Some(count)) = (fields.get(9), fields.get(12)) {
+                if let (Ok(inode), Ok(count)) = (inode.parse::<u64>(), count.parse::<u64>()) {
+                    drops.insert(inode, count);
+                }
+            }
+        }
+    }
+
+    sockets
+        .iter()
+        .map(|socket| {
+            let inode = std::fs::metadata(format!("/proc/self/fd/{}", socket.as_raw_fd()))
+                .ok()?
+                .ino();
+            drops.get(&inode).copied()
+        })
+        .collect()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn receive_drops(sockets: &[&UdpSocket]) -> Vec<Option<u64>> {
+    vec![None; sockets.len()]
+}
+
+pub(crate) struct Datagram<'a> {
+    pub(crate) data: &'a [u8],
+    pub(crate) from: SocketAddr,
+    // Longer than MAX_DATAGRAM, so `data` is only its start.
+    pub(crate) truncated: bool,
+    // When the kernel took it in, where the socket has `receive_timestamps` on.
+    pub(crate) received: Option<Instant>,
+}
+
+// What a failed receive means to the receive thread.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum ReceiveError {
+    // A signal came first, or on Windows an ICMP error for an earlier send reported on the
+    // socket despite `ignore_connection_reset`; receiving again is all there is to do.
+    Interrupted,
+    // Nothing arrived within the read timeout. Which error says so depends on the platform.
+    TimedOut,
+    // Anything else is the socket's, such as ENETDOWN, and counts against its health.
+    Failed,
+}
+
+impl ReceiveError {
+    pub(crate) fn of(err: &io::Error) -> Self {
+        Self::of_kind(err.kind(), cfg!(target_os = "windows"))
+    }
+
+    // As on Windows if `windows`, so both are tested on any platform.
+    fn of_kind(kind: io::ErrorKind, windows: bool) -> Self {
+        match kind {
+            io::ErrorKind::Interrupted => ReceiveError::Interrupted,
+            io::ErrorKind::ConnectionReset if windows => ReceiveError::Interrupted,
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => ReceiveError::TimedOut,
+            _ => ReceiveError::Failed,
+        }
+    }
+}
+
+// Buffers for the datagrams of one receive call, reused from call to call.
+pub(crate) struct Batch {
+    buffers: Vec<u8>,
+    // The length, source, truncation and kernel timestamp of each datagram the last call
+    // returned.
+    received: Vec<(usize, SocketAddr, bool, Option<Instant>)>,
+}
+
+impl Batch {
+    pub(crate) fn new() -> Self {
+        Batch {
+            buffers: vec![0; BATCH * MAX_DATAGRAM],
+            received: Vec::with_capacity(BATCH),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.received.len()
+    }
+
+    pub(crate) fn datagrams(&self) -> impl Iterator<Item = Datagram<'_>> {
+        self.received
+            .iter()
+            .zip(self.buffers.chunks_exact(MAX_DATAGRAM))
+            .map(|(&(length, from, truncated, received), buffer)| Datagram {
+                data: &buffer[..length],
+                from,
+                truncated,
+                received,
+            })
+    }
+
+    // Waits for a datagram as long as the socket's read timeout allows, then takes
+    // whatever else is queued with the same recvmmsg call, without waiting for more.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(crate) fn receive(&mut self, socket: &UdpSocket) -> io::Result<usize> {
+        use std::{mem::zeroed, os::fd::AsRawFd, ptr::null_mut};
+
+        self.received.clear();
+
+        // Plain C structs, for which all zeroes is a valid value.
+        let mut addresses: [libc::sockaddr_storage; BATCH] = unsafe { zeroed() };
+        let mut buffers: [libc::iovec; BATCH] = unsafe { zeroed() };
+        let mut headers: [libc::mmsghdr; BATCH] = unsafe { zeroed() };
+        // Room for the SCM_TIMESTAMPNS message, aligned as a cmsghdr.
+        let mut controls: [[u64; 8]; BATCH] = [[0; 8]; BATCH];
+
+        let chunks = self.buffers.chunks_exact_mut(MAX_DATAGRAM);
+
+        for ((((header, buffer), address), control), chunk) in headers
+            .iter_mut()
+            .zip(&mut buffers)
+            .zip(&mut addresses)
+            .zip(&mut controls)
+            .zip(chunks)
+        {
+            buffer.iov_base = chunk.as_mut_ptr().cast();
+            buffer.iov_len = chunk.len();
+            header.msg_hdr.msg_name = (address as *mut libc::sockaddr_storage).cast();
+            header.msg_hdr.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as _;
+            header.msg_hdr.msg_iov = buffer;
+            header.msg_hdr.msg_iovlen = 1;
+            header.msg_hdr.msg_control = control.as_mut_ptr().cast();
+            header.msg_hdr.msg_controllen = std::mem::size_of_val(control) as _;
+        }
+
+        let count = unsafe {
+            libc::recvmmsg(
+                socket.as_raw_fd(),
+                headers.as_mut_ptr(),
+                BATCH as _,
+                libc::MSG_WAITFORONE as _,
+                null_mut(),
+            )
+        };
+
+        if count < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let clocks = (Instant::now(), std::time::SystemTime::now());
+
+        for (header, address) in headers.iter().zip(&addresses).take(count as usize) {
+            let truncated = header.msg_hdr.msg_flags & libc::MSG_TRUNC != 0;
+
+            // Kept in step with the buffers, though the kernel always fills in the source
+            // of a UDP datagram.
+            let from = socket_address(address)
+                .unwrap_or_else(|| SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0).into());
+
+            let received = timestamp(&header.msg_hdr, clocks);
+            self.received
+                .push((header.msg_len as usize, from, truncated, received));
+        }
+
+        Ok(self.received.len())
+    }
+
+    // One datagram per call. Through socket2, which reports truncation on Windows and Unix
+    // alike.
+    #[cfg(all(
+        any(target_os = "windows", unix),
+        not(any(target_os = "linux", target_os = "android"))
+    ))]
+    pub(crate) fn receive(&mut self, socket: &UdpSocket) -> io::Result<usize> {
+        use socket2::MaybeUninitSlice;
+        use std::mem::MaybeUninit;
+
+        self.received.clear();
+
+        let buffer = &mut self.buffers[..MAX_DATAGRAM];
+        // socket2 only ever writes initialized bytes into it.
+        let buffer = unsafe { &mut *(buffer as *mut [u8] as *mut [MaybeUninit<u8>]) };
+
+        let (count, flags, from) =
+            SockRef::from(socket).recv_from_vectored(&mut [MaybeUninitSlice::new(buffer)])?;
+
+        let from = from
+            .as_socket()
+            .unwrap_or_else(|| SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0).into());
+
+        self.received
+            .push((count, from, flags.is_truncated(), None));
+        Ok(1)
+    }
+
+    #[cfg(not(any(target_os = "windows", unix)))]
+    pub(crate) fn receive(&mut self, socket: &UdpSocket) -> io::Result<usize> {
+        self.received.clear();
+
+        let (count, from) = socket.recv_from(&mut self.buffers[..MAX_DATAGRAM])?;
+
+        self.received.push((count, from, false, None));
+        Ok(1)
+    }
+}
+
+// Has the kernel stamp each datagram with when it took it in (SO_TIMESTAMPNS), for
+// `Datagram::received`. Only on Linux and Android; elsewhere, or where it fails, datagrams
+// count as received when they are read.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn receive_timestamps(socket: &UdpSocket) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let on: libc::c_int = 1;
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_TIMESTAMPNS,
+            (&on as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as _,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) fn receive_timestamps(_socket: &UdpSocket) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "no receive timestamps on this platform",
+    ))
+}
+
+// The SCM_TIMESTAMPNS message of a received datagram, a wall clock time, as an `Instant`
+// by how long before `clocks`, read together after receiving, it was.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn timestamp(header: &libc::msghdr, clocks: (Instant, std::time::SystemTime)) -> Option<Instant> {
+    use std::{
+        convert::TryFrom,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    let mut message = unsafe { libc::CMSG_FIRSTHDR(header) };
+    while !message.is_null() {
+        let control = unsafe { &*message };
+        if control.cmsg_level == libc::SOL_SOCKET && control.cmsg_type == libc::SCM_TIMESTAMPNS {
+            // Not necessarily aligned for a timespec.
+            let time: libc::timespec =
+                unsafe { std::ptr::read_unaligned(libc::CMSG_DATA(message).cast()) };
+            let time = UNIX_EPOCH
+                + Duration::new(
+                    u64::try_from(time.tv_sec).ok()?,
+                    u32::try_from(time.tv_nsec).ok()?,
+                );
+
+            let (now, wall) = clocks;
+            // A time after the datagram was read, or too long before, is the wall clock's,
+            // not the datagram's.
+            let age = wall.duration_since(time).ok()?;
+            return (age <= MAX_TIMESTAMP_AGE)
+                .then(|| now.checked_sub(age))
+                .flatten();
+        }
+        message = unsafe { libc::CMSG_NXTHDR(header, message) };
+    }
+    None
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn socket_address(storage: &libc::sockaddr_storage) -> Option<SocketAddr> {
+    let address: *const libc::sockaddr_storage = storage;
+
+    // The family says which of the layouts sockaddr_storage has room for is in use.
+    match storage.ss_family as libc::c_int {
+        libc::AF_INET => {
+            let address = unsafe { &*address.cast::<libc::sockaddr_in>() };
+
+            Some(
+                SocketAddrV4::new(
+                    Ipv4Addr::from(u32::from_be(address.sin_addr.s_addr)),
+                    u16::from_be(address.sin_port),
+                )
+                .into(),
+            )
+        }
+        libc::AF_INET6 => {
+            let address = unsafe { &*address.cast::<libc::sockaddr_in6>() };
+
+            Some(
+                SocketAddrV6::new(
+                    Ipv6Addr::from(address.sin6_addr.s6_addr),
+                    u16::from_be(address.sin6_port),
+                    address.sin6_flowinfo,
+                    address.sin6_scope_id,
+                )
+                .into(),
+            )
+        }
+        _ => None,
+    }
+}
+
+// Whether `interrupt` wakes a thread blocked receiving, so it needs no read timeout to
+// notice it is to stop.
+pub(crate) const INTERRUPTIBLE: bool = cfg!(any(target_os = "linux", target_os = "android"));
+
+// Shutting down reading wakes every receive blocked on the socket, and any later one
+// returns at once, empty. Linux does so for unconnected UDP sockets too, though it
+// reports ENOTCONN. Sending is unaffected.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn interrupt(socket: &UdpSocket) {
+    SockRef::from(socket)
+        .shutdown(std::net::Shutdown::Read)
+        .ok();
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) fn interrupt(_socket: &UdpSocket) {}
+
+// Leaves the group `multicast` or `multicast_v6` joined, given the same interface, rather
+// than counting on the kernel to drop the membership promptly once the socket is closed.
+// Joins the group again on a socket that has left it, as set up by `multicast` or
+// `multicast_v6`.
+pub(crate) fn join(socket: &UdpSocket, interface: IpAddr, index: u32) -> io::Result<()> {
+    let socket = SockRef::from(socket);
+
+    match interface {
+        #[cfg(target_os = "windows")]
+        IpAddr::V4(ip) => socket.join_multicast_v4_n(&MULTICAST_ADDR, &membership(ip, index)),
+        #[cfg(not(target_os = "windows"))]
+        IpAddr::V4(ip) => socket.join_multicast_v4(&MULTICAST_ADDR, &ip),
+        IpAddr::V6(_) => socket.join_multicast_v6(&MULTICAST_ADDR_V6, index),
+    }
+}
+
+pub(crate) fn leave(socket: &UdpSocket, interface: IpAddr, index: u32) -> io::Result<()> {
+    let socket = SockRef::from(socket);
+
+    match interface {
+        #[cfg(target_os = "windows")]
+        IpAddr::V4(ip) => socket.leave_multicast_v4_n(&MULTICAST_ADDR, &membership(ip, index)),
+        #[cfg(not(target_os = "windows"))]
+        IpAddr::V4(ip) => socket.leave_multicast_v4(&MULTICAST_ADDR, &ip),
+        IpAddr::V6(_) => socket.leave_multicast_v6(&MULTICAST_ADDR_V6, index),
+    }
+}
+
+// Where Windows joins the group: by adapter index, unless if_addrs had none for it.
+#[cfg(target_os = "windows")]
+fn membership(interface: Ipv4Addr, index: u32) -> socket2::InterfaceIndexOrAddress {
+    match index {
+        0 => socket2::InterfaceIndexOrAddress::Address(interface),
+        index => socket2::InterfaceIndexOrAddress::Index(index),
+    }
+}
+
+// socket2 only offers IP_MULTICAST_ALL on Linux proper, though Android kernels have it too.
+#[cfg(target_os = "android")]
+fn disable_multicast_all(
+    socket: &Socket,
+    level: libc::c_int,
+    option: libc::c_int,
+) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let off: libc::c_int = 0;
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            option,
+            &off as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+// Kernels before 5.7 only let privileged processes bind to a device.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn bind_device(socket: &Socket, device: &str) -> io::Result<()> {
+    socket.bind_device(Some(device.as_bytes())).map_err(|err| {
+        let context = if err.kind() == io::ErrorKind::PermissionDenied {
+            format!("binding to device {} needs CAP_NET_RAW or root", device)
+        } else {
+            format!("binding to device {}", device)
+        };
+
+        io::Error::new(err.kind(), format!("{}: {}", context, err))
+    })
+}
+
+#[cfg(all(
+    any(target_os = "windows", unix),
+    not(any(target_os = "linux", target_os = "android"))
+))]
+fn bind_device(_socket: &Socket, device: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("binding to device {} needs Linux or Android", device),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn bound_to_the_group_when_asked() {
+        let interface = Ipv4Addr::new(192, 0, 2, 1);
+
+        for device in [None, Some("eth0")] {
+            let bind = |bind_group| {
+                let options = Options {
+                    device,
+                    receive_buffer: None,
+                    bind_group,
+                };
+                bind_address(interface, options)
+            };
+
+            assert_eq!(bind(false), Ipv4Addr::UNSPECIFIED);
+            assert_eq!(bind(true), MULTICAST_ADDR);
+        }
+    }
+
+    #[test]
+    fn receive_errors_classified() {
+        use io::ErrorKind::*;
+
+        for windows in [false, true] {
+            let of = |kind| ReceiveError::of_kind(kind, windows);
+
+            assert_eq!(of(Interrupted), ReceiveError::Interrupted);
+            assert_eq!(of(WouldBlock), ReceiveError::TimedOut);
+            assert_eq!(of(TimedOut), ReceiveError::TimedOut);
+            assert_eq!(of(NetworkDown), ReceiveError::Failed);
+            assert_eq!(of(PermissionDenied), ReceiveError::Failed);
+        }
+
+        // Only Windows reports an earlier send's ICMP error on a receive.
+        assert_eq!(
+            ReceiveError::of_kind(ConnectionReset, true),
+            ReceiveError::Interrupted
+        );
+        assert_eq!(
+            ReceiveError::of_kind(ConnectionReset, false),
+            ReceiveError::Failed
+        );
+        assert_eq!(
+            ReceiveError::of(&io::Error::from(ConnectionReset)),
+            ReceiveError::of_kind(ConnectionReset, cfg!(target_os = "windows"))
+        );
+    }
+}
@@ -0,0 +1,445 @@
+use crate::{
+    build_follow_up_query, build_mdns_query, build_reconfirm_query,
+    changes::Changes,
+    check_service_name,
+    daemon::{DISCOVERY_RATE_LIMIT, SOURCE_RATE_LIMIT},
+    database::Database,
+    dedup::Recent,
+    error_response,
+    events::Subscription,
+    limit::{Bucket, Sources},
+    lock::Lock,
+    matcher::{Filter, ServiceMatcher},
+    metrics::Metrics,
+    packet::Packet,
+    remove_old_entries,
+    scan::Scan,
+    schedule::QuerySchedule,
+    unresolved_hosts,
+    updates::Updates,
+    AddressFamily, MdnsClient, MdnsClientBuilder, MdnsStats, QueryCache, Service, ServiceRecord,
+};
+use std::{
+    collections::VecDeque,
+    error::Error,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+// Services that would expire within this long are asked after by name first, at most this
+// many per client at a time, so a mass expiry doesn't become one huge query.
+const RECONFIRM_AHEAD: Duration = Duration::from_secs(2);
+const RECONFIRM_LIMIT: usize = 16;
+
+// The question for the services about to expire that haven't been asked after yet, if any.
+// With `srv_only`, only their SRV records, as for the hosts the database doesn't resolve.
+pub(crate) fn reconfirm_query(
+    database: &mut Database,
+    family: AddressFamily,
+    srv_only: bool,
+    metrics: &Metrics,
+    now: Instant,
+) -> Option<Vec<u8>> {
+    let mut names = database.reconfirm(now, RECONFIRM_AHEAD, RECONFIRM_LIMIT);
+    let resolves = |host: &str| !srv_only && database.resolves(host);
+    names.retain(|(instance, host)| instance.is_some() || resolves(host));
+    if names.is_empty() {
+        return None;
+    }
+
+    debug!(
+        services = names.len(),
+        "reconfirming services about to expire"
+    );
+    metrics.reconfirmed(names.len());
+
+    let mut packet = Vec::new();
+    build_reconfirm_query(&mut packet, &names, family, resolves);
+    Some(packet)
+}
+
+// The question for the instances PTR answers pointed at without SRV answers following, if
+// any are due; at most this many at a time.
+const FOLLOW_UP_LIMIT: usize = 16;
+
+pub(crate) fn follow_up_query(
+    database: &mut Database,
+    metrics: &Metrics,
+    now: Instant,
+) -> Option<Vec<u8>> {
+    let instances = database.follow_ups(now, FOLLOW_UP_LIMIT);
+    if instances.is_empty() {
+        return None;
+    }
+
+    debug!(
+        instances = instances.len(),
+        "asking after instances only pointed at"
+    );
+    metrics.followed_up(instances.len());
+
+    let mut packet = Vec::new();
+    build_follow_up_query(&mut packet, &instances, !database.defers_resolution());
+    Some(packet)
+}
+
+// Queries due within this long of each other go out together; the socket backend packs
+// those of its questions into as few datagrams as fit.
+pub(crate) const COALESCE_WINDOW: Duration = Duration::from_millis(50);
+
+// When a question's next query is due, and the interval after that one.
+pub(crate) struct QueryTimer {
+    pub(crate) next: Instant,
+    pub(crate) interval: Duration,
+}
+
+impl QueryTimer {
+    fn new(schedule: &QuerySchedule, now: Instant) -> Self {
+        QueryTimer {
+            next: now,
+            interval: schedule.interval,
+        }
+    }
+
+    fn is_due(&self, now: Instant) -> bool {
+        now + COALESCE_WINDOW >= self.next
+    }
+
+    fn sent(&mut self, now: Instant, schedule: &QuerySchedule) {
+        self.next = now + self.interval;
+        self.interval = schedule.next(self.interval);
+    }
+
+    // When the worker should wake for it: with the refresh if that comes first or close
+    // enough to send it with.
+    pub(crate) fn wake(&self, refresh: Instant) -> Instant {
+        match self.next + COALESCE_WINDOW >= refresh {
+            true => refresh,
+            false => self.next,
+        }
+    }
+}
+
+/// The socket backend's protocol logic for one service type, without its sockets and
+/// threads, for programs that do their own I/O and keep their own time, such as
+/// simulations. Nothing happens on its own: every method takes the time it is called at,
+/// and the caller sends what `poll_transmit` returns, hands every datagram received to
+/// `handle_packet`, and calls `handle_timeout` once `poll_timeout` has passed.
+///
+/// Queries go to 224.0.0.251 port 5353, or FF02::FB, from port 5353. Datagrams are rate
+/// limited per source and copies of one received within a second are dropped, with the
+/// socket backend's defaults; queries aren't answered.
+pub struct MdnsState {
+    pub(crate) service: String,
+    matcher: Option<Arc<dyn ServiceMatcher>>,
+    pub(crate) srv_only: bool,
+    keep_records: bool,
+    pub(crate) family: AddressFamily,
+    pub(crate) database: Arc<Mutex<Database>>,
+    pub(crate) metrics: Arc<Metrics>,
+    pub(crate) changes: Arc<Changes>,
+    pub(crate) scan: Arc<Scan>,
+    query: Mutex<QueryCache>,
+    // Shared with the client's registration, for `MdnsClient::set_query_schedule`.
+    pub(crate) schedule: Arc<Mutex<QuerySchedule>>,
+    pub(crate) timer: Mutex<QueryTimer>,
+    // Services that may still be added this second.
+    discoveries: Mutex<Bucket>,
+    // Only for `handle_packet`: the socket backend's questions share the worker's.
+    sources: Sources,
+    recent: Recent,
+    outgoing: VecDeque<Vec<u8>>,
+}
+
+impl MdnsState {
+    /// Fails for a service type `MdnsClientBuilder::build` would also reject.
+    pub fn new(service: &str, family: AddressFamily) -> Result<MdnsState, Box<dyn Error>> {
+        check_service_name(service)?;
+        let builder = MdnsClient::builder(service.strip_suffix('.').unwrap_or(service))
+            .address_family(family);
+        let metrics = Arc::new(Metrics::new(Vec::new()));
+
+        Ok(MdnsState::with_options(
+            &builder,
+            Arc::default(),
+            metrics.clone(),
+            Arc::new(Changes::new(metrics)),
+            Arc::default(),
+            Arc::default(),
+            Instant::now(),
+        ))
+    }
+
+    // A question of the socket backend: its first query is due at once.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn with_options(
+        builder: &MdnsClientBuilder,
+        database: Arc<Mutex<Database>>,
+        metrics: Arc<Metrics>,
+        changes: Arc<Changes>,
+        scan: Arc<Scan>,
+        schedule: Arc<Mutex<QuerySchedule>>,
+        now: Instant,
+    ) -> MdnsState {
+        let timer = QueryTimer::new(&schedule.locked(), now);
+
+        MdnsState {
+            service: builder.service.clone(),
+            matcher: builder.matcher.clone(),
+            srv_only: builder.srv_only,
+            keep_records: builder.keep_records,
+            family: builder.family,
+            database,
+            metrics,
+            changes,
+            scan,
+            query: Mutex::default(),
+            schedule,
+            timer: Mutex::new(timer),
+            discoveries: Mutex::new(Bucket::new(
+                builder.discovery_rate_limit.unwrap_or(DISCOVERY_RATE_LIMIT),
+                now,
+            )),
+            sources: Sources::new(builder.source_rate_limit.unwrap_or(SOURCE_RATE_LIMIT)),
+            recent: Recent::default(),
+            outgoing: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn filter(&self) -> Filter<'_> {
+        Filter::new(
+            &self.service,
+            self.matcher.as_ref(),
+            self.srv_only,
+            self.keep_records,
+        )
+    }
+
+    // Hands the query to `send` if it is due as of `now`, or with `all` anyway, and starts
+    // the wait for the next one.
+    pub(crate) fn query(&self, now: Instant, all: bool, send: impl FnOnce(&[u8])) {
+        let mut timer = self.timer.locked();
+        if !all && !timer.is_due(now) {
+            return;
+        }
+
+        let schedule = *self.schedule.locked();
+        timer.sent(now, &schedule);
+        let mut query = self.query.locked();
+
+        // Read before building, so a change made meanwhile still rebuilds next time.
+        let generation = self.changes.generation();
+        let hosts = || match self.srv_only {
+            true => Vec::new(),
+            false => unresolved_hosts(&Database::lock(&self.database), self.family),
+        };
+        let packet = query.get(generation, hosts, |packet, hosts| {
+            build_mdns_query(
+                packet,
+                self.filter(),
+                hosts,
+                self.family,
+                schedule.unicast_response,
+            );
+        });
+        send(packet);
+    }
+
+    // The questions by name to send along as of `now`: for the services about to expire,
+    // then for the instances only pointed at.
+    pub(crate) fn questions_by_name(&self, now: Instant) -> Vec<Vec<u8>> {
+        let mut database = Database::lock(&self.database);
+        let reconfirm = reconfirm_query(
+            &mut database,
+            self.family,
+            self.srv_only,
+            &self.metrics,
+            now,
+        );
+        let follow_up = follow_up_query(&mut database, &self.metrics, now);
+
+        reconfirm.into_iter().chain(follow_up).collect()
+    }
+
+    // Applies the datagrams received on interface `interface` (0 if unknown), each with its
+    // source and when it was received, `None` for those that didn't parse. `updates` is
+    // only reused, so its buffers are.
+    pub(crate) fn receive<'a>(
+        &self,
+        packets: impl IntoIterator<Item = (Option<&'a Packet<'a>>, SocketAddr, Instant)>,
+        interface: u32,
+        updates: &mut Updates,
+        now: Instant,
+    ) {
+        updates.clear();
+
+        for (packet, from, received) in packets {
+            self.metrics.packet_received();
+
+            if packet.is_some_and(|packet| packet.skipped > 0) {
+                self.metrics.packet_salvaged();
+            }
+
+            match packet {
+                Some(packet) if error_response(packet) => self.metrics.error_response(),
+                Some(packet) => updates.gather(
+                    packet,
+                    from,
+                    interface,
+                    received,
+                    self.filter(),
+                    self.family,
+                ),
+                None => self.metrics.parse_failure(),
+            }
+        }
+
+        let mut discoveries = self.discoveries.locked();
+        let mut database = Database::lock(&self.database);
+        updates.apply(&mut database, &self.metrics, self.scan.last_query(), || {
+            discoveries.take(now)
+        });
+
+        self.metrics.database_size(database.len());
+        self.changes.update(database, now);
+    }
+
+    // Removes the services not heard from in time as of `now`; returns whether the last
+    // ones went.
+    pub(crate) fn expire(&self, now: Instant) -> bool {
+        remove_old_entries(&self.database, &self.metrics, &self.changes, now)
+    }
+
+    pub(crate) fn next_expiry(&self) -> Option<Instant> {
+        Database::lock(&self.database).next_expiry()
+    }
+
+    // Starts the schedule over, the first query due at `now`.
+    pub(crate) fn restart_queries(&self, now: Instant) {
+        *self.timer.locked() = QueryTimer::new(&self.schedule.locked(), now);
+    }
+
+    // The next query goes out as it was due, or a new interval from `now` if that is
+    // sooner, and the intervals after it follow `schedule`.
+    pub(crate) fn reschedule(&self, schedule: QuerySchedule, now: Instant) {
+        *self.schedule.locked() = schedule;
+
+        let mut timer = self.timer.locked();
+        timer.next = timer.next.min(now + schedule.interval);
+        timer.interval = schedule.interval;
+        // Built for the old schedule's QU bit.
+        *self.query.locked() = QueryCache::default();
+    }
+
+    /// The next datagram to send, if one is due: a query on the query schedule, the first
+    /// at once, each followed by a question for services about to expire and one for
+    /// instances only PTR answers have named, if there are any.
+    pub fn poll_transmit(&mut self, now: Instant) -> Option<Vec<u8>> {
+        if let Some(packet) = self.outgoing.pop_front() {
+            return Some(packet);
+        }
+
+        if self.scan.last_query().is_none() {
+            self.restart_queries(now);
+        }
+        let mut packets = Vec::new();
+        self.query(now, false, |packet| packets.push(packet.to_vec()));
+        if !packets.is_empty() {
+            self.scan.queried(now);
+        }
+
+        packets.extend(self.questions_by_name(now));
+        self.outgoing.extend(packets);
+        self.outgoing.pop_front()
+    }
+
+    /// Applies a datagram received from `source` at `now`, a link-local address in it taking
+    /// the scope of an IPv6 source. Fails if it doesn't parse.
+    pub fn handle_packet(
+        &mut self,
+        now: Instant,
+        data: &[u8],
+        source: SocketAddr,
+    ) -> Result<(), Box<dyn Error>> {
+        if !self.sources.allow(source.ip(), now) {
+            self.metrics.rate_limited(1);
+            return Ok(());
+        }
+        if !self.recent.insert(data, source, now) {
+            self.metrics.duplicates(1);
+            return Ok(());
+        }
+
+        let packet = Packet::parse(data);
+        match &packet {
+            Ok(packet) if packet.skipped > 0 => {
+                debug!(from = %source, skipped = packet.skipped, "skipped records that failed to parse");
+            }
+            Ok(_) => (),
+            Err(_err) => warn!(from = %source, error = %_err, "failed to parse packet"),
+        }
+
+        // Only an IPv6 source says which interface it arrived on.
+        let interface = match source {
+            SocketAddr::V6(source) => source.scope_id(),
+            SocketAddr::V4(_) => 0,
+        };
+        self.receive(
+            [(packet.as_ref().ok(), source, now)],
+            interface,
+            &mut Updates::default(),
+            now,
+        );
+
+        packet?;
+        Ok(())
+    }
+
+    /// When `poll_transmit` or `handle_timeout` next has something to do.
+    pub fn poll_timeout(&self, now: Instant) -> Instant {
+        let next_query = match self.scan.last_query() {
+            Some(_) => self.timer.locked().next,
+            None => now,
+        };
+
+        self.next_expiry()
+            .map_or(next_query, |at| at.min(next_query))
+    }
+
+    /// Removes the services not heard from for five seconds as of `now`.
+    pub fn handle_timeout(&mut self, now: Instant) {
+        self.expire(now);
+    }
+
+    /// Changes the query schedule as `MdnsClient::set_query_schedule` does, as of `now`.
+    pub fn set_query_schedule(
+        &mut self,
+        now: Instant,
+        schedule: QuerySchedule,
+    ) -> Result<(), Box<dyn Error>> {
+        schedule.check()?;
+        self.reschedule(schedule, now);
+        Ok(())
+    }
+
+    /// The services as of the last call, sorted by service.
+    pub fn services(&self) -> Vec<(Service, ServiceRecord)> {
+        let mut services: Vec<_> = Database::lock(&self.database)
+            .iter()
+            .map(|(service, record)| (service.clone(), record.clone()))
+            .collect();
+        services.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        services
+    }
+
+    /// As `MdnsClient::subscribe`, starting with the services not expired as of `now`; the
+    /// events are there as soon as the call that caused them returns.
+    pub fn subscribe(&self, now: Instant) -> Subscription {
+        self.changes.subscribe(crate::DEFAULT_EVENT_CAPACITY, now)
+    }
+
+    pub fn stats(&self) -> MdnsStats {
+        self.metrics.snapshot()
+    }
+}
@@ -0,0 +1,281 @@
+use crate::{
+    catch_panic,
+    changes::Changes,
+    database::{self, Database},
+    lock::Lock,
+    metrics::{FailedOperation, Metrics},
+    Service, ServiceRecord,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
+    sync::{Arc, Condvar, Mutex, PoisonError},
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+
+/// Where a client puts what it discovers, such as a map an application shares with other
+/// subsystems, kept in step as services come, change and go; see
+/// `MdnsClientBuilder::service_store`.
+///
+/// Called on a thread of the client's own, in the order of its events, with none of its
+/// locks held, so a call may use the client's methods; what they change reaches the store
+/// once the call returns. Changes to one service the store hasn't been told of yet are
+/// merged, as a subscription's events are, so a slow store only ends up told less often.
+/// The services the store holds when it is attached are taken up as if heard when they
+/// were last seen: they expire unless heard again, and their addresses count for their
+/// hosts, as any other service's.
+pub trait ServiceStore: Send + Sync {
+    /// A service discovered, or already known when the store was attached.
+    fn insert(&self, service: &Service, record: &ServiceRecord);
+
+    /// Its addresses or `conflicting` changed. Inserts again by default.
+    fn update(&self, service: &Service, record: &ServiceRecord) {
+        self.insert(service, record);
+    }
+
+    /// Heard from again with nothing else changed, so only `last_seen_time` moved.
+    /// Nothing by default.
+    fn refresh(&self, _service: &Service, _record: &ServiceRecord) {}
+
+    /// Expired, moved or forgotten, as with `ServiceEvent::Expired`.
+    fn remove(&self, service: &Service);
+
+    /// Calls `f` with every service held.
+    fn iterate(&self, f: &mut dyn FnMut(&Service, &ServiceRecord));
+}
+
+/// A store shared with the rest of the application.
+impl<S: ServiceStore + ?Sized> ServiceStore for Arc<S> {
+    fn insert(&self, service: &Service, record: &ServiceRecord) {
+        (**self).insert(service, record);
+    }
+
+    fn update(&self, service: &Service, record: &ServiceRecord) {
+        (**self).update(service, record);
+    }
+
+    fn refresh(&self, service: &Service, record: &ServiceRecord) {
+        (**self).refresh(service, record);
+    }
+
+    fn remove(&self, service: &Service) {
+        (**self).remove(service);
+    }
+
+    fn iterate(&self, f: &mut dyn FnMut(&Service, &ServiceRecord)) {
+        (**self).iterate(f);
+    }
+}
+
+/// A store in a map in memory, like the client's own: to share the services with other
+/// subsystems, pass an `Arc` of one to `MdnsClientBuilder::service_store` and keep a
+/// clone.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    services: Mutex<HashMap<Service, ServiceRecord>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+
+    pub fn get(&self, service: &Service) -> Option<ServiceRecord> {
+        self.services.locked().get(service).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.services.locked().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.services.locked().is_empty()
+    }
+}
+
+impl ServiceStore for MemoryStore {
+    fn insert(&self, service: &Service, record: &ServiceRecord) {
+        self.services
+            .locked()
+            .insert(service.clone(), record.clone());
+    }
+
+    fn refresh(&self, service: &Service, record: &ServiceRecord) {
+        if let Some(kept) = self.services.locked().get_mut(service) {
+            kept.last_seen_time = record.last_seen_time;
+        }
+    }
+
+    fn remove(&self, service: &Service) {
+        self.services.locked().remove(service);
+    }
+
+    // On a copy, so `f` may use the store.
+    fn iterate(&self, f: &mut dyn FnMut(&Service, &ServiceRecord)) {
+        let services = self.services.locked().clone();
+        for (service, record) in &services {
+            f(service, record);
+        }
+    }
+}
+
+// What a store is to be told of a service, queued under `Changes`' lock in the order of
+// the events.
+pub(crate) enum Stored {
+    Insert(ServiceRecord),
+    Update(ServiceRecord),
+    Refresh(ServiceRecord),
+    Remove,
+}
+
+// The client's store, told what changed on a thread of its own, so it is called with none
+// of the client's locks held wherever the change was made, and may call the client.
+pub(crate) struct Mirror {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct Shared {
+    store: Arc<dyn ServiceStore>,
+    pending: Mutex<Pending>,
+    ready: Condvar,
+}
+
+// Not told yet, merged per service like a subscription's events, as the store only needs
+// to end up holding what the client does.
+#[derive(Default)]
+struct Pending {
+    order: VecDeque<Service>,
+    stored: HashMap<Service, Stored>,
+    stop: bool,
+}
+
+impl Mirror {
+    pub(crate) fn start(store: Arc<dyn ServiceStore>, metrics: Arc<Metrics>) -> Mirror {
+        let shared = Arc::new(Shared {
+            store,
+            pending: Mutex::default(),
+            ready: Condvar::new(),
+        });
+
+        let thread = thread::spawn({
+            let shared = shared.clone();
+
+            move || {
+                catch_panic(
+                    || shared.run(),
+                    |message| metrics.error(FailedOperation::Panic, None, &message),
+                );
+            }
+        });
+
+        Mirror {
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    pub(crate) fn queue(&self, stored: impl IntoIterator<Item = (Service, Stored)>) {
+        let mut pending = self.shared.pending.locked();
+
+        for (service, stored) in stored {
+            let stored = match (pending.stored.remove(&service), stored) {
+                (Some(Stored::Insert(_)), Stored::Update(record) | Stored::Refresh(record)) => {
+                    Stored::Insert(record)
+                }
+                (Some(Stored::Update(_)), Stored::Refresh(record)) => Stored::Update(record),
+                (Some(_), stored) => stored,
+                (None, stored) => {
+                    pending.order.push_back(service.clone());
+                    stored
+                }
+            };
+            pending.stored.insert(service, stored);
+        }
+
+        self.shared.ready.notify_one();
+    }
+}
+
+// What is still queued is told before the thread ends.
+impl Drop for Mirror {
+    fn drop(&mut self) {
+        self.shared.pending.locked().stop = true;
+        self.shared.ready.notify_one();
+
+        if let Some(thread) = self.thread.take() {
+            // Not when the store itself dropped the client.
+            if thread.thread().id() != thread::current().id() {
+                thread.join().ok();
+            }
+        }
+    }
+}
+
+impl Shared {
+    fn run(&self) {
+        loop {
+            let (service, stored) = {
+                let mut pending = self.pending.locked();
+                loop {
+                    if let Some(service) = pending.order.pop_front() {
+                        if let Some(stored) = pending.stored.remove(&service) {
+                            break (service, stored);
+                        }
+                    } else if pending.stop {
+                        return;
+                    } else {
+                        pending = self
+                            .ready
+                            .wait(pending)
+                            .unwrap_or_else(PoisonError::into_inner);
+                    }
+                }
+            };
+
+            debug_assert!(!database::held(), "store called with a database locked");
+            match stored {
+                Stored::Insert(record) => self.store.insert(&service, &record),
+                Stored::Update(record) => self.store.update(&service, &record),
+                Stored::Refresh(record) => self.store.refresh(&service, &record),
+                Stored::Remove => self.store.remove(&service),
+            }
+        }
+    }
+}
+
+// Takes up the services `store` holds that `database` doesn't, as heard when they were
+// last seen, with their addresses noted for their hosts. The store is read before the
+// database is locked.
+pub(crate) fn load(store: &dyn ServiceStore, database: &Mutex<Database>, changes: &Changes) {
+    let mut held = Vec::new();
+    store.iterate(&mut |service, record| held.push((service.clone(), record.clone())));
+
+    let now = Instant::now();
+    let mut database = Database::lock(database);
+    for (service, mut record) in held {
+        if database.contains_key(&service) {
+            continue;
+        }
+        record.last_seen_time = record.last_seen_time.min(now);
+        record.conflicting = false;
+
+        let addresses: Vec<IpAddr> = record
+            .addresses
+            .iter()
+            .map(|&address| address.into())
+            .chain(
+                record
+                    .ipv6_addresses
+                    .iter()
+                    .map(|address| address.ip.into()),
+            )
+            .collect();
+        database.get_or_insert_with(service.clone(), || record);
+        for address in addresses {
+            database.host_address(&service.host, address);
+        }
+    }
+    changes.update(database, now);
+}
@@ -0,0 +1,73 @@
+use std::{error::Error, net::IpAddr};
+
+// An address range such as `192.168.1.0/24` or `fd00::/8`; a bare address is just itself.
+#[derive(Clone, Copy, Debug)]
+struct Subnet {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Subnet {
+    fn parse(range: &str) -> Result<Subnet, Box<dyn Error>> {
+        let invalid = || format!("invalid address range {:?}", range);
+
+        let (network, prefix) = match range.split_once('/') {
+            Some((network, prefix)) => (network, Some(prefix)),
+            None => (range, None),
+        };
+        let network: IpAddr = network.trim().parse().map_err(|_| invalid())?;
+        let bits = match network {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse().map_err(|_| invalid())?,
+            None => bits,
+        };
+        if prefix > bits {
+            return Err(invalid().into());
+        }
+
+        Ok(Subnet { network, prefix })
+    }
+
+    fn contains(&self, address: IpAddr) -> bool {
+        match (self.network, address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+// The source addresses datagrams are taken from: any in `allowed`, or every one if that is
+// empty, except those in `denied`. Checked before anything else is done with them.
+#[derive(Debug)]
+pub(crate) struct SourceFilter {
+    allowed: Vec<Subnet>,
+    denied: Vec<Subnet>,
+}
+
+impl SourceFilter {
+    pub(crate) fn new(allowed: &[String], denied: &[String]) -> Result<Self, Box<dyn Error>> {
+        let parse = |ranges: &[String]| -> Result<Vec<Subnet>, Box<dyn Error>> {
+            ranges.iter().map(|range| Subnet::parse(range)).collect()
+        };
+
+        Ok(SourceFilter {
+            allowed: parse(allowed)?,
+            denied: parse(denied)?,
+        })
+    }
+
+    pub(crate) fn accepts(&self, source: IpAddr) -> bool {
+        (self.allowed.is_empty() || self.allowed.iter().any(|s| s.contains(source)))
+            && !self.denied.iter().any(|s| s.contains(source))
+    }
+}
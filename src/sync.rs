@@ -0,0 +1,15 @@
+// The primitives the state shared between the workers, readers and subscribers is built
+// on: `Changes`' publication and generation, and the event queues. They are loom's under
+// `cfg(loom)`, so the models in `models.rs` can try every interleaving of the threads using
+// them: `RUSTFLAGS="--cfg loom" cargo test --release --lib models`. `Arc` and `Weak` stay
+// std's, as loom has no `Weak`.
+#[cfg(loom)]
+pub(crate) use loom::sync::{
+    atomic::{AtomicU64, Ordering},
+    Condvar, Mutex,
+};
+#[cfg(not(loom))]
+pub(crate) use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Condvar, Mutex,
+};
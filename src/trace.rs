@@ -0,0 +1,187 @@
+// Thin wrappers around `tracing` so call sites don't need their own cfg. With the
+// feature disabled the arguments are not evaluated and the macros expand to nothing.
+
+#[cfg(feature = "tracing")]
+macro_rules! debug {
+    ($($arg:tt)*) => { tracing::debug!($($arg)*) };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        ()
+    };
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! info {
+    ($($arg:tt)*) => { tracing::info!($($arg)*) };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        ()
+    };
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! warn {
+    ($($arg:tt)*) => { tracing::warn!($($arg)*) };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        ()
+    };
+}
+
+// Evaluates to a guard that keeps the span entered until it is dropped.
+#[cfg(feature = "tracing")]
+macro_rules! span {
+    ($($arg:tt)*) => { tracing::debug_span!($($arg)*).entered() };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! span {
+    ($($arg:tt)*) => {
+        ()
+    };
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use crate::{
+        changes::Changes,
+        database::Database,
+        matcher::Filter,
+        metrics::Metrics,
+        responder::{Advert, Responder},
+        AddressFamily,
+    };
+    use std::{
+        fmt::Debug,
+        net::{Ipv4Addr, SocketAddr, UdpSocket},
+        sync::{Arc, Mutex},
+        time::Instant,
+    };
+    use tracing::{
+        field::{Field, Visit},
+        Event, Level, Subscriber,
+    };
+    use tracing_subscriber::{layer::Context, prelude::*, Layer};
+
+    // Every event.
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<(Level, Fields)>>>);
+
+    #[derive(Clone, Default)]
+    struct Fields {
+        message: String,
+        fields: Vec<(String, String)>,
+    }
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            if field.name() == "message" {
+                self.message = format!("{:?}", value);
+            } else {
+                self.fields
+                    .push((field.name().to_string(), format!("{:?}", value)));
+            }
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for Capture {
+        fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+            let mut fields = Fields::default();
+            event.record(&mut fields);
+            self.0
+                .lock()
+                .unwrap()
+                .push((*event.metadata().level(), fields));
+        }
+    }
+
+    impl Capture {
+        fn find(&self, level: Level, message: &str) -> Option<Vec<(String, String)>> {
+            self.0
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|(l, fields)| *l == level && fields.message == message)
+                .map(|(_, fields)| fields.fields.clone())
+        }
+    }
+
+    fn capture(run: impl FnOnce()) -> Capture {
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::registry().with(capture.clone());
+        tracing::subscriber::with_default(subscriber, run);
+        capture
+    }
+
+    fn announcement() -> Vec<u8> {
+        let mut responder = Responder::default();
+        responder.set_addresses(vec![Ipv4Addr::new(192, 0, 2, 7)]);
+        responder.add(Advert::new("Printer", "_ipp._tcp.local", 631, &[]).unwrap());
+        responder.due(Instant::now()).remove(0)
+    }
+
+    fn process(data: &[u8], from: SocketAddr) {
+        let metrics = Arc::new(Metrics::new(Vec::new()));
+        let changes = Changes::new(metrics.clone());
+        crate::process_packet(
+            data,
+            from,
+            Filter::everything(),
+            AddressFamily::V4,
+            &Mutex::new(Database::default()),
+            &metrics,
+            &changes,
+            Instant::now(),
+            None,
+        )
+        .ok();
+    }
+
+    #[test]
+    fn answers_applied() {
+        let from: SocketAddr = "192.0.2.7:5353".parse().unwrap();
+        let data = announcement();
+        let capture = capture(|| process(&data, from));
+
+        let fields = capture
+            .find(Level::DEBUG, "service discovered")
+            .expect("no event for the service");
+        assert!(fields.contains(&("port".to_string(), "631".to_string())));
+        assert!(fields.contains(&("from".to_string(), from.to_string())));
+        assert!(capture.find(Level::DEBUG, "address applied").is_some());
+    }
+
+    #[test]
+    fn parse_failure() {
+        let from: SocketAddr = "192.0.2.8:5353".parse().unwrap();
+        let capture = capture(|| process(&[0, 1, 2], from));
+
+        let fields = capture
+            .find(Level::WARN, "failed to parse packet")
+            .expect("no event for the parse failure");
+        assert!(fields.contains(&("from".to_string(), from.to_string())));
+    }
+
+    #[test]
+    fn query_sent() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let to = socket.local_addr().unwrap();
+        let capture = capture(|| {
+            crate::send_mdns_query(&socket, to, &[0; 12], "_ipp._tcp.local", None).unwrap()
+        });
+
+        let fields = capture
+            .find(Level::DEBUG, "query sent")
+            .expect("no event for the query");
+        assert!(fields.contains(&("service".to_string(), "\"_ipp._tcp.local\"".to_string())));
+    }
+}
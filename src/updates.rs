@@ -0,0 +1,452 @@
+use crate::{
+    availability::History,
+    database::Database,
+    matcher::{Filter, ServiceName},
+    metrics::Metrics,
+    packet::{Name, Packet, RData, ResourceRecord, TYPE_TXT},
+    record::{self, OwnedRecord},
+    AddressFamily, Latency, Provenance, ScopedIpv6Addr, Service, ServiceRecord,
+};
+use std::{
+    collections::HashSet,
+    fmt::Write,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    ops::Range,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+// An SRV answer counts as answering the last query if it arrives within this long of it:
+// responders may delay answers by up to 500 ms (RFC 6762, section 6).
+const ANSWER_WINDOW: Duration = Duration::from_millis(500);
+
+// What responses say about one question's services, gathered before its database is
+// locked so that the lock is only held for applying it. Kept from batch to batch: the
+// names share one buffer, so once that has grown nothing is allocated.
+#[derive(Default)]
+pub(crate) struct Updates {
+    names: String,
+    updates: Vec<Update>,
+    // Where the updates of each response gathered start, and when it was received.
+    received: Vec<(usize, Instant)>,
+}
+
+// Instance and host names are ranges of `Updates::names`.
+enum Update {
+    Service {
+        instance: Range<usize>,
+        host: Range<usize>,
+        port: u16,
+        // The cache-flush bit.
+        unique: bool,
+        // A TTL of zero.
+        goodbye: bool,
+        from: SocketAddr,
+    },
+    Address {
+        host: Range<usize>,
+        address: IpAddr,
+        unique: bool,
+        goodbye: bool,
+        // In the same response as an SRV record naming the host.
+        tied: bool,
+        from: SocketAddr,
+        // The index of the interface it was heard on, for a link-local address's scope.
+        interface: u32,
+    },
+    // An instance a PTR record points at, asked after by name unless an SRV record places it.
+    Pointer {
+        instance: Range<usize>,
+    },
+    // Kept for the services of the instance `name`, or of the host for an address.
+    Record {
+        name: Range<usize>,
+        address: Option<IpAddr>,
+        record: OwnedRecord,
+        from: SocketAddr,
+    },
+}
+
+impl Updates {
+    pub(crate) fn clear(&mut self) {
+        self.names.clear();
+        self.updates.clear();
+        self.received.clear();
+    }
+
+    fn name(&mut self, name: &Name) -> Range<usize> {
+        let start = self.names.len();
+        write!(self.names, "{}", name).unwrap();
+        start..self.names.len()
+    }
+
+    // As `normalize_host`, in place.
+    fn host(&mut self, name: &Name) -> Range<usize> {
+        let mut host = self.name(name);
+
+        if self.names.ends_with('.') {
+            self.names.pop();
+            host.end -= 1;
+        }
+
+        self.names[host.clone()].make_ascii_lowercase();
+        host
+    }
+
+    // A response's SRV records `filter` matches and the instances its PTR records point at,
+    // then its address records of the families in use, so an address for a service
+    // announced alongside it finds the service. `interface` is the index of the one it
+    // arrived on, 0 if unknown, and `received` when.
+    pub(crate) fn gather(
+        &mut self,
+        packet: &Packet,
+        from: SocketAddr,
+        interface: u32,
+        received: Instant,
+        filter: Filter,
+        family: AddressFamily,
+    ) {
+        if packet.header.query {
+            return;
+        }
+
+        let first = self.updates.len();
+        self.received.push((first, received));
+
+        for answer in &packet.answers {
+            if let ResourceRecord {
+                name: owner,
+                multicast_unique,
+                ttl,
+                data: RData::Srv { target, port },
+                ..
+            } = answer
+            {
+                let owner = self.name(owner);
+
+                if !filter.matches(&self.names[owner.clone()]) {
+                    self.names.truncate(owner.start);
+                    continue;
+                }
+
+                self.names[owner.clone()].make_ascii_lowercase();
+                let host = self.host(target);
+                self.updates.push(Update::Service {
+                    instance: owner,
+                    host,
+                    port: *port,
+                    unique: *multicast_unique,
+                    goodbye: *ttl == 0,
+                    from,
+                });
+            }
+        }
+
+        self.gather_pointers(packet, filter);
+        if !filter.srv_only {
+            self.gather_addresses(packet, from, interface, family, first);
+        }
+        if filter.records {
+            self.gather_records(packet, from, filter, family);
+        }
+    }
+
+    // The instances `filter` matches that PTR records point at; some responders answer a
+    // PTR question with that alone. Service types enumerated have no instance label.
+    fn gather_pointers(&mut self, packet: &Packet, filter: Filter) {
+        for answer in &packet.answers {
+            let Some(target) = answer.ptr_target() else {
+                continue;
+            };
+
+            let instance = self.name(&target);
+            let name = &self.names[instance.clone()];
+            if ServiceName::new(name).instance().is_empty() || !filter.matches(name) {
+                self.names.truncate(instance.start);
+                continue;
+            }
+            self.updates.push(Update::Pointer { instance });
+        }
+    }
+
+    // The records behind the services, as they are: SRV and TXT records for the instance
+    // they are named after, PTR records for the one they point at, address records for
+    // their host's services.
+    fn gather_records(
+        &mut self,
+        packet: &Packet,
+        from: SocketAddr,
+        filter: Filter,
+        family: AddressFamily,
+    ) {
+        for answer in &packet.answers {
+            let (name, address) = match answer.data {
+                RData::Srv { .. } => (answer.name, None),
+                RData::A(address) if family.v4() && !filter.srv_only => {
+                    (answer.name, Some(address.into()))
+                }
+                RData::Aaaa(address) if family.v6() && !filter.srv_only => {
+                    (answer.name, Some(address.into()))
+                }
+                _ if answer.kind == TYPE_TXT => (answer.name, None),
+                _ => match answer.ptr_target() {
+                    Some(target) => (target, None),
+                    None => continue,
+                },
+            };
+
+            let name = match address {
+                Some(_) => self.host(&name),
+                None => {
+                    let instance = self.name(&name);
+                    if !filter.matches(&self.names[instance.clone()]) {
+                        self.names.truncate(instance.start);
+                        continue;
+                    }
+                    instance
+                }
+            };
+
+            self.updates.push(Update::Record {
+                name,
+                address,
+                record: OwnedRecord::new(answer),
+                from,
+            });
+        }
+    }
+
+    // Addresses are matched to services by host name, whichever family the answer arrived
+    // over. Those from `first` on are this response's.
+    fn gather_addresses(
+        &mut self,
+        packet: &Packet,
+        from: SocketAddr,
+        interface: u32,
+        family: AddressFamily,
+        first: usize,
+    ) {
+        for answer in &packet.answers {
+            let address: IpAddr = match &answer.data {
+                RData::A(address) if family.v4() => (*address).into(),
+                RData::Aaaa(address) if family.v6() => (*address).into(),
+                _ => continue,
+            };
+
+            let host = self.host(&answer.name);
+            let tied = self.updates[first..].iter().any(|update| match update {
+                Update::Service { host: target, .. } => {
+                    self.names[target.clone()] == self.names[host.clone()]
+                }
+                Update::Address { .. } | Update::Pointer { .. } | Update::Record { .. } => false,
+            });
+
+            self.updates.push(Update::Address {
+                host,
+                address,
+                unique: answer.multicast_unique,
+                goodbye: answer.ttl == 0,
+                tied,
+                from,
+                interface,
+            });
+        }
+    }
+
+    // A `String` is only allocated for a service or instance seen for the first time, and only if
+    // `admit` lets it in. What is applied counts as heard when its response was received;
+    // `queried` is when the last query went out, if one has.
+    pub(crate) fn apply(
+        &self,
+        database: &mut Database,
+        metrics: &Metrics,
+        queried: Option<Instant>,
+        mut admit: impl FnMut() -> bool,
+    ) {
+        for (i, update) in self.updates.iter().enumerate() {
+            // The last response whose updates start at or before this one is its own.
+            let now = self.received[self.received.partition_point(|&(first, _)| first <= i) - 1].1;
+            let latency = queried
+                .and_then(|at| now.checked_duration_since(at))
+                .filter(|latency| *latency <= ANSWER_WINDOW);
+
+            match update {
+                Update::Service {
+                    instance,
+                    host,
+                    port,
+                    unique,
+                    goodbye,
+                    from,
+                } => {
+                    let instance = &self.names[instance.clone()];
+                    let host = &self.names[host.clone()];
+                    metrics.answer_applied();
+                    if let Some(latency) = latency {
+                        metrics.answer_timed(latency);
+                    }
+
+                    let mut known = None;
+                    let mut withdrawn = false;
+                    database.for_host(host, |s, record| {
+                        if *s.host == *host && s.port == *port {
+                            // Kept, but no longer as heard.
+                            if *goodbye && record.pinned {
+                                record.withdrawn = true;
+                                withdrawn = true;
+                                return;
+                            }
+
+                            record.withdrawn = false;
+                            // Another socket's thread may have applied a later response.
+                            record.last_seen_time = record.last_seen_time.max(now);
+                            if let Some(latency) = latency {
+                                match &mut record.latency {
+                                    Some(timed) => timed.add(latency),
+                                    None => record.latency = Some(Latency::new(latency)),
+                                }
+                            }
+                            record.provenance.heard(latency.is_some());
+                            known = Some(s.clone());
+                        }
+                    });
+
+                    if withdrawn {
+                        continue;
+                    }
+                    if let Some(service) = known {
+                        database.instance_at(instance, &service, Some(from.ip()), now);
+                        if *goodbye {
+                            database.instance_gone(instance, from.ip(), now);
+                        }
+                        if *unique {
+                            database.flush_instance(instance, from.ip(), now);
+                        }
+                        continue;
+                    }
+
+                    if !admit() {
+                        metrics.discovery_limited();
+                        continue;
+                    }
+
+                    debug!(%host, port, %from, "service discovered");
+
+                    let service = Service {
+                        host: host.into(),
+                        port: *port,
+                    };
+
+                    // Some embedded stacks put their address itself in the SRV target,
+                    // which no A record will match.
+                    let literal = host.parse::<Ipv4Addr>().ok();
+
+                    database.get_or_insert_with(service.clone(), || ServiceRecord {
+                        last_seen_time: now,
+                        preferred_address: literal.filter(|a| from.ip() == IpAddr::V4(*a)),
+                        addresses: literal.into_iter().collect(),
+                        ipv6_addresses: HashSet::new(),
+                        conflicting: false,
+                        latency: latency.map(Latency::new),
+                        provenance: Provenance::first(latency.is_some()),
+                        pinned: false,
+                        withdrawn: false,
+                        seeded: false,
+                        mac_address: None,
+                        records: Arc::default(),
+                        history: History::default(),
+                    });
+                    database.instance_at(instance, &service, Some(from.ip()), now);
+                    if *unique {
+                        database.flush_instance(instance, from.ip(), now);
+                    }
+                }
+                Update::Address {
+                    host,
+                    address,
+                    unique,
+                    goodbye,
+                    tied,
+                    from,
+                    interface,
+                } => {
+                    let host = &self.names[host.clone()];
+
+                    // Not asked for, but answered with the SRV record all the same.
+                    if !database.resolves(host) {
+                        continue;
+                    }
+                    if !tied && !database.admits_address(host, *address, from.ip(), *unique) {
+                        metrics.address_rejected();
+                        debug!(%host, %address, %from, "address from an unrelated responder rejected");
+                        continue;
+                    }
+
+                    if let Some(_evicted) = database.host_address(host, *address) {
+                        metrics.address_evicted();
+                        debug!(%host, evicted = %_evicted, "too many addresses, oldest dropped");
+                    }
+
+                    // Hearing the host's address is as good as hearing its services: some
+                    // responders announce it far more often than their SRV records.
+                    database.for_host(host, |_k, v| {
+                        metrics.answer_applied();
+                        if !(*goodbye && v.pinned) {
+                            v.last_seen_time = v.last_seen_time.max(now);
+                        }
+
+                        let inserted = match *address {
+                            IpAddr::V4(address) => {
+                                if from.ip() == IpAddr::V4(address) {
+                                    v.preferred_address = Some(address);
+                                }
+
+                                v.addresses.insert(address)
+                            }
+                            IpAddr::V6(address) => v
+                                .ipv6_addresses
+                                .insert(ScopedIpv6Addr::heard(address, *interface)),
+                        };
+
+                        if inserted {
+                            debug!(host = %_k.host, %address, %from, "address applied");
+                        }
+                    });
+                }
+                Update::Pointer { instance } => {
+                    database.pointed_at(&self.names[instance.clone()], now);
+                }
+                Update::Record {
+                    name,
+                    address,
+                    record,
+                    from,
+                } => {
+                    let name = &self.names[name.clone()];
+
+                    match address {
+                        // Only where the address was taken.
+                        Some(address) => database.for_host(name, |_, v| {
+                            let holds = match address {
+                                IpAddr::V4(address) => v.addresses.contains(address),
+                                IpAddr::V6(address) => {
+                                    v.ipv6_addresses.iter().any(|held| held.ip == *address)
+                                }
+                            };
+                            if holds {
+                                record::keep(&mut v.records, record);
+                            }
+                        }),
+                        None if record.record_type == TYPE_TXT
+                            && !database
+                                .instance_service(name)
+                                .is_some_and(|service| database.resolves(&service.host)) => {}
+                        None => database.for_instance(name, from.ip(), |_, v| {
+                            record::keep(&mut v.records, record)
+                        }),
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,460 @@
+use crate::{
+    availability::History,
+    catch_panic,
+    changes::Changes,
+    database::{normalize_host, Database},
+    metrics::{FailedOperation, Metrics},
+    remove_old_entries, split_service_type, AddressFamily, Backend, Command, MdnsClient,
+    Provenance, Service, ServiceRecord,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    ffi::c_void,
+    mem,
+    net::Ipv4Addr,
+    sync::{
+        mpsc::{channel, sync_channel, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex, OnceLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use windows::{
+    core::{s, w, PCWSTR, PWSTR},
+    Win32::{
+        Foundation::{DNS_REQUEST_PENDING, ERROR_CANCELLED, ERROR_SUCCESS},
+        NetworkManagement::Dns::{
+            DnsFree, DnsFreeRecordList, DNS_QUERY_REQUEST_VERSION1, DNS_RECORDW,
+            DNS_SERVICE_BROWSE_REQUEST, DNS_SERVICE_BROWSE_REQUEST_0, DNS_SERVICE_CANCEL,
+            DNS_SERVICE_INSTANCE, DNS_SERVICE_RESOLVE_REQUEST, DNS_TYPE_PTR,
+        },
+        System::LibraryLoader::{GetProcAddress, LoadLibraryW},
+    },
+};
+
+// Same cadence as the Avahi backend: pick up callbacks quickly, refresh once a second.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+type BrowseFn =
+    unsafe extern "system" fn(*const DNS_SERVICE_BROWSE_REQUEST, *mut DNS_SERVICE_CANCEL) -> i32;
+type ResolveFn =
+    unsafe extern "system" fn(*const DNS_SERVICE_RESOLVE_REQUEST, *mut DNS_SERVICE_CANCEL) -> i32;
+type CancelFn = unsafe extern "system" fn(*const DNS_SERVICE_CANCEL) -> i32;
+type FreeInstanceFn = unsafe extern "system" fn(*const DNS_SERVICE_INSTANCE);
+
+// The DNS-SD functions only exist on Windows 10 and later, so they are looked up at
+// runtime rather than imported; older systems then fall back to the socket backend.
+struct DnsApi {
+    browse: BrowseFn,
+    browse_cancel: CancelFn,
+    resolve: ResolveFn,
+    resolve_cancel: CancelFn,
+    free_instance: FreeInstanceFn,
+}
+
+fn api() -> Option<&'static DnsApi> {
+    static API: OnceLock<Option<DnsApi>> = OnceLock::new();
+
+    API.get_or_init(|| unsafe {
+        let module = LoadLibraryW(w!("dnsapi.dll")).ok()?;
+
+        Some(DnsApi {
+            browse: mem::transmute::<unsafe extern "system" fn() -> isize, BrowseFn>(
+                GetProcAddress(module, s!("DnsServiceBrowse"))?,
+            ),
+            browse_cancel: mem::transmute::<unsafe extern "system" fn() -> isize, CancelFn>(
+                GetProcAddress(module, s!("DnsServiceBrowseCancel"))?,
+            ),
+            resolve: mem::transmute::<unsafe extern "system" fn() -> isize, ResolveFn>(
+                GetProcAddress(module, s!("DnsServiceResolve"))?,
+            ),
+            resolve_cancel: mem::transmute::<unsafe extern "system" fn() -> isize, CancelFn>(
+                GetProcAddress(module, s!("DnsServiceResolveCancel"))?,
+            ),
+            free_instance: mem::transmute::<unsafe extern "system" fn() -> isize, FreeInstanceFn>(
+                GetProcAddress(module, s!("DnsServiceFreeInstance"))?,
+            ),
+        })
+    })
+    .as_ref()
+}
+
+pub(crate) fn is_available() -> bool {
+    api().is_some()
+}
+
+enum Event {
+    Found(String),
+    Lost(String),
+    Resolved(String, Option<(Service, Option<Ipv4Addr>)>),
+}
+
+// Owned by the browse callback, which frees it when it is told the browse was cancelled.
+struct BrowseContext {
+    event_tx: Sender<Event>,
+}
+
+// Owned by the resolve callback, which runs exactly once per request.
+struct ResolveContext {
+    event_tx: Sender<Event>,
+    instance: String,
+    // DnsServiceResolve takes a mutable pointer, so the name lives here until completion.
+    _query_name: Vec<u16>,
+}
+
+fn wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(Some(0)).collect()
+}
+
+fn name(s: PWSTR) -> Option<String> {
+    if s.is_null() {
+        return None;
+    }
+
+    unsafe { s.to_string() }
+        .ok()
+        .map(|s| s.trim_end_matches('.').to_string())
+}
+
+unsafe extern "system" fn browse_callback(
+    status: u32,
+    context: *const c_void,
+    records: *const DNS_RECORDW,
+) {
+    if status == ERROR_CANCELLED.0 {
+        drop(Box::from_raw(context as *mut BrowseContext));
+        return;
+    }
+
+    let context = &*(context as *const BrowseContext);
+
+    if status == ERROR_SUCCESS.0 {
+        let mut record = records;
+
+        while let Some(r) = record.as_ref() {
+            if r.wType == DNS_TYPE_PTR.0 {
+                if let Some(instance) = name(r.Data.PTR.pNameHost) {
+                    // A zero TTL is a goodbye from the responder.
+                    let event = if r.dwTtl == 0 {
+                        Event::Lost(instance)
+                    } else {
+                        Event::Found(instance)
+                    };
+
+                    context.event_tx.send(event).ok();
+                }
+            }
+
+            record = r.pNext;
+        }
+    }
+
+    if !records.is_null() {
+        DnsFree(Some(records as *const c_void), DnsFreeRecordList);
+    }
+}
+
+unsafe extern "system" fn resolve_callback(
+    status: u32,
+    context: *const c_void,
+    instance: *const DNS_SERVICE_INSTANCE,
+) {
+    let context = Box::from_raw(context as *mut ResolveContext);
+
+    let resolved = instance
+        .as_ref()
+        .filter(|_| status == ERROR_SUCCESS.0)
+        .and_then(|i| {
+            let host = name(i.pszHostName)?;
+            let address = i
+                .ip4Address
+                .as_ref()
+                .map(|addr| Ipv4Addr::from(addr.to_ne_bytes()));
+
+            Some((
+                Service {
+                    host: normalize_host(&host).into(),
+                    port: i.wPort,
+                },
+                address,
+            ))
+        });
+
+    context
+        .event_tx
+        .send(Event::Resolved(context.instance.clone(), resolved))
+        .ok();
+
+    if !instance.is_null() {
+        if let Some(api) = api() {
+            (api.free_instance)(instance);
+        }
+    }
+}
+
+struct Browse {
+    api: &'static DnsApi,
+    cancel: Box<DNS_SERVICE_CANCEL>,
+    _query_name: Vec<u16>,
+}
+
+// Raw handles only, used from the worker thread that owns them.
+unsafe impl Send for Browse {}
+
+impl Drop for Browse {
+    fn drop(&mut self) {
+        unsafe { (self.api.browse_cancel)(&*self.cancel) };
+    }
+}
+
+pub(crate) fn spawn(service: &str) -> Result<MdnsClient, Box<dyn Error>> {
+    let api = api().ok_or("DNS-SD APIs need Windows 10 or later")?;
+
+    let (kind, domain) = split_service_type(service)
+        .ok_or_else(|| format!("no service type such as `_http._tcp` in {:?}", service))?;
+    let query_name = wide(&format!("{}.{}", kind, domain));
+
+    let (event_tx, event_rx) = channel();
+
+    let context = Box::into_raw(Box::new(BrowseContext {
+        event_tx: event_tx.clone(),
+    }));
+
+    let request = DNS_SERVICE_BROWSE_REQUEST {
+        Version: DNS_QUERY_REQUEST_VERSION1.0,
+        InterfaceIndex: 0,
+        QueryName: PCWSTR(query_name.as_ptr()),
+        Anonymous: DNS_SERVICE_BROWSE_REQUEST_0 {
+            pBrowseCallback: Some(browse_callback),
+        },
+        pQueryContext: context as *mut c_void,
+    };
+
+    let mut cancel = Box::new(DNS_SERVICE_CANCEL {
+        reserved: std::ptr::null_mut(),
+    });
+
+    let status = unsafe { (api.browse)(&request, &mut *cancel) };
+    if status != DNS_REQUEST_PENDING {
+        drop(unsafe { Box::from_raw(context) });
+        return Err(format!("DnsServiceBrowse failed with status {}", status).into());
+    }
+
+    let browse = Browse {
+        api,
+        cancel,
+        _query_name: query_name,
+    };
+
+    let database = Arc::new(Mutex::new(Database::default()));
+    let metrics = Arc::new(Metrics::new(Vec::new()));
+    let changes = Arc::new(Changes::new(metrics.clone()));
+
+    let (command_tx, command_rx) = sync_channel(1);
+
+    let thread = thread::spawn({
+        let service = service.to_string();
+        let database = database.clone();
+        let metrics = metrics.clone();
+        let changes = changes.clone();
+
+        move || {
+            catch_panic(
+                || {
+                    run(
+                        api,
+                        &service,
+                        &database,
+                        &metrics,
+                        &changes,
+                        &command_rx,
+                        &event_tx,
+                        &event_rx,
+                    )
+                },
+                |message| metrics.error(FailedOperation::Panic, None, &message),
+            );
+
+            drop(browse);
+        }
+    });
+
+    Ok(MdnsClient {
+        service: service.to_string(),
+        database,
+        metrics,
+        changes,
+        scan: Arc::default(),
+        command_tx,
+        thread: Some(thread),
+        registration: None,
+        family: AddressFamily::V4,
+        backend: Backend::WindowsDns,
+        matcher: None,
+        srv_only: false,
+        keep_records: false,
+        route_aware: false,
+        connect_stagger: None,
+        lint: None,
+        observer: None,
+        inventory: None,
+        share: None,
+        seed: None,
+        #[cfg(feature = "neighbors")]
+        neighbors: None,
+        held: None,
+    })
+}
+
+fn start_resolve(
+    api: &DnsApi,
+    instance: &str,
+    event_tx: &Sender<Event>,
+) -> Option<Box<DNS_SERVICE_CANCEL>> {
+    let mut query_name = wide(instance);
+    let query_ptr = PWSTR(query_name.as_mut_ptr());
+
+    let context = Box::into_raw(Box::new(ResolveContext {
+        event_tx: event_tx.clone(),
+        instance: instance.to_string(),
+        _query_name: query_name,
+    }));
+
+    let request = DNS_SERVICE_RESOLVE_REQUEST {
+        Version: DNS_QUERY_REQUEST_VERSION1.0,
+        InterfaceIndex: 0,
+        QueryName: query_ptr,
+        pResolveCompletionCallback: Some(resolve_callback),
+        pQueryContext: context as *mut c_void,
+    };
+
+    let mut cancel = Box::new(DNS_SERVICE_CANCEL {
+        reserved: std::ptr::null_mut(),
+    });
+
+    if unsafe { (api.resolve)(&request, &mut *cancel) } == DNS_REQUEST_PENDING {
+        Some(cancel)
+    } else {
+        drop(unsafe { Box::from_raw(context) });
+        None
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run(
+    api: &DnsApi,
+    service: &str,
+    database: &Mutex<Database>,
+    metrics: &Metrics,
+    changes: &Changes,
+    command_rx: &Receiver<Command>,
+    event_tx: &Sender<Event>,
+    event_rx: &Receiver<Event>,
+) {
+    // Instances reported by the browse, with the cancel handle of an in-flight resolve.
+    let mut instances: HashMap<String, Option<Box<DNS_SERVICE_CANCEL>>> = HashMap::new();
+    // When each instance last resolved; instances that stop resolving are forgotten once
+    // their entry has expired, until the browse reports them again.
+    let mut resolved_at: HashMap<String, Instant> = HashMap::new();
+    let mut next_refresh = Instant::now();
+
+    loop {
+        match command_rx.recv_timeout(POLL_INTERVAL) {
+            Ok(Command::Exit) | Err(RecvTimeoutError::Disconnected) => break,
+            Ok(Command::Query | Command::Recover | Command::Reset) => next_refresh = Instant::now(),
+            Ok(Command::Wake) | Err(RecvTimeoutError::Timeout) => (),
+        }
+
+        while let Ok(event) = event_rx.try_recv() {
+            match event {
+                Event::Found(instance) => {
+                    if instance.contains(service) {
+                        debug!(%instance, "dns-sd instance found");
+                        resolved_at
+                            .entry(instance.clone())
+                            .or_insert_with(Instant::now);
+                        instances.entry(instance).or_insert(None);
+                    }
+                }
+                Event::Lost(instance) => {
+                    debug!(%instance, "dns-sd instance lost");
+                    instances.remove(&instance);
+                    resolved_at.remove(&instance);
+                }
+                Event::Resolved(instance, result) => {
+                    if let Some(in_flight) = instances.get_mut(&instance) {
+                        *in_flight = None;
+                    }
+
+                    if let Some((service, address)) = result {
+                        metrics.answer_applied();
+                        resolved_at.insert(instance.clone(), Instant::now());
+
+                        let mut database = Database::lock(database);
+                        let record =
+                            database.get_or_insert_with(service.clone(), || ServiceRecord {
+                                last_seen_time: Instant::now(),
+                                preferred_address: None,
+                                addresses: HashSet::new(),
+                                ipv6_addresses: HashSet::new(),
+                                conflicting: false,
+                                latency: None,
+                                provenance: Provenance::default(),
+                                pinned: false,
+                                withdrawn: false,
+                                seeded: false,
+                                mac_address: None,
+                                records: Arc::default(),
+                                history: History::default(),
+                            });
+
+                        record.last_seen_time = Instant::now();
+                        if let Some(address) = address {
+                            record.preferred_address = Some(address);
+                            record.addresses.insert(address);
+                        }
+
+                        database.instance_at(&instance, &service, None, Instant::now());
+
+                        metrics.database_size(database.len());
+                        changes.update(database, Instant::now());
+                    }
+                }
+            }
+        }
+
+        if Instant::now() < next_refresh {
+            continue;
+        }
+
+        let _span = span!("dns-sd cycle", service = %service);
+
+        instances.retain(|instance, _| {
+            resolved_at
+                .get(instance)
+                .is_some_and(|t| t.elapsed() < Duration::from_secs(5))
+        });
+        resolved_at.retain(|instance, _| instances.contains_key(instance));
+
+        for (instance, in_flight) in instances.iter_mut().filter(|(_, f)| f.is_none()) {
+            *in_flight = start_resolve(api, instance, event_tx);
+        }
+
+        remove_old_entries(database, metrics, changes, Instant::now());
+        next_refresh = Instant::now() + REFRESH_INTERVAL;
+    }
+
+    // Forget resolves that already completed, then cancel the rest; cancelled resolves
+    // still run their callback, which frees the context.
+    while let Ok(event) = event_rx.try_recv() {
+        if let Event::Resolved(instance, _) = event {
+            instances.remove(&instance);
+        }
+    }
+
+    for cancel in instances.values().flatten() {
+        unsafe { (api.resolve_cancel)(&**cancel) };
+    }
+}
@@ -0,0 +1,60 @@
+// Responses captured from other implementations, run through `MdnsClient::process_packet`:
+// `cargo test --test captures`. Each capture in tests/captures is one datagram as it came
+// off the network, taken with `MdnsClientBuilder::on_packet`.
+use simple_mdns_client::{Backend, LintCode, MdnsClient, Service};
+use std::{collections::HashSet, net::Ipv4Addr};
+
+// libmdns 0.10 answering a PTR query for "Interop Test", registered with port 4500 and
+// TXT entries `path=/` and `version=1` on a host named vm with two IPv4 addresses. Five
+// answers with a TTL of 60 and no name compression, none with the cache-flush bit.
+const LIBMDNS: &[u8] = include_bytes!("captures/libmdns.bin");
+
+fn client(service: &str) -> MdnsClient {
+    MdnsClient::builder(service)
+        .backend(Backend::Sockets)
+        .set_up_in_background()
+        .keep_records()
+        .lint_responders()
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn libmdns_answer() {
+    let client = client("_smcinterop._tcp.local");
+    client
+        .process_packet(LIBMDNS, "192.0.2.2:5353".parse().unwrap())
+        .unwrap();
+
+    let services = client.get_services();
+    let [(service, record)] = &services[..] else {
+        panic!("expected one service, got {:?}", services);
+    };
+    assert_eq!(
+        *service,
+        Service {
+            host: "vm.local".into(),
+            port: 4500,
+        }
+    );
+    let addresses: HashSet<Ipv4Addr> = ["192.0.2.2", "10.9.0.1"]
+        .iter()
+        .map(|address| address.parse().unwrap())
+        .collect();
+    assert_eq!(record.addresses, addresses);
+    assert!(record.ipv6_addresses.is_empty());
+    assert!(!record.conflicting);
+    assert_eq!(
+        client
+            .resolver()
+            .resolve_txt("Interop Test._smcinterop._tcp.local"),
+        ["path=/", "version=1"]
+    );
+
+    let codes: Vec<LintCode> = client
+        .lint_findings()
+        .iter()
+        .map(|finding| finding.code)
+        .collect();
+    assert_eq!(codes, [LintCode::MissingCacheFlush]);
+}
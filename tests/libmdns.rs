@@ -0,0 +1,92 @@
+// Interoperability with another implementation: a service advertised by libmdns is
+// discovered with its port and an address of this host, and expires once libmdns is
+// gone. Uses the network, so it only runs when asked for:
+// `SIMPLE_MDNS_LIBMDNS_TEST=1 cargo test --test libmdns -- --ignored`.
+#![cfg(not(loom))]
+use simple_mdns_client::{MdnsClient, QuerySchedule, ServiceEvent, Subscription};
+use std::{
+    env,
+    net::UdpSocket,
+    time::{Duration, Instant},
+};
+
+const SERVICE: &str = "_smcinterop._tcp.local";
+const PORT: u16 = 4500;
+
+// Generous, as libmdns announces and answers at its own pace.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+// The first event `wanted` takes before `TIMEOUT`.
+fn wait_for<T>(
+    subscription: &Subscription,
+    mut wanted: impl FnMut(ServiceEvent) -> Option<T>,
+) -> Option<T> {
+    let deadline = Instant::now() + TIMEOUT;
+
+    while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+        if let Ok(event) = subscription.recv_timeout(left) {
+            if let Some(found) = wanted(event) {
+                return Some(found);
+            }
+        }
+    }
+    None
+}
+
+#[test]
+#[ignore = "uses the network; set SIMPLE_MDNS_LIBMDNS_TEST=1 and pass --ignored"]
+fn libmdns_service() {
+    if env::var_os("SIMPLE_MDNS_LIBMDNS_TEST").is_none() {
+        eprintln!("SIMPLE_MDNS_LIBMDNS_TEST isn't set, skipping");
+        return;
+    }
+
+    let client = MdnsClient::builder(SERVICE).build().unwrap();
+    let subscription = client.subscribe();
+    // Asks again every second, in case the first queries went out before libmdns listened.
+    client
+        .set_query_schedule(QuerySchedule::fixed(Duration::from_secs(1)))
+        .unwrap();
+
+    let responder = libmdns::Responder::new();
+    let advertised = responder.register(
+        SERVICE.trim_end_matches(".local"),
+        "Interop Test",
+        PORT,
+        &["path=/"],
+    );
+
+    let service = wait_for(&subscription, |event| match event {
+        ServiceEvent::Added(service, record) | ServiceEvent::Updated(service, record)
+            if service.port == PORT && !record.addresses.is_empty() =>
+        {
+            Some((service, record))
+        }
+        _ => None,
+    });
+    let (service, record) = service.expect("libmdns's service wasn't discovered with an address");
+
+    assert!(service.host.ends_with(".local"), "host {:?}", service.host);
+    // Only an address of this host can be bound to.
+    for address in &record.addresses {
+        UdpSocket::bind((*address, 0))
+            .unwrap_or_else(|err| panic!("{} isn't an address of this host: {}", address, err));
+    }
+
+    // Its goodbye alone isn't enough: libmdns still answers for the host's address, and
+    // hearing from a host keeps its services.
+    drop(advertised);
+    drop(responder);
+    let expired = wait_for(&subscription, |event| match event {
+        ServiceEvent::Expired(expired) if expired == service => Some(()),
+        _ => None,
+    });
+    assert!(
+        expired.is_some(),
+        "the service didn't expire once libmdns was gone"
+    );
+    assert!(client
+        .get_services()
+        .iter()
+        .all(|(known, _)| *known != service));
+}
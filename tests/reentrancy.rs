@@ -0,0 +1,259 @@
+// Callbacks that call straight back into the client: a subscriber, a `ServiceStore` and an
+// `on_packet` hook each call every public method of the client that returns on its own,
+// and must get through them rather than deadlock against what the client's threads hold
+// while making the call. Needs a non-loopback IPv4 interface with multicast loopback:
+// `cargo test --features test-util --test reentrancy`.
+use simple_mdns_client::{
+    Direction, ExpiryPolicy, MdnsClient, QuerySchedule, ResponseBuilder, Service, ServiceEvent,
+    ServiceRecord, ServiceStore,
+};
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Sender},
+        Arc, Mutex, OnceLock, Weak,
+    },
+    thread,
+    time::Duration,
+};
+
+const SERVICE: &str = "_reentry._tcp.local";
+const INSTANCE: &str = "Callback._reentry._tcp.local";
+const HOST: &str = "callback.local";
+
+// Long enough for a slow CI machine; a deadlock never finishes.
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+fn response(port: u16) -> Vec<u8> {
+    ResponseBuilder::new()
+        .ptr(SERVICE, INSTANCE)
+        .srv(INSTANCE, HOST, port)
+        .a(HOST, "192.0.2.30".parse().unwrap())
+        .build()
+        .unwrap()
+}
+
+fn source() -> SocketAddr {
+    "192.0.2.30:5353".parse().unwrap()
+}
+
+// On a thread of its own, so a deadlock fails the test on the timeout instead of hanging.
+fn process(client: &Arc<MdnsClient>, port: u16) {
+    let client = client.clone();
+    thread::spawn(move || client.process_packet(&response(port), source()).unwrap());
+}
+
+// A writer that fails at once, so `run_ndjson_bridge` returns.
+struct Broken;
+
+impl io::Write for Broken {
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Every public method of `client` that returns without outside help, with `reset` and the
+// schedule setters only if `first`, as they start discovery over.
+fn call_everything(client: &MdnsClient, service: &Service, first: bool) {
+    let timeout = Duration::from_millis(1);
+
+    client.get_services();
+    client.get_inventory();
+    client.get_services_including_recent();
+    client.snapshot();
+    client.resolver().resolve(HOST);
+    client.report_failure(service);
+    client.pin(service);
+    client.unpin(service);
+    client.seed_errors();
+    client.track(service);
+    client.untrack(service);
+    client.connect_to_service(service, timeout).ok();
+    client.connect_to_instance(INSTANCE, timeout).ok();
+    client
+        .process_packet(&response(service.port), source())
+        .ok();
+    drop(client.advertise("Reentrant", "_reentry._tcp", 9, &[]));
+    client.wait_for_change(client.generation(), Duration::ZERO);
+    #[cfg(feature = "watch")]
+    client.watch();
+    drop(client.subscribe());
+    drop(client.subscribe_with_capacity(1));
+    if !client.get_services().is_empty() {
+        client.run_ndjson_bridge(Broken).ok();
+    }
+    client.skipped_interfaces();
+    client.stats();
+    client.is_ready();
+    client.initial_scan_done();
+    client.wait_for_initial_scan(Duration::ZERO);
+    client.is_running();
+    client.last_error();
+    client.recent_errors();
+    client.lint_findings();
+    client.observed_queries();
+    client.query_sources();
+    client.recent_activity();
+    let _ = format!("{:#?}", client.diagnostics());
+    #[cfg(feature = "metrics")]
+    client.render_prometheus_text();
+    let _ = format!("{:?}", client);
+
+    if first {
+        client.set_expiry_grace(Duration::from_secs(1)).unwrap();
+        client.set_expiry_policy(ExpiryPolicy::default()).unwrap();
+        client
+            .set_query_schedule(QuerySchedule::fixed(Duration::from_secs(1)))
+            .unwrap();
+        client.set_query_interval(Duration::from_secs(2)).unwrap();
+        client.reset();
+    }
+}
+
+#[test]
+fn subscriber_calls_back() {
+    let client = Arc::new(MdnsClient::builder(SERVICE).build().unwrap());
+    let subscription = client.subscribe();
+    let (done, finished) = mpsc::channel();
+
+    thread::spawn({
+        let client = Arc::downgrade(&client);
+        move || {
+            let mut first = true;
+            while let Ok(event) = subscription.recv_timeout(TIMEOUT) {
+                let (ServiceEvent::Added(service, _) | ServiceEvent::Updated(service, _)) = event
+                else {
+                    continue;
+                };
+                let Some(client) = client.upgrade() else {
+                    return;
+                };
+                call_everything(&client, &service, std::mem::take(&mut first));
+                done.send(()).ok();
+            }
+        }
+    });
+
+    process(&client, 4100);
+    finished
+        .recv_timeout(TIMEOUT)
+        .expect("the subscriber didn't get through the client's methods");
+
+    // After `reset`, the service is found again and the subscriber called again.
+    process(&client, 4100);
+    finished
+        .recv_timeout(TIMEOUT)
+        .expect("the subscriber didn't get through the client's methods again");
+}
+
+// Calls back into the client from `insert` and `remove`.
+struct CallingStore {
+    client: OnceLock<Weak<MdnsClient>>,
+    first: AtomicBool,
+    done: Mutex<Sender<&'static str>>,
+}
+
+impl CallingStore {
+    fn call(&self, service: &Service, what: &'static str) {
+        let Some(client) = self.client.get().and_then(Weak::upgrade) else {
+            return;
+        };
+        call_everything(&client, service, self.first.swap(false, Ordering::Relaxed));
+        self.done.lock().unwrap().send(what).ok();
+    }
+}
+
+impl ServiceStore for CallingStore {
+    fn insert(&self, service: &Service, _: &ServiceRecord) {
+        self.call(service, "insert");
+    }
+
+    fn remove(&self, service: &Service) {
+        self.call(service, "remove");
+    }
+
+    fn iterate(&self, _: &mut dyn FnMut(&Service, &ServiceRecord)) {}
+}
+
+#[test]
+fn store_calls_back() {
+    let (done, finished) = mpsc::channel();
+    let store = Arc::new(CallingStore {
+        client: OnceLock::new(),
+        first: AtomicBool::new(true),
+        done: Mutex::new(done),
+    });
+    let client = Arc::new(
+        MdnsClient::builder(SERVICE)
+            .service_store(store.clone())
+            .build()
+            .unwrap(),
+    );
+    store.client.set(Arc::downgrade(&client)).unwrap();
+
+    process(&client, 4200);
+    assert_eq!(
+        finished.recv_timeout(TIMEOUT),
+        Ok("insert"),
+        "the store didn't get through the client's methods"
+    );
+    // The first call's `reset` removes the service.
+    assert_eq!(
+        finished.recv_timeout(TIMEOUT),
+        Ok("remove"),
+        "the store didn't get through the client's methods on removal"
+    );
+}
+
+#[test]
+fn packet_hook_calls_back() {
+    let client: Arc<OnceLock<Weak<MdnsClient>>> = Arc::default();
+    let calls = Arc::new(AtomicUsize::new(0));
+    let (done, finished) = mpsc::channel();
+    let done = Mutex::new(done);
+
+    let built = Arc::new(
+        MdnsClient::builder(SERVICE)
+            .on_packet({
+                let client = client.clone();
+                move |_, _, direction| {
+                    let Some(client) = client.get().and_then(Weak::upgrade) else {
+                        return;
+                    };
+                    // A few calls are enough; every packet would keep the client busy.
+                    let call = calls.fetch_add(1, Ordering::Relaxed);
+                    if call >= 8 {
+                        return;
+                    }
+                    let service = Service {
+                        host: HOST.into(),
+                        port: 4300,
+                    };
+                    call_everything(&client, &service, call == 0);
+                    done.lock().unwrap().send(direction).ok();
+                }
+            })
+            .build()
+            .unwrap(),
+    );
+    client.set(Arc::downgrade(&built)).unwrap();
+    // Queries go out every second, and come back through multicast loopback.
+    built
+        .set_query_schedule(QuerySchedule::fixed(Duration::from_millis(200)))
+        .unwrap();
+
+    let (mut sent, mut received) = (false, false);
+    while !(sent && received) {
+        match finished.recv_timeout(TIMEOUT) {
+            Ok(Direction::Sent) => sent = true,
+            Ok(Direction::Received) => received = true,
+            Err(_) => panic!("the hook didn't get through the client's methods"),
+        }
+    }
+}
@@ -0,0 +1,277 @@
+// Crafted responses through `MdnsState::handle_packet`, the socket backend's parse-and-apply
+// path: `cargo test --features test-util --test responses`.
+use simple_mdns_client::{
+    AddressFamily, MdnsState, ResponseBuilder, ScopedIpv6Addr, Service, ServiceEvent,
+    ServiceRecord, Subscription,
+};
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6},
+    time::{Duration, Instant},
+};
+
+const SERVICE: &str = "_http._tcp.local";
+const INSTANCE: &str = "Device._http._tcp.local";
+
+struct Feed {
+    state: MdnsState,
+    subscription: Subscription,
+    start: Instant,
+}
+
+impl Feed {
+    fn new(family: AddressFamily) -> Feed {
+        let state = MdnsState::new(SERVICE, family).unwrap();
+        let start = Instant::now();
+        let subscription = state.subscribe(start);
+
+        Feed {
+            state,
+            subscription,
+            start,
+        }
+    }
+
+    // `response` from `source`, `at` seconds in.
+    fn hear(&mut self, at: u64, response: ResponseBuilder, source: &str) {
+        let now = self.start + Duration::from_secs(at);
+        let source: SocketAddr = source.parse().unwrap();
+
+        self.state
+            .handle_packet(now, &response.build().unwrap(), source)
+            .unwrap();
+    }
+
+    fn services(&self) -> Vec<(Service, ServiceRecord)> {
+        self.state.services()
+    }
+
+    fn events(&self) -> Vec<ServiceEvent> {
+        std::iter::from_fn(|| self.subscription.recv_timeout(Duration::ZERO).ok()).collect()
+    }
+}
+
+fn hosts_and_ports(services: &[(Service, ServiceRecord)]) -> Vec<(String, u16)> {
+    services
+        .iter()
+        .map(|(service, _)| (service.host.to_string(), service.port))
+        .collect()
+}
+
+const SOURCE: &str = "192.0.2.10:5353";
+
+fn address() -> Ipv4Addr {
+    Ipv4Addr::new(192, 0, 2, 10)
+}
+
+#[test]
+fn host_spellings_are_one_service() {
+    let mut feed = Feed::new(AddressFamily::V4);
+
+    feed.hear(
+        0,
+        ResponseBuilder::new()
+            .ptr(SERVICE, INSTANCE)
+            .srv(INSTANCE, "MyHost.local.", 8080),
+        SOURCE,
+    );
+    feed.hear(
+        1,
+        ResponseBuilder::new().srv(INSTANCE, "myhost.local", 8080),
+        SOURCE,
+    );
+    feed.hear(
+        2,
+        ResponseBuilder::new().a("MYHOST.LOCAL.", address()),
+        SOURCE,
+    );
+
+    let services = feed.services();
+    assert_eq!(hosts_and_ports(&services), [("myhost.local".into(), 8080)]);
+    assert!(services[0].1.addresses.contains(&address()));
+    // Coalesced, as it wasn't read in between.
+    assert!(matches!(
+        &feed.events()[..],
+        [ServiceEvent::Added(_, record)] if record.addresses.contains(&address())
+    ));
+}
+
+#[test]
+fn port_moves_replace_the_service() {
+    let mut feed = Feed::new(AddressFamily::V4);
+    let announce = |port| {
+        ResponseBuilder::new()
+            .ptr(SERVICE, INSTANCE)
+            .srv(INSTANCE, "device.local", port)
+            .a("device.local", address())
+    };
+
+    feed.hear(0, announce(8080), SOURCE);
+    assert!(matches!(&feed.events()[..], [ServiceEvent::Added(..)]));
+
+    feed.hear(1, announce(8081), SOURCE);
+    assert_eq!(
+        hosts_and_ports(&feed.services()),
+        [("device.local".into(), 8081)]
+    );
+    match &feed.events()[..] {
+        [ServiceEvent::Moved { from, to, record }] => {
+            assert_eq!((from.port, to.port), (8080, 8081));
+            assert!(record.addresses.contains(&address()));
+        }
+        events => panic!("expected one move, got {:?}", events),
+    }
+}
+
+#[test]
+fn literal_targets_are_addresses() {
+    let mut feed = Feed::new(AddressFamily::V4);
+    let literal = Ipv4Addr::new(192, 168, 1, 50);
+
+    feed.hear(
+        0,
+        ResponseBuilder::new()
+            .ptr(SERVICE, INSTANCE)
+            .srv(INSTANCE, "192.168.1.50.", 80),
+        "192.168.1.50:5353",
+    );
+    let services = feed.services();
+    assert_eq!(hosts_and_ports(&services), [("192.168.1.50".into(), 80)]);
+    let record = &services[0].1;
+    assert_eq!(record.addresses.iter().collect::<Vec<_>>(), [&literal]);
+    assert_eq!(record.preferred_address, Some(literal));
+
+    // An address record for it still counts.
+    let other = Ipv4Addr::new(10, 0, 0, 50);
+    feed.hear(
+        1,
+        ResponseBuilder::new().a("192.168.1.50", other),
+        "192.168.1.50:5353",
+    );
+    assert!(feed.services()[0].1.addresses.contains(&other));
+}
+
+// Another responder claims the instance `at` seconds after the first, with or without
+// the cache-flush bit on its SRV record.
+fn claimed_again(at: u64, cache_flush: bool) -> Vec<(Service, ServiceRecord)> {
+    let mut feed = Feed::new(AddressFamily::V4);
+    let claim = |host: &str| {
+        ResponseBuilder::new()
+            .ptr(SERVICE, INSTANCE)
+            .cache_flush(cache_flush)
+            .srv(INSTANCE, host, 80)
+    };
+
+    feed.hear(0, claim("first.local"), "192.0.2.10:5353");
+    feed.hear(at, claim("second.local"), "192.0.2.11:5353");
+    feed.services()
+}
+
+#[test]
+fn cache_flush_srv_replaces_other_claims() {
+    let services = claimed_again(2, true);
+    assert_eq!(hosts_and_ports(&services), [("second.local".into(), 80)]);
+    assert!(!services[0].1.conflicting);
+
+    // Within the second, both may be answering the same query.
+    let services = claimed_again(0, true);
+    assert_eq!(
+        hosts_and_ports(&services),
+        [("first.local".into(), 80), ("second.local".into(), 80)]
+    );
+    assert!(services[1].1.conflicting);
+}
+
+#[test]
+fn shared_srv_adds_to_other_claims() {
+    let services = claimed_again(2, false);
+
+    assert_eq!(
+        hosts_and_ports(&services),
+        [("first.local".into(), 80), ("second.local".into(), 80)]
+    );
+    assert!(!services[0].1.conflicting);
+    assert!(services[1].1.conflicting);
+}
+
+// The instance announced at device.local:80, answering a query, then at `host`:`port`.
+fn moved_to(host: &str, port: u16) -> Feed {
+    let mut feed = Feed::new(AddressFamily::V4);
+    let announce = |host: &str, port| {
+        ResponseBuilder::new()
+            .ptr(SERVICE, INSTANCE)
+            .srv(INSTANCE, host, port)
+            .a(host, address())
+    };
+
+    feed.state.poll_transmit(feed.start).unwrap();
+    feed.hear(0, announce("device.local", 80), SOURCE);
+    assert!(matches!(&feed.events()[..], [ServiceEvent::Added(..)]));
+
+    feed.hear(2, announce(host, port), SOURCE);
+    feed
+}
+
+#[test]
+fn host_renames_move_the_service() {
+    for (host, port) in [("device-2.local", 80), ("device-2.local", 8080)] {
+        let feed = moved_to(host, port);
+
+        assert_eq!(hosts_and_ports(&feed.services()), [(host.into(), port)]);
+        match &feed.events()[..] {
+            [ServiceEvent::Moved { from, to, record }] => {
+                assert_eq!((&*from.host, from.port), ("device.local", 80));
+                assert_eq!((&*to.host, to.port), (host, port));
+                // Answered too late to be timed itself.
+                assert!(record.latency.is_some());
+            }
+            events => panic!("expected one move to {}:{}, got {:?}", host, port, events),
+        }
+    }
+}
+
+#[test]
+fn link_local_addresses_keep_each_scope() {
+    let mut feed = Feed::new(AddressFamily::V6);
+    let link_local: Ipv6Addr = "fe80::1".parse().unwrap();
+    let global: Ipv6Addr = "2001:db8::1".parse().unwrap();
+    let announce = || {
+        ResponseBuilder::new()
+            .ptr(SERVICE, INSTANCE)
+            .srv(INSTANCE, "device.local", 80)
+            .aaaa("device.local", link_local)
+            .aaaa("device.local", global)
+    };
+
+    // The same host heard on the interfaces with indexes 2 and 3.
+    feed.hear(0, announce(), "[fe80::1%2]:5353");
+    feed.hear(2, announce(), "[fe80::1%3]:5353");
+
+    let services = feed.services();
+    let [(_, record)] = &services[..] else {
+        panic!("expected one service, got {:?}", services);
+    };
+    let mut addresses: Vec<_> = record.ipv6_addresses.iter().copied().collect();
+    addresses.sort();
+    assert_eq!(
+        addresses,
+        [
+            ScopedIpv6Addr {
+                ip: global,
+                scope_id: 0
+            },
+            ScopedIpv6Addr {
+                ip: link_local,
+                scope_id: 2
+            },
+            ScopedIpv6Addr {
+                ip: link_local,
+                scope_id: 3
+            },
+        ]
+    );
+    assert_eq!(
+        addresses[2].socket_addr(80),
+        SocketAddrV6::new(link_local, 80, 0, 3)
+    );
+    assert_eq!(addresses[1].to_string(), "fe80::1%2");
+}
@@ -0,0 +1,228 @@
+// `MdnsState` on a clock of its own, years from now, so that anything still reading the
+// real one shows: `cargo test --features test-util --test state`.
+use simple_mdns_client::{
+    AddressFamily, MdnsState, QuerySchedule, ResponseBuilder, ServiceEvent, Subscription,
+};
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+const SERVICE: &str = "_state._tcp.local";
+
+fn start() -> Instant {
+    Instant::now() + Duration::from_secs(10 * 365 * 24 * 3600)
+}
+
+fn state() -> MdnsState {
+    MdnsState::new(SERVICE, AddressFamily::V4).unwrap()
+}
+
+fn response(instance: &str, port: u16) -> Vec<u8> {
+    let instance = format!("{}.{}", instance, SERVICE);
+    ResponseBuilder::new()
+        .ptr(SERVICE, &instance)
+        .srv(&instance, "device.local", port)
+        .a("device.local", "192.0.2.10".parse().unwrap())
+        .build()
+        .unwrap()
+}
+
+fn source() -> SocketAddr {
+    "192.0.2.10:5353".parse().unwrap()
+}
+
+fn events(subscription: &Subscription) -> Vec<ServiceEvent> {
+    std::iter::from_fn(|| subscription.recv_timeout(Duration::ZERO).ok()).collect()
+}
+
+#[test]
+fn added_then_expired() {
+    let start = start();
+    let mut state = state();
+    let subscription = state.subscribe(start);
+
+    state
+        .handle_packet(start, &response("Device", 8080), source())
+        .unwrap();
+    let services = state.services();
+    assert_eq!(services.len(), 1);
+    let (service, record) = &services[0];
+    assert_eq!((&*service.host, service.port), ("device.local", 8080));
+    assert_eq!(record.last_seen_time, start);
+    assert!(
+        matches!(&events(&subscription)[..], [ServiceEvent::Added(added, _)] if added == service)
+    );
+
+    // Not heard from again: due to expire when the state says, and gone then.
+    let expiry = state.poll_timeout(start);
+    assert!(expiry <= start + Duration::from_secs(5));
+    state.handle_timeout(start + Duration::from_secs(5));
+    assert!(state.services().is_empty());
+    assert!(
+        matches!(&events(&subscription)[..], [ServiceEvent::Expired(expired)] if expired == service)
+    );
+}
+
+#[test]
+fn queries_follow_the_schedule() {
+    let start = start();
+    let mut state = state();
+
+    // The first at once, then one a second.
+    assert_eq!(state.poll_timeout(start), start);
+    assert!(state.poll_transmit(start).is_some());
+    assert!(state.poll_transmit(start).is_none());
+    assert_eq!(state.poll_timeout(start), start + Duration::from_secs(1));
+
+    // The query due stays due; the one after it waits the new interval.
+    let schedule = QuerySchedule::fixed(Duration::from_secs(10));
+    state.set_query_schedule(start, schedule).unwrap();
+    let next = start + Duration::from_secs(1);
+    assert_eq!(state.poll_timeout(start), next);
+    assert!(state.poll_transmit(next).is_some());
+    assert_eq!(state.poll_timeout(next), next + Duration::from_secs(10));
+    assert!(state.poll_transmit(next + Duration::from_secs(5)).is_none());
+
+    assert!(state
+        .set_query_schedule(next, QuerySchedule::fixed(Duration::ZERO))
+        .is_err());
+}
+
+#[test]
+fn copies_and_floods_are_dropped() {
+    let start = start();
+    let mut state = state();
+
+    let response = response("Device", 8080);
+    state.handle_packet(start, &response, source()).unwrap();
+    state.handle_packet(start, &response, source()).unwrap();
+    assert_eq!(state.stats().duplicates, 1);
+    assert_eq!(state.stats().datagrams_received, 1);
+
+    // 50 datagrams a second from one source, the copy among them.
+    for port in 1..60 {
+        state
+            .handle_packet(start, &self::response("Other", port), source())
+            .unwrap();
+    }
+    assert_eq!(state.stats().rate_limited, 11);
+    assert_eq!(state.stats().datagrams_received, 49);
+}
+
+#[test]
+fn junk_fails_to_parse() {
+    let mut state = state();
+
+    assert!(state.handle_packet(start(), &[0; 5], source()).is_err());
+    assert_eq!(state.stats().parse_failures, 1);
+}
+
+#[test]
+fn discovered_refreshed_expired() {
+    let start = start();
+    let mut state = state();
+    let subscription = state.subscribe(start);
+    let seconds = |seconds: f64| start + Duration::from_secs_f64(seconds);
+    let sent = |state: &mut MdnsState, now: Instant| {
+        std::iter::from_fn(|| state.poll_transmit(now)).count()
+    };
+
+    assert_eq!(sent(&mut state, start), 1);
+    state
+        .handle_packet(seconds(0.1), &response("Device", 8080), source())
+        .unwrap();
+    let service = state.services()[0].0.clone();
+
+    // Close to expiring, it is asked after by name along with the query.
+    assert_eq!(sent(&mut state, seconds(3.5)), 2);
+    assert_eq!(state.stats().services_reconfirmed, 1);
+
+    // The answer keeps it past when it would have expired, without an event.
+    state
+        .handle_packet(seconds(3.6), &response("Device", 8080), source())
+        .unwrap();
+    state.handle_timeout(seconds(5.5));
+    assert_eq!(state.services()[0].1.last_seen_time, seconds(3.6));
+    assert!(matches!(
+        &events(&subscription)[..],
+        [ServiceEvent::Added(..)]
+    ));
+
+    // Silent from then on.
+    state.handle_timeout(seconds(8.6));
+    assert!(state.services().is_empty());
+    assert!(
+        matches!(&events(&subscription)[..], [ServiceEvent::Expired(expired)] if *expired == service)
+    );
+}
+
+// A flood of distinct instances, each on a host of its own and half without an address,
+// from many sources at once: what is kept stays within the discovery rate limit and the
+// expiry time, and the next query is never more than a second away.
+#[test]
+fn instance_floods_stay_bounded() {
+    const SOURCES: u32 = 50;
+    // Within the per-source rate limit.
+    const PER_SOURCE: u32 = 20;
+    const SECONDS: u64 = 10;
+    // Services each second at most, and how many seconds' worth are kept.
+    const DISCOVERY_RATE_LIMIT: usize = 200;
+    const KEPT: usize = 6;
+
+    let start = start();
+    let mut state = state();
+    let subscription = state.subscribe(start);
+    let mut next = 0u32;
+
+    for second in 0..SECONDS {
+        let now = start + Duration::from_secs(second);
+        let mut sent = 0;
+        while let Some(packet) = state.poll_transmit(now) {
+            assert!(packet.len() <= 9000, "{} bytes", packet.len());
+            sent += 1;
+        }
+        // Questions by name go out a few at a time, for the services kept at most.
+        assert!(
+            sent <= 1 + KEPT * DISCOVERY_RATE_LIMIT / 16,
+            "{} datagrams",
+            sent
+        );
+        assert!(state.poll_timeout(now) <= now + Duration::from_secs(1));
+
+        for source in 0..SOURCES {
+            let address = Ipv4Addr::new(10, 0, source as u8, 1);
+            let from = SocketAddr::from((address, 5353));
+            for _ in 0..PER_SOURCE {
+                next += 1;
+                let instance = format!("Flood {}.{}", next, SERVICE);
+                let host = format!("flood-{}.local", next);
+                let mut response = ResponseBuilder::new()
+                    .ptr(SERVICE, &instance)
+                    .srv(&instance, &host, 80);
+                if next.is_multiple_of(2) {
+                    response = response.a(&host, address);
+                }
+                state
+                    .handle_packet(now, &response.build().unwrap(), from)
+                    .unwrap();
+            }
+        }
+        state.handle_timeout(now);
+
+        assert!(state.services().len() <= KEPT * DISCOVERY_RATE_LIMIT);
+    }
+
+    let stats = state.stats();
+    assert_eq!(stats.datagrams_received, u64::from(next));
+    assert_eq!(stats.rate_limited, 0);
+    assert!(stats.discoveries_limited >= u64::from(next) - SECONDS * DISCOVERY_RATE_LIMIT as u64);
+    let added = events(&subscription)
+        .into_iter()
+        .filter(|event| matches!(event, ServiceEvent::Added(..)))
+        .count();
+    assert!(added <= SECONDS as usize * DISCOVERY_RATE_LIMIT);
+
+    state.handle_timeout(start + Duration::from_secs(SECONDS + 5));
+    assert!(state.services().is_empty());
+}